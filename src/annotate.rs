@@ -0,0 +1,35 @@
+//! Internal state attached to replies, for reading Maelstrom histories.
+//!
+//! Maelstrom keeps whole messages in its history but ignores body fields it
+//! doesn't know, so a node can explain a reply inside it: which term it was
+//! leader in, how far its log was applied, which node owned the shard, whether
+//! a cache answered. With [`Runtime::annotate`] on, or [`ANNOTATE_ENV`] set
+//! for a runtime from [`Runtime::from_env`], [`Message::send_annotated`] adds
+//! such a note to the body under [`FIELD`]; otherwise it sends the message as
+//! it is and never builds the note, so the annotations can stay in the code
+//! at no cost:
+//!
+//! ```ignore
+//! let reply = input.reply_with(Payload::ReadOk { value }, &ctx);
+//! reply.send_annotated(&ctx, || json!({"term": term, "applied": applied, "cache": "hit"}))?;
+//! ```
+//!
+//! [`Runtime::annotate`]: crate::Runtime::annotate
+//! [`Runtime::from_env`]: crate::Runtime::from_env
+//! [`Message::send_annotated`]: crate::Message::send_annotated
+
+use serde_json::Value;
+
+/// Environment variable that turns on [`Runtime::annotate`] for
+/// [`Runtime::from_env`] when set to anything but `0`.
+///
+/// [`Runtime::annotate`]: crate::Runtime::annotate
+/// [`Runtime::from_env`]: crate::Runtime::from_env
+pub const ANNOTATE_ENV: &str = "DIST_SYS_ANNOTATE";
+
+/// The body field annotations go in.
+pub const FIELD: &str = "debug";
+
+pub(crate) fn stamp(msg: &mut Value, annotation: Value) {
+    msg["body"][FIELD] = annotation;
+}
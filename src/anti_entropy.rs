@@ -0,0 +1,119 @@
+//! Full-state sync for nodes that start late, restart or fall behind.
+//!
+//! A node pulls a peer's whole state in chunks, each chunk picking up after
+//! the largest item of the previous one, and merges it into its own. Only
+//! grow-only state is supported: an item the peer adds while the pull is in
+//! progress may or may not be included, but nothing present for the whole
+//! pull is missed.
+//!
+//! The serving node handles the request itself: its payload enum carries a
+//! `Sync(SyncRequest<I>)` variant, answered with `SyncOk(serve(..))`.
+
+use crate::Ctx;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::hash::Hash;
+use std::time::Duration;
+
+/// Asks for up to `limit` items greater than `after`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncRequest<I> {
+    pub after: Option<I>,
+    pub limit: usize,
+}
+
+/// One chunk of the peer's state, in ascending order. `done` is set on the
+/// last chunk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncResponse<I> {
+    pub items: Vec<I>,
+    pub done: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SyncPayload<I> {
+    Sync(SyncRequest<I>),
+    SyncOk(SyncResponse<I>),
+}
+
+/// Grow-only state that can be listed in a stable order and merged.
+pub trait Mergeable {
+    type Item: Ord + Clone;
+
+    /// Up to `limit` items greater than `after`, in ascending order.
+    fn items_after(&self, after: Option<&Self::Item>, limit: usize) -> Vec<Self::Item>;
+
+    fn merge(&mut self, items: impl IntoIterator<Item = Self::Item>);
+}
+
+impl<T: Ord + Clone> Mergeable for BTreeSet<T> {
+    type Item = T;
+
+    fn items_after(&self, after: Option<&T>, limit: usize) -> Vec<T> {
+        let items: Box<dyn Iterator<Item = &T>> = match after {
+            Some(after) => Box::new(self.range((std::ops::Bound::Excluded(after), std::ops::Bound::Unbounded))),
+            None => Box::new(self.iter()),
+        };
+        items.take(limit).cloned().collect()
+    }
+
+    fn merge(&mut self, items: impl IntoIterator<Item = T>) {
+        self.extend(items);
+    }
+}
+
+impl<T: Ord + Hash + Clone> Mergeable for HashSet<T> {
+    type Item = T;
+
+    fn items_after(&self, after: Option<&T>, limit: usize) -> Vec<T> {
+        let mut items: Vec<_> = self.iter().filter(|item| after.is_none_or(|after| *item > after)).collect();
+        items.sort_unstable();
+        items.into_iter().take(limit).cloned().collect()
+    }
+
+    fn merge(&mut self, items: impl IntoIterator<Item = T>) {
+        self.extend(items);
+    }
+}
+
+/// Answers a [`SyncRequest`] from `state`.
+pub fn serve<S: Mergeable>(state: &S, request: SyncRequest<S::Item>) -> SyncResponse<S::Item> {
+    let items = state.items_after(request.after.as_ref(), request.limit);
+    let done = items.len() < request.limit;
+    SyncResponse { items, done }
+}
+
+/// Pulls `peer`'s whole state, `chunk_len` items per round trip, handing
+/// each chunk to `merge` as it arrives. Each round trip times out after
+/// `timeout`. Returns the number of items received.
+pub async fn pull<I>(
+    ctx: &Ctx,
+    peer: &str,
+    chunk_len: usize,
+    timeout: Duration,
+    mut merge: impl FnMut(Vec<I>),
+) -> anyhow::Result<usize>
+where
+    I: Serialize + DeserializeOwned + Clone,
+{
+    assert!(chunk_len > 0, "chunks must hold at least one item");
+    let mut after = None;
+    let mut received = 0;
+    loop {
+        let request = SyncPayload::Sync(SyncRequest {
+            after: after.take(),
+            limit: chunk_len,
+        });
+        let SyncPayload::SyncOk(response) = ctx.rpc_timeout::<_, SyncPayload<I>>(peer, request, timeout).await? else {
+            anyhow::bail!("{} answered sync with another sync", peer);
+        };
+        received += response.items.len();
+        after = response.items.last().cloned();
+        merge(response.items);
+        if response.done || after.is_none() {
+            return Ok(received);
+        }
+    }
+}
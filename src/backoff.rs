@@ -0,0 +1,145 @@
+//! Delays between retries, shared by everything in the crate that retries,
+//! so retry timing is consistent and tunable in one place.
+//!
+//! A [`Backoff`] is a schedule of delays, which [`Backoff::delays`] iterates
+//! over; a [`Policy`] also limits the number of attempts. [`retry`] runs an
+//! operation under a policy, sleeping on the node's clock in between.
+//!
+//! Jittered schedules keep nodes that failed at the same moment, e.g. CAS
+//! losers contending for one key, from colliding again on every retry.
+
+use crate::time::Clock;
+use rand::Rng;
+use std::future::Future;
+use std::time::Duration;
+
+/// For retrying after losing a race, such as a failed CAS: short, jittered
+/// and unbounded, since the winner's progress makes the next attempt likely
+/// to succeed.
+pub const CONTENTION: Policy = Policy {
+    backoff: Backoff::DecorrelatedJitter {
+        base: Duration::from_millis(1),
+        max: Duration::from_millis(50),
+    },
+    max_attempts: None,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// The same delay every time.
+    Constant(Duration),
+    /// `base`, doubling after every retry, up to `max`.
+    Exponential { base: Duration, max: Duration },
+    /// Each delay drawn uniformly between `base` and three times the
+    /// previous one, up to `max`.
+    DecorrelatedJitter { base: Duration, max: Duration },
+    /// `base` times successive Fibonacci numbers (1, 1, 2, 3, 5, ...), up to
+    /// `max`: gentler growth than doubling.
+    Fibonacci { base: Duration, max: Duration },
+}
+
+impl Backoff {
+    /// The delays before each retry, without end.
+    pub fn delays(&self) -> Delays {
+        Delays {
+            backoff: *self,
+            previous: None,
+            next_fibonacci: Duration::ZERO,
+            remaining: None,
+        }
+    }
+}
+
+/// How long to wait before each retry, and how many attempts to make.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    pub backoff: Backoff,
+    /// Attempts in all, the first included; `None` retries forever.
+    pub max_attempts: Option<usize>,
+}
+
+impl Policy {
+    pub fn new(backoff: Backoff) -> Self {
+        Self {
+            backoff,
+            max_attempts: None,
+        }
+    }
+
+    /// At most `max_attempts` attempts, and at least one.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = Some(max_attempts.max(1));
+        self
+    }
+
+    /// The delays before each retry the policy allows.
+    pub fn delays(&self) -> Delays {
+        Delays {
+            remaining: self.max_attempts.map(|max| max.saturating_sub(1)),
+            ..self.backoff.delays()
+        }
+    }
+}
+
+/// Iterates over a [`Backoff`]'s delays.
+#[derive(Debug, Clone)]
+pub struct Delays {
+    backoff: Backoff,
+    previous: Option<Duration>,
+    /// The Fibonacci number after `previous`, in units of `base`.
+    next_fibonacci: Duration,
+    /// Delays left before the policy's attempts run out; unbounded if `None`.
+    remaining: Option<usize>,
+}
+
+impl Iterator for Delays {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        if let Some(remaining) = &mut self.remaining {
+            *remaining = remaining.checked_sub(1)?;
+        }
+        let delay = match (self.backoff, self.previous) {
+            (Backoff::Constant(delay), _) => delay,
+            (Backoff::Exponential { base, max }, previous) => previous.map_or(base, |p| p.saturating_mul(2)).min(max),
+            (Backoff::DecorrelatedJitter { base, max }, previous) => {
+                let high = previous.unwrap_or(base).saturating_mul(3).max(base);
+                rand::rng().random_range(base..=high).min(max)
+            }
+            (Backoff::Fibonacci { base, max }, None) => {
+                self.next_fibonacci = base;
+                base.min(max)
+            }
+            (Backoff::Fibonacci { max, .. }, Some(previous)) => {
+                let delay = self.next_fibonacci.min(max);
+                self.next_fibonacci = self.next_fibonacci.saturating_add(previous);
+                delay
+            }
+        };
+        self.previous = Some(delay);
+        Some(delay)
+    }
+}
+
+/// Calls `op` with the attempt number, from 0, until it returns `Some`,
+/// sleeping on `clock` for the policy's next delay after each `None`.
+/// Returns `None` once the policy's attempts run out. An error from `op`
+/// isn't retried.
+pub async fn retry<T, F, Fut>(clock: &dyn Clock, policy: &Policy, mut op: F) -> anyhow::Result<Option<T>>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = anyhow::Result<Option<T>>>,
+{
+    let mut delays = policy.delays();
+    let mut attempt = 0;
+    loop {
+        if let Some(done) = op(attempt).await? {
+            return Ok(Some(done));
+        }
+        let Some(delay) = delays.next() else {
+            return Ok(None);
+        };
+        clock.sleep(delay).await;
+        attempt += 1;
+    }
+}
@@ -1,9 +1,15 @@
 use anyhow::Context;
+use dist_sys::anti_entropy::{Mergeable, SyncRequest, SyncResponse};
+use dist_sys::gossip::{GossipStats, PeerSampler, PeerSampling, PeerStats, RangeDigest};
+use dist_sys::selftest::{self, Script};
+use dist_sys::state::StateCell;
+use dist_sys::supervise::RestartPolicy;
 use dist_sys::*;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::{
-    collections::{HashMap, HashSet}, io::StdoutLock, sync::{Arc, Mutex}, time::Duration
+    collections::{BTreeMap, HashMap, HashSet}, time::Duration
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,12 +24,18 @@ enum Payload {
     ReadOk {
         messages: HashSet<usize>,
     },
-    Topology {
-        topology: HashMap<String, Vec<String>>,
-    },
-    TopologyOk,
     Gossip {
         seen: HashSet<usize>,
+        /// Everything the sender has, when gossiping push-pull.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        digest: Option<RangeDigest>,
+    },
+    Sync(SyncRequest<usize>),
+    SyncOk(SyncResponse<usize>),
+    /// Asks for the node's gossip statistics, by neighbour.
+    Stats,
+    StatsOk {
+        peers: BTreeMap<String, PeerStats>,
     },
 }
 
@@ -31,48 +43,137 @@ enum InjectedPayload {
     Gossip,
 }
 
+// a gossip round sends whatever is current when it runs, so queued ticks
+// behind it add nothing
+impl Coalescible for InjectedPayload {
+    fn coalesces(&self, queued: &Self) -> bool {
+        matches!((self, queued), (InjectedPayload::Gossip, InjectedPayload::Gossip))
+    }
+}
+
+/// Gossip tuning for each part of the Gossip Glomers broadcast challenge
+/// (3a to 3e), picked by the node's mode (see [`config`]) and defaulting to
+/// `fault-tolerant`. The `gossip-interval`, `resend-percent`, `gossip-peers`,
+/// `push-pull` and `adaptive-resend` knobs override the variant's settings.
+#[derive(Debug, Clone, Copy)]
+struct Variant {
+    /// `None` never gossips, which is all a lone node needs.
+    gossip_interval: Option<Duration>,
+    /// Share of messages a neighbour is already known to have that are
+    /// re-sent anyway, in percent of the new ones, to cover lost gossip.
+    resend_percent: usize,
+    /// Which neighbours each round goes to.
+    peers: PeerSampling,
+    /// Whether gossip carries a digest for the receiver to answer with what
+    /// this node is missing; see [`gossip`](dist_sys::gossip).
+    push_pull: bool,
+    /// Whether each neighbour's resends are scaled by how much of its own
+    /// gossip is news; see [`GossipStats::resend_percent`].
+    adaptive_resend: bool,
+}
+
+impl std::str::FromStr for Variant {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> anyhow::Result<Self> {
+        let (interval_ms, resend_percent) = match name {
+            "single" => {
+                return Ok(Self {
+                    gossip_interval: None,
+                    resend_percent: 0,
+                    peers: PeerSampling::All,
+                    push_pull: false,
+                    adaptive_resend: false,
+                });
+            }
+            "multi" => (100, 0),
+            "fault-tolerant" => (100, 10),
+            // fewer, larger gossip messages trade latency for message count
+            "efficient-1" => (200, 10),
+            "efficient-2" => (500, 10),
+            _ => anyhow::bail!(
+                "unknown broadcast variant {:?}; expected single, multi, fault-tolerant, efficient-1 or efficient-2",
+                name
+            ),
+        };
+        Ok(Self {
+            gossip_interval: Some(Duration::from_millis(interval_ms)),
+            resend_percent,
+            peers: PeerSampling::All,
+            push_pull: false,
+            adaptive_resend: false,
+        })
+    }
+}
+
 // Shared mutable state
 #[derive(Debug)]
 struct NodeState {
-    id: usize,
     messages: HashSet<usize>,
     known: HashMap<String, HashSet<usize>>,
-    neighborhood: Vec<String>,
+    /// The neighbour each message was first heard from, which never needs it
+    /// re-sent.
+    learned_from: HashMap<usize, String>,
 }
 
 struct BroadcastNode {
     node: String,
-    state: Mutex<NodeState>,
+    /// Every other node, gossiped to until a topology says otherwise.
+    peers: Vec<String>,
+    variant: Variant,
+    sampler: PeerSampler,
+    stats: GossipStats,
+    state: StateCell<NodeState>,
 }
 
-impl Node<(), Payload, (), InjectedPayload> for BroadcastNode {
+impl BroadcastNode {
+    /// The topology's neighbours, or every peer before a topology arrives.
+    fn neighborhood(&self, ctx: &Ctx) -> Vec<String> {
+        let neighbors = ctx.neighbors();
+        if neighbors.is_empty() { self.peers.clone() } else { neighbors }
+    }
+
+    /// Sends `gossip` to `dst`, counting it in the stats.
+    fn gossip(&self, gossip: Message<Payload>, ctx: &Ctx) -> anyhow::Result<()> {
+        let bytes = gossip.send_sized(ctx).with_context(|| format!("gossip to {}", gossip.dst))?;
+        if let Payload::Gossip { seen, .. } = &gossip.body.payload {
+            self.stats.sent(&gossip.dst, seen.len(), bytes);
+        }
+        Ok(())
+    }
+}
+
+impl Node<Variant, Payload, (), InjectedPayload> for BroadcastNode {
     async fn from_init(
-        _state: (),
+        variant: Variant,
         init: Init,
         tx: tokio::sync::mpsc::UnboundedSender<Event<Payload, (), InjectedPayload>>,
-        _output: &mut StdoutLock<'_>
+        ctx: &Ctx
     ) -> anyhow::Result<Self> {
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_millis(100));
-            loop {
-                interval.tick().await;
-                if let Err(_) = tx.send(Event::Injected(InjectedPayload::Gossip)) {
-                    break;
+        if let Some(interval) = variant.gossip_interval {
+            ctx.spawn_supervised("gossip-timer", RestartPolicy::default(), move |ctx| {
+                let tx = tx.clone();
+                async move {
+                    loop {
+                        ctx.sleep(interval).await;
+                        if tx.send(Event::Injected(InjectedPayload::Gossip)).is_err() {
+                            return Ok(());
+                        }
+                    }
                 }
-            }
-        });
+            });
+        }
 
         Ok(Self {
             node: init.node_id.clone(),
-            state: Mutex::new(NodeState {
-                id: 1,
+            peers: init.node_ids.iter().filter(|n| **n != init.node_id).cloned().collect(),
+            variant,
+            sampler: PeerSampler::new(variant.peers),
+            stats: GossipStats::default(),
+            state: StateCell::new(NodeState {
                 messages: HashSet::new(),
-                neighborhood: vec![],
-                known: init
-                    .node_ids
-                    .into_iter()
-                    .map(|nid| (nid, HashSet::new()))
-                    .collect(),
+                known: HashMap::new(),
+                learned_from: HashMap::new(),
             }),
         })
     }
@@ -80,30 +181,76 @@ impl Node<(), Payload, (), InjectedPayload> for BroadcastNode {
     async fn step(
         &self,
         input: Event<Payload, (), InjectedPayload>,
-        output: Arc<Mutex<std::io::Stdout>>,
+        ctx: Ctx,
     ) -> anyhow::Result<()> {
         match input {
-            Event::EOF => {}
+            Event::Signal(signal::Signal::Terminate) => {}
+            Event::EOF | Event::Signal(signal::Signal::DumpStats) => {
+                let stats = json!({"event": "gossip_stats", "node": self.node, "peers": self.stats.all()});
+                eprintln!("{}", stats);
+            }
             Event::ServiceMessage(..) => {}
-            
+            Event::StaleLeader(_) => {}
+            Event::PeerRestarted(restarted) => {
+                // whatever the old incarnation had is gone, so gossip to it
+                // starts over from everything
+                self.state.with(|state| {
+                    state.known.remove(&restarted.peer);
+                    state.learned_from.retain(|_, n| *n != restarted.peer);
+                });
+                self.stats.forget(&restarted.peer);
+            }
+            Event::TopologyChanged(neighborhood) => {
+                // catch up on whatever was broadcast before this node
+                // (re)started, from every neighbour at once
+                let pulls = neighborhood.iter().map(|n| {
+                    let ctx = &ctx;
+                    async move {
+                        let pulled = anti_entropy::pull(ctx, n, 1024, Duration::from_secs(1), |seen| {
+                            self.state.with(|state| state.messages.merge(seen))
+                        })
+                        .await;
+                        if let Err(e) = pulled {
+                            eprintln!("sync from {} failed: {:#}", n, e);
+                        }
+                        anyhow::Ok(())
+                    }
+                });
+                fanout::all(pulls).await?;
+            }
+
             Event::Injected(payload) => match payload {
                 InjectedPayload::Gossip => {
                     // Get current state snapshot
-                    let (neighborhood, messages, known) = {
-                        let state = self.state.lock().unwrap();
-                        (state.neighborhood.clone(), state.messages.clone(), state.known.clone())
-                    };
+                    let neighborhood = self.neighborhood(&ctx);
+                    let (messages, known, learned_from) = self
+                        .state
+                        .with(|state| (state.messages.clone(), state.known.clone(), state.learned_from.clone()));
 
-                    for n in &neighborhood {
-                        let known_to_n = &known[n];
+                    let digest = self
+                        .variant
+                        .push_pull
+                        .then(|| RangeDigest::new(messages.iter().map(|&m| m as u64)));
+                    let nothing = HashSet::new();
+                    for n in &self.sampler.pick(&neighborhood) {
+                        let known_to_n = known.get(n).unwrap_or(&nothing);
                         let (already_known, mut notify_of): (HashSet<_>, HashSet<_>) = messages
                             .iter()
                             .copied()
                             .partition(|m| known_to_n.contains(m));
+                        // resends cover gossip n may have lost, but not what
+                        // n itself told us about
+                        let already_known: Vec<_> = already_known
+                            .into_iter()
+                            .filter(|m| learned_from.get(m) != Some(n))
+                            .collect();
 
                         let mut rng = rand::rng();
- 
-                        let additional_cap = (10 * notify_of.len() / 100) as u32;
+                        let resend_percent = match self.variant.adaptive_resend {
+                            true => self.stats.resend_percent(n, self.variant.resend_percent),
+                            false => self.variant.resend_percent,
+                        };
+                        let additional_cap = (resend_percent * notify_of.len() / 100) as u32;
                         notify_of.extend(already_known.iter().filter(|_| {
                             rng.random_ratio(
                                 additional_cap.min(already_known.len() as u32),
@@ -111,75 +258,128 @@ impl Node<(), Payload, (), InjectedPayload> for BroadcastNode {
                             )
                         }));
 
-                        Message {
+                        let gossip = Message {
                             src: self.node.clone(),
                             dst: n.clone(),
                             body: Body {
                                 id: None,
                                 in_reply_to: None,
-                                payload: Payload::Gossip { seen: notify_of },
+                                payload: Payload::Gossip {
+                                    seen: notify_of,
+                                    digest: digest.clone(),
+                                },
                             },
-                        }
-                        .send(output.clone())
-                        .with_context(|| format!("gossip to {}", n))?
+                        };
+                        self.gossip(gossip, &ctx)?;
                     }
                 }
             },
 
             Event::Message(input) => {
-                let mut reply = {
-                    let mut state = self.state.lock().unwrap();
-                    input.into_reply(Some(&mut state.id))
-                };
-                match reply.body.payload {
-                    Payload::Gossip { seen } => {
-                        let mut state = self.state.lock().unwrap();
-                        state.known
-                            .get_mut(&reply.dst)
-                            .expect("got gossip from unknown node")
-                            .extend(seen.iter().copied());
-
-                        state.messages.extend(seen);
+                let (payload, request) = input.take_payload();
+                match payload {
+                    Payload::Gossip { seen, digest } => {
+                        let (novel, missing) = self.state.with(|state| {
+                            let mut novel = 0;
+                            for &m in &seen {
+                                if state.messages.insert(m) {
+                                    state.learned_from.insert(m, request.src.clone());
+                                    novel += 1;
+                                }
+                            }
+                            // the digest also says what the sender already has
+                            let (has, missing): (Vec<usize>, Vec<usize>) = match &digest {
+                                Some(digest) => state.messages.iter().partition(|&&m| digest.contains(m as u64)),
+                                None => (Vec::new(), Vec::new()),
+                            };
+                            let known = state.known.entry(request.src.clone()).or_default();
+                            known.extend(seen.iter().copied());
+                            known.extend(has);
+                            (novel, digest.map(|_| missing))
+                        });
+                        self.stats.received(&request.src, seen.len(), novel);
+
+                        // push-pull: hand the sender what its digest lacks
+                        if let Some(missing) = missing.filter(|missing| !missing.is_empty()) {
+                            let gossip = Payload::Gossip {
+                                seen: missing.into_iter().collect(),
+                                digest: None,
+                            };
+                            self.gossip(request.reply_with(gossip, &ctx), &ctx)?;
+                        }
                     }
 
                     Payload::Broadcast { message } => {
-                        {
-                            let mut state = self.state.lock().unwrap();
-                            state.messages.insert(message);
-                        } // Lock released here
-                        
-                        reply.body.payload = Payload::BroadcastOk;
-                        reply.send(output).context("reply to broadcast")?;
+                        self.state.with(|state| state.messages.insert(message));
+
+                        let reply = request.reply_with(Payload::BroadcastOk, &ctx);
+                        reply.send(&ctx).context("reply to broadcast")?;
                     }
                     Payload::Read => {
-                        let messages = {
-                            let state = self.state.lock().unwrap();
-                            state.messages.clone()
-                        };
+                        let messages = self.state.with(|state| state.messages.clone());
 
-                        reply.body.payload = Payload::ReadOk { messages };
-                        reply.send(output).context("reply to read")?;
+                        let reply = request.reply_with(Payload::ReadOk { messages }, &ctx);
+                        reply.send(&ctx).context("reply to read")?;
                     }
-                    Payload::Topology { mut topology } => {
-                        {
-                            let mut state = self.state.lock().unwrap();
-                            state.neighborhood = topology
-                                .remove(&self.node)
-                                .unwrap_or_else(|| panic!("no topology given for node {}", self.node));
-                        } // Lock released here
-
-                        reply.body.payload = Payload::TopologyOk;
-                        reply.send(output).context("reply to topology")?;
+                    Payload::Sync(sync) => {
+                        let response = self.state.with(|state| anti_entropy::serve(&state.messages, sync));
+
+                        let reply = request.reply_with(Payload::SyncOk(response), &ctx);
+                        reply.send(&ctx).context("reply to sync")?;
+                    }
+                    Payload::Stats => {
+                        let reply = request.reply_with(Payload::StatsOk { peers: self.stats.all() }, &ctx);
+                        reply.send(&ctx).context("reply to stats")?;
                     }
-                    Payload::ReadOk { .. } | Payload::BroadcastOk { .. } | Payload::TopologyOk => {}
+                    Payload::ReadOk { .. } | Payload::BroadcastOk | Payload::SyncOk(..) | Payload::StatsOk { .. } => {}
                 }
             }
         }
         Ok(())
     }
+
+    async fn on_idle(&self, ctx: Ctx) -> anyhow::Result<()> {
+        // gossip only consults what neighbours have seen, so what the rest
+        // told us is dead weight
+        let neighborhood = self.neighborhood(&ctx);
+        self.state.with(|state| {
+            state.known.retain(|n, _| neighborhood.contains(n));
+            for known in state.known.values_mut() {
+                known.shrink_to_fit();
+            }
+        });
+        Ok(())
+    }
+}
+
+// also the workload's entry point in maelstrom-node
+pub(crate) fn run() -> anyhow::Result<()> {
+    let config = config::NodeConfig::from_env_and_args()?;
+    let mut variant: Variant = config.mode().unwrap_or("fault-tolerant").parse()?;
+    if let Some(interval) = config.duration("gossip-interval")? {
+        variant.gossip_interval = Some(interval);
+    }
+    variant.resend_percent = config.get_or("resend-percent", variant.resend_percent)?;
+    variant.peers = config.get_or("gossip-peers", variant.peers)?;
+    variant.push_pull = config.get_or("push-pull", variant.push_pull)?;
+    variant.adaptive_resend = config.get_or("adaptive-resend", variant.adaptive_resend)?;
+    // gossip and anti-entropy run alongside the handlers
+    let mut runtime = Runtime::from_config(&config)
+        .flavor(flavor::Flavor::MultiThread)
+        .idle(Duration::from_secs(1));
+    if config.get_or("epochs", false)? {
+        runtime = runtime.epochs();
+    }
+    if selftest::requested() {
+        let script = Script::new()
+            .exchange(json!({"type": "topology", "topology": {"n1": []}}), json!({"type": "topology_ok"}))
+            .exchange(json!({"type": "broadcast", "message": 7}), json!({"type": "broadcast_ok"}))
+            .exchange(json!({"type": "read"}), json!({"type": "read_ok", "messages": [7]}));
+        return selftest::start::<_, BroadcastNode, _, _, _>(runtime, variant, script);
+    }
+    runtime.start::<_, BroadcastNode, _, _, _>(variant)
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    main_loop::<_, BroadcastNode, _, _ ,_>(()).await
+fn main() -> anyhow::Result<()> {
+    run()
 }
@@ -1,8 +1,10 @@
 use anyhow::Context;
+use dist_sys::error::{ErrorCode, MaelstromError};
+use dist_sys::selftest::{self, Script};
 use dist_sys::*;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, io::StdoutLock, sync::{Arc, Mutex}, time::Duration};
-use tokio::sync::{oneshot, Mutex as AsyncMutex};
+use serde_json::json;
+use std::time::Duration;
 
 // Counter operations (from clients to counter)
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,178 +17,42 @@ enum Payload {
     ReadOk { value: usize },
 }
 
-// KV operations (counter to/from seq-kv)
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
-#[serde(rename_all = "snake_case")]
-enum KvPayload {
-    Read { key: String },
-    ReadOk { value: usize },
-    Cas { key: String, from: usize, to: usize },
-    CasOk,
-    Write { key: String, value: usize },
-    WriteOk,
-    Error { code: u32, text: String },
-}
-
-// Shared state that needs synchronization
-#[derive(Debug)]
-struct NodeState {
-    id: usize,
-    pending_kv_responses: HashMap<usize, oneshot::Sender<Result<usize, String>>>,
+// How long reads wait before summing, so adds elsewhere can land; set with
+// the read-delay and final-read-delay knobs
+#[derive(Debug, Clone, Copy)]
+struct ReadDelays {
+    read: Duration,
+    final_read: Duration,
 }
 
 struct CounterNode {
     node: String,
     node_ids: Vec<String>,
-    state: Mutex<NodeState>,
-    add_lock: AsyncMutex<()>,
+    delays: ReadDelays,
 }
 
-impl CounterNode {
-    async fn kv_read(&self, key: String, output: Arc<Mutex<std::io::Stdout>>) -> anyhow::Result<(usize, oneshot::Receiver<Result<usize, String>>)> {
-        let (msg_id, rx) = {
-            let mut state = self.state.lock().unwrap();
-            let msg_id = state.id;
-            state.id += 1;
-
-            let (tx, rx) = oneshot::channel();
-            state.pending_kv_responses.insert(msg_id, tx);
-            (msg_id, rx)
-        }; // Lock is released here
-
-        let msg = Message {
-            src: self.node.clone(),
-            dst: "seq-kv".to_string(),
-            body: Body {
-                id: Some(msg_id),
-                in_reply_to: None,
-                payload: KvPayload::Read { key: key.clone() },
-            },
-        };
-
-        msg.send(output)
-            .with_context(|| format!("failed to send read request for key {}", key))?;
-
-        Ok((msg_id, rx))
-    }
-
-    async fn kv_cas(&self, key: String, from: usize, to: usize, output: Arc<Mutex<std::io::Stdout>>) -> anyhow::Result<(usize, oneshot::Receiver<Result<usize, String>>)> {
-        let (msg_id, rx) = {
-            let mut state = self.state.lock().unwrap();
-            let msg_id = state.id;
-            state.id += 1;
-
-            let (tx, rx) = oneshot::channel();
-            state.pending_kv_responses.insert(msg_id, tx);
-            (msg_id, rx)
-        };
-
-        let msg = Message {
-            src: self.node.clone(),
-            dst: "seq-kv".to_string(),
-            body: Body {
-                id: Some(msg_id),
-                in_reply_to: None,
-                payload: KvPayload::Cas { key: key.clone(), from, to },
-            },
-        };
-
-        msg.send(output)
-            .with_context(|| format!("failed to send CAS request for key {} (from {} to {})", key, from, to))?;
-
-        Ok((msg_id, rx))
-    }
-
-    async fn kv_write_async(&self, key: String, value: usize, output: Arc<Mutex<std::io::Stdout>>) -> anyhow::Result<()> {
-        let (msg_id, rx) = {
-            let mut state = self.state.lock().unwrap();
-            let msg_id = state.id;
-            state.id += 1;
-
-            let (tx, rx) = oneshot::channel();
-            state.pending_kv_responses.insert(msg_id, tx);
-            (msg_id, rx)
-        };
-
-        let msg = Message {
-            src: self.node.clone(),
-            dst: "seq-kv".to_string(),
-            body: Body {
-                id: Some(msg_id),
-                in_reply_to: None,
-                payload: KvPayload::Write { key: key.clone(), value },
-            },
-        };
-
-        msg.send(output)
-            .with_context(|| format!("failed to send async write request for key {} with value {}", key, value))?;
-
-        // Wait for response
-        match rx.await {
-            Ok(Ok(_)) => Ok(()),
-            Ok(Err(e)) => Err(anyhow::anyhow!("KV write failed: {}", e)),
-            Err(_) => Err(anyhow::anyhow!("Failed to receive write response")),
-        }
-    }
-
-    fn kv_write(
-        &self,
-        key: String,
-        value: usize,
-        output: &mut StdoutLock<'_>,
-    ) -> anyhow::Result<()> {
-        let msg_id = {
-            let mut state = self.state.lock().unwrap();
-            let msg_id = state.id;
-            state.id += 1;
-            msg_id
-        };
-
-        let msg = Message {
-            src: self.node.clone(),
-            dst: "seq-kv".to_string(),
-            body: Body {
-                id: Some(msg_id),
-                in_reply_to: None,
-                payload: KvPayload::Write { key: key.clone(), value },
-            },
-        };
-        msg.send_sync(output)
-            .with_context(|| format!("failed to send write request for key {} with value {}", key, value))?;
-        Ok(())
-    }
-}
-
-impl Node<(), Payload, KvPayload> for CounterNode {
+impl Node<ReadDelays, Payload> for CounterNode {
     async fn from_init(
-        _state: (),
+        delays: ReadDelays,
         init: Init,
-        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload, KvPayload>>,
-        output: &mut StdoutLock<'_>,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
     ) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        let node = CounterNode {
+        // no initial write: the first add creates the node's key
+        Ok(CounterNode {
             node_ids: init.node_ids,
-            node: init.node_id.clone(),
-            state: Mutex::new(NodeState {
-                id: 0,
-                pending_kv_responses: HashMap::new(),
-            }),
-            add_lock: AsyncMutex::new(()),
-        };
-
-        node.kv_write(init.node_id.clone(), 0, output)
-            .with_context(|| format!("failed to initialize counter for node {}", init.node_id))?;
-        Ok(node)
+            node: init.node_id,
+            delays,
+        })
     }
 
     async fn step(
         &self,
-        input: Event<Payload, KvPayload>,
-        output: Arc<Mutex<std::io::Stdout>>,
+        input: Event<Payload>,
+        ctx: Ctx,
     ) -> anyhow::Result<()> {
         match input {
             Event::Message(input) => {
@@ -194,59 +60,18 @@ impl Node<(), Payload, KvPayload> for CounterNode {
                     Payload::Add { delta } => {
                         // Optimization: if delta is 0, no need to do anything
                         if delta == 0 {
-                            let mut reply = {
-                                let mut state = self.state.lock().unwrap();
-                                input.into_reply(Some(&mut state.id))
-                            };
-                            reply.body.payload = Payload::AddOk;
-                            reply.send(output).context("failed to send Add response")?;
+                            let reply = input.reply_with(Payload::AddOk, &ctx);
+                            reply.send(&ctx).context("failed to send Add response")?;
                             return Ok(());
                         }
 
-                        // Acquire the add lock to serialize Add operations
-                        let _add_guard = self.add_lock.lock().await;
-                        
-                        loop {
-                            let old_val = match self.kv_read(self.node.clone(), output.clone()).await {
-                                Ok((_msg_id, rx)) => {
-                                    match rx.await {
-                                        Ok(Ok(value)) => value,
-                                        Ok(Err(err)) => {
-                                            if err.contains("key does not exist") || err.contains("does not exist") {
-                                                // Key doesn't exist, initialize it with the delta value
-                                                match self.kv_write_async(self.node.clone(), delta, output.clone()).await {
-                                                    Ok(()) => break, // Successfully initialized with delta
-                                                    Err(_) => continue, // Retry
-                                                }
-                                            } else {
-                                                continue; // Retry on other errors
-                                            }
-                                        }
-                                        Err(_) => continue, // Retry on channel errors
-                                    }
-                                }
-                                Err(_) => continue, // Retry on send errors
-                            };
+                        // past the client budget this answers with a timeout
+                        let kv = kv::Kv::new(&ctx, ctx.service("kv"));
+                        ctx.within(kv.update(&self.node, |count: usize| count + delta))
+                            .await?;
 
-                            // Try CAS from old_val to old_val + delta
-                            match self.kv_cas(self.node.clone(), old_val, old_val + delta, output.clone()).await {
-                                Ok((_msg_id, rx)) => {
-                                    match rx.await {
-                                        Ok(Ok(_)) => break, // Success
-                                        Ok(Err(_)) => continue, // CAS failed, retry
-                                        Err(_) => continue, // Channel error, retry
-                                    }
-                                }
-                                Err(_) => continue, // Send error, retry
-                            }
-                        }
-                        
-                        let mut reply = {
-                            let mut state = self.state.lock().unwrap();
-                            input.into_reply(Some(&mut state.id))
-                        };
-                        reply.body.payload = Payload::AddOk;
-                        reply.send(output).context("failed to send Add response")?;
+                        let reply = input.reply_with(Payload::AddOk, &ctx);
+                        reply.send(&ctx).context("failed to send Add response")?;
                     }
 
                     Payload::Read => {
@@ -255,53 +80,41 @@ impl Node<(), Payload, KvPayload> for CounterNode {
                         
                         if is_final_read {
                             // Final reads need extra time to ensure cluster-wide consistency
-                            tokio::time::sleep(Duration::from_millis(500)).await;
+                            ctx.sleep(self.delays.final_read).await;
                         } else {
                             // Regular reads during test execution
-                            tokio::time::sleep(Duration::from_millis(200)).await;
-                        }
-                        
-                        // Set up all KV read requests and collect receivers
-                        let mut receivers = Vec::new();
-                        
-                        for node_id in &self.node_ids.clone() {
-                            match self.kv_read(node_id.clone(), output.clone()).await {
-                                Ok((_msg_id, rx)) => receivers.push((node_id.clone(), rx)),
-                                Err(e) => {
-                                    eprintln!("Failed to send read request to node {}: {}", node_id, e);
-                                }
-                            }
+                            ctx.sleep(self.delays.read).await;
                         }
                         
-                        // wait for all responses (lock is not held)
+                        // one round trip for every node's count
+                        let kv = kv::Kv::new(&ctx, ctx.service("kv"));
+                        let reads = self.node_ids.iter().fold(kv.batch::<usize>(), |batch, node_id| batch.read(node_id));
+                        let reads = reads.run().await;
                         let mut total_value = 0;
-                        for (node_id, rx) in receivers {
-                            match rx.await {
-                                Ok(Ok(value)) => {
+                        // what each node's key held, for the reply's annotation
+                        let mut counts = serde_json::Map::new();
+                        for (node_id, read) in self.node_ids.iter().zip(reads) {
+                            match read {
+                                Ok(kv::Outcome::Read(Some(value))) => {
                                     total_value += value;
+                                    counts.insert(node_id.clone(), value.into());
                                 }
-                                Ok(Err(e)) => {
-                                    if e.contains("key does not exist") || e.contains("does not exist") {
-                                        // Treat missing keys as 0 - node failed to initialize properly
-                                        eprintln!("INFO: Node {} key does not exist, treating as 0", node_id);
-                                        // total_value += 0; (implicit)
-                                    } else {
-                                        eprintln!("KV read error from node {}: {}", node_id, e);
-                                    }
+                                // a node that hasn't been added to yet has no key
+                                Ok(_) => {
+                                    counts.insert(node_id.clone(), serde_json::Value::Null);
                                 }
-                                Err(_) => {
-                                    eprintln!("Failed to receive KV response from node {}", node_id);
+                                // past the client budget this answers with a timeout
+                                Err(e) if ctx.deadline().is_some() && MaelstromError::code_of(&e) == Some(ErrorCode::TIMEOUT) => {
+                                    return Err(e);
                                 }
+                                Err(e) => eprintln!("KV read error from node {}: {:#}", node_id, e),
                             }
                         }
-                        
-                        let mut reply = {
-                            let mut state = self.state.lock().unwrap();
-                            input.into_reply(Some(&mut state.id))
-                        };
 
-                        reply.body.payload = Payload::ReadOk { value: total_value };
-                        reply.send(output).context("failed to send Read response")?;
+                        let reply = input.reply_with(Payload::ReadOk { value: total_value }, &ctx);
+                        reply
+                            .send_annotated(&ctx, || json!({"counts": counts}))
+                            .context("failed to send Read response")?;
                     }
 
                     Payload::AddOk | Payload::ReadOk { .. } => {
@@ -310,77 +123,44 @@ impl Node<(), Payload, KvPayload> for CounterNode {
                 }
             }
 
-            Event::ServiceMessage(service_msg) => {
-                match service_msg.body.payload {
-                    KvPayload::ReadOk { value } => {
-                        if let Some(msg_id) = service_msg.body.in_reply_to {
-                            let tx_opt = {
-                                let mut state = self.state.lock().unwrap();
-                                state.pending_kv_responses.remove(&msg_id)
-                            }; // Lock is released here
-
-                            if let Some(tx) = tx_opt {
-                                let _ = tx.send(Ok(value));
-                            } else {
-                                eprintln!("ERROR: got a read ok from non pending msg")
-                            }
-                        }
-                    }
-
-                    KvPayload::CasOk => {
-                        if let Some(msg_id) = service_msg.body.in_reply_to {
-                            let tx_opt = {
-                                let mut state = self.state.lock().unwrap();
-                                state.pending_kv_responses.remove(&msg_id)
-                            }; // Lock is released here
-
-                            if let Some(tx) = tx_opt {
-                                let _ = tx.send(Ok(0)); // CAS success, 
-                            }
-                        }
-                    }
-
-                    KvPayload::Error { code: _, text } => {
-                        if let Some(msg_id) = service_msg.body.in_reply_to {
-                            let tx_opt = {
-                                let mut state = self.state.lock().unwrap();
-                                state.pending_kv_responses.remove(&msg_id)
-                            };
-
-                            if let Some(tx) = tx_opt {
-                                let _ = tx.send(Err(text));
-                            }
-                        }
-                    }
-
-                    KvPayload::WriteOk => {
-                        if let Some(msg_id) = service_msg.body.in_reply_to {
-                            let tx_opt = {
-                                let mut state = self.state.lock().unwrap();
-                                state.pending_kv_responses.remove(&msg_id)
-                            };
-
-                            if let Some(tx) = tx_opt {
-                                let _ = tx.send(Ok(0)); // Write success
-                            }
-                        }
-                    }
-
-                    KvPayload::Cas { .. } => {}
-                    KvPayload::Write { .. } => {}
-                    KvPayload::Read { .. } => {}
-                }
-            }
+            // the kv service's replies are answered through `Kv`
+            Event::ServiceMessage(_) => {}
 
             Event::EOF => {}
             Event::Injected(_) => {}
+            Event::TopologyChanged(_) => {}
+            Event::StaleLeader(_) => {}
+            Event::PeerRestarted(_) => {}
+            Event::Signal(_) => {}
         }
         Ok(())
     }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    main_loop::<_, CounterNode, Payload, KvPayload, _>(()).await
+// also the workload's entry point in maelstrom-node
+pub(crate) fn run() -> anyhow::Result<()> {
+    let config = config::NodeConfig::from_env_and_args()?;
+    let delays = ReadDelays {
+        read: config.duration_or("read-delay", Duration::from_millis(200))?,
+        final_read: config.duration_or("final-read-delay", Duration::from_millis(500))?,
+    };
+    let runtime = Runtime::from_config(&config)
+        // reads sleep and wait on the kv service, many at a time
+        .flavor(flavor::Flavor::MultiThread)
+        // adds read-modify-write the node's key, so run them one at a time
+        .limit("add", 1)
+        .default_service("kv", kv::SEQ_KV)
+        .client_budget(config.duration_or("budget", Duration::from_secs(1))?);
+    if selftest::requested() {
+        let script = Script::new()
+            .exchange(json!({"type": "add", "delta": 3}), json!({"type": "add_ok"}))
+            .exchange(json!({"type": "add", "delta": 4}), json!({"type": "add_ok"}))
+            .exchange(json!({"type": "read"}), json!({"type": "read_ok", "value": 7}));
+        return selftest::start::<_, CounterNode, Payload, (), _>(runtime, delays, script);
+    }
+    runtime.start::<_, CounterNode, Payload, (), _>(delays)
 }
 
+fn main() -> anyhow::Result<()> {
+    run()
+}
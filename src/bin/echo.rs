@@ -1,7 +1,8 @@
 use anyhow::{Context, Ok};
+use dist_sys::selftest::{self, Script};
 use dist_sys::*;
 use serde::{Deserialize, Serialize};
-use std::{io::{StdoutLock}, sync::{Arc, Mutex}};
+use serde_json::json;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -11,39 +12,39 @@ enum Payload {
     EchoOk { echo: String },
 }
 
-struct EchoNode {
-    id: Mutex<usize>,
-}
+struct EchoNode;
 
 impl Node<(), Payload> for EchoNode {
     async fn from_init(
         _state: (),
         _init: Init,
         _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
-        _output: &mut StdoutLock<'_>
+        _ctx: &Ctx
     ) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        Ok(EchoNode { 
-            id: Mutex::new(1),
-        })
+        Ok(EchoNode)
     }
 
-    async fn step(&self, input: Event<Payload>, output: Arc<Mutex<std::io::Stdout>>) -> anyhow::Result<()> {
-        let Event::Message(input) = input else {
-            panic!("no event injection");
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            // including service replies that arrive after their rpc gave up
+            Event::ServiceMessage(_)
+            | Event::StaleLeader(_)
+            | Event::PeerRestarted(_)
+            | Event::EOF
+            | Event::TopologyChanged(_)
+            | Event::Signal(_) => return Ok(()),
+            Event::Injected(_) => panic!("no event injection"),
         };
 
-        let mut reply = {
-            let mut id = self.id.lock().unwrap();
-            input.into_reply(Some(&mut *id))
-        };
-        
-        match reply.body.payload {
+        let (payload, request) = input.take_payload();
+        match payload {
             Payload::Echo { echo } => {
-                reply.body.payload = Payload::EchoOk { echo };
-                reply.send(output).context("reply to echo")?;
+                let reply = request.reply_with(Payload::EchoOk { echo }, &ctx);
+                reply.send(&ctx).context("reply to echo")?;
             }
             Payload::EchoOk { .. } => {}
         }
@@ -51,7 +52,20 @@ impl Node<(), Payload> for EchoNode {
     }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    main_loop::<_, EchoNode, _, _, _>(()).await
+// also the workload's entry point in maelstrom-node
+pub(crate) fn run() -> anyhow::Result<()> {
+    // each echo is answered at once, so worker threads would only add locking
+    let runtime = Runtime::from_env()?.flavor(flavor::Flavor::CurrentThread);
+    if selftest::requested() {
+        let script = Script::new().exchange(
+            json!({"type": "echo", "echo": "selftest"}),
+            json!({"type": "echo_ok", "echo": "selftest"}),
+        );
+        return selftest::start::<_, EchoNode, _, _, _>(runtime, (), script);
+    }
+    runtime.start::<_, EchoNode, _, _, _>(())
+}
+
+fn main() -> anyhow::Result<()> {
+    run()
 }
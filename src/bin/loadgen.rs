@@ -0,0 +1,419 @@
+//! Drives a node binary with a stream of workload operations and reports
+//! throughput and latency percentiles, for catching performance regressions
+//! without a full Maelstrom run.
+//!
+//! Usage: loadgen [options] -- <node-binary> [node args...]
+//!
+//! Options:
+//!   --workload W        echo, unique-ids, broadcast, counter or txn-list-append (default echo)
+//!   --rate N            operations started per second (default 100)
+//!   --duration-ms N     how long to keep starting operations (default 5000)
+//!   --concurrency N     most operations in flight at once (default 16)
+//!   --timeout-ms N      how long an operation may take before it counts as timed out (default 1000)
+//!   --mix op:w,...      relative weights of the workload's operations
+//!   --keys N            keys txn-list-append spreads its operations over (default 100)
+//!   --key-dist D        uniform or zipf (default uniform)
+//!   --value-size N      bytes per echo string (default 16)
+//!   --history PATH      write the operations as a Jepsen EDN history, e.g. for Elle
+//!
+//! The node runs as `n1` of a one-node cluster with every Maelstrom service
+//! emulated in-process (see `dist_sys::emulate`), so workloads backed by
+//! `seq-kv` or `lin-kv` need nothing else running. An operation started
+//! while `--concurrency` are in flight is skipped, not queued, so a node
+//! that can't keep up shows as a lower throughput than `--rate`.
+//!
+//! In the history, each operation runs on the lowest-numbered process that
+//! has none outstanding, and an operation the node never answered keeps its
+//! process busy, as Jepsen expects of one that may still take effect.
+//! Errors other than `timeout` and `crash` are definite failures.
+
+use anyhow::Context;
+use dist_sys::emulate::EMULATE_ENV;
+use dist_sys::sim::History;
+use dist_sys::sim::edn::{self, Edn, Outcome};
+use rand::Rng;
+use rand::distr::Alphanumeric;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::time::{Instant, MissedTickBehavior};
+
+const USAGE: &str = "usage: loadgen [--workload W] [--rate N] [--duration-ms N] [--concurrency N] [--timeout-ms N] \
+                     [--mix op:w,...] [--keys N] [--key-dist uniform|zipf] [--value-size N] [--history PATH] \
+                     -- <node-binary> [args...]";
+
+/// Each workload's operations and their default weights.
+const WORKLOADS: &[(&str, &[(&str, u32)])] = &[
+    ("echo", &[("echo", 1)]),
+    ("unique-ids", &[("generate", 1)]),
+    ("broadcast", &[("broadcast", 9), ("read", 1)]),
+    ("counter", &[("add", 9), ("read", 1)]),
+    ("txn-list-append", &[("append", 1), ("r", 1)]),
+];
+
+struct Args {
+    workload: String,
+    rate: f64,
+    duration: Duration,
+    concurrency: usize,
+    timeout: Duration,
+    mix: Vec<(String, u32)>,
+    keys: usize,
+    zipf: bool,
+    value_size: usize,
+    history: Option<String>,
+    node: Vec<String>,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut args = std::env::args().skip(1);
+    let mut workload = "echo".to_string();
+    let mut rate = 100.0;
+    let mut duration = Duration::from_millis(5000);
+    let mut concurrency = 16;
+    let mut timeout = Duration::from_millis(1000);
+    let mut mix = None;
+    let mut keys = 100;
+    let mut zipf = false;
+    let mut value_size = 16;
+    let mut history = None;
+    let mut node = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            node.extend(args.by_ref());
+            break;
+        }
+        let value = args.next().with_context(|| format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--workload" => workload = value,
+            "--rate" => rate = value.parse().context("--rate")?,
+            "--duration-ms" => duration = Duration::from_millis(value.parse().context("--duration-ms")?),
+            "--concurrency" => concurrency = value.parse().context("--concurrency")?,
+            "--timeout-ms" => timeout = Duration::from_millis(value.parse().context("--timeout-ms")?),
+            "--mix" => mix = Some(parse_mix(&value)?),
+            "--keys" => keys = value.parse().context("--keys")?,
+            "--key-dist" => {
+                zipf = match value.as_str() {
+                    "uniform" => false,
+                    "zipf" => true,
+                    _ => anyhow::bail!("unknown key distribution {:?}; expected uniform or zipf", value),
+                }
+            }
+            "--value-size" => value_size = value.parse().context("--value-size")?,
+            "--history" => history = Some(value),
+            _ => anyhow::bail!("unknown option {}\n{}", arg, USAGE),
+        }
+    }
+    anyhow::ensure!(!node.is_empty(), "no node binary given\n{}", USAGE);
+    anyhow::ensure!(rate > 0.0 && concurrency > 0 && keys > 0, "--rate, --concurrency and --keys must be positive");
+
+    let Some((_, ops)) = WORKLOADS.iter().find(|(name, _)| *name == workload) else {
+        let names = WORKLOADS.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ");
+        anyhow::bail!("unknown workload {:?}; expected one of {}", workload, names);
+    };
+    let mix = mix.unwrap_or_else(|| ops.iter().map(|(op, w)| (op.to_string(), *w)).collect());
+    for (op, _) in &mix {
+        anyhow::ensure!(ops.iter().any(|(known, _)| known == op), "{} has no {:?} operation", workload, op);
+    }
+    anyhow::ensure!(mix.iter().any(|(_, w)| *w > 0), "--mix gives every operation weight 0");
+    Ok(Args {
+        workload,
+        rate,
+        duration,
+        concurrency,
+        timeout,
+        mix,
+        keys,
+        zipf,
+        value_size,
+        history,
+        node,
+    })
+}
+
+fn parse_mix(spec: &str) -> anyhow::Result<Vec<(String, u32)>> {
+    spec.split(',')
+        .map(|part| {
+            let (op, weight) = part.split_once(':').with_context(|| format!("{:?} is not op:weight", part))?;
+            Ok((op.trim().to_string(), weight.trim().parse().with_context(|| format!("weight of {}", op))?))
+        })
+        .collect()
+}
+
+/// Makes the request bodies for a workload.
+struct Generator {
+    workload: String,
+    mix: Vec<(String, u32)>,
+    total_weight: u32,
+    /// Cumulative probabilities of each key under a Zipf distribution, if
+    /// keys aren't uniform.
+    zipf: Option<Vec<f64>>,
+    keys: usize,
+    value_size: usize,
+    /// Broadcast messages and appended elements are unique.
+    next_value: u64,
+}
+
+impl Generator {
+    fn new(args: &Args) -> Self {
+        let zipf = args.zipf.then(|| {
+            let weights: Vec<f64> = (1..=args.keys).map(|k| 1.0 / k as f64).collect();
+            let total: f64 = weights.iter().sum();
+            weights
+                .iter()
+                .scan(0.0, |acc, w| {
+                    *acc += w / total;
+                    Some(*acc)
+                })
+                .collect()
+        });
+        Self {
+            workload: args.workload.clone(),
+            total_weight: args.mix.iter().map(|(_, w)| w).sum(),
+            mix: args.mix.clone(),
+            zipf,
+            keys: args.keys,
+            value_size: args.value_size,
+            next_value: 0,
+        }
+    }
+
+    fn pick_op(&self, rng: &mut impl Rng) -> &str {
+        let mut n = rng.random_range(0..self.total_weight);
+        for (op, w) in &self.mix {
+            if n < *w {
+                return op;
+            }
+            n -= w;
+        }
+        unreachable!("weights sum to total_weight")
+    }
+
+    fn pick_key(&self, rng: &mut impl Rng) -> usize {
+        match &self.zipf {
+            None => rng.random_range(0..self.keys),
+            Some(cdf) => {
+                let p: f64 = rng.random();
+                cdf.partition_point(|&c| c < p).min(self.keys - 1)
+            }
+        }
+    }
+
+    /// The next operation's name and request body.
+    fn next(&mut self, rng: &mut impl Rng) -> (String, Value) {
+        let op = self.pick_op(rng).to_string();
+        self.next_value += 1;
+        let body = match (self.workload.as_str(), op.as_str()) {
+            ("echo", _) => {
+                let echo: String = rng.sample_iter(Alphanumeric).take(self.value_size).map(char::from).collect();
+                json!({"type": "echo", "echo": echo})
+            }
+            ("broadcast", "broadcast") => json!({"type": "broadcast", "message": self.next_value}),
+            ("counter", "add") => json!({"type": "add", "delta": 1}),
+            ("txn-list-append", _) => {
+                let value = (op == "append").then_some(self.next_value);
+                json!({"type": "txn", "txn": [[op, self.pick_key(rng), value]]})
+            }
+            (_, op) => json!({"type": op}),
+        };
+        (op, body)
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    latencies: BTreeMap<String, Vec<Duration>>,
+    errors: BTreeMap<u64, usize>,
+    timed_out: usize,
+    skipped: usize,
+}
+
+impl Stats {
+    fn report(&mut self, workload: &str, elapsed: Duration) {
+        let ok: usize = self.latencies.values().map(Vec::len).sum();
+        let failed: usize = self.errors.values().sum();
+        println!(
+            "{}: {} ok, {} failed, {} timed out, {} skipped in {:.2?} ({:.1} ok/s)",
+            workload,
+            ok,
+            failed,
+            self.timed_out,
+            self.skipped,
+            elapsed,
+            ok as f64 / elapsed.as_secs_f64()
+        );
+        for (op, latencies) in &mut self.latencies {
+            latencies.sort();
+            println!(
+                "  {:<10} n={:<7} p50={:.2?} p90={:.2?} p99={:.2?} max={:.2?}",
+                op,
+                latencies.len(),
+                percentile(latencies, 0.50),
+                percentile(latencies, 0.90),
+                percentile(latencies, 0.99),
+                latencies.last().copied().unwrap_or_default()
+            );
+        }
+        for (code, count) in &self.errors {
+            println!("  error {:<4} x{}", code, count);
+        }
+    }
+}
+
+/// A request body's `:f` and `:value` in Jepsen's terms.
+fn invocation(body: &Value) -> (String, Edn) {
+    let f = body["type"].as_str().unwrap_or_default().to_string();
+    let value = match f.as_str() {
+        "txn" => micro_ops(&body["txn"]),
+        "add" => Edn::from(&body["delta"]),
+        "broadcast" => Edn::from(&body["message"]),
+        "echo" => Edn::from(&body["echo"]),
+        _ => Edn::Nil,
+    };
+    (f, value)
+}
+
+/// How the operation `request` ended, given the node's reply.
+fn completion(request: &Value, reply: &Value) -> (Outcome, Edn) {
+    let value = match reply["type"].as_str().unwrap_or_default() {
+        "error" => {
+            let definite = !matches!(reply["code"].as_u64(), Some(0 | 13));
+            let outcome = if definite { Outcome::Fail } else { Outcome::Info };
+            return (outcome, invocation(request).1);
+        }
+        "txn_ok" => micro_ops(&reply["txn"]),
+        "read_ok" => Edn::from(reply.get("value").or(reply.get("messages")).unwrap_or(&Value::Null)),
+        "generate_ok" => Edn::from(&reply["id"]),
+        "echo_ok" => Edn::from(&reply["echo"]),
+        _ => invocation(request).1,
+    };
+    (Outcome::Ok, value)
+}
+
+/// `[["append", 1, 5], ["r", 1, null]]` as Elle's `[[:append 1 5] [:r 1 nil]]`.
+fn micro_ops(txn: &Value) -> Edn {
+    let ops = txn.as_array().map(Vec::as_slice).unwrap_or_default();
+    Edn::Vector(
+        ops.iter()
+            .map(|op| match Edn::from(op) {
+                Edn::Vector(mut parts) if !parts.is_empty() => {
+                    if let Edn::String(f) = &parts[0] {
+                        parts[0] = Edn::keyword(f.clone());
+                    }
+                    Edn::Vector(parts)
+                }
+                other => other,
+            })
+            .collect(),
+    )
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = parse_args()?;
+    let mut child = Command::new(&args.node[0])
+        .args(&args.node[1..])
+        .env(EMULATE_ENV, "all")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("spawn {}", args.node[0]))?;
+    let mut stdin = child.stdin.take().context("child stdin")?;
+    let mut stdout = BufReader::new(child.stdout.take().context("child stdout")?).lines();
+
+    let init = json!({"src": "c0", "dest": "n1", "body": {"type": "init", "msg_id": 0, "node_id": "n1", "node_ids": ["n1"]}});
+    stdin.write_all(format!("{}\n", init).as_bytes()).await.context("write init")?;
+    loop {
+        let line = tokio::time::timeout(args.timeout, stdout.next_line())
+            .await
+            .context("no init_ok")??
+            .context("node exited before init_ok")?;
+        if serde_json::from_str::<Value>(&line).is_ok_and(|msg| msg["body"]["type"] == "init_ok") {
+            break;
+        }
+    }
+
+    let mut generator = Generator::new(&args);
+    let mut rng = rand::rng();
+    let mut stats = Stats::default();
+    let history = History::new();
+    // Jepsen processes with an operation outstanding
+    let mut busy = Vec::new();
+    // operations awaiting a reply, by msg_id, with their history entry and process
+    let mut in_flight: HashMap<u64, (String, Instant, usize, usize)> = HashMap::new();
+    let mut next_msg_id = 1;
+    let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / args.rate));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let started = Instant::now();
+    let stop_at = started + args.duration;
+    let give_up_at = stop_at + args.timeout;
+
+    while Instant::now() < stop_at || !in_flight.is_empty() {
+        tokio::select! {
+            _ = ticker.tick(), if Instant::now() < stop_at => {
+                if in_flight.len() >= args.concurrency {
+                    stats.skipped += 1;
+                    continue;
+                }
+                let (op, mut body) = generator.next(&mut rng);
+                body["msg_id"] = next_msg_id.into();
+                let process = (0..).find(|p| !busy.contains(p)).expect("processes are unbounded");
+                busy.push(process);
+                let entry = history.invoke(process, body.clone());
+                let msg = json!({"src": "c1", "dest": "n1", "body": body});
+                stdin.write_all(format!("{}\n", msg).as_bytes()).await.context("write to node")?;
+                in_flight.insert(next_msg_id, (op, Instant::now(), entry, process));
+                next_msg_id += 1;
+            }
+            line = stdout.next_line() => {
+                let Some(line) = line? else {
+                    anyhow::bail!("node exited with {} operations in flight", in_flight.len());
+                };
+                let Ok(msg) = serde_json::from_str::<Value>(&line) else {
+                    continue;
+                };
+                let Some(in_reply_to) = msg["body"]["in_reply_to"].as_u64() else {
+                    continue;
+                };
+                let Some((op, sent, entry, process)) = in_flight.remove(&in_reply_to) else {
+                    continue;
+                };
+                history.complete(entry, msg["body"].clone());
+                busy.retain(|p| *p != process);
+                let took = sent.elapsed();
+                if took > args.timeout {
+                    stats.timed_out += 1;
+                } else if msg["body"]["type"] == "error" {
+                    *stats.errors.entry(msg["body"]["code"].as_u64().unwrap_or_default()).or_default() += 1;
+                } else {
+                    stats.latencies.entry(op).or_default().push(took);
+                }
+            }
+            _ = tokio::time::sleep_until(give_up_at) => break,
+        }
+    }
+    stats.timed_out += in_flight.len();
+    let elapsed = started.elapsed().min(args.duration);
+    drop(stdin);
+    let _ = child.kill().await;
+    stats.report(&args.workload, elapsed);
+    if let Some(path) = &args.history {
+        let ops = history.into_operations();
+        let edn = edn::history(&ops, invocation, completion);
+        std::fs::write(path, edn).with_context(|| format!("write history to {}", path))?;
+        eprintln!("wrote {} operations to {}", ops.len(), path);
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! Every bundled workload in one binary, picked at startup with
+//! `--workload <name>` (or `DIST_SYS_WORKLOAD`), so a single build can be
+//! pointed at any Maelstrom test:
+//!
+//!     maelstrom test -w broadcast --bin maelstrom-node --workload broadcast multi
+//!
+//! The remaining arguments and environment are the workload's own, as if
+//! its binary had been run directly.
+
+use dist_sys::config::NodeConfig;
+
+// each workload's own main goes unused here
+#[allow(dead_code)]
+#[path = "broadcast.rs"]
+mod broadcast;
+#[allow(dead_code)]
+#[path = "counter.rs"]
+mod counter;
+#[allow(dead_code)]
+#[path = "echo.rs"]
+mod echo;
+#[allow(dead_code)]
+#[path = "txn-list-append.rs"]
+mod txn_list_append;
+#[allow(dead_code)]
+#[path = "unique-ids.rs"]
+mod unique_ids;
+
+type Entry = fn() -> anyhow::Result<()>;
+
+/// Workloads by the name of their own binary, with how to run each on the
+/// kind of runtime its binary runs on.
+const WORKLOADS: &[(&str, Entry)] = &[
+    ("echo", echo::run),
+    ("unique-ids", unique_ids::run),
+    ("broadcast", broadcast::run),
+    ("counter", counter::run),
+    ("txn-list-append", txn_list_append::run),
+];
+
+fn main() -> anyhow::Result<()> {
+    let config = NodeConfig::from_env_and_args()?;
+    let names = WORKLOADS.iter().map(|(name, ..)| *name).collect::<Vec<_>>().join(", ");
+    let Some(workload) = config.get::<String>("workload")? else {
+        anyhow::bail!("--workload is required; one of {}", names);
+    };
+    let Some((_, run)) = WORKLOADS.iter().find(|(name, _)| *name == workload) else {
+        anyhow::bail!("no {:?} workload in this build; expected one of {}", workload, names);
+    };
+    run()
+}
@@ -0,0 +1,208 @@
+//! Replays a recorded message log into a node binary and diffs what it emits
+//! against what was originally recorded.
+//!
+//! Usage: replay <node-binary> <log> [--interval-ms N] [--settle-ms N]
+//!
+//! The log holds one Maelstrom JSON message per line. Lines may carry the
+//! golden-transcript `>`/`<` prefixes or be records from a capture file
+//! (see `dist_sys::capture`); anything that doesn't parse as a message is
+//! skipped. The node id is taken from the first `init` message: messages
+//! addressed to it are fed to the node, messages it sent are the expected
+//! output. Outputs are compared as multisets with `msg_id` removed,
+//! since id allocation depends on handler interleaving.
+//!
+//! Capture records are fed at their recorded `at_us`, so timers and
+//! timeouts see the gaps they saw the first time. Lines without one follow
+//! the previous line after `--interval-ms`. The msg_ids the replayed node
+//! issues needn't match the recorded ones, so each message it sends is
+//! matched to an identical recorded one, and recorded replies to that
+//! message have their `in_reply_to` rewritten to the new id before they are
+//! fed in. A reply waits up to `--settle-ms` for its request to go out.
+
+use anyhow::Context;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+struct Args {
+    bin: String,
+    log: String,
+    interval: Duration,
+    settle: Duration,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut positional = Vec::new();
+    let mut interval = Duration::ZERO;
+    let mut settle = Duration::from_millis(1000);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--interval-ms" => {
+                let v = args.next().context("--interval-ms needs a value")?;
+                interval = Duration::from_millis(v.parse().context("--interval-ms")?);
+            }
+            "--settle-ms" => {
+                let v = args.next().context("--settle-ms needs a value")?;
+                settle = Duration::from_millis(v.parse().context("--settle-ms")?);
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let [bin, log] = <[String; 2]>::try_from(positional)
+        .map_err(|_| anyhow::anyhow!("usage: replay <node-binary> <log> [--interval-ms N] [--settle-ms N]"))?;
+    Ok(Args {
+        bin,
+        log,
+        interval,
+        settle,
+    })
+}
+
+/// A logged message and, for capture records, when it was recorded.
+fn parse_line(line: &str) -> Option<(Value, Option<Duration>)> {
+    let line = line.trim();
+    let line = line
+        .strip_prefix('>')
+        .or_else(|| line.strip_prefix('<'))
+        .unwrap_or(line);
+    let mut msg: Value = serde_json::from_str(line.trim()).ok()?;
+    let mut at = None;
+    if msg.get("msg").is_some() && msg.get("dir").is_some() {
+        at = msg["at_us"].as_u64().map(Duration::from_micros);
+        msg = msg["msg"].take();
+    }
+    (msg.get("src").is_some() && msg.get("dest").is_some() && msg.get("body").is_some()).then_some((msg, at))
+}
+
+fn normalize(mut msg: Value) -> String {
+    if let Some(body) = msg["body"].as_object_mut() {
+        body.remove("msg_id");
+    }
+    msg.to_string()
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = parse_args()?;
+    let log = std::fs::read_to_string(&args.log).with_context(|| format!("read {}", args.log))?;
+    let messages: Vec<(Value, Option<Duration>)> = log.lines().filter_map(parse_line).collect();
+
+    let node = messages
+        .iter()
+        .map(|(m, _)| m)
+        .find(|m| m["body"]["type"] == "init")
+        .and_then(|m| m["dest"].as_str())
+        .context("log contains no init message")?
+        .to_string();
+
+    let mut inputs = Vec::new();
+    let mut expected: HashMap<String, usize> = HashMap::new();
+    // the recorded msg_ids of each message the node sent, in the order sent
+    let mut recorded_ids: HashMap<String, VecDeque<u64>> = HashMap::new();
+    for (msg, at) in messages {
+        if msg["dest"] == node.as_str() {
+            inputs.push((msg, at));
+        } else if msg["src"] == node.as_str() {
+            let msg_id = msg["body"]["msg_id"].as_u64();
+            let line = normalize(msg);
+            if let Some(msg_id) = msg_id {
+                recorded_ids.entry(line.clone()).or_default().push_back(msg_id);
+            }
+            *expected.entry(line).or_default() += 1;
+        }
+    }
+    let issued: HashSet<u64> = recorded_ids.values().flatten().copied().collect();
+    eprintln!("replaying {} input messages into {} as {}", inputs.len(), args.bin, node);
+
+    let mut child = Command::new(&args.bin)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("spawn {}", args.bin))?;
+    let mut stdin = child.stdin.take().context("child stdin")?;
+    let mut stdout = BufReader::new(child.stdout.take().context("child stdout")?).lines();
+
+    // recorded msg_id -> the one the replayed node used for the same message
+    let (ids_tx, mut ids) = watch::channel(HashMap::<u64, u64>::new());
+    let reader = tokio::spawn(async move {
+        let mut produced: HashMap<String, usize> = HashMap::new();
+        while let Ok(Some(line)) = stdout.next_line().await {
+            match serde_json::from_str::<Value>(&line) {
+                Ok(msg) => {
+                    let msg_id = msg["body"]["msg_id"].as_u64();
+                    let line = normalize(msg);
+                    if let Some(actual) = msg_id
+                        && let Some(recorded) = recorded_ids.get_mut(&line).and_then(VecDeque::pop_front)
+                    {
+                        ids_tx.send_modify(|ids| {
+                            ids.insert(recorded, actual);
+                        });
+                    }
+                    *produced.entry(line).or_default() += 1;
+                }
+                Err(_) => eprintln!("node emitted non-JSON line: {}", line),
+            }
+        }
+        produced
+    });
+
+    let start = Instant::now();
+    let mut next = start;
+    for (mut msg, at) in inputs {
+        let due = at.map_or(next, |at| start + at);
+        tokio::time::sleep_until(due).await;
+        next = due + args.interval;
+
+        if let Some(recorded) = msg["body"]["in_reply_to"].as_u64()
+            && issued.contains(&recorded)
+        {
+            let bound = tokio::time::timeout(args.settle, ids.wait_for(|ids| ids.contains_key(&recorded))).await;
+            match bound {
+                Ok(Ok(ids)) => msg["body"]["in_reply_to"] = ids[&recorded].into(),
+                _ => eprintln!("node never sent the message {} replies to; feeding it unchanged", msg),
+            }
+        }
+        stdin
+            .write_all(format!("{}\n", msg).as_bytes())
+            .await
+            .context("write to node")?;
+    }
+    stdin.flush().await.context("flush node stdin")?;
+
+    // let in-flight handlers and timers run before cutting the node off
+    tokio::time::sleep(args.settle).await;
+    drop(stdin);
+    let _ = child.kill().await;
+    let produced = reader.await.context("reader task panicked")?;
+
+    let mut differences = 0;
+    for (line, &count) in &expected {
+        let got = produced.get(line).copied().unwrap_or(0);
+        for _ in got..count {
+            println!("- {}", line);
+            differences += 1;
+        }
+    }
+    for (line, &count) in &produced {
+        let want = expected.get(line).copied().unwrap_or(0);
+        for _ in want..count {
+            println!("+ {}", line);
+            differences += 1;
+        }
+    }
+
+    if differences > 0 {
+        eprintln!("{} differences", differences);
+        std::process::exit(1);
+    }
+    eprintln!("outputs match");
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use anyhow::Context;
+use dist_sys::error::{ErrorCode, MaelstromError};
+use dist_sys::kv::{Kv, Txn};
+use dist_sys::selftest::{self, Script};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+// Maelstrom's txn-list-append workload: each key holds a list of integers,
+// and a transaction is a sequence of reads and appends.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Txn { txn: Vec<Op> },
+    TxnOk { txn: Vec<Op> },
+}
+
+/// `["r", key, null]` or `["append", key, element]`; reads come back with the
+/// list filled in, or still `null` if the key has never been appended to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Op(Func, u64, Option<OpValue>);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+enum Func {
+    #[serde(rename = "r")]
+    Read,
+    #[serde(rename = "append")]
+    Append,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum OpValue {
+    Element(u64),
+    List(Vec<u64>),
+}
+
+// Every node is a stateless transactor in front of lin-kv: transactions run
+// through Kv::transact, whose commit is validated against every key read, so
+// the history is strictly serializable.
+struct TxnNode;
+
+impl TxnNode {
+    async fn execute(&self, ops: Vec<Op>, ctx: &Ctx) -> anyhow::Result<Vec<Op>> {
+        let kv = Kv::lin(ctx);
+        kv.transact(async move |txn: &mut Txn<'_, Vec<u64>>| {
+            let mut done = Vec::with_capacity(ops.len());
+            for Op(func, key, value) in &ops {
+                let key_str = key.to_string();
+                let list = txn.read(&key_str).await?;
+                match (func, value) {
+                    (Func::Read, _) => done.push(Op(Func::Read, *key, list.map(OpValue::List))),
+                    (Func::Append, Some(OpValue::Element(element))) => {
+                        let mut list = list.unwrap_or_default();
+                        list.push(*element);
+                        txn.write(&key_str, list);
+                        done.push(Op(Func::Append, *key, Some(OpValue::Element(*element))));
+                    }
+                    (Func::Append, _) => {
+                        return Err(MaelstromError::new(ErrorCode::MALFORMED_REQUEST, "append needs an element").into());
+                    }
+                }
+            }
+            Ok(done)
+        })
+        .await
+    }
+}
+
+impl Node<(), Payload> for TxnNode {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(TxnNode)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            // including service replies that arrive after their rpc gave up
+            Event::ServiceMessage(_)
+            | Event::StaleLeader(_)
+            | Event::PeerRestarted(_)
+            | Event::EOF
+            | Event::TopologyChanged(_)
+            | Event::Signal(_) => return Ok(()),
+            Event::Injected(_) => panic!("no event injection"),
+        };
+
+        let Payload::Txn { txn } = input.body.payload.clone() else {
+            return Ok(());
+        };
+        match self.execute(txn, &ctx).await {
+            Ok(txn) => {
+                let reply = input.reply_with(Payload::TxnOk { txn }, &ctx);
+                reply.send(&ctx).context("reply to txn")?;
+            }
+            Err(e) => {
+                let err = match e.downcast::<MaelstromError>() {
+                    Ok(e) => e,
+                    // lost or failed KV requests leave the outcome unknown
+                    Err(e) => MaelstromError::new(ErrorCode::CRASH, format!("{:#}", e)),
+                };
+                bail_with_error_reply!(&ctx, input, err);
+            }
+        }
+        Ok(())
+    }
+}
+
+// also the workload's entry point in maelstrom-node
+pub(crate) fn run() -> anyhow::Result<()> {
+    // transactions wait on lin-kv, many at a time
+    let runtime = Runtime::from_env()?.flavor(flavor::Flavor::MultiThread);
+    if selftest::requested() {
+        let script = Script::new()
+            .exchange(
+                json!({"type": "txn", "txn": [["append", 1, 5], ["r", 1, null]]}),
+                json!({"type": "txn_ok", "txn": [["append", 1, 5], ["r", 1, [5]]]}),
+            )
+            // a lin-kv reply to a request that already timed out
+            .send("lin-kv", json!({"type": "read_ok", "value": 7, "in_reply_to": 9999}))
+            .exchange(
+                json!({"type": "txn", "txn": [["r", 1, null], ["r", 2, null]]}),
+                json!({"type": "txn_ok", "txn": [["r", 1, [5]], ["r", 2, null]]}),
+            );
+        return selftest::start::<_, TxnNode, _, _, _>(runtime, (), script);
+    }
+    runtime.start::<_, TxnNode, _, _, _>(())
+}
+
+fn main() -> anyhow::Result<()> {
+    run()
+}
@@ -1,8 +1,8 @@
 use anyhow::Context;
+use dist_sys::selftest::{self, Script};
 use dist_sys::*;
 use serde::{Deserialize, Serialize};
-use std::io::StdoutLock;
-use std::sync::{Arc, Mutex};
+use serde_json::json;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -17,7 +17,6 @@ enum Payload {
 
 struct UniqueNode {
     node: String,
-    id: Mutex<usize>,
 }
 
 impl Node<(), Payload> for UniqueNode {
@@ -25,33 +24,33 @@ impl Node<(), Payload> for UniqueNode {
         _state: (),
         init: Init,
         _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
-        _output: &mut StdoutLock<'_>
+        _ctx: &Ctx
     ) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        Ok(UniqueNode {
-            node: init.node_id,
-            id: Mutex::new(1),
-        })
+        Ok(UniqueNode { node: init.node_id })
     }
-    async fn step(&self, input: Event<Payload>, output: Arc<Mutex<std::io::Stdout>>) -> anyhow::Result<()> {
-        let Event::Message(input) = input else {
-            panic!("no event injection");
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            // including service replies that arrive after their rpc gave up
+            Event::ServiceMessage(_)
+            | Event::StaleLeader(_)
+            | Event::PeerRestarted(_)
+            | Event::EOF
+            | Event::TopologyChanged(_)
+            | Event::Signal(_) => return Ok(()),
+            Event::Injected(_) => panic!("no event injection"),
         };
 
-        let (mut reply, guid_id) = {
-            let mut id = self.id.lock().unwrap();
-            let guid_id = *id;  // Capture ID before into_reply increments it
-            let reply = input.into_reply(Some(&mut *id));
-            (reply, guid_id)
-        };
-        match reply.body.payload {
+        let (payload, request) = input.take_payload();
+        match payload {
             Payload::Generate => {
-                let guid = format!("{}-{}", self.node, guid_id);
-                reply.body.payload = Payload::GenerateOk { guid };
-
-                reply.send(output).context("reply to generate")?;
+                // the node's msg_ids are never handed out twice
+                let guid = format!("{}-{}", self.node, ctx.next_msg_id());
+                let reply = request.reply_with(Payload::GenerateOk { guid }, &ctx);
+                reply.send(&ctx).context("reply to generate")?;
             }
             Payload::GenerateOk { .. } => {}
         }
@@ -59,7 +58,19 @@ impl Node<(), Payload> for UniqueNode {
     }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    main_loop::<_, UniqueNode, _, _, _>(()).await
+// also the workload's entry point in maelstrom-node
+pub(crate) fn run() -> anyhow::Result<()> {
+    // ids come from a local counter, so worker threads would only add locking
+    let runtime = Runtime::from_env()?.flavor(flavor::Flavor::CurrentThread);
+    if selftest::requested() {
+        let script = Script::new()
+            .exchange(json!({"type": "generate"}), json!({"type": "generate_ok"}))
+            .exchange(json!({"type": "generate"}), json!({"type": "generate_ok"}));
+        return selftest::start::<_, UniqueNode, _, _, _>(runtime, (), script);
+    }
+    runtime.start::<_, UniqueNode, _, _, _>(())
+}
+
+fn main() -> anyhow::Result<()> {
+    run()
 }
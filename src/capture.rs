@@ -0,0 +1,119 @@
+//! A copy of a node's traffic on disk, for replaying with the `replay`
+//! binary when a bug only shows up in a full Maelstrom run.
+//!
+//! Each line of the capture file is one message the node read from its input
+//! or wrote to its output:
+//!
+//! ```json
+//! {"at_us": 1520, "dir": "recv", "msg": {"src": "c1", "dest": "n1", "body": {...}}}
+//! ```
+//!
+//! `at_us` counts microseconds on the node's clock since the capture began,
+//! and `dir` is `recv` or `send`.
+//!
+//! Under Maelstrom every node process sees the same environment, so each
+//! writes to a file of its own: `{node}` in the path is replaced by the node's
+//! id, and a path without it gets the id before its extension, so
+//! `capture.jsonl` becomes `capture.n1.jsonl`. The file is created once
+//! `init` names the node; what came before is held until then, and a run
+//! that never gets a valid `init` isn't captured. Replies from emulated services and, with
+//! [`Runtime::loopback`](crate::Runtime::loopback), messages a node sends
+//! itself never touch the input or output, so they aren't captured.
+
+use crate::time::Clock;
+use anyhow::Context;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::time::Instant;
+
+/// Environment variable naming the file to capture traffic into, as
+/// [`node_path`] expands it; read by
+/// [`Runtime::from_env`](crate::Runtime::from_env) unless
+/// [`Runtime::capture`](crate::Runtime::capture) is called.
+pub const CAPTURE_ENV: &str = "DIST_SYS_CAPTURE";
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Direction {
+    Recv,
+    Send,
+}
+
+/// The file `node_id` captures into, given the configured `path`.
+pub fn node_path(path: &Path, node_id: &str) -> PathBuf {
+    let expanded = path.to_string_lossy();
+    if expanded.contains("{node}") {
+        return PathBuf::from(expanded.replace("{node}", node_id));
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(ext) => path.with_file_name(format!("{}.{}.{}", stem, node_id, ext.to_string_lossy())),
+        None => path.with_file_name(format!("{}.{}", stem, node_id)),
+    }
+}
+
+#[derive(Debug)]
+enum Sink {
+    /// Records made before the node's id, and so its file, is known.
+    Held(Vec<String>),
+    Open(LineWriter<File>),
+}
+
+#[derive(Debug)]
+pub(crate) struct Tap {
+    path: PathBuf,
+    sink: Mutex<Sink>,
+    clock: Arc<dyn Clock>,
+    started: Instant,
+}
+
+impl Tap {
+    /// Starts a capture into `path`, holding records until [`Tap::open`].
+    pub(crate) fn new(path: PathBuf, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            path,
+            sink: Mutex::new(Sink::Held(Vec::new())),
+            started: clock.now(),
+            clock,
+        }
+    }
+
+    /// Creates (or truncates) `node_id`'s capture file and writes out the
+    /// records held so far.
+    pub(crate) fn open(&self, node_id: &str) -> anyhow::Result<()> {
+        let path = node_path(&self.path, node_id);
+        let file = File::create(&path).with_context(|| format!("create capture file {}", path.display()))?;
+        let mut file = LineWriter::new(file);
+        let mut sink = self.sink.lock().unwrap();
+        if let Sink::Held(records) = &*sink {
+            for record in records {
+                writeln!(file, "{}", record).with_context(|| format!("write capture file {}", path.display()))?;
+            }
+        }
+        *sink = Sink::Open(file);
+        Ok(())
+    }
+
+    /// Appends `line` to the capture. Failures are reported on stderr rather
+    /// than disturbing the node.
+    pub(crate) fn record(&self, dir: Direction, line: &[u8]) {
+        let at_us = self.clock.now().saturating_duration_since(self.started).as_micros();
+        let msg = serde_json::from_slice(line)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(line).trim_end().to_string()));
+        let dir = match dir {
+            Direction::Recv => "recv",
+            Direction::Send => "send",
+        };
+        let record = serde_json::json!({ "at_us": at_us as u64, "dir": dir, "msg": msg }).to_string();
+        match &mut *self.sink.lock().unwrap() {
+            Sink::Held(records) => records.push(record),
+            Sink::Open(file) => {
+                if let Err(e) = writeln!(file, "{}", record) {
+                    eprintln!("capture failed: {}", e);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Fault injection on the outbound path, for hardening retry logic locally
+//! before spending time on long Maelstrom runs.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::ops::Range;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// What to do to outbound messages. Each message is independently dropped
+/// with `drop_prob`, otherwise duplicated with `dup_prob` and held back for a
+/// delay drawn uniformly from `delay_range`. Decisions come from an RNG seeded
+/// with `seed`, so the same sequence of sends sees the same faults.
+///
+/// Enabled through [`Runtime::chaos`](crate::Runtime::chaos). Only messages
+/// to other nodes are affected; the `init_ok` reply, replies to clients and
+/// requests to services always go out as sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chaos {
+    pub drop_prob: f64,
+    pub delay_range: Range<Duration>,
+    pub dup_prob: f64,
+    pub seed: u64,
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Self {
+            drop_prob: 0.0,
+            delay_range: Duration::ZERO..Duration::ZERO,
+            dup_prob: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+pub(crate) enum Fate {
+    Drop,
+    Deliver { copies: usize, delay: Duration },
+}
+
+#[derive(Debug)]
+pub(crate) struct ChaosLayer {
+    config: Chaos,
+    rng: Mutex<StdRng>,
+}
+
+impl ChaosLayer {
+    pub(crate) fn new(config: Chaos) -> Self {
+        Self {
+            rng: Mutex::new(StdRng::seed_from_u64(config.seed)),
+            config,
+        }
+    }
+
+    pub(crate) fn decide(&self) -> Fate {
+        let mut rng = self.rng.lock().unwrap();
+        if rng.random_bool(self.config.drop_prob.clamp(0.0, 1.0)) {
+            return Fate::Drop;
+        }
+        let copies = if rng.random_bool(self.config.dup_prob.clamp(0.0, 1.0)) { 2 } else { 1 };
+        let delay = if self.config.delay_range.is_empty() {
+            Duration::ZERO
+        } else {
+            rng.random_range(self.config.delay_range.clone())
+        };
+        Fate::Deliver { copies, delay }
+    }
+}
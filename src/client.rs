@@ -0,0 +1,233 @@
+//! A Maelstrom client in Rust, for driving nodes from tests and tools
+//! without Maelstrom.
+//!
+//! [`MaelstromClient`] writes requests to a node's input and reads its
+//! output, numbering requests and matching replies to them. Replies nobody
+//! is waiting on yet are kept until asked for, and everything else the node
+//! writes, such as requests to other nodes, is kept for
+//! [`MaelstromClient::next_other`], so a test can also play the node's peers.
+//! [`MaelstromClient::in_process`] sets up a node in-process to talk to:
+//!
+//! ```ignore
+//! let (mut client, node) = MaelstromClient::in_process::<_, EchoNode, _, _, _>(Runtime::new(), (), "n1");
+//! let node = tokio::spawn(node);
+//! client.init(&["n1"]).await?;
+//! assert_eq!(client.echo("hi").await?, "hi");
+//! ```
+
+use crate::error::MaelstromError;
+use crate::{Coalescible, Node, Runtime};
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, Lines};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Input = Box<dyn AsyncWrite + Unpin + Send>;
+type Output = Lines<Box<dyn AsyncBufRead + Unpin + Send>>;
+
+pub struct MaelstromClient {
+    id: String,
+    node: String,
+    input: Input,
+    output: Output,
+    next_msg_id: u64,
+    timeout: Duration,
+    /// Replies read while waiting for something else, by recipient and the
+    /// msg_id they answer.
+    replies: HashMap<(String, u64), Value>,
+    /// Messages from the node that answer nothing, oldest first.
+    others: VecDeque<Value>,
+}
+
+impl MaelstromClient {
+    /// A client named `id` talking to `node`, writing to its `input` and
+    /// reading its `output`.
+    pub fn new(
+        id: impl Into<String>,
+        node: impl Into<String>,
+        input: impl AsyncWrite + Unpin + Send + 'static,
+        output: impl AsyncBufRead + Unpin + Send + 'static,
+    ) -> Self {
+        let output: Box<dyn AsyncBufRead + Unpin + Send> = Box::new(output);
+        Self {
+            id: id.into(),
+            node: node.into(),
+            input: Box::new(input),
+            output: output.lines(),
+            next_msg_id: 1,
+            timeout: DEFAULT_TIMEOUT,
+            replies: HashMap::new(),
+            others: VecDeque::new(),
+        }
+    }
+
+    /// A client `c1` connected to an `N` that will run as `node` in-process
+    /// once the returned future is polled, e.g. by spawning it. The node
+    /// still has to be sent [`MaelstromClient::init`].
+    pub fn in_process<S, N, P, SP, IP>(
+        runtime: Runtime,
+        init_state: S,
+        node: &str,
+    ) -> (Self, impl Future<Output = anyhow::Result<()>>)
+    where
+        P: DeserializeOwned + Send + 'static,
+        SP: DeserializeOwned + Send + 'static,
+        N: Node<S, P, SP, IP> + Send + 'static,
+        IP: Coalescible + Send + 'static,
+    {
+        let (input, node_input) = tokio::io::duplex(64 * 1024);
+        let (node_output, output) = tokio::io::duplex(64 * 1024);
+        let run = runtime.run_with_io::<S, N, P, SP, IP>(init_state, BufReader::new(node_input), node_output);
+        (Self::new("c1", node, input, BufReader::new(output)), run)
+    }
+
+    /// How long to wait for any one reply. Five seconds unless set.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Initializes the node as part of a cluster of `node_ids`.
+    pub async fn init(&mut self, node_ids: &[&str]) -> anyhow::Result<()> {
+        let init = json!({"type": "init", "node_id": self.node, "node_ids": node_ids});
+        self.request(init).await.context("init")?;
+        Ok(())
+    }
+
+    /// Sends the body `body` from this client and returns its msg_id.
+    pub async fn send(&mut self, body: Value) -> anyhow::Result<u64> {
+        let id = self.id.clone();
+        self.send_from(&id, body).await
+    }
+
+    /// Sends the body `body` as if from `src`, e.g. one of the node's peers,
+    /// and returns its msg_id. A `msg_id` already in `body` is kept.
+    pub async fn send_from(&mut self, src: &str, mut body: Value) -> anyhow::Result<u64> {
+        let msg_id = match body["msg_id"].as_u64() {
+            Some(msg_id) => msg_id,
+            None => {
+                let msg_id = self.next_msg_id;
+                self.next_msg_id += 1;
+                body["msg_id"] = msg_id.into();
+                msg_id
+            }
+        };
+        let msg = json!({"src": src, "dest": self.node, "body": body});
+        self.input.write_all(format!("{}\n", msg).as_bytes()).await.context("write to node")?;
+        Ok(msg_id)
+    }
+
+    /// Waits for the node's reply to `src`'s message `msg_id` and returns
+    /// the whole message.
+    pub async fn reply_to(&mut self, src: &str, msg_id: u64) -> anyhow::Result<Value> {
+        let key = (src.to_string(), msg_id);
+        loop {
+            if let Some(reply) = self.replies.remove(&key) {
+                return Ok(reply);
+            }
+            self.read_one().await.with_context(|| format!("awaiting reply to {} {}", src, msg_id))?;
+        }
+    }
+
+    /// Sends `body` and returns the reply's body, or the error it carries as
+    /// a [`MaelstromError`].
+    pub async fn request(&mut self, body: Value) -> anyhow::Result<Value> {
+        let msg_id = self.send(body).await?;
+        let id = self.id.clone();
+        let reply = self.reply_to(&id, msg_id).await?;
+        let body = reply["body"].clone();
+        if body["type"] == "error" {
+            let err: MaelstromError = serde_json::from_value(body).context("parse error reply")?;
+            return Err(err.into());
+        }
+        Ok(body)
+    }
+
+    /// Sends `body` and fails unless the reply's body matches `expected`,
+    /// which only needs to list the fields worth checking. Returns the body.
+    pub async fn expect(&mut self, body: Value, expected: Value) -> anyhow::Result<Value> {
+        let msg_id = self.send(body.clone()).await?;
+        let id = self.id.clone();
+        let reply = self.reply_to(&id, msg_id).await?;
+        anyhow::ensure!(
+            matches(&expected, &reply["body"]),
+            "unexpected reply to {}\nexpected: {}\n     got: {}",
+            body,
+            expected,
+            reply["body"]
+        );
+        Ok(reply["body"].clone())
+    }
+
+    /// The oldest message from the node that isn't a reply, waiting for one
+    /// if there is none.
+    pub async fn next_other(&mut self) -> anyhow::Result<Value> {
+        loop {
+            if let Some(msg) = self.others.pop_front() {
+                return Ok(msg);
+            }
+            self.read_one().await?;
+        }
+    }
+
+    async fn read_one(&mut self) -> anyhow::Result<()> {
+        let line = tokio::time::timeout(self.timeout, self.output.next_line())
+            .await
+            .with_context(|| format!("nothing from {} within {:?}", self.node, self.timeout))??
+            .with_context(|| format!("{} closed its output", self.node))?;
+        let msg: Value = serde_json::from_str(&line).with_context(|| format!("parse {}", line))?;
+        match (msg["dest"].as_str(), msg["body"]["in_reply_to"].as_u64()) {
+            (Some(dest), Some(in_reply_to)) => {
+                self.replies.insert((dest.to_string(), in_reply_to), msg);
+            }
+            _ => self.others.push_back(msg),
+        }
+        Ok(())
+    }
+
+    pub async fn echo(&mut self, echo: &str) -> anyhow::Result<String> {
+        let reply = self.request(json!({"type": "echo", "echo": echo})).await?;
+        reply["echo"].as_str().map(str::to_string).context("echo_ok without echo")
+    }
+
+    /// Asks for a unique id and returns it as the node sent it.
+    pub async fn generate(&mut self) -> anyhow::Result<Value> {
+        Ok(self.request(json!({"type": "generate"})).await?["id"].clone())
+    }
+
+    pub async fn topology(&mut self, topology: &HashMap<String, Vec<String>>) -> anyhow::Result<()> {
+        self.request(json!({"type": "topology", "topology": topology})).await?;
+        Ok(())
+    }
+
+    pub async fn broadcast(&mut self, message: u64) -> anyhow::Result<()> {
+        self.request(json!({"type": "broadcast", "message": message})).await?;
+        Ok(())
+    }
+
+    pub async fn add(&mut self, delta: i64) -> anyhow::Result<()> {
+        self.request(json!({"type": "add", "delta": delta})).await?;
+        Ok(())
+    }
+
+    /// Sends a `read` and returns the whole `read_ok` body, whose fields
+    /// differ between workloads.
+    pub async fn read(&mut self) -> anyhow::Result<Value> {
+        self.request(json!({"type": "read"})).await
+    }
+}
+
+/// Whether `got` matches `expected`: objects match if every field of
+/// `expected` matches, anything else if it is equal.
+pub(crate) fn matches(expected: &Value, got: &Value) -> bool {
+    match (expected, got) {
+        (Value::Object(expected), Value::Object(got)) => expected
+            .iter()
+            .all(|(k, v)| got.get(k).is_some_and(|g| matches(v, g))),
+        _ => expected == got,
+    }
+}
@@ -0,0 +1,140 @@
+//! Encodings and framing for node-to-node traffic over transports other than
+//! Maelstrom's stdio, which always uses JSON lines.
+//!
+//! Frames are a 4-byte big-endian length followed by the encoded message, so
+//! binary encodings that may contain newlines can share a stream. JSON is
+//! always available; MessagePack and CBOR are behind the `msgpack` and `cbor`
+//! features. Gossip payloads full of integers are a fraction of their JSON size
+//! in either.
+
+use anyhow::Context;
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Frames larger than this are refused on write, and rejected on read rather
+/// than allocated.
+pub const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+pub trait Codec: Send + Sync {
+    fn encode<T: Serialize>(&self, value: &T) -> anyhow::Result<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> anyhow::Result<T>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+impl Codec for Json {
+    fn encode<T: Serialize>(&self, value: &T) -> anyhow::Result<Vec<u8>> {
+        serde_json::to_vec(value).context("encode json")
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> anyhow::Result<T> {
+        serde_json::from_slice(bytes).context("decode json")
+    }
+}
+
+/// MessagePack with structs encoded as maps, which `Body`'s flattened payload
+/// and internally tagged payload enums require.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn encode<T: Serialize>(&self, value: &T) -> anyhow::Result<Vec<u8>> {
+        rmp_serde::to_vec_named(value).context("encode msgpack")
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> anyhow::Result<T> {
+        rmp_serde::from_slice(bytes).context("decode msgpack")
+    }
+}
+
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    fn encode<T: Serialize>(&self, value: &T) -> anyhow::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        ciborium::into_writer(value, &mut out).context("encode cbor")?;
+        Ok(out)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> anyhow::Result<T> {
+        ciborium::from_reader(bytes).context("decode cbor")
+    }
+}
+
+/// Writes length-prefixed frames.
+#[derive(Debug)]
+pub struct FrameWriter<W, C> {
+    inner: W,
+    codec: C,
+}
+
+impl<W, C> FrameWriter<W, C>
+where
+    W: AsyncWrite + Unpin,
+    C: Codec,
+{
+    pub fn new(inner: W, codec: C) -> Self {
+        Self { inner, codec }
+    }
+
+    pub async fn write<T: Serialize>(&mut self, value: &T) -> anyhow::Result<()> {
+        let bytes = self.codec.encode(value)?;
+        // the peer would reject it anyway, and only after reading the length
+        anyhow::ensure!(bytes.len() <= MAX_FRAME_LEN, "frame of {} bytes exceeds limit", bytes.len());
+        let len = bytes.len() as u32;
+        self.inner.write_all(&len.to_be_bytes()).await.context("write frame length")?;
+        self.inner.write_all(&bytes).await.context("write frame")?;
+        self.inner.flush().await.context("flush frame")
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Reads length-prefixed frames.
+#[derive(Debug)]
+pub struct FrameReader<R, C> {
+    inner: R,
+    codec: C,
+}
+
+impl<R, C> FrameReader<R, C>
+where
+    R: AsyncRead + Unpin,
+    C: Codec,
+{
+    pub fn new(inner: R, codec: C) -> Self {
+        Self { inner, codec }
+    }
+
+    /// Reads the next frame, or `None` if the stream ended between frames.
+    /// A stream that ends partway through a frame, length included, is an
+    /// error.
+    pub async fn read<T: DeserializeOwned>(&mut self) -> anyhow::Result<Option<T>> {
+        let mut len = [0u8; 4];
+        let mut filled = 0;
+        while filled < len.len() {
+            match self.inner.read(&mut len[filled..]).await.context("read frame length")? {
+                0 if filled == 0 => return Ok(None),
+                0 => anyhow::bail!("stream ended {} bytes into a frame length", filled),
+                n => filled += n,
+            }
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        anyhow::ensure!(len <= MAX_FRAME_LEN, "frame of {} bytes exceeds limit", len);
+        let mut bytes = vec![0u8; len];
+        self.inner.read_exact(&mut bytes).await.context("read frame")?;
+        self.codec.decode(&bytes).map(Some)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
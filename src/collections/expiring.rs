@@ -0,0 +1,136 @@
+use crate::time::Clock;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A map whose entries each expire a while after they were written, for
+/// state that should clean itself up: lease records, hints for peers that
+/// may never come back, dedup entries for requests that won't be repeated.
+///
+/// Expired entries are invisible at once but only removed by
+/// [`ExpiringMap::purge`], which is meant for [`Node::on_idle`] so that reads
+/// never pay for the cleanup:
+///
+/// ```ignore
+/// async fn on_idle(&self, _ctx: Ctx) -> anyhow::Result<()> {
+///     self.state.with(|state| state.hints.purge());
+///     Ok(())
+/// }
+/// ```
+///
+/// [`Node::on_idle`]: crate::Node::on_idle
+#[derive(Debug, Clone)]
+pub struct ExpiringMap<K, V> {
+    clock: Arc<dyn Clock>,
+    /// Values and when they expire.
+    entries: HashMap<K, (V, Instant)>,
+}
+
+impl<K: Hash + Eq, V> ExpiringMap<K, V> {
+    /// An empty map, timing entries by `clock`, e.g. [`Ctx::clock`].
+    ///
+    /// [`Ctx::clock`]: crate::Ctx::clock
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Sets `key` to `value` for the next `ttl`, returning the value it had
+    /// if that hadn't expired.
+    pub fn insert(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+        let now = self.clock.now();
+        let old = self.entries.insert(key, (value, now + ttl))?;
+        (old.1 > now).then_some(old.0)
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.clock.now();
+        self.entries
+            .get(key)
+            .filter(|(_, expires)| *expires > now)
+            .map(|(value, _)| value)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// When `key` expires, if it hasn't yet.
+    pub fn expires_at<Q>(&self, key: &Q) -> Option<Instant>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.clock.now();
+        self.entries.get(key).map(|(_, expires)| *expires).filter(|expires| *expires > now)
+    }
+
+    /// Keeps `key` for `ttl` from now, e.g. when a lease is renewed.
+    /// Returns false, changing nothing, if it has already expired.
+    pub fn touch<Q>(&mut self, key: &Q, ttl: Duration) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.clock.now();
+        match self.entries.get_mut(key) {
+            Some((_, expires)) if *expires > now => {
+                *expires = now + ttl;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.clock.now();
+        self.entries
+            .remove(key)
+            .filter(|(_, expires)| *expires > now)
+            .map(|(value, _)| value)
+    }
+
+    /// Removes every expired entry, returning how many there were.
+    pub fn purge(&mut self) -> usize {
+        let now = self.clock.now();
+        let before = self.entries.len();
+        self.entries.retain(|_, (_, expires)| *expires > now);
+        before - self.entries.len()
+    }
+
+    /// The entries that haven't expired, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        let now = self.clock.now();
+        self.entries
+            .iter()
+            .filter(move |(_, (_, expires))| *expires > now)
+            .map(|(key, (value, _))| (key, value))
+    }
+
+    /// How many entries haven't expired. Counts them, so takes time
+    /// proportional to the entries not yet purged.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
@@ -0,0 +1,89 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// A Merkle tree over a set of items, for finding where two nodes' sets
+/// differ without exchanging them.
+///
+/// Items are bucketed by the top `depth` bits of their hash. A tree node's
+/// digest is the XOR of the hashes of every item under it, so inserts and
+/// removals update one path in O(depth) and the caller must not insert an
+/// item that is already present. Two nodes reconcile by comparing roots,
+/// then the children of every node that differs, level by level, until they
+/// reach the differing leaves and exchange only the items in those buckets.
+///
+/// Hashes come from [`DefaultHasher`] with its fixed keys, so they agree
+/// between processes running the same build but not across Rust versions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleTree {
+    depth: u32,
+    /// `levels[l]` holds the `2^l` digests at depth `l`; the last level are
+    /// the leaves.
+    levels: Vec<Vec<u64>>,
+}
+
+impl MerkleTree {
+    pub fn new(depth: u32) -> Self {
+        assert!(depth <= 24, "a tree of depth {} would not fit in memory", depth);
+        Self {
+            depth,
+            levels: (0..=depth).map(|level| vec![0; 1 << level]).collect(),
+        }
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    pub fn insert(&mut self, item: &impl Hash) {
+        self.toggle(hash(item));
+    }
+
+    pub fn remove(&mut self, item: &impl Hash) {
+        self.toggle(hash(item));
+    }
+
+    fn toggle(&mut self, hash: u64) {
+        let leaf = self.leaf_of_hash(hash);
+        for (level, digests) in self.levels.iter_mut().enumerate() {
+            digests[leaf >> (self.depth as usize - level)] ^= hash;
+        }
+    }
+
+    pub fn root(&self) -> u64 {
+        self.levels[0][0]
+    }
+
+    pub fn digest(&self, level: u32, index: usize) -> u64 {
+        self.levels[level as usize][index]
+    }
+
+    /// The indices and digests of the children of node `index` at `level`.
+    pub fn children(&self, level: u32, index: usize) -> [(usize, u64); 2] {
+        let below = &self.levels[level as usize + 1];
+        [(2 * index, below[2 * index]), (2 * index + 1, below[2 * index + 1])]
+    }
+
+    /// Which of `theirs`, digests of nodes at `level` on another tree, differ
+    /// from this tree's.
+    pub fn differing(&self, level: u32, theirs: &[(usize, u64)]) -> Vec<usize> {
+        theirs
+            .iter()
+            .filter(|&&(index, digest)| self.digest(level, index) != digest)
+            .map(|&(index, _)| index)
+            .collect()
+    }
+
+    /// The leaf bucket `item` falls into.
+    pub fn leaf_of(&self, item: &impl Hash) -> usize {
+        self.leaf_of_hash(hash(item))
+    }
+
+    fn leaf_of_hash(&self, hash: u64) -> usize {
+        hash.checked_shr(64 - self.depth).unwrap_or(0) as usize
+    }
+}
+
+fn hash(item: &impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    item.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,9 @@
+//! Data structures shared by node implementations.
+
+mod expiring;
+mod merkle;
+mod persistent;
+
+pub use expiring::ExpiringMap;
+pub use merkle::MerkleTree;
+pub use persistent::{MapStore, PersistentMap};
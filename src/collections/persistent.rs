@@ -0,0 +1,459 @@
+use crate::kv::Kv;
+use anyhow::Context;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Bits of the key's hash consumed per level.
+const BITS: u32 = 4;
+const FANOUT: usize = 1 << BITS;
+const MAX_DEPTH: u32 = u64::BITS / BITS;
+/// Subtrees with at most this many entries are stored as a single leaf.
+const LEAF_MAX: usize = 8;
+
+/// An immutable hash trie. Updates return a new map that shares every
+/// untouched subtree with the old one, so keeping old versions around is
+/// cheap and cloning is O(1).
+///
+/// The shape of the trie depends only on its contents, never on the order of
+/// updates: a subtree is a leaf exactly when it holds at most a handful of
+/// entries. Equal maps therefore have equal nodes, which is what lets a
+/// [`MapStore`] address nodes by their content and share them between
+/// versions and nodes. Entries are ordered by key hash, not by key.
+#[derive(Debug)]
+pub struct PersistentMap<K, V> {
+    root: Option<Arc<TrieNode<K, V>>>,
+}
+
+#[derive(Debug)]
+struct TrieNode<K, V> {
+    kind: NodeKind<K, V>,
+    /// Memoized content address; see [`MapStore`].
+    address: OnceLock<String>,
+}
+
+#[derive(Debug)]
+enum NodeKind<K, V> {
+    /// Sorted by key.
+    Leaf(Vec<(K, V)>),
+    Branch {
+        len: usize,
+        children: Vec<Option<Arc<TrieNode<K, V>>>>,
+    },
+}
+
+impl<K, V> Clone for PersistentMap<K, V> {
+    fn clone(&self) -> Self {
+        Self { root: self.root.clone() }
+    }
+}
+
+impl<K, V> Default for PersistentMap<K, V> {
+    fn default() -> Self {
+        Self { root: None }
+    }
+}
+
+impl<K, V> TrieNode<K, V> {
+    fn new(kind: NodeKind<K, V>) -> Arc<Self> {
+        Arc::new(Self {
+            kind,
+            address: OnceLock::new(),
+        })
+    }
+
+    fn len(&self) -> usize {
+        match &self.kind {
+            NodeKind::Leaf(entries) => entries.len(),
+            NodeKind::Branch { len, .. } => *len,
+        }
+    }
+}
+
+impl<K, V> PersistentMap<K, V>
+where
+    K: Hash + Ord + Clone,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.root.as_ref().map_or(0, |root| root.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let hash = hash(key);
+        let mut node = self.root.as_ref()?;
+        let mut depth = 0;
+        loop {
+            match &node.kind {
+                NodeKind::Leaf(entries) => {
+                    let i = entries.binary_search_by(|(k, _)| k.cmp(key)).ok()?;
+                    return Some(&entries[i].1);
+                }
+                NodeKind::Branch { children, .. } => {
+                    node = children[slot(hash, depth)].as_ref()?;
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// A copy of the map with `key` set to `value`.
+    pub fn insert(&self, key: K, value: V) -> Self {
+        let hash = hash(&key);
+        Self {
+            root: Some(insert(self.root.as_ref(), hash, 0, key, value).0),
+        }
+    }
+
+    /// A copy of the map without `key`. Shares the whole trie with `self` if
+    /// the key wasn't there.
+    pub fn remove(&self, key: &K) -> Self {
+        let Some(root) = &self.root else {
+            return self.clone();
+        };
+        match remove(root, hash(key), 0, key) {
+            Some(root) => Self { root },
+            None => self.clone(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        let mut stack: Vec<&TrieNode<K, V>> = self.root.as_deref().into_iter().collect();
+        let mut leaf: std::slice::Iter<'_, (K, V)> = [].iter();
+        std::iter::from_fn(move || {
+            loop {
+                if let Some((k, v)) = leaf.next() {
+                    return Some((k, v));
+                }
+                match &stack.pop()?.kind {
+                    NodeKind::Leaf(entries) => leaf = entries.iter(),
+                    NodeKind::Branch { children, .. } => stack.extend(children.iter().rev().flatten().map(|c| &**c)),
+                }
+            }
+        })
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for PersistentMap<K, V>
+where
+    K: Hash + Ord + Clone,
+    V: Clone,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut entries: Vec<(K, V)> = iter.into_iter().collect();
+        // the last value given for a key wins, as with repeated inserts
+        entries.reverse();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries.dedup_by(|a, b| a.0 == b.0);
+        Self {
+            root: (!entries.is_empty()).then(|| build(entries, 0)),
+        }
+    }
+}
+
+fn hash(key: &impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The child of a branch at `depth` that `hash` falls into.
+fn slot(hash: u64, depth: u32) -> usize {
+    ((hash >> (u64::BITS - BITS * (depth + 1))) as usize) & (FANOUT - 1)
+}
+
+/// The canonical subtree at `depth` holding `entries`, which are sorted by
+/// key and all share the hash prefix above `depth`.
+fn build<K: Hash + Ord, V>(entries: Vec<(K, V)>, depth: u32) -> Arc<TrieNode<K, V>> {
+    if entries.len() <= LEAF_MAX || depth == MAX_DEPTH {
+        return TrieNode::new(NodeKind::Leaf(entries));
+    }
+    let len = entries.len();
+    let mut buckets: Vec<Vec<(K, V)>> = (0..FANOUT).map(|_| Vec::new()).collect();
+    for (k, v) in entries {
+        buckets[slot(hash(&k), depth)].push((k, v));
+    }
+    let children = buckets
+        .into_iter()
+        .map(|bucket| (!bucket.is_empty()).then(|| build(bucket, depth + 1)))
+        .collect();
+    TrieNode::new(NodeKind::Branch { len, children })
+}
+
+/// Returns the new subtree and whether the key is new to it.
+fn insert<K, V>(node: Option<&Arc<TrieNode<K, V>>>, hash: u64, depth: u32, key: K, value: V) -> (Arc<TrieNode<K, V>>, bool)
+where
+    K: Hash + Ord + Clone,
+    V: Clone,
+{
+    let Some(node) = node else {
+        return (TrieNode::new(NodeKind::Leaf(vec![(key, value)])), true);
+    };
+    match &node.kind {
+        NodeKind::Leaf(entries) => {
+            let mut entries = entries.clone();
+            let added = match entries.binary_search_by(|(k, _)| k.cmp(&key)) {
+                Ok(i) => {
+                    entries[i].1 = value;
+                    false
+                }
+                Err(i) => {
+                    entries.insert(i, (key, value));
+                    true
+                }
+            };
+            (build(entries, depth), added)
+        }
+        NodeKind::Branch { len, children } => {
+            let i = slot(hash, depth);
+            let (child, added) = insert(children[i].as_ref(), hash, depth + 1, key, value);
+            let mut children = children.clone();
+            children[i] = Some(child);
+            let len = len + usize::from(added);
+            (TrieNode::new(NodeKind::Branch { len, children }), added)
+        }
+    }
+}
+
+/// Returns the new subtree (`None` if it became empty), or `None` if the key
+/// wasn't in it.
+fn remove<K, V>(node: &Arc<TrieNode<K, V>>, hash: u64, depth: u32, key: &K) -> Option<Option<Arc<TrieNode<K, V>>>>
+where
+    K: Hash + Ord + Clone,
+    V: Clone,
+{
+    match &node.kind {
+        NodeKind::Leaf(entries) => {
+            let i = entries.binary_search_by(|(k, _)| k.cmp(key)).ok()?;
+            let mut entries = entries.clone();
+            entries.remove(i);
+            Some((!entries.is_empty()).then(|| TrieNode::new(NodeKind::Leaf(entries))))
+        }
+        NodeKind::Branch { len, children } => {
+            let i = slot(hash, depth);
+            let child = remove(children[i].as_ref()?, hash, depth + 1, key)?;
+            let mut children = children.clone();
+            children[i] = child;
+            let len = len - 1;
+            if len > LEAF_MAX {
+                return Some(Some(TrieNode::new(NodeKind::Branch { len, children })));
+            }
+            // small enough to be a leaf again
+            let mut entries = Vec::with_capacity(len);
+            collect(&children, &mut entries);
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Some(Some(TrieNode::new(NodeKind::Leaf(entries))))
+        }
+    }
+}
+
+fn collect<K: Clone, V: Clone>(children: &[Option<Arc<TrieNode<K, V>>>], out: &mut Vec<(K, V)>) {
+    for child in children.iter().flatten() {
+        match &child.kind {
+            NodeKind::Leaf(entries) => out.extend(entries.iter().cloned()),
+            NodeKind::Branch { children, .. } => collect(children, out),
+        }
+    }
+}
+
+/// A trie node as stored in a KV service: children are referred to by
+/// address.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum StoredNode<K, V> {
+    Leaf { entries: Vec<(K, V)> },
+    Branch { len: usize, children: Vec<Option<String>> },
+}
+
+impl<K, V> TrieNode<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    fn stored(&self) -> StoredNode<&K, &V> {
+        match &self.kind {
+            NodeKind::Leaf(entries) => StoredNode::Leaf {
+                entries: entries.iter().map(|(k, v)| (k, v)).collect(),
+            },
+            NodeKind::Branch { len, children } => StoredNode::Branch {
+                len: *len,
+                children: children
+                    .iter()
+                    .map(|child| child.as_ref().map(|child| child.address().to_string()))
+                    .collect(),
+            },
+        }
+    }
+
+    /// The hash of the stored form, which covers the addresses of the
+    /// children and so the whole subtree.
+    fn address(&self) -> &str {
+        self.address.get_or_init(|| {
+            let bytes = serde_json::to_vec(&self.stored()).expect("trie nodes serialize");
+            format!("{:016x}", hash(&bytes))
+        })
+    }
+}
+
+/// Stores [`PersistentMap`]s in a KV service one trie node per key, under
+/// `<prefix>/<address>`, and publishes versions by swapping a root pointer.
+///
+/// Nodes are immutable and named by their content, so saving a new version
+/// only writes the nodes it doesn't share with versions already saved or
+/// loaded through this store, and loading one only fetches nodes it hasn't
+/// seen. Those are remembered for the life of the store.
+///
+/// Commits follow the usual copy-on-write protocol: load the version a root
+/// key points at, derive a new one, and [`MapStore::commit`] it, which writes
+/// the new nodes and then CASes the root from the old address to the new. A
+/// failed CAS means another writer committed first; reload and retry.
+#[derive(Debug, Clone)]
+pub struct MapStore<K, V> {
+    kv: Kv,
+    prefix: String,
+    saved: Arc<Mutex<NodesByAddress<K, V>>>,
+}
+
+type NodesByAddress<K, V> = HashMap<String, Arc<TrieNode<K, V>>>;
+
+impl<K, V> MapStore<K, V>
+where
+    K: Hash + Ord + Clone + Serialize + DeserializeOwned,
+    V: Clone + Serialize + DeserializeOwned,
+{
+    /// `kv` should be linearizable for [`MapStore::commit`] to be safe.
+    pub fn new(kv: Kv, prefix: impl Into<String>) -> Self {
+        Self {
+            kv,
+            prefix: prefix.into(),
+            saved: Arc::default(),
+        }
+    }
+
+    fn node_key(&self, address: &str) -> String {
+        format!("{}/{}", self.prefix, address)
+    }
+
+    /// Writes every node of `map` not yet in the store, children before
+    /// parents, and returns the map's address.
+    pub async fn save(&self, map: &PersistentMap<K, V>) -> anyhow::Result<String> {
+        let empty = TrieNode::new(NodeKind::Leaf(Vec::new()));
+        let root = map.root.as_ref().unwrap_or(&empty);
+        let mut unsaved = Vec::new();
+        {
+            let saved = self.saved.lock().unwrap();
+            let mut stack = vec![(root, false)];
+            while let Some((node, expanded)) = stack.pop() {
+                if saved.contains_key(node.address()) {
+                    continue;
+                }
+                if expanded {
+                    unsaved.push(node.clone());
+                    continue;
+                }
+                stack.push((node, true));
+                if let NodeKind::Branch { children, .. } = &node.kind {
+                    stack.extend(children.iter().flatten().map(|child| (child, false)));
+                }
+            }
+        }
+        for node in unsaved {
+            let address = node.address();
+            let stored = serde_json::to_value(node.stored()).context("serialize trie node")?;
+            self.kv.write(&self.node_key(address), stored).await?;
+            self.saved.lock().unwrap().insert(address.to_string(), node.clone());
+        }
+        Ok(root.address().to_string())
+    }
+
+    /// Fetches the map saved under `address`.
+    pub async fn load(&self, address: &str) -> anyhow::Result<PersistentMap<K, V>> {
+        let mut fetched: HashMap<String, StoredNode<K, V>> = HashMap::new();
+        let mut queue = vec![address.to_string()];
+        while let Some(address) = queue.pop() {
+            if fetched.contains_key(&address) || self.saved.lock().unwrap().contains_key(&address) {
+                continue;
+            }
+            let node: StoredNode<K, V> = self
+                .kv
+                .read(&self.node_key(&address))
+                .await?
+                .with_context(|| format!("missing trie node {}", address))?;
+            if let StoredNode::Branch { children, .. } = &node {
+                queue.extend(children.iter().flatten().cloned());
+            }
+            fetched.insert(address, node);
+        }
+
+        let mut saved = self.saved.lock().unwrap();
+        let root = assemble(address, &mut fetched, &mut saved)?;
+        Ok(PersistentMap {
+            root: (root.len() > 0).then_some(root),
+        })
+    }
+
+    /// The address `root_key` points at and the map saved there, or `None`
+    /// if nothing has been committed to it yet.
+    pub async fn head(&self, root_key: &str) -> anyhow::Result<Option<(String, PersistentMap<K, V>)>> {
+        let Some(address) = self.kv.read::<String>(root_key).await? else {
+            return Ok(None);
+        };
+        let map = self.load(&address).await?;
+        Ok(Some((address, map)))
+    }
+
+    /// Saves `map` and points `root_key` at it, provided the key still points
+    /// at `base` (or is unset, if `base` is `None`). Returns the new address,
+    /// or `None` if someone else committed in the meantime.
+    pub async fn commit(&self, root_key: &str, base: Option<&str>, map: &PersistentMap<K, V>) -> anyhow::Result<Option<String>> {
+        let address = self.save(map).await?;
+        let from = base.unwrap_or_default().to_string();
+        let swapped = self.kv.cas(root_key, from, address.clone(), base.is_none()).await?;
+        Ok(swapped.then_some(address))
+    }
+}
+
+/// Rebuilds the subtree at `address` from freshly fetched nodes and ones
+/// already in the store.
+fn assemble<K, V>(
+    address: &str,
+    fetched: &mut HashMap<String, StoredNode<K, V>>,
+    saved: &mut NodesByAddress<K, V>,
+) -> anyhow::Result<Arc<TrieNode<K, V>>> {
+    if let Some(node) = saved.get(address) {
+        return Ok(node.clone());
+    }
+    let stored = fetched
+        .remove(address)
+        .with_context(|| format!("missing trie node {}", address))?;
+    let kind = match stored {
+        StoredNode::Leaf { entries } => NodeKind::Leaf(entries),
+        StoredNode::Branch { len, children } => NodeKind::Branch {
+            len,
+            children: children
+                .iter()
+                .map(|child| child.as_deref().map(|child| assemble(child, fetched, saved)).transpose())
+                .collect::<anyhow::Result<_>>()?,
+        },
+    };
+    let node = Arc::new(TrieNode {
+        kind,
+        address: OnceLock::from(address.to_string()),
+    });
+    saved.insert(address.to_string(), node.clone());
+    Ok(node)
+}
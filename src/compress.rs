@@ -0,0 +1,130 @@
+//! Compressed bodies for large messages between nodes of this crate.
+//!
+//! Nodes advertise support by adding `"accept_encoding": "gzip"` to the
+//! bodies they send to other nodes, until the peer stops advertising back
+//! (showing it has heard them). Once a peer is known to accept gzip, bodies
+//! of at least [`Compression::min_bytes`] are sent to it as
+//!
+//! ```json
+//! {"type": "compressed", "encoding": "gzip", "data": "<base64 of the gzipped body>"}
+//! ```
+//!
+//! and unwrapped by the receiving runtime before anything else sees them.
+//! Clients and services never see either.
+
+use anyhow::Context;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use flate2::Compression as Level;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::Mutex;
+
+const GZIP: &str = "gzip";
+
+/// When to compress. Enabled through
+/// [`Runtime::compress`](crate::Runtime::compress).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compression {
+    /// Bodies smaller than this, in bytes of JSON, are sent as they are.
+    pub min_bytes: usize,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self { min_bytes: 4096 }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Peer {
+    /// The peer has said it accepts gzip.
+    accepts: bool,
+    /// The peer was still advertising in its last message, so it hasn't
+    /// heard our advertisement yet.
+    needs_ad: bool,
+}
+
+#[derive(Debug)]
+pub(crate) struct Compressor {
+    config: Compression,
+    peers: Mutex<HashMap<String, Peer>>,
+}
+
+impl Compressor {
+    pub(crate) fn new(config: Compression) -> Self {
+        Self {
+            config,
+            peers: Mutex::default(),
+        }
+    }
+
+    /// Compresses or annotates a line bound for `dst`, if it is a node.
+    pub(crate) fn outbound(&self, dst: &str, line: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        if !crate::is_node_id(dst) {
+            return Ok(line);
+        }
+        let (accepts, advertise) = {
+            let peers = self.peers.lock().unwrap();
+            let peer = peers.get(dst);
+            let accepts = peer.is_some_and(|peer| peer.accepts);
+            (accepts, !accepts || peer.is_some_and(|peer| peer.needs_ad))
+        };
+        let compress = accepts && line.len() >= self.config.min_bytes;
+        if !compress && !advertise {
+            return Ok(line);
+        }
+
+        let mut msg: Value = serde_json::from_slice(&line).context("parse outbound message")?;
+        if compress {
+            let body = serde_json::to_vec(&msg["body"]).context("serialize body")?;
+            let mut gz = GzEncoder::new(Vec::new(), Level::fast());
+            gz.write_all(&body).context("gzip body")?;
+            let data = BASE64.encode(gz.finish().context("gzip body")?);
+            msg["body"] = serde_json::json!({ "type": "compressed", "encoding": GZIP, "data": data });
+        } else if let Some(body) = msg["body"].as_object_mut() {
+            body.insert("accept_encoding".to_string(), GZIP.into());
+        }
+        let mut line = serde_json::to_vec(&msg).context("serialize outbound message")?;
+        line.push(b'\n');
+        Ok(line)
+    }
+
+    /// Unwraps a compressed body and records what the sender accepts.
+    pub(crate) fn inbound(&self, mut msg: Value) -> anyhow::Result<Value> {
+        let Some(src) = msg["src"].as_str().filter(|src| crate::is_node_id(src)).map(str::to_string) else {
+            return Ok(msg);
+        };
+        let compressed = msg["body"]["type"] == "compressed";
+        let advertised = msg["body"]
+            .as_object_mut()
+            .and_then(|body| body.remove("accept_encoding"))
+            .is_some_and(|encoding| encoding == GZIP);
+        {
+            let mut peers = self.peers.lock().unwrap();
+            let peer = peers.entry(src).or_default();
+            peer.accepts |= compressed || advertised;
+            peer.needs_ad = advertised;
+        }
+        if !compressed {
+            return Ok(msg);
+        }
+
+        anyhow::ensure!(
+            msg["body"]["encoding"] == GZIP,
+            "unsupported body encoding {}",
+            msg["body"]["encoding"]
+        );
+        let data = msg["body"]["data"].as_str().context("compressed body without data")?;
+        let gz = BASE64.decode(data).context("decode compressed body")?;
+        let mut body = Vec::new();
+        GzDecoder::new(&gz[..])
+            .read_to_end(&mut body)
+            .context("gunzip body")?;
+        msg["body"] = serde_json::from_slice(&body).context("parse decompressed body")?;
+        Ok(msg)
+    }
+}
@@ -0,0 +1,144 @@
+//! Knobs for node binaries, so a Maelstrom run can tune a node without
+//! recompiling it.
+//!
+//! A knob named `gossip-interval` is set with `--gossip-interval 150ms` or
+//! `--gossip-interval=150ms` after the binary in Maelstrom's `--bin`
+//! command, or with `DIST_SYS_GOSSIP_INTERVAL=150ms` in its environment; the
+//! argument wins if both are given. The first argument that isn't a knob is
+//! the processing mode, which falls back to `DIST_SYS_MODE`. The
+//! [`selftest::FLAG`](crate::selftest::FLAG) argument is left to the self-test.
+
+use anyhow::Context;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Prefix of the environment variables knobs are read from.
+pub const ENV_PREFIX: &str = "DIST_SYS_";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NodeConfig {
+    knobs: HashMap<String, String>,
+    mode: Option<String>,
+}
+
+impl NodeConfig {
+    /// Reads the process' arguments and environment.
+    pub fn from_env_and_args() -> anyhow::Result<Self> {
+        let args = std::env::args().skip(1).filter(|arg| arg != crate::selftest::FLAG);
+        Self::from_parts(args, std::env::vars())
+    }
+
+    /// Builds a configuration from arguments (without the program name) and
+    /// environment variables.
+    pub fn from_parts(
+        args: impl IntoIterator<Item = String>,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        for (name, value) in env {
+            let Some(name) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let name = name.to_ascii_lowercase().replace('_', "-");
+            if name == "mode" {
+                config.mode = Some(value);
+            } else {
+                config.knobs.insert(name, value);
+            }
+        }
+
+        let mut mode = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(knob) = arg.strip_prefix("--") else {
+                anyhow::ensure!(mode.is_none(), "unexpected argument {:?}", arg);
+                mode = Some(arg);
+                continue;
+            };
+            let (name, value) = match knob.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => {
+                    let value = args.next().with_context(|| format!("--{} needs a value", knob))?;
+                    (knob.to_string(), value)
+                }
+            };
+            config.knobs.insert(name, value);
+        }
+        config.mode = mode.or(config.mode);
+        Ok(config)
+    }
+
+    /// The processing mode, if one was given.
+    pub fn mode(&self) -> Option<&str> {
+        self.mode.as_deref()
+    }
+
+    /// The knob `name` parsed as a `T`, or `None` if it isn't set.
+    pub fn get<T>(&self, name: &str) -> anyhow::Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.knobs
+            .get(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("invalid value {:?} for {}: {}", value, name, e))
+            })
+            .transpose()
+    }
+
+    /// Every knob whose name starts with `prefix`, with the prefix stripped
+    /// from its name.
+    pub fn prefixed<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        self.knobs
+            .iter()
+            .filter_map(move |(name, value)| Some((name.strip_prefix(prefix)?, value.as_str())))
+    }
+
+    /// Whether the knob `name` is set to anything but nothing or `0`.
+    pub fn flag(&self, name: &str) -> bool {
+        self.knobs.get(name).is_some_and(|value| !value.is_empty() && value != "0")
+    }
+
+    /// Like [`NodeConfig::get`], with a default for when the knob isn't set.
+    pub fn get_or<T>(&self, name: &str, default: T) -> anyhow::Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        Ok(self.get(name)?.unwrap_or(default))
+    }
+
+    /// The knob `name` as a duration such as `150ms`, `2s` or `1m`; a bare
+    /// number is taken as milliseconds.
+    pub fn duration(&self, name: &str) -> anyhow::Result<Option<Duration>> {
+        self.knobs
+            .get(name)
+            .map(|value| parse_duration(value).with_context(|| format!("invalid value {:?} for {}", value, name)))
+            .transpose()
+    }
+
+    /// Like [`NodeConfig::duration`], with a default for when the knob isn't
+    /// set.
+    pub fn duration_or(&self, name: &str, default: Duration) -> anyhow::Result<Duration> {
+        Ok(self.duration(name)?.unwrap_or(default))
+    }
+}
+
+fn parse_duration(value: &str) -> anyhow::Result<Duration> {
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().context("not a number")?;
+    let seconds = match unit {
+        "" | "ms" => number / 1000.0,
+        "us" => number / 1_000_000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        _ => anyhow::bail!("unknown unit {:?}", unit),
+    };
+    Duration::try_from_secs_f64(seconds).context("out of range")
+}
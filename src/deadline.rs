@@ -0,0 +1,59 @@
+//! Time budgets for operations that fan out into several RPCs.
+//!
+//! A [`Ctx`] may carry a deadline: the instant by which the operation its
+//! handler serves has to be answered. [`Runtime::client_budget`] gives every
+//! client request one, [`Ctx::with_budget`] tightens it and [`Ctx::share`]
+//! splits what is left among RPCs made one after another. RPCs never wait
+//! past it, and one issued after it fails at once, without being sent.
+//!
+//! A handler working to a deadline that fails with an [`ErrorCode::TIMEOUT`]
+//! error, its own or a share's, answers the request with that error rather
+//! than crashing the node, so the client hears of the timeout instead of
+//! giving up on its own.
+//!
+//! With [`Runtime::propagate_deadlines`] on, requests to other nodes carry
+//! what is left of the budget in a `budget_ms` body field, and handlers for
+//! such requests get a deadline that far from the request's arrival. A
+//! budget rather than an instant, since clocks differ between nodes.
+//!
+//! [`Ctx`]: crate::Ctx
+//! [`Ctx::with_budget`]: crate::Ctx::with_budget
+//! [`Ctx::share`]: crate::Ctx::share
+//! [`Runtime::client_budget`]: crate::Runtime::client_budget
+//! [`Runtime::propagate_deadlines`]: crate::Runtime::propagate_deadlines
+
+use crate::Ctx;
+use crate::error::{ErrorCode, MaelstromError};
+use serde_json::Value;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Records `remaining`, rounded down to the millisecond, on `msg`.
+pub(crate) fn stamp(msg: &mut Value, remaining: Duration) {
+    msg["body"]["budget_ms"] = u64::try_from(remaining.as_millis()).unwrap_or(u64::MAX).into();
+}
+
+/// Removes the budget [`stamp`] recorded on `msg`, if any.
+pub(crate) fn take(msg: &mut Value) -> Option<Duration> {
+    let budget = msg["body"].as_object_mut()?.remove("budget_ms")?;
+    budget.as_u64().map(Duration::from_millis)
+}
+
+/// The error an operation fails with once `what` runs out of time.
+pub fn exceeded(what: impl std::fmt::Display) -> MaelstromError {
+    MaelstromError::new(ErrorCode::TIMEOUT, format!("deadline exceeded {}", what))
+}
+
+/// Whether an operation under `ctx` that failed with `err` may try again:
+/// only if `err` is a timeout and `ctx` has time left. Past the deadline
+/// every RPC fails at once, so loops that retry timeouts have to stop.
+pub(crate) fn retryable(err: &anyhow::Error, ctx: &Ctx) -> bool {
+    MaelstromError::code_of(err) == Some(ErrorCode::TIMEOUT) && ctx.remaining() != Some(Duration::ZERO)
+}
+
+/// The error to answer a request with if its handler, working to
+/// `deadline`, failed with `err` for lack of time.
+pub(crate) fn expired(err: &anyhow::Error, deadline: Option<Instant>) -> Option<MaelstromError> {
+    let err = err.downcast_ref::<MaelstromError>()?;
+    (err.code == ErrorCode::TIMEOUT && deadline.is_some()).then(|| err.clone())
+}
@@ -0,0 +1,149 @@
+//! Nodes behind trait objects.
+//!
+//! [`Node`] returns its futures as `impl Future`, which keeps handlers free
+//! of allocation but means a `dyn Node` can't exist. [`DynNode`] is the same
+//! interface with boxed futures, for applications that would rather pick or
+//! mix node implementations at runtime than monomorphize over each.
+//!
+//! [`erase`] turns any node into a `Box<dyn DynNode>`, and a
+//! `Box<dyn DynNode>` is itself a [`Node`] whose initial state is a
+//! [`DynInit`], so the runtime drives it like any other:
+//!
+//! ```ignore
+//! let init = if quiet { DynInit::new::<_, QuietNode>(()) } else { DynInit::new::<_, LoudNode>(()) };
+//! Runtime::new().run::<_, Box<dyn DynNode<Payload>>, _, _, _>(init).await
+//! ```
+
+use crate::{Ctx, Event, Init, Node};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// The object-safe half of [`Node`]: everything but construction.
+pub trait DynNode<Payload, ServicePayload = (), InjectedPayload = ()>: Send + Sync {
+    fn step_dyn(
+        &self,
+        input: Event<Payload, ServicePayload, InjectedPayload>,
+        ctx: Ctx,
+    ) -> BoxFuture<'_, anyhow::Result<()>>;
+
+    fn on_reinit_dyn(&self, init: Init, ctx: Ctx) -> BoxFuture<'_, anyhow::Result<()>>;
+
+    fn recover_dyn(&self, ctx: Ctx) -> BoxFuture<'_, anyhow::Result<()>>;
+
+    fn on_idle_dyn(&self, ctx: Ctx) -> BoxFuture<'_, anyhow::Result<()>>;
+}
+
+/// A [`Node`] seen as a [`DynNode`]. The initial state type `S` only pins down
+/// which `Node` implementation is meant.
+pub struct Erased<N, S> {
+    node: N,
+    state: PhantomData<fn(S)>,
+}
+
+impl<N, S, P, SP, IP> DynNode<P, SP, IP> for Erased<N, S>
+where
+    N: Node<S, P, SP, IP>,
+    P: 'static,
+    SP: 'static,
+    IP: 'static,
+{
+    fn step_dyn(&self, input: Event<P, SP, IP>, ctx: Ctx) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(self.node.step(input, ctx))
+    }
+
+    fn on_reinit_dyn(&self, init: Init, ctx: Ctx) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(self.node.on_reinit(init, ctx))
+    }
+
+    fn recover_dyn(&self, ctx: Ctx) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(self.node.recover(ctx))
+    }
+
+    fn on_idle_dyn(&self, ctx: Ctx) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(self.node.on_idle(ctx))
+    }
+}
+
+/// Boxes `node` as a [`DynNode`].
+pub fn erase<S, N, P, SP, IP>(node: N) -> Box<dyn DynNode<P, SP, IP>>
+where
+    S: 'static,
+    N: Node<S, P, SP, IP> + 'static,
+    P: 'static,
+    SP: 'static,
+    IP: 'static,
+{
+    Box::new(Erased {
+        node,
+        state: PhantomData,
+    })
+}
+
+type MakeNode<P, SP, IP> = Box<
+    dyn FnOnce(
+            Init,
+            UnboundedSender<Event<P, SP, IP>>,
+            Ctx,
+        ) -> BoxFuture<'static, anyhow::Result<Box<dyn DynNode<P, SP, IP>>>>
+        + Send,
+>;
+
+/// The initial state of a `Box<dyn DynNode>`: which node to build on `init`,
+/// and that node's own initial state.
+pub struct DynInit<P, SP = (), IP = ()> {
+    make: MakeNode<P, SP, IP>,
+}
+
+impl<P: Send + 'static, SP: Send + 'static, IP: Send + 'static> DynInit<P, SP, IP> {
+    /// Builds an `N` from `state` once the node is initialized.
+    pub fn new<S, N>(state: S) -> Self
+    where
+        S: Send + 'static,
+        N: Node<S, P, SP, IP> + 'static,
+    {
+        Self {
+            make: Box::new(move |init, inject, ctx| {
+                Box::pin(async move {
+                    let node = N::from_init(state, init, inject, &ctx).await?;
+                    Ok(erase::<S, N, P, SP, IP>(node))
+                })
+            }),
+        }
+    }
+}
+
+impl<P, SP, IP> Node<DynInit<P, SP, IP>, P, SP, IP> for Box<dyn DynNode<P, SP, IP>>
+where
+    P: Send + 'static,
+    SP: Send + 'static,
+    IP: Send + 'static,
+{
+    async fn from_init(
+        state: DynInit<P, SP, IP>,
+        init: Init,
+        inject: UnboundedSender<Event<P, SP, IP>>,
+        ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        (state.make)(init, inject, ctx.clone()).await
+    }
+
+    fn step(&self, input: Event<P, SP, IP>, ctx: Ctx) -> impl Future<Output = anyhow::Result<()>> + Send {
+        self.step_dyn(input, ctx)
+    }
+
+    fn on_reinit(&self, init: Init, ctx: Ctx) -> impl Future<Output = anyhow::Result<()>> + Send {
+        self.on_reinit_dyn(init, ctx)
+    }
+
+    fn recover(&self, ctx: Ctx) -> impl Future<Output = anyhow::Result<()>> + Send {
+        self.recover_dyn(ctx)
+    }
+
+    fn on_idle(&self, ctx: Ctx) -> impl Future<Output = anyhow::Result<()>> + Send {
+        self.on_idle_dyn(ctx)
+    }
+}
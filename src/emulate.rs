@@ -0,0 +1,246 @@
+//! In-process stand-ins for Maelstrom's `seq-kv`, `lin-kv`, `lww-kv` and
+//! `lin-tso` services, so a node binary can be driven standalone (e.g. piped
+//! from a script) and still get its service RPCs answered.
+//!
+//! Requests addressed to an emulated service never reach the output; the
+//! runtime answers them and feeds the reply back through the input path as if
+//! it had arrived on stdin.
+
+use crate::error::{ErrorCode, MaelstromError};
+use crate::kv::{LIN_KV, LWW_KV, SEQ_KV};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const LIN_TSO: &str = "lin-tso";
+
+/// Environment variable read by [`Emulation::from_env`] and
+/// [`Runtime::from_env`](crate::Runtime::from_env): a comma-separated
+/// list of service names, or `all`.
+pub const EMULATE_ENV: &str = "DIST_SYS_EMULATE";
+
+/// How many past versions of each key the emulated `seq-kv` keeps around for
+/// stale reads.
+const KEPT_VERSIONS: usize = 8;
+
+/// Which services to emulate and how they behave.
+///
+/// `lin-kv` and `lin-tso` are always linearizable. `seq-kv` answers a read
+/// with an older version of the key with probability `seq_kv_stale_reads`,
+/// but never one older than what the requesting node has already observed or
+/// written, which is what sequential consistency allows. `lww-kv` is served
+/// like `lin-kv`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Emulation {
+    pub services: Vec<String>,
+    pub seq_kv_stale_reads: f64,
+    /// Each reply is held back for a delay drawn from this range.
+    pub latency: Range<Duration>,
+    pub seed: u64,
+}
+
+impl Default for Emulation {
+    fn default() -> Self {
+        Self {
+            services: Vec::new(),
+            seq_kv_stale_reads: 0.0,
+            latency: Duration::ZERO..Duration::ZERO,
+            seed: 0,
+        }
+    }
+}
+
+impl Emulation {
+    pub fn all() -> Self {
+        Self {
+            services: [SEQ_KV, LIN_KV, LWW_KV, LIN_TSO].map(String::from).to_vec(),
+            ..Self::default()
+        }
+    }
+
+    /// Builds the emulation named by [`EMULATE_ENV`], if set.
+    pub fn from_env() -> Option<Self> {
+        Self::from_spec(&std::env::var(EMULATE_ENV).ok()?)
+    }
+
+    /// Builds the emulation `spec` names: `all`, or a comma-separated list of
+    /// services. `None` if it is empty.
+    pub fn from_spec(spec: &str) -> Option<Self> {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            return None;
+        }
+        if spec == "all" {
+            return Some(Self::all());
+        }
+        Some(Self {
+            services: spec.split(',').map(|s| s.trim().to_string()).collect(),
+            ..Self::default()
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct KeyState {
+    /// Oldest first; the last entry is the current value.
+    versions: Vec<Value>,
+    /// Total number of versions ever written, so floors survive trimming.
+    written: usize,
+}
+
+#[derive(Debug)]
+struct EmulatorState {
+    kv: HashMap<(String, String), KeyState>,
+    /// Per (service, key, client): the newest version number it has seen.
+    floors: HashMap<(String, String, String), usize>,
+    ts: u64,
+    next_msg_id: usize,
+    rng: StdRng,
+}
+
+#[derive(Debug)]
+pub(crate) struct Emulator {
+    config: Emulation,
+    state: Mutex<EmulatorState>,
+}
+
+impl Emulator {
+    pub(crate) fn new(config: Emulation) -> Self {
+        Self {
+            state: Mutex::new(EmulatorState {
+                kv: HashMap::new(),
+                floors: HashMap::new(),
+                ts: 0,
+                next_msg_id: 0,
+                rng: StdRng::seed_from_u64(config.seed),
+            }),
+            config,
+        }
+    }
+
+    pub(crate) fn handles(&self, dst: &str) -> bool {
+        self.config.services.iter().any(|s| s == dst)
+    }
+
+    /// Answers `request`, returning the reply line and how long to hold it
+    /// back. Requests without a msg_id get no reply.
+    pub(crate) fn handle(&self, request: &Value) -> Option<(String, Duration)> {
+        let service = request["dest"].as_str()?;
+        let client = request["src"].as_str()?;
+        let msg_id = request["body"]["msg_id"].as_u64()?;
+        let body = &request["body"];
+
+        let mut state = self.state.lock().unwrap();
+        let result = if service == LIN_TSO {
+            state.timestamp(body)
+        } else {
+            let stale = if service == SEQ_KV { self.config.seq_kv_stale_reads } else { 0.0 };
+            state.kv_op(service, client, body, stale)
+        };
+        let mut reply_body = match result {
+            Ok(body) => body,
+            Err(e) => json!({ "type": "error", "code": e.code, "text": e.text }),
+        };
+        reply_body["msg_id"] = state.next_msg_id.into();
+        reply_body["in_reply_to"] = msg_id.into();
+        state.next_msg_id += 1;
+
+        let latency = if self.config.latency.is_empty() {
+            Duration::ZERO
+        } else {
+            state.rng.random_range(self.config.latency.clone())
+        };
+        let reply = json!({ "src": service, "dest": client, "body": reply_body });
+        Some((reply.to_string(), latency))
+    }
+}
+
+impl EmulatorState {
+    fn timestamp(&mut self, body: &Value) -> Result<Value, MaelstromError> {
+        match body["type"].as_str() {
+            Some("ts") => {
+                self.ts += 1;
+                Ok(json!({ "type": "ts_ok", "ts": self.ts }))
+            }
+            other => Err(not_supported(other)),
+        }
+    }
+
+    fn kv_op(&mut self, service: &str, client: &str, body: &Value, stale: f64) -> Result<Value, MaelstromError> {
+        let key = match &body["key"] {
+            Value::String(key) => key.clone(),
+            other => other.to_string(),
+        };
+        let floor_key = (service.to_string(), key.clone(), client.to_string());
+        let floor = self.floors.get(&floor_key).copied().unwrap_or(0);
+        let entry = self.kv.entry((service.to_string(), key.clone())).or_default();
+
+        let (reply, seen) = match body["type"].as_str() {
+            Some("read") => {
+                let Some(latest) = entry.versions.last() else {
+                    return Err(MaelstromError::new(ErrorCode::KEY_DOES_NOT_EXIST, "key does not exist"));
+                };
+                // version numbers of the retained entries are written-len..written
+                let oldest_kept = entry.written - entry.versions.len();
+                let lowest = floor.max(oldest_kept + 1).min(entry.written);
+                let version = if lowest < entry.written && self.rng.random_bool(stale.clamp(0.0, 1.0)) {
+                    self.rng.random_range(lowest..entry.written)
+                } else {
+                    entry.written
+                };
+                let value = if version == entry.written {
+                    latest.clone()
+                } else {
+                    entry.versions[version - oldest_kept - 1].clone()
+                };
+                (json!({ "type": "read_ok", "value": value }), version)
+            }
+            Some("write") => {
+                entry.push(body["value"].clone());
+                (json!({ "type": "write_ok" }), entry.written)
+            }
+            Some("cas") => {
+                let create = body["create_if_not_exists"].as_bool().unwrap_or(false);
+                match entry.versions.last() {
+                    None if !create => {
+                        return Err(MaelstromError::new(ErrorCode::KEY_DOES_NOT_EXIST, "key does not exist"));
+                    }
+                    Some(current) if *current != body["from"] => {
+                        return Err(MaelstromError::new(
+                            ErrorCode::PRECONDITION_FAILED,
+                            format!("expected {}, but had {}", body["from"], current),
+                        ));
+                    }
+                    _ => {}
+                }
+                entry.push(body["to"].clone());
+                (json!({ "type": "cas_ok" }), entry.written)
+            }
+            other => return Err(not_supported(other)),
+        };
+
+        self.floors.insert(floor_key, seen);
+        Ok(reply)
+    }
+}
+
+impl KeyState {
+    fn push(&mut self, value: Value) {
+        self.versions.push(value);
+        self.written += 1;
+        if self.versions.len() > KEPT_VERSIONS {
+            self.versions.remove(0);
+        }
+    }
+}
+
+fn not_supported(kind: Option<&str>) -> MaelstromError {
+    MaelstromError::new(
+        ErrorCode::NOT_SUPPORTED,
+        format!("emulated service does not support {}", kind.unwrap_or("untyped message")),
+    )
+}
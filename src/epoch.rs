@@ -0,0 +1,129 @@
+//! Telling a restarted peer from the one that was there before.
+//!
+//! With [`Runtime::epochs`](crate::Runtime::epochs) on, a node bumps a counter
+//! in `lin-kv` each time it starts, under [`key`], and stamps the result on
+//! every message it sends to another node. A restarted node therefore speaks
+//! with a higher epoch than its previous incarnation did, and a peer that sees
+//! the epoch go up is handed an [`Event::PeerRestarted`](crate::Event::PeerRestarted)
+//! before the message that carried it, so it can drop what it believed about
+//! the old incarnation (what it had acknowledged, what it was owed) and
+//! resync.
+//!
+//! Messages sent before the bump completes carry no epoch and are taken as
+//! they are. The bump runs as a supervised task named [`TASK`].
+
+use crate::kv::Kv;
+use crate::supervise::RestartPolicy;
+use crate::{Ctx, is_node_id};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::sync::Mutex;
+
+/// Name of the supervised task that bumps the node's epoch.
+pub const TASK: &str = "epoch";
+
+/// Which incarnation of a node sent a message. Higher is newer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Epoch(pub u64);
+
+/// A peer's epoch went up: it restarted since this node last heard from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerRestarted {
+    pub peer: String,
+    pub previous: Epoch,
+    pub epoch: Epoch,
+}
+
+/// The `lin-kv` key holding `node`'s latest epoch.
+pub fn key(node: &str) -> String {
+    format!("epoch/{}", node)
+}
+
+/// This node's epoch and the latest one seen from each peer.
+#[derive(Debug, Default)]
+pub(crate) struct Epochs {
+    own: Mutex<Option<Epoch>>,
+    peers: Mutex<HashMap<String, Epoch>>,
+}
+
+impl Epochs {
+    pub(crate) fn own(&self) -> Option<Epoch> {
+        *self.own.lock().unwrap()
+    }
+
+    /// Records `epoch` for `peer`, returning the restart it reveals, if any.
+    /// Epochs older than the latest are from messages the previous
+    /// incarnation sent before it went down, and are ignored.
+    fn observe(&self, peer: &str, epoch: Epoch) -> Option<PeerRestarted> {
+        match self.peers.lock().unwrap().entry(peer.to_string()) {
+            Entry::Vacant(entry) => {
+                entry.insert(epoch);
+                None
+            }
+            Entry::Occupied(mut entry) if *entry.get() < epoch => Some(PeerRestarted {
+                peer: peer.to_string(),
+                previous: entry.insert(epoch),
+                epoch,
+            }),
+            Entry::Occupied(_) => None,
+        }
+    }
+}
+
+impl Ctx {
+    /// This node's epoch, once it has been bumped.
+    pub fn epoch(&self) -> Option<Epoch> {
+        self.epochs.own()
+    }
+
+    /// The latest epoch seen from `peer`.
+    pub fn peer_epoch(&self, peer: &str) -> Option<Epoch> {
+        self.epochs.peers.lock().unwrap().get(peer).copied()
+    }
+}
+
+/// Starts the task that bumps the node's epoch.
+pub(crate) fn spawn_bump(ctx: &Ctx) {
+    ctx.spawn_supervised(TASK, RestartPolicy::default(), |ctx| async move {
+        let epoch = bump(&ctx).await?;
+        *ctx.epochs.own.lock().unwrap() = Some(epoch);
+        Ok(())
+    });
+}
+
+/// Increments the node's counter in `lin-kv`, returning the new epoch.
+async fn bump(ctx: &Ctx) -> anyhow::Result<Epoch> {
+    let kv = Kv::lin(ctx);
+    let key = key(ctx.node_id());
+    loop {
+        let current = kv.read::<u64>(&key).await.context("read epoch")?;
+        let next = current.unwrap_or(0) + 1;
+        if kv.cas(&key, current.unwrap_or(0), next, current.is_none()).await.context("bump epoch")? {
+            return Ok(Epoch(next));
+        }
+    }
+}
+
+/// Adds the node's epoch to a message's body if it is bound for another node.
+pub(crate) fn stamp(msg: &mut serde_json::Value, epoch: Epoch) {
+    if msg["dest"].as_str().is_some_and(is_node_id)
+        && let Some(body) = msg["body"].as_object_mut()
+    {
+        body.insert("epoch".to_string(), epoch.0.into());
+    }
+}
+
+/// Strips the epoch off a message from another node and records it.
+pub(crate) fn check(msg: &mut serde_json::Value, ctx: &Ctx) -> anyhow::Result<Option<PeerRestarted>> {
+    let Some(src) = msg["src"].as_str().filter(|src| is_node_id(src)).map(str::to_string) else {
+        return Ok(None);
+    };
+    let Some(epoch) = msg["body"].as_object_mut().and_then(|body| body.remove("epoch")) else {
+        return Ok(None);
+    };
+    let epoch: Epoch = serde_json::from_value(epoch).context("parse epoch")?;
+    Ok(ctx.epochs.observe(&src, epoch))
+}
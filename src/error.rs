@@ -0,0 +1,74 @@
+//! Maelstrom's error message body and its well-known codes.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ErrorCode(pub u32);
+
+impl ErrorCode {
+    pub const TIMEOUT: Self = Self(0);
+    pub const NODE_NOT_FOUND: Self = Self(1);
+    pub const NOT_SUPPORTED: Self = Self(10);
+    pub const TEMPORARILY_UNAVAILABLE: Self = Self(11);
+    pub const MALFORMED_REQUEST: Self = Self(12);
+    pub const CRASH: Self = Self(13);
+    pub const ABORT: Self = Self(14);
+    pub const KEY_DOES_NOT_EXIST: Self = Self(20);
+    pub const KEY_ALREADY_EXISTS: Self = Self(21);
+    pub const PRECONDITION_FAILED: Self = Self(22);
+    pub const TXN_CONFLICT: Self = Self(30);
+}
+
+/// The body of an `error` message. Returned (wrapped in `anyhow::Error`) by
+/// RPCs that got an error reply, so callers can `downcast_ref` to inspect the
+/// code.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaelstromError {
+    pub code: ErrorCode,
+    pub text: String,
+}
+
+impl MaelstromError {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Self {
+            code,
+            text: text.into(),
+        }
+    }
+
+    /// Returns the Maelstrom error code if `err` is (or wraps) a `MaelstromError`.
+    pub fn code_of(err: &anyhow::Error) -> Option<ErrorCode> {
+        err.downcast_ref::<Self>().map(|e| e.code)
+    }
+}
+
+/// Answers `request` (a [`Message`](crate::Message)) with an error and
+/// returns `Ok(())` from the handler, so input the node can't handle costs
+/// the client a request rather than the node its life. Takes either a
+/// [`MaelstromError`] or a code and a format string:
+///
+/// ```ignore
+/// let Some(value) = state.get(&key) else {
+///     bail_with_error_reply!(&ctx, input, ErrorCode::KEY_DOES_NOT_EXIST, "no key {}", key);
+/// };
+/// ```
+#[macro_export]
+macro_rules! bail_with_error_reply {
+    ($ctx:expr, $request:expr, $err:expr $(,)?) => {{
+        $request.reply_error($err, $ctx)?;
+        return Ok(());
+    }};
+    ($ctx:expr, $request:expr, $code:expr, $($fmt:tt)+) => {{
+        $request.reply_error($crate::error::MaelstromError::new($code, format!($($fmt)+)), $ctx)?;
+        return Ok(());
+    }};
+}
+
+impl std::fmt::Display for MaelstromError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error {}: {}", self.code.0, self.text)
+    }
+}
+
+impl std::error::Error for MaelstromError {}
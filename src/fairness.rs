@@ -0,0 +1,42 @@
+//! Taking turns between reading input and running handlers.
+//!
+//! The runtime reads and parses input in one task and starts handlers from
+//! another, and each handler is a task of its own. When input arrives faster
+//! than it is handled, as it does at the efficiency challenges' request
+//! rates, a loop that always finds its next event ready never gives the
+//! scheduler back, and the handlers already started wait behind a backlog
+//! they have no part in; on a current-thread runtime they wait for all of
+//! it. Each loop therefore yields after handling [`Runtime::event_budget`]
+//! events in a row, so handlers run between bursts of input and the time to
+//! answer a request stays bounded by the budget rather than by the backlog.
+//!
+//! [`Runtime::event_budget`]: crate::Runtime::event_budget
+
+/// The default for how many events a loop handles before yielding.
+pub const DEFAULT_BUDGET: usize = 64;
+
+/// Counts the events a loop has handled since it last yielded.
+#[derive(Debug)]
+pub(crate) struct Turns {
+    budget: usize,
+    used: usize,
+}
+
+impl Turns {
+    pub(crate) fn new(budget: usize) -> Self {
+        Self {
+            budget: budget.max(1),
+            used: 0,
+        }
+    }
+
+    /// Counts one more event, first yielding to the scheduler if the budget
+    /// is spent.
+    pub(crate) async fn take(&mut self) {
+        if self.used == self.budget {
+            self.used = 0;
+            tokio::task::yield_now().await;
+        }
+        self.used += 1;
+    }
+}
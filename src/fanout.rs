@@ -0,0 +1,142 @@
+//! Waiting on several RPCs at once.
+//!
+//! Each combinator takes the requests as futures, usually [`Ctx::rpc`] calls,
+//! and polls them concurrently, so a fan-out costs one round trip rather than
+//! one per peer. Requests still outstanding when a combinator returns are
+//! dropped, which stops [`Ctx::rpc`] waiting for their replies.
+
+use crate::Ctx;
+use crate::error::{ErrorCode, MaelstromError};
+use std::future::{Future, poll_fn};
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Duration;
+
+/// Futures polled together, yielding each output with its position in the
+/// input as it completes.
+struct Racing<F> {
+    futures: Vec<Option<Pin<Box<F>>>>,
+    remaining: usize,
+}
+
+impl<F: Future> Racing<F> {
+    fn new(futures: impl IntoIterator<Item = F>) -> Self {
+        let futures: Vec<_> = futures.into_iter().map(|f| Some(Box::pin(f))).collect();
+        Self {
+            remaining: futures.len(),
+            futures,
+        }
+    }
+
+    async fn next(&mut self) -> Option<(usize, F::Output)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        poll_fn(|cx| {
+            for (i, slot) in self.futures.iter_mut().enumerate() {
+                let Some(future) = slot else { continue };
+                if let Poll::Ready(output) = future.as_mut().poll(cx) {
+                    *slot = None;
+                    self.remaining -= 1;
+                    return Poll::Ready(Some((i, output)));
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// Every reply, in the order the requests were given. Fails with the first
+/// error to arrive.
+pub async fn all<T, F>(rpcs: impl IntoIterator<Item = F>) -> anyhow::Result<Vec<T>>
+where
+    F: Future<Output = anyhow::Result<T>>,
+{
+    let mut racing = Racing::new(rpcs);
+    let mut replies: Vec<Option<T>> = (0..racing.futures.len()).map(|_| None).collect();
+    while let Some((i, reply)) = racing.next().await {
+        replies[i] = Some(reply?);
+    }
+    Ok(replies.into_iter().flatten().collect())
+}
+
+/// The first `k` successful replies, in the order they arrived. Fails as soon
+/// as so many requests have failed that `k` successes are out of reach.
+pub async fn quorum<T, F>(rpcs: impl IntoIterator<Item = F>, k: usize) -> anyhow::Result<Vec<T>>
+where
+    F: Future<Output = anyhow::Result<T>>,
+{
+    let mut racing = Racing::new(rpcs);
+    let n = racing.futures.len();
+    if k > n {
+        return Err(MaelstromError::new(
+            ErrorCode::TEMPORARILY_UNAVAILABLE,
+            format!("quorum of {} needs more than {} requests", k, n),
+        )
+        .into());
+    }
+    let mut replies = Vec::with_capacity(k);
+    let mut failed = 0;
+    while replies.len() < k
+        && let Some((_, reply)) = racing.next().await
+    {
+        match reply {
+            Ok(reply) => replies.push(reply),
+            Err(e) => {
+                failed += 1;
+                if failed > n - k {
+                    return Err(e.context(format!("{} of {} requests failed, quorum of {} lost", failed, n, k)));
+                }
+            }
+        }
+    }
+    Ok(replies)
+}
+
+/// The first successful reply. Fails with the last error if every request
+/// fails.
+pub async fn first_ok<T, F>(rpcs: impl IntoIterator<Item = F>) -> anyhow::Result<T>
+where
+    F: Future<Output = anyhow::Result<T>>,
+{
+    let mut racing = Racing::new(rpcs);
+    let n = racing.futures.len();
+    let mut last_err = None;
+    while let Some((_, reply)) = racing.next().await {
+        match reply {
+            Ok(reply) => return Ok(reply),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(match last_err {
+        Some(e) => e.context(format!("all {} requests failed", n)),
+        None => MaelstromError::new(ErrorCode::TEMPORARILY_UNAVAILABLE, "no requests to wait on").into(),
+    })
+}
+
+/// Whatever replies arrive within `timeout` on the node's clock, in the order
+/// the requests were given; `None` for requests still unanswered.
+pub async fn within<T, F>(
+    ctx: &Ctx,
+    rpcs: impl IntoIterator<Item = F>,
+    timeout: Duration,
+) -> Vec<Option<anyhow::Result<T>>>
+where
+    F: Future<Output = anyhow::Result<T>>,
+{
+    let mut racing = Racing::new(rpcs);
+    let mut replies: Vec<_> = (0..racing.futures.len()).map(|_| None).collect();
+    let deadline = ctx.sleep(timeout);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            next = racing.next() => match next {
+                Some((i, reply)) => replies[i] = Some(reply),
+                None => break,
+            },
+            _ = &mut deadline => break,
+        }
+    }
+    replies
+}
@@ -0,0 +1,58 @@
+//! Which kind of tokio runtime a node runs on.
+//!
+//! A node whose handlers answer at once from local state, like echo or
+//! unique-ids, gains nothing from worker threads and pays for them in
+//! synchronization; one that gossips, replicates or waits on other nodes
+//! while handling requests wants them. A binary picks with
+//! [`Runtime::flavor`] and [`Runtime::start`], or [`Flavor::main`] around an
+//! async entry point, in place of `#[tokio::main]`; the `runtime` knob
+//! (`--runtime current-thread`, or `DIST_SYS_RUNTIME`) overrides the choice
+//! for a run, to compare the two.
+//!
+//! [`Runtime::flavor`]: crate::Runtime::flavor
+//! [`Runtime::start`]: crate::Runtime::start
+
+use crate::config::NodeConfig;
+use anyhow::Context;
+use std::str::FromStr;
+
+/// The knob that overrides a binary's choice of flavor.
+pub const KNOB: &str = "runtime";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Flavor {
+    /// Everything on the thread that started the node.
+    CurrentThread,
+    /// A worker thread per core, as `#[tokio::main]` gives.
+    #[default]
+    MultiThread,
+}
+
+impl FromStr for Flavor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "current-thread" => Ok(Self::CurrentThread),
+            "multi-thread" => Ok(Self::MultiThread),
+            _ => anyhow::bail!("unknown runtime {:?}; expected current-thread or multi-thread", s),
+        }
+    }
+}
+
+impl Flavor {
+    /// Runs `entry` to completion on a new runtime of this flavor, or of the
+    /// one the [`KNOB`] names. For `main`, outside any runtime.
+    pub fn main<F>(self, entry: F) -> anyhow::Result<()>
+    where
+        F: Future<Output = anyhow::Result<()>>,
+    {
+        let flavor = NodeConfig::from_env_and_args()?.get_or(KNOB, self)?;
+        let mut builder = match flavor {
+            Flavor::CurrentThread => tokio::runtime::Builder::new_current_thread(),
+            Flavor::MultiThread => tokio::runtime::Builder::new_multi_thread(),
+        };
+        let runtime = builder.enable_all().build().context("start the tokio runtime")?;
+        runtime.block_on(entry)
+    }
+}
@@ -0,0 +1,201 @@
+//! Building blocks for gossip protocols.
+//!
+//! A gossip round sends to some of the node's neighbours rather than to a
+//! fixed set: [`PeerSampling`] says which, and a [`PeerSampler`] picks them
+//! round by round. Sampling a few peers on a faster tick often spreads
+//! messages about as quickly as sending to every neighbour, for fewer
+//! messages per operation.
+//!
+//! Push gossip alone leaves a node that missed messages waiting for a peer to
+//! happen to push them again. With push-pull, a round also carries a
+//! [`RangeDigest`] of everything the sender has, and the receiver answers
+//! with what the digest shows the sender is missing.
+//!
+//! [`GossipStats`] keeps count, per neighbour, of what gossip costs and what
+//! it brings, so intervals, sampling and resends can be tuned from a run's
+//! numbers.
+
+use rand::seq::IndexedRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Which neighbours a gossip round goes to. Parses from `all`, `random:<k>`
+/// or `round-robin:<k>`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PeerSampling {
+    /// Every neighbour, every round.
+    #[default]
+    All,
+    /// `k` neighbours chosen at random each round.
+    Random(usize),
+    /// The next `k` neighbours in turn, so every neighbour is reached once
+    /// every `neighbours / k` rounds.
+    RoundRobin(usize),
+}
+
+impl std::str::FromStr for PeerSampling {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s == "all" {
+            return Ok(Self::All);
+        }
+        let parsed = s.split_once(':').and_then(|(name, k)| Some((name, k.parse().ok()?)));
+        match parsed {
+            Some(("random", k)) if k > 0 => Ok(Self::Random(k)),
+            Some(("round-robin", k)) if k > 0 => Ok(Self::RoundRobin(k)),
+            _ => anyhow::bail!(
+                "unknown peer sampling {:?}; expected all, random:<k> or round-robin:<k> with k > 0",
+                s
+            ),
+        }
+    }
+}
+
+/// Picks the peers for each gossip round.
+#[derive(Debug, Default)]
+pub struct PeerSampler {
+    sampling: PeerSampling,
+    /// Where the next round-robin round starts.
+    cursor: AtomicUsize,
+}
+
+impl PeerSampler {
+    pub fn new(sampling: PeerSampling) -> Self {
+        Self {
+            sampling,
+            cursor: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn sampling(&self) -> PeerSampling {
+        self.sampling
+    }
+
+    /// The peers to gossip to this round, out of `peers`.
+    pub fn pick(&self, peers: &[String]) -> Vec<String> {
+        match self.sampling {
+            PeerSampling::All => peers.to_vec(),
+            PeerSampling::Random(k) => peers.choose_multiple(&mut rand::rng(), k).cloned().collect(),
+            PeerSampling::RoundRobin(k) => {
+                if peers.is_empty() {
+                    return Vec::new();
+                }
+                let k = k.min(peers.len());
+                let start = self.cursor.fetch_add(k, Ordering::Relaxed);
+                (start..start + k).map(|i| peers[i % peers.len()].clone()).collect()
+            }
+        }
+    }
+}
+
+/// A set of integers as sorted, disjoint, inclusive ranges: compact for the
+/// mostly-consecutive ids a broadcast workload hands out.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RangeDigest {
+    ranges: Vec<(u64, u64)>,
+}
+
+impl RangeDigest {
+    pub fn new(items: impl IntoIterator<Item = u64>) -> Self {
+        let mut items: Vec<_> = items.into_iter().collect();
+        items.sort_unstable();
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        for item in items {
+            match ranges.last_mut() {
+                Some((_, end)) if item <= end.saturating_add(1) => *end = (*end).max(item),
+                _ => ranges.push((item, item)),
+            }
+        }
+        Self { ranges }
+    }
+
+    pub fn contains(&self, item: u64) -> bool {
+        let i = self.ranges.partition_point(|&(_, end)| end < item);
+        self.ranges.get(i).is_some_and(|&(start, _)| start <= item)
+    }
+
+    /// The `items` this digest doesn't cover.
+    pub fn missing(&self, items: impl IntoIterator<Item = u64>) -> Vec<u64> {
+        items.into_iter().filter(|&item| !self.contains(item)).collect()
+    }
+}
+
+/// Gossip exchanged with one neighbour.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerStats {
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub entries_sent: u64,
+    pub messages_received: u64,
+    pub entries_received: u64,
+    /// Entries received that this node didn't have yet.
+    pub novel_received: u64,
+    /// Share of the entries received that this node already had, from 0 to 1.
+    pub redundancy: f64,
+}
+
+/// [`PeerStats`] for every neighbour gossiped with.
+#[derive(Debug, Default)]
+pub struct GossipStats {
+    peers: Mutex<HashMap<String, PeerStats>>,
+}
+
+impl GossipStats {
+    /// Counts a gossip of `entries` entries, `bytes` long, sent to `peer`.
+    pub fn sent(&self, peer: &str, entries: usize, bytes: usize) {
+        self.update(peer, |stats| {
+            stats.messages_sent += 1;
+            stats.bytes_sent += bytes as u64;
+            stats.entries_sent += entries as u64;
+        });
+    }
+
+    /// Counts a gossip from `peer` of `entries` entries, `novel` of them new.
+    pub fn received(&self, peer: &str, entries: usize, novel: usize) {
+        self.update(peer, |stats| {
+            stats.messages_received += 1;
+            stats.entries_received += entries as u64;
+            stats.novel_received += novel as u64;
+            if stats.entries_received > 0 {
+                stats.redundancy = 1.0 - stats.novel_received as f64 / stats.entries_received as f64;
+            }
+        });
+    }
+
+    pub fn peer(&self, peer: &str) -> PeerStats {
+        self.peers.lock().unwrap().get(peer).copied().unwrap_or_default()
+    }
+
+    pub fn all(&self) -> BTreeMap<String, PeerStats> {
+        self.peers.lock().unwrap().iter().map(|(peer, stats)| (peer.clone(), *stats)).collect()
+    }
+
+    /// Starts `peer` over, e.g. once it has restarted.
+    pub fn forget(&self, peer: &str) {
+        self.peers.lock().unwrap().remove(peer);
+    }
+
+    /// `percent` of resends to `peer`, scaled down by how redundant its own
+    /// gossip has been: a neighbour that rarely tells this node anything new
+    /// is on a well-connected stretch of the network, where lost gossip is
+    /// soon made up by other paths. Unchanged until `peer` has sent anything.
+    pub fn resend_percent(&self, peer: &str, percent: usize) -> usize {
+        let stats = self.peer(peer);
+        if stats.entries_received == 0 {
+            return percent;
+        }
+        (percent as f64 * (1.0 - stats.redundancy)).round() as usize
+    }
+
+    fn update(&self, peer: &str, f: impl FnOnce(&mut PeerStats)) {
+        let mut peers = self.peers.lock().unwrap();
+        match peers.get_mut(peer) {
+            Some(stats) => f(stats),
+            None => f(peers.entry(peer.to_string()).or_default()),
+        }
+    }
+}
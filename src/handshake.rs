@@ -0,0 +1,142 @@
+//! The `init` message every node starts with.
+//!
+//! Maelstrom sends `init` before anything else, but a hand-written script, a
+//! capture edited in a text editor or a harness on another platform can
+//! start with blank lines or a byte-order mark, which are skipped, or with
+//! something that isn't `init` at all. Then the node can't start: the
+//! offending line is logged on stderr as a `bad_init` event, answered with a
+//! [`ErrorCode::MALFORMED_REQUEST`] error if it is a request, and
+//! [`Runtime::run_with_io`] fails with a [`HandshakeError`]. [`Runtime::run`]
+//! exits with [`EXIT_CODE`], so a script can tell a botched startup from a
+//! node that crashed later.
+//!
+//! [`Runtime::run_with_io`]: crate::Runtime::run_with_io
+//! [`Runtime::run`]: crate::Runtime::run
+
+use crate::capture::{Direction, Tap};
+use crate::error::{ErrorCode, MaelstromError};
+use crate::services::Services;
+use crate::{Body, Ctx, Init, Message, SystemPayload};
+use serde_json::Value;
+use tokio::io::{AsyncBufRead, Lines};
+
+/// What [`Runtime::run`] exits with when the input doesn't start with a
+/// valid `init`, unlike the 1 of a node that fails after starting.
+///
+/// [`Runtime::run`]: crate::Runtime::run
+pub const EXIT_CODE: i32 = 3;
+
+/// The input didn't start with a valid `init`.
+#[derive(Debug)]
+pub struct HandshakeError {
+    reason: String,
+    line: Option<String>,
+    /// The line as JSON, if it was, to answer it.
+    request: Option<Value>,
+}
+
+impl HandshakeError {
+    /// What was wrong with the first line.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    /// The first line that wasn't blank, if there was one.
+    pub fn line(&self) -> Option<&str> {
+        self.line.as_deref()
+    }
+
+    fn new(reason: impl Into<String>, line: Option<&str>, request: Option<&Value>) -> Self {
+        Self {
+            reason: reason.into(),
+            line: line.map(str::to_string),
+            request: request.cloned(),
+        }
+    }
+
+    /// Logs the offending line and answers it if it is a request.
+    pub(crate) fn report(&self, ctx: &Ctx) {
+        let event = serde_json::json!({
+            "event": "bad_init",
+            "reason": self.reason,
+            "line": self.line,
+        });
+        eprintln!("{}", event);
+        let Some(request) = &self.request else {
+            return;
+        };
+        let (Some(src), Some(dest), Some(msg_id)) = (
+            request["src"].as_str(),
+            request["dest"].as_str(),
+            request["body"]["msg_id"].as_u64(),
+        ) else {
+            return;
+        };
+        let reply = Message {
+            src: dest.to_string(),
+            dst: src.to_string(),
+            body: Body {
+                id: Some(0),
+                in_reply_to: Some(msg_id as usize),
+                payload: SystemPayload::Error(MaelstromError::new(
+                    ErrorCode::MALFORMED_REQUEST,
+                    format!("expected init first: {}", self.reason),
+                )),
+            },
+        };
+        if let Err(e) = reply.send(ctx) {
+            eprintln!("failed to answer the bad init: {:#}", e);
+        }
+    }
+}
+
+impl std::fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no valid init: {}", self.reason)
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+/// Reads up to the `init` message, skipping blank lines and byte-order
+/// marks, returning it without its payload, and with the services it maps.
+pub(crate) async fn read_init<R>(
+    stdin: &mut Lines<R>,
+    tap: Option<&Tap>,
+) -> Result<(Message<()>, Init, Services), HandshakeError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let line = loop {
+        let line = match stdin.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => return Err(HandshakeError::new("input ended before init", None, None)),
+            Err(e) => return Err(HandshakeError::new(format!("failed to read input: {}", e), None, None)),
+        };
+        if let Some(tap) = tap {
+            tap.record(Direction::Recv, line.as_bytes());
+        }
+        let trimmed = line.trim_start_matches('\u{feff}').trim();
+        if !trimmed.is_empty() {
+            break trimmed.to_string();
+        }
+    };
+    let value: Value = serde_json::from_str(&line)
+        .map_err(|e| HandshakeError::new(format!("not JSON: {}", e), Some(&line), None))?;
+    let kind = &value["body"]["type"];
+    if kind != "init" {
+        return Err(HandshakeError::new(
+            format!("first message is {} rather than init", kind),
+            Some(&line),
+            Some(&value),
+        ));
+    }
+    let fail = |reason: String| HandshakeError::new(reason, Some(&line), Some(&value));
+    let services = Services::from_init(&value["body"]).map_err(|e| fail(format!("{:#}", e)))?;
+    let msg: Message<SystemPayload> =
+        serde_json::from_value(value.clone()).map_err(|e| fail(format!("malformed init: {}", e)))?;
+    let (SystemPayload::Init(init), request) = msg.take_payload() else {
+        return Err(fail("malformed init".to_string()));
+    };
+    Ok((request, init, services))
+}
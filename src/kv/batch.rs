@@ -0,0 +1,121 @@
+//! Several KV operations in one round trip.
+
+use super::Kv;
+use crate::fanout;
+use serde::{Serialize, de::DeserializeOwned};
+
+/// Independent reads, writes and compare-and-sets on one KV service, sent
+/// together and waited on together, so they cost one round trip rather than
+/// one each:
+///
+/// ```ignore
+/// let reads = node_ids.iter().fold(kv.batch::<u64>(), |batch, node| batch.read(node));
+/// let mut total = 0;
+/// for read in reads.run().await {
+///     total += read?.value().unwrap_or(0);
+/// }
+/// ```
+///
+/// The operations are not atomic: the service sees them as separate requests
+/// and may apply them in any order, and some can succeed while others fail.
+#[derive(Debug, Clone)]
+#[must_use = "a batch does nothing until run"]
+pub struct Batch<V> {
+    kv: Kv,
+    ops: Vec<Op<V>>,
+}
+
+#[derive(Debug, Clone)]
+enum Op<V> {
+    Read(String),
+    Write(String, V),
+    Cas {
+        key: String,
+        from: V,
+        to: V,
+        create_if_not_exists: bool,
+    },
+}
+
+/// How one operation of a [`Batch`] went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome<V> {
+    /// The key's value, or `None` if it doesn't exist.
+    Read(Option<V>),
+    Written,
+    /// Whether the compare-and-set's precondition held.
+    Cas(bool),
+}
+
+impl<V> Outcome<V> {
+    /// What a read found; `None` for a missing key or any other operation.
+    pub fn value(self) -> Option<V> {
+        match self {
+            Outcome::Read(value) => value,
+            Outcome::Written | Outcome::Cas(_) => None,
+        }
+    }
+}
+
+impl<V> Batch<V>
+where
+    V: Serialize + DeserializeOwned,
+{
+    pub(super) fn new(kv: Kv) -> Self {
+        Self { kv, ops: Vec::new() }
+    }
+
+    pub fn read(mut self, key: impl Into<String>) -> Self {
+        self.ops.push(Op::Read(key.into()));
+        self
+    }
+
+    pub fn write(mut self, key: impl Into<String>, value: V) -> Self {
+        self.ops.push(Op::Write(key.into(), value));
+        self
+    }
+
+    /// See [`Kv::cas`].
+    pub fn cas(mut self, key: impl Into<String>, from: V, to: V, create_if_not_exists: bool) -> Self {
+        self.ops.push(Op::Cas {
+            key: key.into(),
+            from,
+            to,
+            create_if_not_exists,
+        });
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Sends every operation and waits for all of them, returning how each
+    /// went in the order they were added.
+    pub async fn run(self) -> Vec<anyhow::Result<Outcome<V>>> {
+        let kv = &self.kv;
+        // wrapped so that one failure doesn't stop `all` waiting for the rest
+        let ops = self.ops.into_iter().map(|op| async move { anyhow::Ok(run(kv, op).await) });
+        fanout::all(ops).await.expect("batched operations report their own errors")
+    }
+}
+
+async fn run<V>(kv: &Kv, op: Op<V>) -> anyhow::Result<Outcome<V>>
+where
+    V: Serialize + DeserializeOwned,
+{
+    match op {
+        Op::Read(key) => kv.read(&key).await.map(Outcome::Read),
+        Op::Write(key, value) => kv.write(&key, value).await.map(|()| Outcome::Written),
+        Op::Cas {
+            key,
+            from,
+            to,
+            create_if_not_exists,
+        } => kv.cas(&key, from, to, create_if_not_exists).await.map(Outcome::Cas),
+    }
+}
@@ -0,0 +1,102 @@
+//! Immutable blobs named by the hash of their contents.
+
+use super::Kv;
+use crate::backoff::{self, Backoff, Policy};
+use anyhow::Context;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Content-addressed storage over a KV service: [`BlobStore::put`] stores
+/// bytes under `<prefix>/<hash>` and returns the hash, which
+/// [`BlobStore::get`] takes back.
+///
+/// A key's value never changes once written, so the weaker `seq-kv` is
+/// enough: a stale replica can only claim a blob doesn't exist yet, never
+/// return different bytes. A missing blob is re-read a few times before
+/// `get` gives up on it. Every blob put or fetched is cached for the life of
+/// the store (and its clones), so repeated gets cost no round trips.
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    kv: Kv,
+    prefix: String,
+    cache: Arc<Mutex<HashMap<String, Arc<[u8]>>>>,
+    policy: Policy,
+}
+
+impl BlobStore {
+    pub fn new(kv: Kv, prefix: impl Into<String>) -> Self {
+        Self {
+            kv,
+            prefix: prefix.into(),
+            cache: Arc::default(),
+            policy: Policy::new(Backoff::Constant(Duration::from_millis(10))).max_attempts(10),
+        }
+    }
+
+    /// How long to wait between re-reads of a missing blob, and how many
+    /// reads to try before reporting it missing.
+    pub fn retry(self, interval: Duration, max_attempts: usize) -> Self {
+        self.backoff(Policy::new(Backoff::Constant(interval)).max_attempts(max_attempts))
+    }
+
+    /// Like [`BlobStore::retry`], for any schedule.
+    pub fn backoff(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// The name `bytes` are stored under.
+    pub fn hash(bytes: &[u8]) -> String {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    fn key(&self, hash: &str) -> String {
+        format!("{}/{}", self.prefix, hash)
+    }
+
+    /// Stores `bytes` unless this store already has, and returns their hash.
+    pub async fn put(&self, bytes: impl Into<Arc<[u8]>>) -> anyhow::Result<String> {
+        let bytes = bytes.into();
+        let hash = Self::hash(&bytes);
+        if self.cache.lock().unwrap().contains_key(&hash) {
+            return Ok(hash);
+        }
+        self.kv.write(&self.key(&hash), to_hex(&bytes)).await?;
+        self.cache.lock().unwrap().insert(hash.clone(), bytes);
+        Ok(hash)
+    }
+
+    /// The blob stored under `hash`, or `None` if it still can't be found
+    /// after the configured retries.
+    pub async fn get(&self, hash: &str) -> anyhow::Result<Option<Arc<[u8]>>> {
+        if let Some(bytes) = self.cache.lock().unwrap().get(hash) {
+            return Ok(Some(bytes.clone()));
+        }
+        backoff::retry(self.kv.ctx.clock().as_ref(), &self.policy, |_| async {
+            let Some(hex) = self.kv.read::<String>(&self.key(hash)).await? else {
+                return Ok(None);
+            };
+            let bytes: Arc<[u8]> = from_hex(&hex).with_context(|| format!("corrupt blob {}", hash))?.into();
+            anyhow::ensure!(Self::hash(&bytes) == hash, "blob {} does not match its hash", hash);
+            self.cache.lock().unwrap().insert(hash.to_string(), bytes.clone());
+            Ok(Some(bytes))
+        })
+        .await
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(hex.is_ascii() && hex.len().is_multiple_of(2), "not a hex string");
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).context("invalid hex"))
+        .collect()
+}
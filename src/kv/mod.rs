@@ -0,0 +1,339 @@
+//! Client for Maelstrom's key-value services (`seq-kv`, `lin-kv`, `lww-kv`).
+
+mod batch;
+mod blob;
+mod txn;
+mod watch;
+
+pub use batch::{Batch, Outcome};
+pub use blob::BlobStore;
+pub use txn::{Slot, SlotLock, Txn};
+pub use watch::Watch;
+
+use crate::Ctx;
+use crate::backoff::{self, Backoff, Policy};
+use crate::error::{ErrorCode, MaelstromError};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::time::Duration;
+
+pub const SEQ_KV: &str = "seq-kv";
+pub const LIN_KV: &str = "lin-kv";
+pub const LWW_KV: &str = "lww-kv";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum KvRequest<'a, V> {
+    Read {
+        key: &'a str,
+    },
+    Write {
+        key: &'a str,
+        value: V,
+    },
+    Cas {
+        key: &'a str,
+        from: V,
+        to: V,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        create_if_not_exists: bool,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
+enum KvResponse<V> {
+    ReadOk { value: V },
+    WriteOk,
+    CasOk,
+}
+
+/// Typed handle to one KV service. Requests are issued through [`Ctx::rpc`]
+/// and time out after [`Kv::timeout`] (one second by default), which Maelstrom
+/// reports as indeterminate for writes.
+///
+/// Values are typed per call rather than per handle: anything `Serialize +
+/// DeserializeOwned` can be stored, so one handle can keep counters, structs,
+/// vectors of offsets and strings under different keys. Reading a key as the
+/// wrong type fails to deserialize; read it as a [`serde_json::Value`] when
+/// its shape isn't known ahead of time:
+///
+/// ```ignore
+/// kv.write("offsets", vec![0u64, 4, 9]).await?;
+/// let offsets: Option<Vec<u64>> = kv.read("offsets").await?;
+/// let anything: Option<serde_json::Value> = kv.read("offsets").await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Kv {
+    ctx: Ctx,
+    service: String,
+    timeout: Duration,
+    /// How [`Kv::update`] retries a lost race.
+    policy: Policy,
+}
+
+impl Kv {
+    pub fn new(ctx: &Ctx, service: impl Into<String>) -> Self {
+        Self {
+            ctx: ctx.clone(),
+            service: service.into(),
+            timeout: Duration::from_secs(1),
+            policy: backoff::CONTENTION,
+        }
+    }
+
+    pub fn seq(ctx: &Ctx) -> Self {
+        Self::new(ctx, SEQ_KV)
+    }
+
+    pub fn lin(ctx: &Ctx) -> Self {
+        Self::new(ctx, LIN_KV)
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How [`Kv::update`] waits between attempts and how many it makes;
+    /// [`backoff::CONTENTION`] unless set.
+    pub fn backoff(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    /// Starts a [`Batch`] of operations on values of type `V`, to be sent
+    /// together.
+    pub fn batch<V>(&self) -> Batch<V>
+    where
+        V: Serialize + DeserializeOwned,
+    {
+        Batch::new(self.clone())
+    }
+
+    /// Reads `key`, returning `None` if it doesn't exist.
+    pub async fn read<V>(&self, key: &str) -> anyhow::Result<Option<V>>
+    where
+        V: Serialize + DeserializeOwned,
+    {
+        let request: KvRequest<'_, V> = KvRequest::Read { key };
+        match self.ctx.rpc_timeout(&self.service, request, self.timeout).await {
+            Ok(KvResponse::ReadOk { value }) => Ok(Some(value)),
+            Ok(other) => anyhow::bail!("unexpected reply to read: {:?}", kind(&other)),
+            Err(e) if MaelstromError::code_of(&e) == Some(ErrorCode::KEY_DOES_NOT_EXIST) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn write<V>(&self, key: &str, value: V) -> anyhow::Result<()>
+    where
+        V: Serialize + DeserializeOwned,
+    {
+        match self
+            .ctx
+            .rpc_timeout(&self.service, KvRequest::Write { key, value }, self.timeout)
+            .await?
+        {
+            KvResponse::<V>::WriteOk => Ok(()),
+            other => anyhow::bail!("unexpected reply to write: {:?}", kind(&other)),
+        }
+    }
+
+    /// Sets `key` to `to` if it currently holds `from` (or doesn't exist and
+    /// `create_if_not_exists` is set). Returns `false` if the precondition
+    /// didn't hold.
+    pub async fn cas<V>(&self, key: &str, from: V, to: V, create_if_not_exists: bool) -> anyhow::Result<bool>
+    where
+        V: Serialize + DeserializeOwned,
+    {
+        let request = KvRequest::Cas {
+            key,
+            from,
+            to,
+            create_if_not_exists,
+        };
+        match self.ctx.rpc_timeout(&self.service, request, self.timeout).await {
+            Ok(KvResponse::<V>::CasOk) => Ok(true),
+            Ok(other) => anyhow::bail!("unexpected reply to cas: {:?}", kind(&other)),
+            Err(e)
+                if matches!(
+                    MaelstromError::code_of(&e),
+                    Some(ErrorCode::PRECONDITION_FAILED | ErrorCode::KEY_DOES_NOT_EXIST)
+                ) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Sets `key` to `modify` of its current value, creating it as
+    /// `V::default()` first if it doesn't exist. A compare-and-set makes sure
+    /// nothing was written in between; when something was, the key is read
+    /// again and `modify` called again after the next of [`Kv::backoff`]'s
+    /// delays. Returns the value written.
+    ///
+    /// Fails with [`ErrorCode::TEMPORARILY_UNAVAILABLE`] once the policy's
+    /// attempts run out. Any other error, including a timeout, is returned
+    /// at once: a compare-and-set that timed out may still have been
+    /// applied, and retrying it would apply `modify` twice.
+    pub async fn update<V, F>(&self, key: &str, mut modify: F) -> anyhow::Result<V>
+    where
+        V: Serialize + DeserializeOwned + Clone + Default,
+        F: FnMut(V) -> V,
+    {
+        let mut delays = self.policy.delays();
+        loop {
+            let written = match self.read::<V>(key).await? {
+                Some(current) => {
+                    let next = modify(current.clone());
+                    self.cas(key, current, next.clone(), false).await?.then_some(next)
+                }
+                // Creating the key with `modify`'s value instead couldn't tell
+                // whether it was this cas or a racing one with the same value
+                // that created it; creating the default either way can't go
+                // wrong, and the next attempt applies `modify` to whatever is
+                // there by then.
+                None => {
+                    self.cas(key, V::default(), V::default(), true).await?;
+                    None
+                }
+            };
+            if let Some(next) = written {
+                return Ok(next);
+            }
+            let Some(delay) = delays.next() else {
+                return Err(MaelstromError::new(
+                    ErrorCode::TEMPORARILY_UNAVAILABLE,
+                    format!("{} kept changing under updates to {}", self.service, key),
+                )
+                .into());
+            };
+            self.ctx.sleep(delay).await;
+        }
+    }
+}
+
+fn kind<V>(response: &KvResponse<V>) -> &'static str {
+    match response {
+        KvResponse::ReadOk { .. } => "read_ok",
+        KvResponse::WriteOk => "write_ok",
+        KvResponse::CasOk => "cas_ok",
+    }
+}
+
+/// A value tagged with a per-key version that increases with every write made
+/// through [`SessionKv`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Versioned<V> {
+    pub version: u64,
+    pub value: V,
+}
+
+/// Session guarantees (read-your-writes and monotonic reads) on top of a
+/// sequentially consistent store.
+///
+/// Every write goes through a version-bumping CAS, and the session remembers
+/// the highest version of each key it has written or read. A session read
+/// that comes back older than that was served from a stale replica and is
+/// retried until the store catches up. Keys used through a `SessionKv` hold
+/// [`Versioned`] values and must only be written through one.
+#[derive(Debug)]
+pub struct SessionKv {
+    kv: Kv,
+    seen: std::sync::Mutex<std::collections::HashMap<String, u64>>,
+    policy: Policy,
+}
+
+impl SessionKv {
+    pub fn new(kv: Kv) -> Self {
+        Self {
+            kv,
+            seen: Default::default(),
+            policy: Policy::new(Backoff::Constant(Duration::from_millis(10))).max_attempts(50),
+        }
+    }
+
+    /// How long to wait between re-reads of a stale key, and how many reads
+    /// to try before giving up with [`ErrorCode::TEMPORARILY_UNAVAILABLE`].
+    pub fn retry(self, interval: Duration, max_attempts: usize) -> Self {
+        self.backoff(Policy::new(Backoff::Constant(interval)).max_attempts(max_attempts))
+    }
+
+    /// Like [`SessionKv::retry`], for any schedule. A write that loses a
+    /// race retries under [`backoff::CONTENTION`] instead.
+    pub fn backoff(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    fn floor(&self, key: &str) -> u64 {
+        self.seen.lock().unwrap().get(key).copied().unwrap_or(0)
+    }
+
+    fn observe(&self, key: &str, version: u64) {
+        let mut seen = self.seen.lock().unwrap();
+        let floor = seen.entry(key.to_string()).or_default();
+        *floor = (*floor).max(version);
+    }
+
+    /// Writes `value` as the next version of `key`.
+    pub async fn write<V>(&self, key: &str, value: V) -> anyhow::Result<()>
+    where
+        V: Serialize + DeserializeOwned + Clone,
+    {
+        // losing a race or reading a stale version both resolve on a re-read
+        backoff::retry(self.kv.ctx.clock().as_ref(), &backoff::CONTENTION, |_| async {
+            let current = self.kv.read::<Versioned<V>>(key).await?;
+            let version = current.as_ref().map_or(0, |c| c.version) + 1;
+            let next = Versioned {
+                version,
+                value: value.clone(),
+            };
+            let written = match current {
+                Some(current) => self.kv.cas(key, current, next, false).await?,
+                // nothing to compare against; `from` is ignored when the key is created
+                None => self.kv.cas(key, next.clone(), next, true).await?,
+            };
+            if written {
+                self.observe(key, version);
+            }
+            Ok(written.then_some(()))
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Reads `key`, retrying until the store returns a version at least as
+    /// new as any this session has written or read before.
+    pub async fn read_after_my_writes<V>(&self, key: &str) -> anyhow::Result<Option<V>>
+    where
+        V: Serialize + DeserializeOwned,
+    {
+        let floor = self.floor(key);
+        let read = backoff::retry(self.kv.ctx.clock().as_ref(), &self.policy, |_| async {
+            match self.kv.read::<Versioned<V>>(key).await? {
+                Some(current) if current.version >= floor => {
+                    self.observe(key, current.version);
+                    Ok(Some(Some(current.value)))
+                }
+                None if floor == 0 => Ok(Some(None)),
+                _ => Ok(None),
+            }
+        })
+        .await?;
+        read.ok_or_else(|| {
+            MaelstromError::new(
+                ErrorCode::TEMPORARILY_UNAVAILABLE,
+                format!("{} did not catch up with version {} of {}", self.kv.service, floor, key),
+            )
+            .into()
+        })
+    }
+}
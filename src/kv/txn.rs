@@ -0,0 +1,280 @@
+//! Optimistic multi-key transactions over a linearizable KV service.
+//!
+//! Each key holds a [`Slot`]: a version, the committed value, and optionally
+//! a lock left by an in-flight transaction along with the value it intends to
+//! write. Committing a transaction:
+//!
+//! 1. claims a free status record `txn/<node>/<n>` and marks it `pending`
+//!    for the transaction;
+//! 2. locks every key it read or wrote, in key order, by CASing each slot from
+//!    the exact state the transaction observed (so reads are validated too);
+//! 3. flips the status record to `committed` — the commit point;
+//! 4. rolls every lock forward: written keys get the new value and the next
+//!    version, read-only keys are simply unlocked;
+//! 5. frees the status record for the node's next transaction.
+//!
+//! Anyone who runs into a lock consults the status record: committed locks are
+//! rolled forward, aborted ones rolled back, and pending ones waited on for a
+//! while and then aborted, so a crashed transaction never wedges a key. A
+//! record that has been freed, or claimed by a later transaction, means the
+//! lock's transaction released every lock it knew it held, so the lock is one
+//! that landed after its transaction gave up on it, and is rolled back.
+
+use super::Kv;
+use crate::backoff;
+use crate::error::{ErrorCode, MaelstromError};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Duration;
+
+/// How many times a transaction runs before giving up, with
+/// [`backoff::CONTENTION`]'s delays in between.
+const MAX_ATTEMPTS: usize = 100;
+/// How many times to back off on another transaction's pending lock before
+/// aborting it.
+const MAX_LOCK_WAITS: usize = 20;
+const LOCK_WAIT: Duration = Duration::from_millis(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Slot<V> {
+    pub version: u64,
+    pub value: Option<V>,
+    pub lock: Option<SlotLock<V>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotLock<V> {
+    /// The key of the transaction's status record.
+    pub record: String,
+    pub txn: String,
+    /// The value the transaction will install on commit; `None` for keys it
+    /// only read.
+    pub write: Option<V>,
+}
+
+/// A status record, and the transaction it currently belongs to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", content = "txn", rename_all = "snake_case")]
+enum Record {
+    Free,
+    Pending(String),
+    Committed(String),
+    Aborted(String),
+}
+
+impl<V> Slot<V> {
+    fn empty() -> Self {
+        Self {
+            version: 0,
+            value: None,
+            lock: None,
+        }
+    }
+}
+
+/// The transaction handed to the body of [`Kv::transact`]. Reads see a
+/// consistent snapshot validated at commit; writes are buffered until then.
+#[derive(Debug)]
+pub struct Txn<'a, V> {
+    kv: &'a Kv,
+    read: HashMap<String, Slot<V>>,
+    writes: BTreeMap<String, V>,
+}
+
+impl<V> Txn<'_, V>
+where
+    V: Serialize + DeserializeOwned + Clone + PartialEq,
+{
+    pub async fn read(&mut self, key: &str) -> anyhow::Result<Option<V>> {
+        if let Some(value) = self.writes.get(key) {
+            return Ok(Some(value.clone()));
+        }
+        if let Some(slot) = self.read.get(key) {
+            return Ok(slot.value.clone());
+        }
+        let slot = self.kv.read_unlocked(key).await?;
+        let value = slot.value.clone();
+        self.read.insert(key.to_string(), slot);
+        Ok(value)
+    }
+
+    pub fn write(&mut self, key: &str, value: V) {
+        self.writes.insert(key.to_string(), value);
+    }
+}
+
+impl Kv {
+    /// Runs `body` as a serializable transaction, re-running it from scratch
+    /// on conflict. Only keys accessed through the [`Txn`] are covered, and
+    /// they must only ever be accessed through transactions.
+    ///
+    /// Inside `Node::step`, pass an `async move` closure so the returned future
+    /// stays `Send`.
+    pub async fn transact<V, T>(&self, mut body: impl AsyncFnMut(&mut Txn<'_, V>) -> anyhow::Result<T>) -> anyhow::Result<T>
+    where
+        V: Serialize + DeserializeOwned + Clone + PartialEq,
+    {
+        let mut delays = backoff::CONTENTION.max_attempts(MAX_ATTEMPTS).delays();
+        loop {
+            let mut txn = Txn {
+                kv: self,
+                read: HashMap::new(),
+                writes: BTreeMap::new(),
+            };
+            let result = body(&mut txn).await?;
+            if txn.writes.is_empty() && txn.read.len() <= 1 {
+                // a single linearizable read needs no validation
+                return Ok(result);
+            }
+            if self.commit(txn.read, txn.writes).await? {
+                return Ok(result);
+            }
+            let Some(delay) = delays.next() else {
+                return Err(MaelstromError::new(ErrorCode::TXN_CONFLICT, "transaction kept conflicting").into());
+            };
+            self.ctx.sleep(delay).await;
+        }
+    }
+
+    async fn commit<V>(&self, mut read: HashMap<String, Slot<V>>, writes: BTreeMap<String, V>) -> anyhow::Result<bool>
+    where
+        V: Serialize + DeserializeOwned + Clone + PartialEq,
+    {
+        let id = format!("{}-{}-{}", self.ctx.node_id(), self.ctx.next_msg_id(), rand::random::<u32>());
+        let record = self.claim_record(&id).await?;
+
+        let keys: BTreeSet<String> = read.keys().chain(writes.keys()).cloned().collect();
+        let mut locked = Vec::new();
+        let mut ok = true;
+        for key in &keys {
+            // blind writes still need the slot they lock from
+            let observed = match read.remove(key) {
+                Some(slot) => slot,
+                None => self.read_unlocked(key).await?,
+            };
+            let lock = Slot {
+                lock: Some(SlotLock {
+                    record: record.clone(),
+                    txn: id.clone(),
+                    write: writes.get(key).cloned(),
+                }),
+                ..observed.clone()
+            };
+            let create = observed == Slot::empty();
+            match self.cas(key, observed, lock.clone(), create).await {
+                Ok(true) => locked.push((key, lock)),
+                Ok(false) => {
+                    ok = false;
+                    break;
+                }
+                Err(_) => {
+                    // the lock may or may not be in place; releasing below handles both
+                    locked.push((key, lock));
+                    ok = false;
+                    break;
+                }
+            }
+        }
+
+        let committed = self.settle(&record, &id, ok).await?;
+        let mut released = true;
+        for (key, lock) in locked {
+            released &= self.release(key, lock, committed).await.is_ok();
+        }
+        if released {
+            // no lock we know of points at the record any more
+            let settled = if committed { Record::Committed(id) } else { Record::Aborted(id) };
+            let _ = self.cas(&record, settled, Record::Free, false).await;
+        }
+        Ok(committed)
+    }
+
+    /// Claims the first of this node's status records that is free, or
+    /// doesn't exist yet, for `txn`, returning its key.
+    async fn claim_record(&self, txn: &str) -> anyhow::Result<String> {
+        for n in 0.. {
+            let key = format!("txn/{}/{}", self.ctx.node_id(), n);
+            if self.cas(&key, Record::Free, Record::Pending(txn.to_string()), true).await? {
+                return Ok(key);
+            }
+        }
+        unreachable!("some record is free")
+    }
+
+    /// Moves `txn`'s pending `record` to committed if `commit` and that
+    /// lands, or to aborted otherwise, returning whether it committed. Fails
+    /// if that can't be told, leaving the locks to be resolved through the
+    /// record as a crashed transaction's would be.
+    async fn settle(&self, record: &str, txn: &str, commit: bool) -> anyhow::Result<bool> {
+        let pending = Record::Pending(txn.to_string());
+        if commit && let Ok(true) = self.cas(record, pending.clone(), Record::Committed(txn.to_string()), false).await {
+            return Ok(true);
+        }
+        // not committing, or not knowing whether the commit landed
+        if let Ok(true) = self.cas(record, pending, Record::Aborted(txn.to_string()), false).await {
+            return Ok(false);
+        }
+        // someone aborted us, or the commit landed after all
+        Ok(self.read::<Record>(record).await? == Some(Record::Committed(txn.to_string())))
+    }
+
+    /// Reads the slot for `key`, resolving any lock on it first.
+    async fn read_unlocked<V>(&self, key: &str) -> anyhow::Result<Slot<V>>
+    where
+        V: Serialize + DeserializeOwned + Clone + PartialEq,
+    {
+        let mut waits = 0;
+        loop {
+            let Some(slot) = self.read::<Slot<V>>(key).await? else {
+                return Ok(Slot::empty());
+            };
+            let Some(lock) = &slot.lock else {
+                return Ok(slot);
+            };
+
+            let pending = Record::Pending(lock.txn.clone());
+            let committed = match self.read::<Record>(&lock.record).await? {
+                Some(record) if record == pending && waits < MAX_LOCK_WAITS => {
+                    waits += 1;
+                    self.ctx.sleep(LOCK_WAIT).await;
+                    continue;
+                }
+                Some(record) if record == pending => {
+                    if !self.cas(&lock.record, pending, Record::Aborted(lock.txn.clone()), false).await? {
+                        // it committed or aborted in the meantime; look again
+                        continue;
+                    }
+                    false
+                }
+                Some(Record::Committed(txn)) => txn == lock.txn,
+                // aborted, or the record was freed or reused: see the module docs
+                _ => false,
+            };
+            let _ = self.release(key, slot, committed).await;
+        }
+    }
+
+    /// Replaces the locked `slot` with its rolled-forward (or rolled-back)
+    /// successor. Fails quietly if someone else already did it.
+    async fn release<V>(&self, key: &str, slot: Slot<V>, committed: bool) -> anyhow::Result<bool>
+    where
+        V: Serialize + DeserializeOwned + Clone + PartialEq,
+    {
+        let Some(lock) = &slot.lock else {
+            return Ok(true);
+        };
+        let next = match (&lock.write, committed) {
+            (Some(value), true) => Slot {
+                version: slot.version + 1,
+                value: Some(value.clone()),
+                lock: None,
+            },
+            _ => Slot {
+                version: slot.version,
+                value: slot.value.clone(),
+                lock: None,
+            },
+        };
+        self.cas(key, slot, next, false).await
+    }
+}
@@ -0,0 +1,79 @@
+//! Change notifications for a single key, by polling.
+
+use super::Kv;
+use crate::deadline;
+use serde::{Serialize, de::DeserializeOwned};
+use std::time::Duration;
+
+/// Follows one key's value. Created by [`Kv::watch`].
+///
+/// The key is polled, starting at the minimum interval after every change
+/// and backing off to the maximum while it stays the same, so a busy key is
+/// noticed quickly and an idle one costs little. Changes between two polls
+/// are coalesced, and on `seq-kv` a poll may be served by a stale replica,
+/// so a watch can see a key move back to an older value.
+#[derive(Debug)]
+pub struct Watch<V> {
+    kv: Kv,
+    key: String,
+    /// The value last returned by [`Watch::changed`]; `None` before the
+    /// first call.
+    last: Option<Option<V>>,
+    min_interval: Duration,
+    max_interval: Duration,
+    interval: Duration,
+}
+
+impl Kv {
+    /// Watches `key`. Nothing is read until [`Watch::changed`] is called.
+    pub fn watch<V>(&self, key: impl Into<String>) -> Watch<V> {
+        let min_interval = Duration::from_millis(10);
+        Watch {
+            kv: self.clone(),
+            key: key.into(),
+            last: None,
+            min_interval,
+            max_interval: Duration::from_secs(1),
+            interval: min_interval,
+        }
+    }
+}
+
+impl<V> Watch<V>
+where
+    V: Serialize + DeserializeOwned + Clone + PartialEq,
+{
+    /// Polls between every `min` and every `max` (10ms and 1s by default).
+    pub fn poll_interval(mut self, min: Duration, max: Duration) -> Self {
+        assert!(min <= max, "minimum poll interval exceeds the maximum");
+        self.min_interval = min;
+        self.max_interval = max;
+        self.interval = min;
+        self
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Waits until the key holds something other than what the previous call
+    /// returned, and returns it (`None` if the key doesn't exist). The first
+    /// call returns the current value right away. Reads that time out are
+    /// retried until the context's deadline, if it has one.
+    pub async fn changed(&mut self) -> anyhow::Result<Option<V>> {
+        loop {
+            match self.kv.read(&self.key).await {
+                Ok(value) if self.last.as_ref() != Some(&value) => {
+                    self.last = Some(value.clone());
+                    self.interval = self.min_interval;
+                    return Ok(value);
+                }
+                Ok(_) => {}
+                Err(e) if deadline::retryable(&e, &self.kv.ctx) => {}
+                Err(e) => return Err(e),
+            }
+            self.kv.ctx.sleep(self.interval).await;
+            self.interval = (self.interval * 2).min(self.max_interval);
+        }
+    }
+}
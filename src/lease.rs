@@ -0,0 +1,223 @@
+//! Named leases over lin-kv, for electing a single coordinator without full
+//! consensus.
+//!
+//! The lease record holds the current holder and a heartbeat counter. The
+//! holder bumps the counter with a CAS every renew interval; contenders watch
+//! the record and only take it over once it has stayed unchanged for a whole
+//! TTL, measured on their own clock. No wall-clock timestamps are exchanged,
+//! so the scheme tolerates clock skew between nodes (though not drift).
+//!
+//! The holder treats the lease as valid for one TTL from when it *sent* its
+//! last successful renewal; a contender can only see that renewal after it
+//! was sent, so it waits at least as long before taking over.
+
+use crate::kv::Kv;
+use crate::{Ctx, Event};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct LeaseRecord {
+    holder: Option<String>,
+    beat: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeaseEvent {
+    Acquired { name: String },
+    Lost { name: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct LeaseOptions {
+    /// How long a record must go unrenewed before another node may take it.
+    pub ttl: Duration,
+    /// How often the holder renews, and how often contenders poll. Should be
+    /// well under `ttl`.
+    pub renew_interval: Duration,
+}
+
+impl Default for LeaseOptions {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_millis(1000),
+            renew_interval: Duration::from_millis(250),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LeaseState {
+    /// The record we last wrote, while we hold the lease.
+    record: Option<LeaseRecord>,
+    valid_until: Option<Instant>,
+}
+
+/// A lease being contended for (or held) in the background. Acquisition and
+/// loss are delivered to the node as injected events; dropping the handle
+/// stops renewing without releasing.
+#[derive(Debug)]
+pub struct Lease {
+    name: String,
+    ctx: Ctx,
+    kv: Kv,
+    state: Arc<Mutex<LeaseState>>,
+    task: JoinHandle<()>,
+}
+
+impl Lease {
+    /// Starts contending for `name`. `wrap` turns lease events into the node's
+    /// injected payload type.
+    pub fn spawn<P, SP, IP>(
+        ctx: &Ctx,
+        name: impl Into<String>,
+        options: LeaseOptions,
+        inject: mpsc::UnboundedSender<Event<P, SP, IP>>,
+        wrap: impl Fn(LeaseEvent) -> IP + Send + 'static,
+    ) -> Self
+    where
+        P: Send + 'static,
+        SP: Send + 'static,
+        IP: Send + 'static,
+    {
+        let name = name.into();
+        let kv = Kv::lin(ctx).timeout(options.renew_interval);
+        let state = Arc::new(Mutex::new(LeaseState::default()));
+        let task = tokio::spawn(contend(
+            ctx.clone(),
+            kv.clone(),
+            name.clone(),
+            options,
+            state.clone(),
+            move |event| {
+                let _ = inject.send(Event::Injected(wrap(event)));
+            },
+        ));
+        Self {
+            name,
+            ctx: ctx.clone(),
+            kv,
+            state,
+            task,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether this node currently holds the lease. Turns false as soon as the
+    /// validity window lapses, even before the background task notices.
+    pub fn is_held(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.valid_until.is_some_and(|until| self.ctx.now() < until)
+    }
+
+    /// Stops contending and, if held, marks the record free so others can
+    /// take it immediately instead of waiting out the TTL.
+    pub async fn release(&self) -> anyhow::Result<()> {
+        self.task.abort();
+        let record = {
+            let mut state = self.state.lock().unwrap();
+            state.valid_until = None;
+            state.record.take()
+        };
+        if let Some(record) = record {
+            let free = LeaseRecord {
+                holder: None,
+                beat: record.beat + 1,
+            };
+            self.kv.cas(&self.name, record, free, false).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn contend(
+    ctx: Ctx,
+    kv: Kv,
+    name: String,
+    options: LeaseOptions,
+    state: Arc<Mutex<LeaseState>>,
+    emit: impl Fn(LeaseEvent),
+) {
+    let me = Some(ctx.node_id().to_string());
+    // a foreign record and when we first saw it in that exact form
+    let mut observed: Option<(LeaseRecord, Instant)> = None;
+
+    loop {
+        let held = state.lock().unwrap().record.clone();
+        match held {
+            Some(current) => {
+                let next = LeaseRecord {
+                    holder: me.clone(),
+                    beat: current.beat + 1,
+                };
+                let sent_at = ctx.now();
+                let renewed = kv.cas(&name, current, next.clone(), false).await;
+                let mut st = state.lock().unwrap();
+                let lost = match renewed {
+                    Ok(true) => {
+                        st.record = Some(next);
+                        st.valid_until = Some(sent_at + options.ttl);
+                        false
+                    }
+                    Ok(false) => true,
+                    // indeterminate; keep trying while the lease is still valid
+                    Err(_) => st.valid_until.is_none_or(|until| ctx.now() >= until),
+                };
+                if lost {
+                    st.record = None;
+                    st.valid_until = None;
+                    drop(st);
+                    emit(LeaseEvent::Lost { name: name.clone() });
+                }
+            }
+            None => {
+                let now = ctx.now();
+                let claim = match kv.read::<LeaseRecord>(&name).await {
+                    Ok(None) => Some((LeaseRecord { holder: None, beat: 0 }, true)),
+                    Ok(Some(record)) if record.holder.is_none() || record.holder == me => Some((record, false)),
+                    Ok(Some(record)) => match &observed {
+                        Some((seen, since)) if *seen == record => {
+                            (now.duration_since(*since) >= options.ttl).then_some((record, false))
+                        }
+                        _ => {
+                            observed = Some((record, now));
+                            None
+                        }
+                    },
+                    Err(_) => None,
+                };
+
+                if let Some((from, create)) = claim {
+                    let next = LeaseRecord {
+                        holder: me.clone(),
+                        beat: from.beat + 1,
+                    };
+                    let sent_at = ctx.now();
+                    if let Ok(true) = kv.cas(&name, from, next.clone(), create).await {
+                        {
+                            let mut st = state.lock().unwrap();
+                            st.record = Some(next);
+                            st.valid_until = Some(sent_at + options.ttl);
+                        }
+                        observed = None;
+                        emit(LeaseEvent::Acquired { name: name.clone() });
+                    }
+                }
+            }
+        }
+        ctx.sleep(options.renew_interval).await;
+    }
+}
@@ -1,8 +1,76 @@
+pub mod annotate;
+pub mod anti_entropy;
+pub mod backoff;
+pub mod capture;
+pub mod chaos;
+pub mod client;
+pub mod codec;
+pub mod collections;
+pub mod config;
+#[cfg(feature = "compression")]
+pub mod compress;
+pub mod deadline;
+pub mod dyn_node;
+pub mod emulate;
+pub mod epoch;
+pub mod error;
+pub mod fairness;
+pub mod fanout;
+pub mod flavor;
+pub mod gossip;
+pub mod handshake;
+pub mod kv;
+pub mod lease;
+pub mod link;
+pub mod lock;
+pub mod misroute;
+mod output;
+pub mod prepared;
+#[cfg(feature = "profile")]
+pub mod profile;
+pub mod rate;
+pub mod replication;
+mod replies;
+mod rpc;
+pub mod selftest;
+pub mod services;
+pub mod signal;
+pub mod sim;
+pub mod sizes;
+pub mod stall;
+pub mod state;
+pub mod supervise;
+pub mod time;
+pub mod timer;
+pub mod wal;
+
 use anyhow::Context;
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
-use std::io::{StdoutLock, Write};
-use tokio::io::{AsyncBufReadExt, BufReader};
-use std::sync::{Arc, Mutex};
+use capture::{Direction, Tap};
+use chaos::{Chaos, ChaosLayer};
+use emulate::{Emulation, Emulator};
+use flavor::Flavor;
+use epoch::{Epochs, PeerRestarted};
+use error::{ErrorCode, MaelstromError};
+use link::Links;
+use lock::{Fence, FencingToken, StaleLeader};
+use misroute::Misrouted;
+use output::{Outbox, Output};
+use rate::{RateLimit, RateLimiter};
+use rpc::{PendingGuard, PendingReplies};
+use services::Services;
+use signal::{Signal, Signals};
+use supervise::Supervisor;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use time::{Clock, Hlc, HybridClock, TokioClock};
+use tokio::time::Instant;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, BufReader};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Message<Payload> {
@@ -22,6 +90,47 @@ pub struct Body<Payload> {
 }
 
 impl<Payload> Message<Payload> {
+    /// The reply to this request, carrying `payload` and a msg_id from
+    /// `ctx`. Nothing of the request's payload carries over, so a handler
+    /// can't send the request back by forgetting to replace it, as it can
+    /// with [`Message::into_reply`]. To take the request's payload apart
+    /// first, see [`Message::take_payload`].
+    pub fn reply_with<Reply>(self, payload: Reply, ctx: &Ctx) -> Message<Reply> {
+        Message {
+            src: self.dst,
+            dst: self.src,
+            body: Body {
+                id: Some(ctx.next_msg_id()),
+                in_reply_to: self.body.id,
+                payload,
+            },
+        }
+    }
+
+    /// Splits the payload off, leaving what [`Message::reply_with`] needs to
+    /// answer the request:
+    ///
+    /// ```ignore
+    /// let (payload, request) = input.take_payload();
+    /// let reply = match payload {
+    ///     Payload::Echo { echo } => Payload::EchoOk { echo },
+    ///     _ => return Ok(()),
+    /// };
+    /// request.reply_with(reply, &ctx).send(&ctx)
+    /// ```
+    pub fn take_payload(self) -> (Payload, Message<()>) {
+        let request = Message {
+            src: self.src,
+            dst: self.dst,
+            body: Body {
+                id: self.body.id,
+                in_reply_to: self.body.in_reply_to,
+                payload: (),
+            },
+        };
+        (self.body.payload, request)
+    }
+
     pub fn into_reply(self, id: Option<&mut usize>) -> Self {
         Self {
             src: self.dst,
@@ -38,41 +147,470 @@ impl<Payload> Message<Payload> {
         }
     }
 
-    pub fn send_sync(&self, output: &mut StdoutLock<'_>) -> anyhow::Result<()>
+    pub fn send(&self, ctx: &Ctx) -> anyhow::Result<()>
     where
         Payload: Serialize,
     {
-        serde_json::to_writer(&mut *output, self).context("serialize response")?;
-        output.write_all(b"\n").context("newline")?;
-        Ok(())
+        self.send_sized(ctx).map(|_| ())
     }
 
-    pub fn send(&self, output: Arc<Mutex<std::io::Stdout>>) -> anyhow::Result<()>
+    /// Sends the message as [`Message::send`] does and returns the length of
+    /// the line written, for keeping count of bytes sent without
+    /// serializing the message twice.
+    pub fn send_sized(&self, ctx: &Ctx) -> anyhow::Result<usize>
     where
         Payload: Serialize,
     {
-        let mut guard = output.lock().unwrap();
-        let out = guard.by_ref();
-        serde_json::to_writer(&mut *out, self).context("serialize response")?;
-        out.write_all(b"\n").context("newline")?;
-        drop(guard);
+        let line = self.encode(ctx, None).context("serialize response")?;
+        let len = line.len();
+        ctx.output.write_line(&self.dst, line)?;
+        Ok(len)
+    }
+
+    /// Sends the message with `annotation` in its body when
+    /// [`Runtime::annotate`] is on, and as [`Message::send`] would otherwise,
+    /// without calling `annotation`; see [`annotate`].
+    pub fn send_annotated(&self, ctx: &Ctx, annotation: impl FnOnce() -> serde_json::Value) -> anyhow::Result<()>
+    where
+        Payload: Serialize,
+    {
+        if !ctx.annotate {
+            return self.send(ctx);
+        }
+        let line = self.encode(ctx, Some(annotation())).context("serialize response")?;
+        ctx.output.write_line(&self.dst, line)
+    }
+
+    /// Answers this request with `err`; does nothing if it isn't a request,
+    /// i.e. carries no msg_id. See also [`bail_with_error_reply!`].
+    pub fn reply_error(&self, err: MaelstromError, ctx: &Ctx) -> anyhow::Result<()> {
+        let Some(msg_id) = self.body.id else {
+            return Ok(());
+        };
+        let reply = Message {
+            src: self.dst.clone(),
+            dst: self.src.clone(),
+            body: Body {
+                id: Some(ctx.next_msg_id()),
+                in_reply_to: Some(msg_id),
+                payload: SystemPayload::Error(err),
+            },
+        };
+        reply.send(ctx).with_context(|| format!("reply error to {}", self.src))
+    }
+
+    /// Queues the message in the current handler's outbox instead of sending
+    /// it right away. Staged messages are sent, in order, only if the handler
+    /// (or `from_init`) returns `Ok`, and dropped otherwise, so a handler that
+    /// fails halfway through a state change never leaves a partial set of
+    /// messages behind. Fails if called after the handler has returned, e.g.
+    /// from a task it spawned.
+    pub fn stage(&self, ctx: &Ctx) -> anyhow::Result<()>
+    where
+        Payload: Serialize,
+    {
+        let line = self.encode(ctx, None).context("serialize message")?;
+        match &ctx.outbox {
+            Some(outbox) => outbox.stage(&self.dst, line),
+            None => anyhow::bail!("no outbox outside of a handler"),
+        }
+    }
+
+    /// The message as a line of JSON, with whatever [`Ctx::stamp`] puts on
+    /// it and `annotation`, which goes on whatever the destination.
+    fn encode(&self, ctx: &Ctx, annotation: Option<serde_json::Value>) -> serde_json::Result<Vec<u8>>
+    where
+        Payload: Serialize,
+    {
+        let request = self.body.in_reply_to.is_none();
+        let mut line = if annotation.is_some() || ctx.stamps(&self.dst, request) {
+            let mut msg = serde_json::to_value(self)?;
+            ctx.stamp(&mut msg, request)?;
+            if let Some(annotation) = annotation {
+                annotate::stamp(&mut msg, annotation);
+            }
+            serde_json::to_vec(&msg)?
+        } else {
+            serde_json::to_vec(self)?
+        };
+        line.push(b'\n');
+        Ok(line)
+    }
+}
+
+/// A lock-free counter handing out ids, e.g. msg_ids or the sequence part of
+/// generated unique ids. Every id is handed out once.
+#[derive(Debug)]
+pub struct IdAllocator {
+    next: AtomicU64,
+}
+
+impl IdAllocator {
+    /// An allocator whose first id is `first`.
+    pub fn new(first: u64) -> Self {
+        Self {
+            next: AtomicU64::new(first),
+        }
+    }
+
+    pub fn next(&self) -> u64 {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+impl Default for IdAllocator {
+    /// Starts at 1, leaving 0 to the runtime's `init_ok`.
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+/// Runtime services handed to a node: the outbound message stream, the clock
+/// and RPCs to other nodes and services. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Ctx {
+    output: Output,
+    clock: Arc<dyn Clock>,
+    hlc: Arc<HybridClock>,
+    stamp_hlc: bool,
+    node_id: String,
+    msg_ids: Arc<IdAllocator>,
+    pending: Arc<PendingReplies>,
+    links: Arc<Links>,
+    neighbors: Arc<std::sync::Mutex<Vec<String>>>,
+    fence: Arc<Fence>,
+    fencing_token: Option<FencingToken>,
+    deadline: Option<Instant>,
+    stamp_budget: bool,
+    /// Whether [`Message::send_annotated`] annotates.
+    annotate: bool,
+    outbox: Option<Arc<Outbox>>,
+    supervisor: Arc<Supervisor>,
+    services: Arc<Services>,
+    epochs: Arc<Epochs>,
+    ready: Arc<tokio::sync::watch::Sender<bool>>,
+}
+
+impl Ctx {
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Releases the messages [`Runtime::park_until_ready`] held back, in the
+    /// order they arrived, and stops holding any more. Idempotent.
+    pub fn ready(&self) {
+        self.ready.send_replace(true);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        *self.ready.borrow()
+    }
+
+    /// This node's neighbours in the most recent `topology` message; empty
+    /// until one arrives.
+    pub fn neighbors(&self) -> Vec<String> {
+        self.neighbors.lock().unwrap().clone()
+    }
+
+    /// A copy of this context that stamps `token` on every message it sends
+    /// to another node, including RPCs and replies. Receivers that have seen
+    /// a newer token reject the messages; see [`lock`].
+    pub fn fenced(&self, token: FencingToken) -> Self {
+        Self {
+            fencing_token: Some(token),
+            ..self.clone()
+        }
+    }
+
+    /// When the operation this context serves has to be answered by, if it
+    /// has a deadline; see [`deadline`].
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// How long until [`Ctx::deadline`], zero once it has passed.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| deadline.saturating_duration_since(self.now()))
+    }
+
+    /// A copy of this context whose deadline is `budget` from now, unless
+    /// it already has an earlier one.
+    pub fn with_budget(&self, budget: Duration) -> Self {
+        let deadline = self.now() + budget;
+        Self {
+            deadline: Some(self.deadline.map_or(deadline, |earlier| earlier.min(deadline))),
+            ..self.clone()
+        }
+    }
+
+    /// A copy of this context with a `1/parts` share of the time left, for
+    /// the first of `parts` steps taken one after another. Call it again for
+    /// each step, with the steps left, so time one step doesn't use passes to
+    /// the rest. The same context if it has no deadline.
+    pub fn share(&self, parts: usize) -> Self {
+        match self.remaining() {
+            Some(remaining) => self.with_budget(remaining / parts.max(1) as u32),
+            None => self.clone(),
+        }
+    }
+
+    /// Runs `operation` until it completes or the deadline passes, whichever
+    /// comes first, failing with [`deadline::exceeded`] in the latter case.
+    /// For waits that aren't RPCs, which respect the deadline by themselves.
+    pub async fn within<T>(&self, operation: impl std::future::Future<Output = anyhow::Result<T>>) -> anyhow::Result<T> {
+        let Some(remaining) = self.remaining() else {
+            return operation.await;
+        };
+        tokio::select! {
+            result = operation => result,
+            _ = self.sleep(remaining) => Err(deadline::exceeded("waiting on the operation").into()),
+        }
+    }
+
+    /// The highest fencing token this node has accepted from its peers.
+    /// A node that becomes primary can [`admit`](Fence::admit) its own token
+    /// here to also turn away messages from its predecessors.
+    pub fn fence(&self) -> &Fence {
+        &self.fence
+    }
+
+    /// Allocates a msg_id from the counter shared with [`Ctx::rpc`].
+    pub fn next_msg_id(&self) -> usize {
+        self.msg_ids.next() as usize
+    }
+
+    /// Whether [`Ctx::stamp`] has anything to put on a message to `dst`.
+    pub(crate) fn stamps(&self, dst: &str, request: bool) -> bool {
+        is_node_id(dst)
+            && (self.fencing_token.is_some()
+                || self.stamp_hlc
+                || self.epochs.own().is_some()
+                || (request && self.stamp_budget && self.deadline.is_some()))
+    }
+
+    /// Stamps `msg`, if it goes to another node, with this context's fencing
+    /// token if it has one, with a send timestamp if [`Runtime::hlc`] is on,
+    /// with the node's epoch once [`Runtime::epochs`] has bumped it and, if
+    /// it is a `request` under a deadline, with the budget left if
+    /// [`Runtime::propagate_deadlines`] is on.
+    pub(crate) fn stamp(&self, msg: &mut serde_json::Value, request: bool) -> serde_json::Result<()> {
+        if !msg["dest"].as_str().is_some_and(is_node_id) {
+            return Ok(());
+        }
+        if let Some(token) = self.fencing_token {
+            lock::stamp(msg, token);
+        }
+        if let Some(epoch) = self.epochs.own() {
+            epoch::stamp(msg, epoch);
+        }
+        if self.stamp_hlc {
+            msg["body"]["hlc"] = serde_json::to_value(self.hlc.tick())?;
+        }
+        if let Some(budget) = self.remaining().filter(|_| request && self.stamp_budget) {
+            deadline::stamp(msg, budget);
+        }
         Ok(())
     }
+
+    /// Sends `payload` to `dst` and waits for its reply. An `error` reply is
+    /// returned as a [`MaelstromError`].
+    pub async fn rpc<Req, Resp>(&self, dst: &str, payload: Req) -> anyhow::Result<Resp>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+    {
+        self.rpc_inner(dst, payload, None).await
+    }
+
+    /// Like [`Ctx::rpc`], but gives up after `timeout` with a
+    /// [`ErrorCode::TIMEOUT`] error. Both give up at the context's
+    /// [`deadline`](Ctx::deadline) if that comes first.
+    pub async fn rpc_timeout<Req, Resp>(&self, dst: &str, payload: Req, timeout: Duration) -> anyhow::Result<Resp>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+    {
+        self.rpc_inner(dst, payload, Some(timeout)).await
+    }
+
+    async fn rpc_inner<Req, Resp>(&self, dst: &str, payload: Req, timeout: Option<Duration>) -> anyhow::Result<Resp>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+    {
+        let remaining = self.remaining();
+        if remaining == Some(Duration::ZERO) {
+            return Err(deadline::exceeded(format!("before rpc to {}", dst)).into());
+        }
+        let timeout = match (timeout, remaining) {
+            (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+            (timeout, remaining) => timeout.or(remaining),
+        };
+        let msg_id = self.next_msg_id();
+        let rx = self.pending.register(dst, msg_id);
+        // stop waiting for the reply however this returns, including the
+        // caller dropping the future
+        let _registered = PendingGuard {
+            pending: &self.pending,
+            dst,
+            msg_id,
+        };
+        let request = Message {
+            src: self.node_id.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: Some(msg_id),
+                in_reply_to: None,
+                payload,
+            },
+        };
+        if let Err(e) = request.send(self) {
+            return Err(e).with_context(|| format!("send rpc to {}", dst));
+        }
+
+        let reply = match timeout {
+            None => rx.await.context("rpc reply dropped")?,
+            Some(timeout) => tokio::select! {
+                reply = rx => reply.context("rpc reply dropped")?,
+                _ = self.sleep(timeout) => {
+                    if remaining.is_some_and(|remaining| remaining <= timeout) {
+                        return Err(deadline::exceeded(format!("waiting on rpc to {}", dst)).into());
+                    }
+                    return Err(MaelstromError::new(ErrorCode::TIMEOUT, format!("rpc to {} timed out", dst)).into());
+                }
+            },
+        };
+
+        if reply["body"]["type"] == "error" {
+            let err: MaelstromError =
+                serde_json::from_value(reply["body"].clone()).context("parse error reply")?;
+            return Err(err.into());
+        }
+        let reply: Message<Resp> = serde_json::from_value(reply)
+            .with_context(|| format!("unexpected reply from {}", dst))?;
+        Ok(reply.body.payload)
+    }
+
+    /// Waits until every RPC this node issued before the call has been
+    /// answered or has timed out. RPCs without a timeout are waited for until
+    /// they are answered.
+    pub async fn settle(&self) {
+        self.pending.settle().await
+    }
+
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    pub fn now(&self) -> tokio::time::Instant {
+        self.clock.now()
+    }
+
+    pub fn sleep(&self, duration: Duration) -> time::Sleep {
+        self.clock.sleep(duration)
+    }
+
+    /// A hybrid logical clock timestamp for an event happening now. Greater
+    /// than every timestamp this node has handed out or received before; see
+    /// [`Runtime::hlc`].
+    pub fn now_hlc(&self) -> Hlc {
+        self.hlc.tick()
+    }
+
+    /// Moves the hybrid logical clock past `remote`, a timestamp that arrived
+    /// some other way than the runtime's stamping (e.g. in a payload or a KV
+    /// value), and returns the timestamp of receiving it.
+    pub fn observe_hlc(&self, remote: Hlc) -> Hlc {
+        self.hlc.observe(remote)
+    }
+
+    /// The sizes of the messages this node has sent, by payload type; see
+    /// [`sizes`].
+    pub fn message_sizes(&self) -> std::collections::BTreeMap<String, sizes::SizeStats> {
+        self.output.sizes()
+    }
+
+    /// How often and how long writing to the output has stalled; see
+    /// [`stall`].
+    pub fn write_stalls(&self) -> stall::StallStats {
+        self.output.stalls()
+    }
+
+    /// Waits until every message this node sent before the call has been
+    /// written out, including ones held back in per-destination queues. A
+    /// barrier for protocols that must not act before their messages are on
+    /// the wire.
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        self.output.flush(None).await
+    }
+
+    /// Like [`Ctx::flush`], but only waits for messages to `dst`.
+    pub async fn flush_to(&self, dst: &str) -> anyhow::Result<()> {
+        self.output.flush(Some(dst)).await
+    }
+
+    /// A copy of this context with a fresh outbox for one handler invocation.
+    fn with_outbox(&self) -> (Self, Arc<Outbox>) {
+        let outbox = Arc::new(Outbox::new());
+        let ctx = Self {
+            outbox: Some(outbox.clone()),
+            ..self.clone()
+        };
+        (ctx, outbox)
+    }
 }
 
 pub enum Event<Payload, ServicePayload = (), InjectedPayload = ()> {
     Message(Message<Payload>),
     ServiceMessage(Message<ServicePayload>),
     Injected(InjectedPayload),
+    /// A `topology` message was received. The runtime has already replied
+    /// to it and updated [`Ctx::neighbors`]; carries the new neighbours.
+    TopologyChanged(Vec<String>),
+    /// A message from another node carried a superseded fencing token and
+    /// was rejected; see [`lock`].
+    StaleLeader(StaleLeader),
+    /// A message from another node carried a higher epoch than that node's
+    /// earlier messages: it restarted. Comes before the message itself; see
+    /// [`epoch`].
+    PeerRestarted(PeerRestarted),
+    /// The process received a signal; see [`signal`]. After
+    /// [`Signal::Terminate`], the input is treated as ended.
+    Signal(Signal),
     EOF,
 }
 
+/// Injected payloads the runtime may collapse while they wait to be handled.
+///
+/// When an [`Event::Injected`] is taken off the queue, every payload still
+/// queued behind it that it [`coalesces`](Coalescible::coalesces) with is
+/// dropped, so a node that falls behind on, say, periodic gossip ticks
+/// handles one tick rather than a burst of them. Other events keep their
+/// order.
+pub trait Coalescible {
+    /// Whether handling `self` makes the later `queued` payload redundant.
+    /// Nothing is coalesced unless overridden.
+    fn coalesces(&self, queued: &Self) -> bool {
+        let _ = queued;
+        false
+    }
+}
+
+impl Coalescible for () {}
+
+/// Messages defined by Maelstrom itself rather than by a workload. The
+/// runtime tries these before the node's payload types and answers `init`
+/// and `topology` on the node's behalf, so node payload enums only need
+/// their workload's variants.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
-enum InitPayload {
+pub enum SystemPayload {
     Init(Init),
     InitOk,
+    Topology { topology: HashMap<String, Vec<String>> },
+    TopologyOk,
+    Error(MaelstromError),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -81,114 +619,1122 @@ pub struct Init {
     pub node_ids: Vec<String>,
 }
 
-#[allow(async_fn_in_trait)]
 pub trait Node<S, Payload, ServicePayload = (), InjectedPayload = ()>: Send + Sync {
-    async fn from_init(
+    fn from_init(
         state: S,
         init: Init,
         inject: tokio::sync::mpsc::UnboundedSender<Event<Payload, ServicePayload, InjectedPayload>>,
-        output: &mut StdoutLock,
-    ) -> anyhow::Result<Self>
+        ctx: &Ctx,
+    ) -> impl std::future::Future<Output = anyhow::Result<Self>> + std::marker::Send
     where
         Self: Sized;
 
     fn step(
-        &self,  // Changed from &mut self to &self
+        &self,
         input: Event<Payload, ServicePayload, InjectedPayload>,
-        output: Arc<std::sync::Mutex<std::io::Stdout>>,
+        ctx: Ctx,
     ) -> impl std::future::Future<Output = anyhow::Result<()>> + std::marker::Send;
+
+    /// Called for every `init` after the first, e.g. when Maelstrom restarts
+    /// the node. `init_ok` is sent if this returns `Ok` and an error reply
+    /// otherwise. An `init` naming a different node id is rejected without
+    /// calling this. Rejects re-initialization with
+    /// [`ErrorCode::NOT_SUPPORTED`] unless overridden.
+    fn on_reinit(
+        &self,
+        init: Init,
+        ctx: Ctx,
+    ) -> impl std::future::Future<Output = anyhow::Result<()>> + std::marker::Send {
+        let _ = (init, ctx);
+        async {
+            Err(MaelstromError::new(ErrorCode::NOT_SUPPORTED, "node does not support re-initialization").into())
+        }
+    }
+
+    /// Rebuilds what the node persisted before it was restarted, e.g. by
+    /// replaying a [`Wal`](wal::Wal) opened in `from_init` and fetching what
+    /// it lacks from peers. Runs once, right after `from_init`. Until it
+    /// returns, client messages are held back and then handed to `step` in
+    /// the order they arrived; replies to its RPCs, service messages and
+    /// messages from other nodes are delivered as usual. An error stops the
+    /// node. Does nothing unless overridden.
+    fn recover(&self, ctx: Ctx) -> impl std::future::Future<Output = anyhow::Result<()>> + std::marker::Send {
+        let _ = ctx;
+        async { Ok(()) }
+    }
+
+    /// Background work such as compaction, run when the node has nothing
+    /// else to do if [`Runtime::idle`] is set. Messages that arrive while it
+    /// runs are handled concurrently, as with any other handler. Does nothing
+    /// unless overridden.
+    fn on_idle(&self, ctx: Ctx) -> impl std::future::Future<Output = anyhow::Result<()>> + std::marker::Send {
+        let _ = ctx;
+        async { Ok(()) }
+    }
 }
 
+/// Runs a node over the process' stdin and stdout with the runtime
+/// [`Runtime::from_env`] configures.
 pub async fn main_loop<S, N, P, SP, IP>(init_state: S) -> anyhow::Result<()>
 where
     P: DeserializeOwned + Send + 'static,
     SP: DeserializeOwned + Send + 'static,
     N: Node<S, P, SP, IP> + Send + 'static,
-    IP: Send + 'static,
+    IP: Coalescible + Send + 'static,
 {
-    let stdin = tokio::io::stdin();
-    let mut stdin = BufReader::new(stdin).lines();
-    let mut stdout = std::io::stdout().lock();
+    Runtime::from_env()?.run::<S, N, P, SP, IP>(init_state).await
+}
 
-    let init_msg: Message<InitPayload> =
-        serde_json::from_str(&stdin.next_line().await?.expect("no init msg"))
-            .context("init could not be deserialised")?;
+/// Runs a node over an arbitrary input and output with the default runtime.
+/// See [`Runtime::run_with_io`].
+pub async fn main_loop_with_io<S, N, P, SP, IP>(
+    init_state: S,
+    input: impl AsyncBufRead + Unpin + Send + 'static,
+    output: impl AsyncWrite + Unpin + Send + 'static,
+) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + Send + 'static,
+    SP: DeserializeOwned + Send + 'static,
+    N: Node<S, P, SP, IP> + Send + 'static,
+    IP: Coalescible + Send + 'static,
+{
+    Runtime::new()
+        .run_with_io::<S, N, P, SP, IP>(init_state, input, output)
+        .await
+}
 
-    let InitPayload::Init(init) = init_msg.body.payload else {
-        panic!("first msg shoulb be init");
-    };
+/// Environment variable that turns on [`Runtime::strict`] for
+/// [`Runtime::from_env`] when set to anything but `0`.
+pub const STRICT_ENV: &str = "DIST_SYS_STRICT";
 
-    let reply = Message {
-        src: init_msg.dst,
-        dst: init_msg.src,
-        body: Body {
-            id: Some(0),
-            in_reply_to: init_msg.body.id,
-            payload: InitPayload::InitOk,
-        },
-    };
-    serde_json::to_writer(&mut stdout, &reply).context("serialize response")?;
-    stdout.write_all(b"\n").context("newline")?;
+/// Configures and drives a node.
+#[derive(Debug, Clone)]
+pub struct Runtime {
+    clock: Arc<dyn Clock>,
+    chaos: Option<Chaos>,
+    rate_limit: Option<RateLimit>,
+    #[cfg(feature = "compression")]
+    compression: Option<compress::Compression>,
+    emulation: Option<Emulation>,
+    limits: HashMap<String, usize>,
+    idle_interval: Option<Duration>,
+    hlc: bool,
+    epochs: bool,
+    client_budget: Option<Duration>,
+    propagate_deadlines: bool,
+    size_warning: usize,
+    write_stall: (Duration, usize),
+    event_budget: usize,
+    flavor: Flavor,
+    #[cfg(feature = "profile")]
+    profile: Option<(Duration, usize)>,
+    misrouted: Misrouted,
+    reply_cache: Option<Duration>,
+    signals: bool,
+    capture: Option<PathBuf>,
+    strict: bool,
+    annotate: bool,
+    default_services: Services,
+    services: Services,
+    park: Option<Park>,
+    loopback: bool,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self {
+            clock: Arc::new(TokioClock),
+            chaos: None,
+            rate_limit: None,
+            #[cfg(feature = "compression")]
+            compression: None,
+            emulation: None,
+            limits: HashMap::new(),
+            idle_interval: None,
+            hlc: false,
+            epochs: false,
+            client_budget: None,
+            propagate_deadlines: false,
+            size_warning: sizes::DEFAULT_WARNING,
+            write_stall: (stall::DEFAULT_THRESHOLD, stall::DEFAULT_BUFFER),
+            event_budget: fairness::DEFAULT_BUDGET,
+            flavor: Flavor::default(),
+            #[cfg(feature = "profile")]
+            profile: None,
+            misrouted: Misrouted::default(),
+            reply_cache: None,
+            signals: false,
+            capture: None,
+            strict: false,
+            annotate: false,
+            default_services: Services::default(),
+            services: Services::default(),
+            park: None,
+            loopback: false,
+        }
+    }
+}
 
-    drop(stdin);
+impl Runtime {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    /// A runtime configured by the process' arguments and environment, as
+    /// [`Runtime::from_config`] would be; for node binaries. Fails if they
+    /// don't parse.
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self::from_config(&config::NodeConfig::from_env_and_args()?))
+    }
 
-    let node: N = N::from_init(init_state, init, tx.clone(), &mut stdout)
-        .await
-        .context("node initialization failed")?;
-
-    let jh = tokio::spawn(async move {
-        let stdin = tokio::io::stdin();
-        let mut stdin= BufReader::new(stdin).lines();
-        while let Some(line) = stdin.next_line().await? {
-            // Parse the JSON to extract src field for context
-            let raw_value: serde_json::Value =
-                serde_json::from_str(&line).context("input could not be parsed as JSON")?;
-
-            let src = raw_value.get("src").and_then(|v| v.as_str()).unwrap_or("");
-
-            // Check if source is not a client (clients typically start with 'c' like c1, c2, etc.)
-            let is_client = (src.starts_with('c') || src.starts_with('n'))
-                && src.chars().skip(1).all(|c| c.is_ascii_digit());
-
-            // If not from a client, try service message first
-            if is_client {
-                if let Ok(node_msg) = serde_json::from_str::<Message<P>>(&line) {
-                    if let Err(_) = tx.send(Event::Message(node_msg)) {
+    /// A runtime that maps services as the `service-<role>` knobs in
+    /// `config` say (see [`services`]), with the `emulate`, `capture`,
+    /// `strict` and `annotate` knobs standing in for the builder methods of
+    /// those names; from the environment, they are [`emulate::EMULATE_ENV`],
+    /// [`capture::CAPTURE_ENV`], [`STRICT_ENV`] and [`annotate::ANNOTATE_ENV`]. Other
+    /// knobs are the node's own.
+    pub fn from_config(config: &config::NodeConfig) -> Self {
+        Self {
+            emulation: config
+                .get::<String>("emulate")
+                .ok()
+                .flatten()
+                .and_then(|spec| Emulation::from_spec(&spec)),
+            capture: config.get("capture").ok().flatten(),
+            strict: config.flag("strict"),
+            annotate: config.flag("annotate"),
+            services: Services::from_config(config),
+            ..Self::default()
+        }
+    }
+
+    /// Replaces the clock handed to nodes through their [`Ctx`], e.g. with
+    /// a [`time::ManualClock`] so tests can step through timers deterministically.
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Injects drops, delays and duplicates into messages to other nodes. Off
+    /// unless set.
+    pub fn chaos(mut self, chaos: Chaos) -> Self {
+        assert!(
+            chaos.drop_prob.is_finite() && chaos.dup_prob.is_finite(),
+            "chaos probabilities must be finite"
+        );
+        self.chaos = Some(chaos);
+        self
+    }
+
+    /// Caps the rate of messages to other nodes, queueing the excess. Off
+    /// unless set.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Negotiates compressed bodies with other nodes running this crate and
+    /// compresses large messages to those that accept them. Off unless set.
+    #[cfg(feature = "compression")]
+    pub fn compress(mut self, compression: compress::Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Answers requests to Maelstrom services in-process. Off unless set, or
+    /// for [`Runtime::from_env`], [`emulate::EMULATE_ENV`] names some, so
+    /// binaries can be run standalone without code changes.
+    pub fn emulate(mut self, emulation: Emulation) -> Self {
+        self.emulation = Some(emulation);
+        self
+    }
+
+    /// Runs at most `max` handlers for messages of type `kind` at a time;
+    /// further ones wait for a slot before [`Node::step`] is called. A limit
+    /// of 1 makes the operation exclusive.
+    pub fn limit(mut self, kind: impl Into<String>, max: usize) -> Self {
+        assert!(max > 0, "limit must allow at least one handler");
+        self.limits.insert(kind.into(), max);
+        self
+    }
+
+    /// Calls [`Node::on_idle`] whenever no handler is running and no input
+    /// is waiting, at most once every `interval`. Off unless set.
+    pub fn idle(mut self, interval: Duration) -> Self {
+        self.idle_interval = Some(interval);
+        self
+    }
+
+    /// Stamps every message to another node with the sender's
+    /// [`Ctx::now_hlc`] in a `hlc` body field, and advances the hybrid
+    /// logical clock past the stamp of every message received, so timestamps
+    /// respect causality across nodes. All nodes must agree on this. Off
+    /// unless set, in which case the clock only orders this node's events.
+    pub fn hlc(mut self) -> Self {
+        self.hlc = true;
+        self
+    }
+
+    /// Bumps the node's epoch in `lin-kv` once it starts and stamps it on
+    /// every message to another node, so peers can tell when it restarts;
+    /// see [`epoch`].
+    pub fn epochs(mut self) -> Self {
+        self.epochs = true;
+        self
+    }
+
+    /// Gives the handler of every client request a [`Ctx::deadline`]
+    /// `budget` after the request arrived, e.g. a little under the client's
+    /// own timeout, so operations that overrun it are answered with a
+    /// timeout error; see [`deadline`]. Off unless set.
+    pub fn client_budget(mut self, budget: Duration) -> Self {
+        self.client_budget = Some(budget);
+        self
+    }
+
+    /// Passes what is left of a handler's deadline on with the requests it
+    /// sends to other nodes, whose handlers then work to the same deadline;
+    /// see [`deadline`]. All nodes must agree on this. Off unless set.
+    pub fn propagate_deadlines(mut self) -> Self {
+        self.propagate_deadlines = true;
+        self
+    }
+
+    /// Logs outbound messages larger than `bytes` that are also the largest
+    /// of their type so far; see [`sizes`]. Defaults to
+    /// [`sizes::DEFAULT_WARNING`].
+    pub fn message_size_warning(mut self, bytes: usize) -> Self {
+        self.size_warning = bytes;
+        self
+    }
+
+    /// Counts writes to the output that take longer than `threshold` as
+    /// stalls and, while one lasts, queues messages to other nodes only
+    /// while fewer than `buffer` bytes wait behind it; see [`stall`]. Defaults to
+    /// [`stall::DEFAULT_THRESHOLD`] and [`stall::DEFAULT_BUFFER`].
+    pub fn write_stall(mut self, threshold: Duration, buffer: usize) -> Self {
+        self.write_stall = (threshold, buffer);
+        self
+    }
+
+    /// Has the input reader and the loop starting handlers each yield to
+    /// the scheduler after `events` events in a row; see [`fairness`].
+    /// Defaults to [`fairness::DEFAULT_BUDGET`].
+    pub fn event_budget(mut self, events: usize) -> Self {
+        self.event_budget = events;
+        self
+    }
+
+    /// The kind of tokio runtime [`Runtime::start`] runs the node on, unless
+    /// the [`flavor::KNOB`] names another; see [`flavor`]. Defaults to
+    /// [`Flavor::MultiThread`].
+    pub fn flavor(mut self, flavor: Flavor) -> Self {
+        self.flavor = flavor;
+        self
+    }
+
+    /// Times every handler and, once handlers for one message type have
+    /// taken longer than `budget` `repeats` times in a row, reports them and
+    /// every handler still running to stderr; see [`profile`]. Off unless
+    /// set.
+    #[cfg(feature = "profile")]
+    pub fn profile(mut self, budget: Duration, repeats: usize) -> Self {
+        self.profile = Some((budget, repeats));
+        self
+    }
+
+    /// Delivers messages the node sends to itself, e.g. as a shard owner or
+    /// leader asking its own replica, straight back to its input instead of
+    /// through Maelstrom's network, saving the round trip. They skip chaos
+    /// and rate limits, as the network would never see them. Off unless set.
+    pub fn loopback(mut self) -> Self {
+        self.loopback = true;
+        self
+    }
+
+    /// What to do with messages whose `dest` is another node; see
+    /// [`misroute`]. They are dropped unless set.
+    pub fn misrouted(mut self, misrouted: Misrouted) -> Self {
+        self.misrouted = misrouted;
+        self
+    }
+
+    /// Answers a client request that arrives again within `ttl` of the first
+    /// time with the reply already sent, instead of handling it twice; see
+    /// [`replies`]. Off unless set.
+    pub fn reply_cache(mut self, ttl: Duration) -> Self {
+        self.reply_cache = Some(ttl);
+        self
+    }
+
+    /// Delivers `SIGTERM` and `SIGUSR1` to the node as [`Event::Signal`]s;
+    /// see [`signal`]. Always on for [`Runtime::run`], off otherwise unless
+    /// set.
+    pub fn signals(mut self) -> Self {
+        self.signals = true;
+        self
+    }
+
+    /// Answers a request that matches none of the node's payload types with
+    /// an [`ErrorCode::MALFORMED_REQUEST`] error naming the parse failure,
+    /// rather than only logging it and leaving the client to time out. Off
+    /// unless set, or for [`Runtime::from_env`], [`STRICT_ENV`] asks for it.
+    ///
+    /// A line that isn't JSON at all names no one to answer, so it is logged
+    /// and skipped either way.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Lets [`Message::send_annotated`] add its notes to the messages it
+    /// sends; see [`annotate`]. Off unless set, or for [`Runtime::from_env`],
+    /// [`annotate::ANNOTATE_ENV`] asks for it.
+    pub fn annotate(mut self) -> Self {
+        self.annotate = true;
+        self
+    }
+
+    /// Maps `role` to `service` unless the `init` message or a knob maps it;
+    /// see [`services`].
+    pub fn default_service(mut self, role: impl Into<String>, service: impl Into<String>) -> Self {
+        self.default_services = self.default_services.with(role, service);
+        self
+    }
+
+    /// Holds back messages for which `park` returns true, e.g. client
+    /// requests that arrive before the node knows its topology or leader,
+    /// until the node calls [`Ctx::ready`], then hands them to the node in
+    /// the order they arrived. `park` sees each message for the node as
+    /// JSON; replies to RPCs and service messages are never held. Off unless
+    /// set.
+    pub fn park_until_ready(mut self, park: impl Fn(&serde_json::Value) -> bool + Send + Sync + 'static) -> Self {
+        self.park = Some(Park(Arc::new(park)));
+        self
+    }
+
+    /// Copies every message read or written to a file at `path`, with the
+    /// node's id filled in; see [`capture`]. Off unless set, or for
+    /// [`Runtime::from_env`], [`capture::CAPTURE_ENV`] names a path.
+    pub fn capture(mut self, path: impl Into<PathBuf>) -> Self {
+        self.capture = Some(path.into());
+        self
+    }
+
+    /// Runs a node over the process' stdin and stdout, reporting panics on
+    /// stderr as JSON. Exits the process with [`handshake::EXIT_CODE`] if
+    /// the input doesn't start with a valid `init`.
+    pub async fn run<S, N, P, SP, IP>(self, init_state: S) -> anyhow::Result<()>
+    where
+        P: DeserializeOwned + Send + 'static,
+        SP: DeserializeOwned + Send + 'static,
+        N: Node<S, P, SP, IP> + Send + 'static,
+        IP: Coalescible + Send + 'static,
+    {
+        install_panic_hook();
+        let result = self
+            .signals()
+            .run_with_io::<S, N, P, SP, IP>(init_state, BufReader::new(tokio::io::stdin()), tokio::io::stdout())
+            .await;
+        if let Err(e) = &result
+            && e.downcast_ref::<handshake::HandshakeError>().is_some()
+        {
+            eprintln!("{:#}", e);
+            std::process::exit(handshake::EXIT_CODE);
+        }
+        result
+    }
+
+    /// [`Runtime::run`] on a tokio runtime of its own, of the kind
+    /// [`Runtime::flavor`] picks, for a `main` without `#[tokio::main]`.
+    /// Panics if called from within a tokio runtime.
+    pub fn start<S, N, P, SP, IP>(self, init_state: S) -> anyhow::Result<()>
+    where
+        P: DeserializeOwned + Send + 'static,
+        SP: DeserializeOwned + Send + 'static,
+        N: Node<S, P, SP, IP> + Send + 'static,
+        IP: Coalescible + Send + 'static,
+    {
+        self.flavor.main(self.run::<S, N, P, SP, IP>(init_state))
+    }
+
+    /// Runs a node over an arbitrary line-oriented input and output, e.g.
+    /// in-memory pipes from `tokio::io::duplex` in tests.
+    ///
+    /// Returns once the input is exhausted, every handler has finished and the
+    /// output has been flushed and shut down. A handler that panics or fails
+    /// ends the run early with an error, after replying
+    /// [`ErrorCode::CRASH`] to the request it was handling.
+    pub async fn run_with_io<S, N, P, SP, IP>(
+        self,
+        init_state: S,
+        input: impl AsyncBufRead + Unpin + Send + 'static,
+        output: impl AsyncWrite + Unpin + Send + 'static,
+    ) -> anyhow::Result<()>
+    where
+        P: DeserializeOwned + Send + 'static,
+        SP: DeserializeOwned + Send + 'static,
+        N: Node<S, P, SP, IP> + Send + 'static,
+        IP: Coalescible + Send + 'static,
+    {
+        let mut stdin = input.lines();
+        // before anything else, so a signal never finds the process unguarded
+        let mut signals = Signals::new(self.signals).context("listen for signals")?;
+        let (loopback_tx, mut loopback_rx) = tokio::sync::mpsc::unbounded_channel();
+        let tap = match &self.capture {
+            Some(path) => Some(Arc::new(Tap::new(path.clone(), self.clock.clone()))),
+            None => None,
+        };
+        let (threshold, buffer) = self.write_stall;
+        let stalls = stall::Stalls::new(threshold, buffer);
+        let (mut output, writer) = Output::spawn(output, self.clock.clone(), loopback_tx, tap.clone(), stalls);
+        output.set_size_warning(self.size_warning);
+        let links = Links::new(&*self.clock);
+        let mut ctx = Ctx {
+            output,
+            hlc: Arc::new(HybridClock::new(self.clock.clone())),
+            stamp_hlc: self.hlc,
+            clock: self.clock,
+            node_id: String::new(),
+            msg_ids: Arc::new(IdAllocator::default()),
+            pending: Arc::default(),
+            links: Arc::new(links),
+            neighbors: Arc::default(),
+            fence: Arc::default(),
+            fencing_token: None,
+            deadline: None,
+            stamp_budget: self.propagate_deadlines,
+            annotate: self.annotate,
+            supervisor: Arc::default(),
+            services: Arc::default(),
+            epochs: Arc::default(),
+            ready: Arc::new(tokio::sync::watch::Sender::new(false)),
+            outbox: None,
+        };
+
+        let (init_msg, init, init_services) = match handshake::read_init(&mut stdin, tap.as_deref()).await {
+            Ok(handshake) => handshake,
+            Err(err) => {
+                // the error reply goes out before the node gives up
+                err.report(&ctx);
+                let _ = ctx.output.flush(None).await;
+                ctx.output.shutdown();
+                let _ = writer.await;
+                return Err(err.into());
+            }
+        };
+        ctx.node_id = init.node_id.clone();
+        if let Some(tap) = &tap {
+            tap.open(&ctx.node_id)?;
+        }
+        ctx.services = Arc::new(self.default_services.merge(init_services).merge(self.services));
+
+        let reply = Message {
+            src: init_msg.dst,
+            dst: init_msg.src,
+            body: Body {
+                id: Some(0),
+                in_reply_to: init_msg.body.id,
+                payload: SystemPayload::InitOk,
+            },
+        };
+        reply.send(&ctx).context("reply to init")?;
+
+        if self.loopback {
+            ctx.output.set_loopback(ctx.node_id.clone());
+        }
+        let reply_cache = self
+            .reply_cache
+            .map(|ttl| Arc::new(replies::ReplyCache::new(ttl, ctx.clock.clone())));
+        if let Some(reply_cache) = &reply_cache {
+            ctx.output.set_reply_cache(reply_cache.clone());
+        }
+        if let Some(chaos) = self.chaos {
+            ctx.output.set_chaos(ChaosLayer::new(chaos));
+        }
+        #[cfg(feature = "compression")]
+        if let Some(compression) = self.compression {
+            ctx.output.set_compressor(compress::Compressor::new(compression));
+        }
+        if let Some(limit) = self.rate_limit {
+            ctx.output.set_rate_limit(RateLimiter::new(limit, &*ctx.clock));
+        }
+        if let Some(emulation) = self.emulation {
+            ctx.output.set_emulator(Emulator::new(emulation), ctx.clock.clone());
+        }
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let (init_ctx, outbox) = ctx.with_outbox();
+        let node = N::from_init(init_state, init, tx, &init_ctx).await;
+        outbox.close(&ctx.output, node.is_ok())?;
+        let node: N = node.context("node initialization failed")?;
+
+        let pending = ctx.pending.clone();
+        let link_ctx = ctx.clone();
+        let (inbound_tx, mut inbound_rx) = tokio::sync::mpsc::unbounded_channel();
+        let strict = self.strict;
+        let misrouted = self.misrouted;
+        let epochs = self.epochs;
+        let client_budget = self.client_budget;
+        let propagate_deadlines = self.propagate_deadlines;
+        let park = self.park;
+        let event_budget = self.event_budget;
+        let mut ready = ctx.ready.subscribe();
+        let jh = tokio::spawn(async move {
+            // message types from peers that have been rejected, logged once each
+            let mut unknown_kinds = HashSet::new();
+            // messages held back until the node is ready, oldest first
+            let mut parked = VecDeque::new();
+            let mut turns = fairness::Turns::new(event_budget);
+            loop {
+                turns.take().await;
+                let line = tokio::select! {
+                    line = stdin.next_line() => match line? {
+                        Some(line) => {
+                            if let Some(tap) = &tap {
+                                tap.record(Direction::Recv, line.as_bytes());
+                            }
+                            line
+                        }
+                        None => break,
+                    },
+                    Some(line) = loopback_rx.recv() => line,
+                    signal = signals.recv() => {
+                        if inbound_tx.send(Inbound::Event(Event::Signal(signal), None, None)).is_err() {
+                            return Ok(());
+                        }
+                        match signal {
+                            Signal::Terminate => break,
+                            Signal::DumpStats => continue,
+                        }
+                    }
+                    _ = ready.wait_for(|ready| *ready), if !parked.is_empty() => {
+                        for inbound in parked.drain(..) {
+                            if inbound_tx.send(inbound).is_err() {
+                                return Ok(());
+                            }
+                        }
+                        continue;
+                    }
+                };
+                // as before init, e.g. from a harness padding its output
+                let trimmed = line.trim_start_matches('\u{feff}').trim();
+                if trimmed.is_empty() {
+                    continue;
+                }
+                // Parse the JSON to extract src field for context
+                let raw_value: serde_json::Value = match serde_json::from_str(trimmed) {
+                    Ok(raw_value) => raw_value,
+                    Err(e) => {
+                        // without the JSON there's no sender or msg_id to answer
+                        eprintln!("skipping input that isn't JSON ({}): {}", e, trimmed);
+                        continue;
+                    }
+                };
+
+                #[cfg(feature = "compression")]
+                let raw_value = link_ctx.output.decompress(raw_value)?;
+
+                // before anything, e.g. a reply resolving one of our RPCs,
+                // mistakes another node's message for ours
+                let Some(mut raw_value) = misroute::check(raw_value, misrouted, &link_ctx)? else {
+                    continue;
+                };
+                if link_ctx.stamp_hlc
+                    && raw_value["src"].as_str().is_some_and(is_node_id)
+                    && let Some(hlc) = raw_value["body"].as_object_mut().and_then(|body| body.remove("hlc"))
+                {
+                    link_ctx.hlc.observe(serde_json::from_value(hlc).context("parse hlc")?);
+                }
+                if epochs
+                    && let Some(restarted) = epoch::check(&mut raw_value, &link_ctx)?
+                    && inbound_tx.send(Inbound::Event(Event::PeerRestarted(restarted), None, None)).is_err()
+                {
+                    return Ok(());
+                }
+
+                // replies to Ctx::rpc calls go straight to the waiting caller
+                let Some(raw_value) = pending.resolve(raw_value) else {
+                    continue;
+                };
+                let raw_value = match lock::check(raw_value, &link_ctx)? {
+                    Ok(raw_value) => raw_value,
+                    Err(stale) => {
+                        if inbound_tx.send(Inbound::Event(Event::StaleLeader(stale), None, None)).is_err() {
+                            return Ok(());
+                        }
+                        continue;
+                    }
+                };
+                // reliable-link envelopes are acked and unwrapped once
+                let Some(mut raw_value) = link_ctx.links.accept(raw_value, &link_ctx)? else {
+                    continue;
+                };
+
+                // an error nobody's RPC claimed may still answer a request the
+                // node built by hand, so those are left to its payload types
+                if let Ok(system_msg) = Message::<SystemPayload>::deserialize(&raw_value)
+                    && !matches!(system_msg.body.payload, SystemPayload::Error(_))
+                {
+                    let inbound = match system_msg.body.payload {
+                        SystemPayload::Init(_) => Inbound::Reinit(system_msg),
+                        SystemPayload::Topology { .. } => {
+                            let neighbors = answer_topology(system_msg, &link_ctx)?;
+                            Inbound::Event(Event::TopologyChanged(neighbors), Some("topology".to_string()), None)
+                        }
+                        SystemPayload::InitOk | SystemPayload::TopologyOk | SystemPayload::Error(_) => continue,
+                    };
+                    if inbound_tx.send(inbound).is_err() {
+                        return Ok(());
+                    }
+                    continue;
+                }
+
+                let peer_budget = match propagate_deadlines && raw_value["src"].as_str().is_some_and(is_node_id) {
+                    true => deadline::take(&mut raw_value),
+                    false => None,
+                };
+                let src = raw_value.get("src").and_then(|v| v.as_str()).unwrap_or("");
+                let kind = raw_value["body"]["type"].as_str().map(str::to_string);
+
+                // Check if source is not a client (clients typically start with 'c' like c1, c2, etc.)
+                let is_client = (src.starts_with('c') || src.starts_with('n'))
+                    && src.chars().skip(1).all(|c| c.is_ascii_digit());
+
+                // If not from a client, try service message first
+                if is_client && let Ok(node_msg) = Message::<P>::deserialize(&raw_value) {
+                    if let Some(reply_cache) = &reply_cache
+                        && !is_node_id(src)
+                        && let Some(msg_id) = node_msg.body.id
+                    {
+                        match reply_cache.request(src, msg_id as u64) {
+                            replies::Seen::New => {}
+                            replies::Seen::InFlight => continue,
+                            replies::Seen::Answered(reply) => {
+                                link_ctx.output.write_line(src, reply)?;
+                                continue;
+                            }
+                        }
+                    }
+                    let budget = if is_node_id(src) { peer_budget } else { client_budget };
+                    let deadline = budget.map(|budget| link_ctx.now() + budget);
+                    let inbound = Inbound::Event(Event::Message(node_msg), kind, deadline);
+                    // once anything is held, later messages it would hold
+                    // queue behind it even if the node is now ready
+                    if let Some(park) = &park
+                        && (!parked.is_empty() || !*ready.borrow())
+                        && (park.0)(&raw_value)
+                    {
+                        parked.push_back(inbound);
+                        continue;
+                    }
+                    if inbound_tx.send(inbound).is_err() {
+                        return Ok::<_, anyhow::Error>(());
+                    };
+                    continue;
+                }
+
+                // a peer running a newer build may send types this one
+                // doesn't know; tell it so rather than leave it waiting, and
+                // never mistake it for a service reply
+                if is_node_id(src) {
+                    let kind = kind.unwrap_or_default();
+                    if unknown_kinds.insert(kind.clone()) {
+                        eprintln!("ignoring {:?} messages from other nodes: not a type this node handles", kind);
+                    }
+                    let err = MaelstromError::new(ErrorCode::NOT_SUPPORTED, format!("cannot handle {:?} messages", kind));
+                    reply_error(&raw_value, err, &link_ctx)?;
+                    continue;
+                }
+
+                // strict: a client request that isn't one of the node's
+                // payloads is rejected rather than tried as a service reply
+                if strict
+                    && is_client
+                    && let Err(e) = Message::<P>::deserialize(&raw_value)
+                {
+                    let text = format!("could not parse {} message: {}", kind.as_deref().unwrap_or("untyped"), e);
+                    eprintln!("Could not deserialize message from {}: {}", src, text);
+                    reply_error(&raw_value, MaelstromError::new(ErrorCode::MALFORMED_REQUEST, text), &link_ctx)?;
+                    continue;
+                }
+
+                if let Ok(service_msg) = Message::<SP>::deserialize(&raw_value) {
+                    if inbound_tx.send(Inbound::Event(Event::ServiceMessage(service_msg), kind, None)).is_err() {
                         return Ok::<_, anyhow::Error>(());
                     };
                     continue;
+                } else {
+                    eprintln!("Could not deserialize message from {}: {}", src, line);
                 }
             }
+            let _ = inbound_tx.send(Inbound::Event(Event::EOF, None, None));
+            Ok(())
+        });
+
+        if epochs {
+            epoch::spawn_bump(&ctx);
+        }
 
-            if let Ok(service_msg) = serde_json::from_str::<Message<SP>>(&line) {
-                if let Err(_) = tx.send(Event::ServiceMessage(service_msg)) {
-                    return Ok::<_, anyhow::Error>(());
+        #[cfg(feature = "profile")]
+        let profiler = self
+            .profile
+            .map(|(budget, repeats)| Arc::new(profile::Profiler::new(budget, repeats)));
+        let limits: HashMap<_, _> = self
+            .limits
+            .into_iter()
+            .map(|(kind, max)| (kind, Arc::new(Semaphore::new(max))))
+            .collect();
+        let node = std::sync::Arc::new(node);
+        let recovering = node.clone();
+        let recovery_ctx = ctx.clone();
+        let mut recovery = Some(tokio::spawn(async move { recovering.recover(recovery_ctx).await }));
+        // client messages held back until recovery is done, oldest first
+        let mut parked = VecDeque::new();
+        let mut handlers = JoinSet::new();
+        // the request each running handler is answering, if any
+        let mut in_flight = HashMap::new();
+        let mut crashed = None;
+        let mut last_idle = ctx.now();
+        // injected events taken off `rx` early to coalesce them
+        let mut backlog = VecDeque::new();
+        let mut input_ended = false;
+        let mut turns = fairness::Turns::new(self.event_budget);
+        loop {
+            turns.take().await;
+            let idle_in = self
+                .idle_interval
+                .map(|interval| interval.saturating_sub(ctx.now().saturating_duration_since(last_idle)));
+            let (input, kind, deadline) = tokio::select! {
+                inbound = inbound_rx.recv(), if !input_ended => match inbound {
+                    // supervised tasks would otherwise keep the node running
+                    None => {
+                        input_ended = true;
+                        ctx.supervisor.shutdown();
+                        continue;
+                    }
+                    Some(Inbound::Event(Event::Message(msg), kind, deadline))
+                        if (recovery.is_some() || !parked.is_empty()) && !is_node_id(&msg.src) =>
+                    {
+                        parked.push_back((Event::Message(msg), kind, deadline));
+                        continue;
+                    }
+                    Some(Inbound::Event(input, kind, deadline)) => (input, kind, deadline),
+                    Some(Inbound::Reinit(init_msg)) => {
+                        let (ctx_clone, outbox) = ctx.with_outbox();
+                        let output = ctx.output.clone();
+                        let node_clone = node.clone();
+                        let origin = init_msg.body.id.map(|id| (init_msg.src.clone(), id));
+                        let handler = handlers.spawn(async move {
+                            let result = reinit(&*node_clone, init_msg, ctx_clone).await;
+                            outbox.close(&output, result.is_ok()).unwrap();
+                            result.unwrap();
+                        });
+                        if let Some(origin) = origin {
+                            in_flight.insert(handler.id(), origin);
+                        }
+                        continue;
+                    }
+                },
+                Some(input) = next_injected(&mut rx, &mut backlog) => {
+                    if let Event::Injected(payload) = &input {
+                        while let Ok(queued) = rx.try_recv() {
+                            backlog.push_back(queued);
+                        }
+                        backlog.retain(|queued| !matches!(queued, Event::Injected(q) if payload.coalesces(q)));
+                    }
+                    (input, None, None)
+                }
+                recovered = async { recovery.as_mut().expect("recovery is running").await }, if recovery.is_some() => {
+                    recovery = None;
+                    let failed = match recovered {
+                        Ok(Ok(())) => continue,
+                        Ok(Err(e)) => e.context("recovery failed"),
+                        Err(e) => anyhow::anyhow!("recovery failed: {}", e),
+                    };
+                    for (input, ..) in parked.drain(..) {
+                        if let Event::Message(msg) = input {
+                            let request = serde_json::json!({"src": msg.src, "body": {"msg_id": msg.body.id}});
+                            let err = MaelstromError::new(ErrorCode::CRASH, format!("{:#}", failed));
+                            let _ = reply_error(&request, err, &ctx);
+                        }
+                    }
+                    crashed = Some(failed);
+                    break;
+                }
+                Some(parked) = async { parked.pop_front() }, if recovery.is_none() && !parked.is_empty() => parked,
+                Some(joined) = handlers.join_next_with_id(), if !handlers.is_empty() => {
+                    if let Err(e) = reap(joined, &mut in_flight, &ctx) {
+                        crashed = Some(e);
+                        break;
+                    }
+                    continue;
+                }
+                // only once the input is quiet, and never after it has ended
+                _ = ctx.sleep(idle_in.unwrap_or_default()),
+                    if idle_in.is_some()
+                        && handlers.is_empty()
+                        && recovery.is_none()
+                        && parked.is_empty()
+                        && inbound_rx.is_empty()
+                        && rx.is_empty()
+                        && backlog.is_empty()
+                        && !inbound_rx.is_closed() =>
+                {
+                    last_idle = ctx.now();
+                    let ctx_clone = ctx.clone();
+                    let node_clone = node.clone();
+                    handlers.spawn(async move {
+                        node_clone.on_idle(ctx_clone).await.unwrap();
+                    });
+                    continue;
+                }
+                else => break,
+            };
+            let limit = kind.as_ref().and_then(|kind| limits.get(kind).cloned());
+            #[cfg(feature = "profile")]
+            let profiler = profiler.clone().map(|profiler| (profiler, profile::label(&input, kind.as_deref())));
+            let (mut ctx_clone, outbox) = ctx.with_outbox();
+            ctx_clone.deadline = deadline;
+            let output = ctx.output.clone();
+            let node_clone = node.clone();
+            let origin = match &input {
+                Event::Message(msg) => msg.body.id.map(|id| (msg.src.clone(), id)),
+                _ => None,
+            };
+            let request = origin
+                .as_ref()
+                .map(|(src, msg_id)| serde_json::json!({"src": src, "body": {"msg_id": msg_id}}));
+            let handler = handlers.spawn(async move {
+                let _permit = match limit {
+                    Some(limit) => Some(limit.acquire_owned().await.expect("limit semaphore is never closed")),
+                    None => None,
+                };
+                let step = node_clone.step(input, ctx_clone.clone());
+                #[cfg(feature = "profile")]
+                let result = match profiler {
+                    Some((profiler, label)) => profiler.wrap(label, step).await,
+                    None => step.await,
                 };
-                continue;
-            } else {
-                eprintln!("Could not deserialize message from {}: {}", src, line);
+                #[cfg(not(feature = "profile"))]
+                let result = step.await;
+                outbox.close(&output, result.is_ok()).unwrap();
+                // running out of time is the client's problem, not a crash
+                if let Err(e) = &result
+                    && let Some(request) = &request
+                    && let Some(err) = deadline::expired(e, deadline)
+                {
+                    reply_error(request, err, &ctx_clone).unwrap();
+                    return;
+                }
+                result.unwrap();
+            });
+            if let Some(origin) = origin {
+                in_flight.insert(handler.id(), origin);
+            }
+        }
+        if crashed.is_none() {
+            while let Some(joined) = handlers.join_next_with_id().await {
+                if let Err(e) = reap(joined, &mut in_flight, &ctx) {
+                    crashed = Some(e);
+                    break;
+                }
             }
         }
-        let _ = tx.send(Event::EOF);
+        if let Some(e) = crashed {
+            // the crash reply is on its way; don't wait for anything else
+            handlers.abort_all();
+            ctx.supervisor.shutdown();
+            jh.abort();
+            let _ = ctx.output.flush(None).await;
+            ctx.output.shutdown();
+            let _ = writer.await;
+            return Err(e);
+        }
+
+        jh.await
+            .context("stdin task panicked")?
+            .context("stdin task err")?;
+
+        drop(node);
+        // let messages held back by rate limits or chaos delays go out; if
+        // the writer has failed, awaiting it below reports why
+        let _ = ctx.output.flush(None).await;
+        ctx.output.shutdown();
+        writer
+            .await
+            .context("output task panicked")?
+            .context("output task err")?;
+
         Ok(())
-    });
-    drop(stdout);
-
-    let stdout = Arc::new(Mutex::new(std::io::stdout()));
-    let node = Arc::new(node);
-    while let Some(input) = rx.recv().await {
-        let stdout_clone = stdout.clone();
-        let node_clone = node.clone();
-        tokio::spawn(async move {
-            node_clone.step(input, stdout_clone).await.unwrap();
-        });
     }
+}
+
+/// Whether `id` names a node (as opposed to a client or a service).
+pub(crate) fn is_node_id(id: &str) -> bool {
+    id.strip_prefix('n')
+        .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Which messages [`Runtime::park_until_ready`] holds back.
+#[derive(Clone)]
+struct Park(Arc<dyn Fn(&serde_json::Value) -> bool + Send + Sync>);
+
+impl std::fmt::Debug for Park {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Park(..)")
+    }
+}
+
+/// What the input task hands to the dispatch loop.
+enum Inbound<P, SP, IP> {
+    /// An event, the `type` of the message it carries, if any, and the
+    /// deadline for answering it, if it has one.
+    Event(Event<P, SP, IP>, Option<String>, Option<Instant>),
+    Reinit(Message<SystemPayload>),
+}
+
+/// Accounts for a finished handler. A handler that panicked (including by
+/// returning an error) takes the node down: the request it was answering,
+/// if any, gets a [`ErrorCode::CRASH`] reply, and the error is returned.
+fn reap(
+    joined: Result<(tokio::task::Id, ()), tokio::task::JoinError>,
+    in_flight: &mut HashMap<tokio::task::Id, (String, usize)>,
+    ctx: &Ctx,
+) -> anyhow::Result<()> {
+    let e = match joined {
+        Ok((id, ())) => {
+            if let Some((src, msg_id)) = in_flight.remove(&id) {
+                ctx.output.handled(&src, msg_id as u64);
+            }
+            return Ok(());
+        }
+        Err(e) if e.is_cancelled() => {
+            if let Some((src, msg_id)) = in_flight.remove(&e.id()) {
+                ctx.output.handled(&src, msg_id as u64);
+            }
+            return Ok(());
+        }
+        Err(e) => e,
+    };
+    let origin = in_flight.remove(&e.id());
+    let text = format!("handler panicked: {}", panic_message(&*e.into_panic()));
+    if let Some((src, msg_id)) = origin {
+        let reply = Message {
+            src: ctx.node_id.clone(),
+            dst: src,
+            body: Body {
+                id: Some(ctx.next_msg_id()),
+                in_reply_to: Some(msg_id),
+                payload: SystemPayload::Error(MaelstromError::new(ErrorCode::CRASH, text.clone())),
+            },
+        };
+        let _ = reply.send(ctx);
+    }
+    Err(anyhow::anyhow!(text))
+}
+
+/// The next event a node injected, taking anything set aside while coalescing
+/// first.
+async fn next_injected<E>(rx: &mut tokio::sync::mpsc::UnboundedReceiver<E>, backlog: &mut VecDeque<E>) -> Option<E> {
+    match backlog.pop_front() {
+        Some(event) => Some(event),
+        None => rx.recv().await,
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
+
+/// Reports panics on stderr as one JSON object per line, with the payload,
+/// where it happened and a backtrace, instead of the default free-form text.
+fn install_panic_hook() {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        std::panic::set_hook(Box::new(|info| {
+            let report = serde_json::json!({
+                "event": "panic",
+                "message": panic_message(info.payload()),
+                "location": info.location().map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column())),
+                "thread": std::thread::current().name(),
+                "backtrace": std::backtrace::Backtrace::force_capture().to_string(),
+            });
+            eprintln!("{}", report);
+        }));
+    });
+}
+
+/// Replies `topology_ok` and records this node's neighbours, returning them.
+fn answer_topology(msg: Message<SystemPayload>, ctx: &Ctx) -> anyhow::Result<Vec<String>> {
+    let mut reply = msg.into_reply(None);
+    let SystemPayload::Topology { mut topology } = std::mem::replace(&mut reply.body.payload, SystemPayload::TopologyOk)
+    else {
+        anyhow::bail!("expected topology, got {:?}", reply.body.payload);
+    };
+    let neighbors = topology.remove(&ctx.node_id).unwrap_or_default();
+    *ctx.neighbors.lock().unwrap() = neighbors.clone();
+    reply.body.id = Some(ctx.next_msg_id());
+    reply.send(ctx).context("reply to topology")?;
+    Ok(neighbors)
+}
 
-    jh.await
-        .context("stdin task panicked")?
-        .context("stdin task err")?;
+/// Answers `msg` with `err` if it is a request, i.e. carries a msg_id.
+fn reply_error(msg: &serde_json::Value, err: MaelstromError, ctx: &Ctx) -> anyhow::Result<()> {
+    let (Some(src), Some(msg_id)) = (msg["src"].as_str(), msg["body"]["msg_id"].as_u64()) else {
+        return Ok(());
+    };
+    let reply = Message {
+        src: ctx.node_id().to_string(),
+        dst: src.to_string(),
+        body: Body {
+            id: Some(ctx.next_msg_id()),
+            in_reply_to: Some(msg_id as usize),
+            payload: SystemPayload::Error(err),
+        },
+    };
+    reply.send(ctx).with_context(|| format!("reply error to {}", src))
+}
 
-    Ok(())
+/// Runs [`Node::on_reinit`] for a repeated `init` and answers it.
+async fn reinit<S, N, P, SP, IP>(node: &N, init_msg: Message<SystemPayload>, ctx: Ctx) -> anyhow::Result<()>
+where
+    N: Node<S, P, SP, IP>,
+{
+    let SystemPayload::Init(init) = init_msg.body.payload else {
+        anyhow::bail!("expected init, got {:?}", init_msg.body.payload);
+    };
+    let result = if init.node_id != ctx.node_id {
+        Err(MaelstromError::new(
+            ErrorCode::NOT_SUPPORTED,
+            format!("node {} cannot be re-initialized as {}", ctx.node_id, init.node_id),
+        )
+        .into())
+    } else {
+        node.on_reinit(init, ctx.clone()).await
+    };
+    let payload = match result {
+        Ok(()) => SystemPayload::InitOk,
+        Err(e) => SystemPayload::Error(match e.downcast::<MaelstromError>() {
+            Ok(e) => e,
+            Err(e) => MaelstromError::new(ErrorCode::CRASH, format!("{:#}", e)),
+        }),
+    };
+    let reply = Message {
+        src: ctx.node_id.clone(),
+        dst: init_msg.src,
+        body: Body {
+            id: Some(ctx.next_msg_id()),
+            in_reply_to: init_msg.body.id,
+            payload,
+        },
+    };
+    reply.send(&ctx).context("reply to init")
 }
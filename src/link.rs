@@ -0,0 +1,329 @@
+//! At-least-once, deduplicated delivery between nodes.
+//!
+//! A reliable send wraps the payload in an envelope carrying a per-peer
+//! sequence number and retransmits it until the peer acks that number. The
+//! receiving runtime acks every copy it sees, hands the unwrapped payload to
+//! the node once and swallows retransmissions, so the node just sees an
+//! ordinary message from the peer.
+//!
+//! Envelopes also carry the sender's incarnation, so a restarted peer, whose
+//! sequence numbers start over, is heard from again, and the lowest sequence
+//! number it is still sending, so sends it gave up on don't leave gaps the
+//! receiver keeps track of forever.
+
+use crate::backoff::{Backoff, Policy};
+use crate::deadline;
+use crate::error::{ErrorCode, MaelstromError};
+use crate::time::Clock;
+use crate::{Body, Ctx, Message};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Envelope<P> {
+    Reliable {
+        seq: u64,
+        /// Which run of the sender this is; see [`Links::incarnation`].
+        incarnation: u64,
+        /// The sender is done with every sequence number below this one:
+        /// each was acked or given up on.
+        floor: u64,
+        payload: P,
+    },
+    ReliableOk {
+        seq: u64,
+    },
+}
+
+/// Sequence numbers received from one peer.
+#[derive(Debug, Default)]
+struct Received {
+    /// The peer's run these were sent by.
+    incarnation: u64,
+    /// Every sequence number up to and including this one has been delivered,
+    /// or will never be sent again.
+    contiguous: u64,
+    /// Delivered sequence numbers past `contiguous`.
+    ahead: BTreeSet<u64>,
+}
+
+impl Received {
+    /// Records `seq` from the peer's `incarnation`, returning whether it was
+    /// new. A newer incarnation starts over; messages from an older one are
+    /// never new.
+    fn insert(&mut self, incarnation: u64, floor: u64, seq: u64) -> bool {
+        if incarnation < self.incarnation {
+            return false;
+        }
+        if incarnation > self.incarnation {
+            *self = Self {
+                incarnation,
+                ..Self::default()
+            };
+        }
+        // sends the peer gave up on leave gaps that would never fill
+        if floor > self.contiguous + 1 {
+            self.contiguous = floor - 1;
+            self.ahead = self.ahead.split_off(&floor);
+        }
+        if seq <= self.contiguous || !self.ahead.insert(seq) {
+            return false;
+        }
+        while self.ahead.remove(&(self.contiguous + 1)) {
+            self.contiguous += 1;
+        }
+        true
+    }
+}
+
+/// Sequence numbers sent to one peer.
+#[derive(Debug)]
+struct Sent {
+    next: u64,
+    /// Sent and neither acked nor given up on yet.
+    outstanding: BTreeSet<u64>,
+}
+
+impl Default for Sent {
+    fn default() -> Self {
+        Self {
+            next: 1,
+            outstanding: BTreeSet::new(),
+        }
+    }
+}
+
+/// Link state shared by every [`ReliableLink`] of a node and the input task.
+#[derive(Debug)]
+pub(crate) struct Links {
+    /// Tells this run of the node from earlier ones, so a restarted node's
+    /// sequence numbers, which start over, aren't taken for duplicates. Its
+    /// start time in milliseconds on the node's clock: later runs have
+    /// higher ones.
+    incarnation: u64,
+    sent: Mutex<HashMap<String, Sent>>,
+    received: Mutex<HashMap<String, Received>>,
+}
+
+/// The last incarnation handed out in this process.
+static LAST_INCARNATION: AtomicU64 = AtomicU64::new(0);
+
+impl Links {
+    pub(crate) fn new(clock: &dyn Clock) -> Self {
+        // nodes restarted in the same process, as in simulations, may start
+        // within one millisecond, or one tick of a manual clock
+        let started = clock.wall_ms();
+        let previous = LAST_INCARNATION
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(started.max(last + 1)))
+            .unwrap_or_default();
+        Self {
+            incarnation: started.max(previous + 1),
+            sent: Mutex::default(),
+            received: Mutex::default(),
+        }
+    }
+}
+
+impl Links {
+    fn next_seq(&self, dst: &str) -> u64 {
+        let mut sent = self.sent.lock().unwrap();
+        let sent = sent.entry(dst.to_string()).or_default();
+        let seq = sent.next;
+        sent.next += 1;
+        sent.outstanding.insert(seq);
+        seq
+    }
+
+    /// The lowest sequence number to `dst` still being sent.
+    fn floor(&self, dst: &str) -> u64 {
+        let sent = self.sent.lock().unwrap();
+        let sent = &sent[dst];
+        sent.outstanding.first().copied().unwrap_or(sent.next)
+    }
+
+    /// Stops sending `seq` to `dst`, acked or not.
+    fn done(&self, dst: &str, seq: u64) {
+        if let Some(sent) = self.sent.lock().unwrap().get_mut(dst) {
+            sent.outstanding.remove(&seq);
+        }
+    }
+
+    /// Unwraps a reliable envelope into the message it carries, acking it.
+    /// Returns `None` for retransmissions and stray acks, and anything that
+    /// isn't a link message unchanged.
+    pub(crate) fn accept(&self, msg: Value, ctx: &Ctx) -> anyhow::Result<Option<Value>> {
+        match msg["body"]["type"].as_str() {
+            Some("reliable") => {}
+            // acks that arrive after their send gave up on that attempt
+            Some("reliable_ok") => return Ok(None),
+            _ => return Ok(Some(msg)),
+        }
+        let envelope: Message<Envelope<Value>> = serde_json::from_value(msg)?;
+        let Envelope::Reliable {
+            seq,
+            incarnation,
+            floor,
+            payload,
+        } = envelope.body.payload
+        else {
+            unreachable!("checked the type above");
+        };
+
+        let ack = Message {
+            src: ctx.node_id().to_string(),
+            dst: envelope.src.clone(),
+            body: Body {
+                id: None,
+                in_reply_to: envelope.body.id,
+                payload: Envelope::<()>::ReliableOk { seq },
+            },
+        };
+        ack.send(ctx)?;
+
+        let fresh = self
+            .received
+            .lock()
+            .unwrap()
+            .entry(envelope.src.clone())
+            .or_default()
+            .insert(incarnation, floor, seq);
+        if !fresh {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::json!({
+            "src": envelope.src,
+            "dest": envelope.dst,
+            "body": payload,
+        })))
+    }
+}
+
+/// Sends payloads to other nodes so that each is delivered exactly once,
+/// however many times it has to be retransmitted.
+///
+/// The receiver gets the payload as a plain [`Event::Message`](crate::Event)
+/// without a msg_id, so it cannot be replied to; delivery is acknowledged by
+/// the runtime. Sends to the same peer may still be delivered out of order,
+/// and handlers run concurrently, so sequence-sensitive protocols should
+/// carry their own ordering in the payload.
+///
+/// At most [`ReliableLink::window`] sends per peer are in flight at once;
+/// further sends wait for an ack before they are transmitted, so a slow or
+/// partitioned peer holds back callers instead of piling up retransmissions.
+/// Clones share their windows.
+#[derive(Debug, Clone)]
+pub struct ReliableLink {
+    ctx: Ctx,
+    policy: Policy,
+    window: usize,
+    in_flight: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+impl ReliableLink {
+    pub fn new(ctx: &Ctx) -> Self {
+        Self {
+            ctx: ctx.clone(),
+            policy: Policy::new(Backoff::Constant(Duration::from_millis(200))),
+            window: 32,
+            in_flight: Arc::default(),
+        }
+    }
+
+    /// Limits each peer to `max_in_flight` unacked sends (32 by default).
+    pub fn window(mut self, max_in_flight: usize) -> Self {
+        assert!(max_in_flight > 0, "window must allow at least one send");
+        self.window = max_in_flight;
+        self.in_flight = Arc::default();
+        self
+    }
+
+    /// Retransmits after `interval` without an ack, giving up with
+    /// [`ErrorCode::TEMPORARILY_UNAVAILABLE`] after `max_attempts` sends if
+    /// given. Retries forever by default, or until the context's deadline.
+    pub fn retry(self, interval: Duration, max_attempts: Option<usize>) -> Self {
+        self.backoff(Policy {
+            backoff: Backoff::Constant(interval),
+            max_attempts,
+        })
+    }
+
+    /// Like [`ReliableLink::retry`], but waits for each ack as long as the
+    /// policy's next delay, e.g. longer and longer for a peer that stays
+    /// silent.
+    pub fn backoff(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sends `payload` to `dst`, returning once `dst` has acknowledged it.
+    /// Waits for room in the window first.
+    pub async fn send_reliable<P>(&self, dst: &str, payload: P) -> anyhow::Result<()>
+    where
+        P: Serialize,
+    {
+        let window = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(dst.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.window)))
+            .clone();
+        let _permit = window.acquire_owned().await.expect("window semaphore is never closed");
+
+        let seq = self.ctx.links.next_seq(dst);
+        // given up on however this returns, including the caller dropping
+        // the future
+        let _outstanding = Outstanding {
+            links: &self.ctx.links,
+            dst,
+            seq,
+        };
+        let mut patience = self.policy.backoff.delays();
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let timeout = patience.next().expect("a backoff's delays never end");
+            let envelope = Envelope::Reliable {
+                seq,
+                incarnation: self.ctx.links.incarnation,
+                floor: self.ctx.links.floor(dst),
+                payload: &payload,
+            };
+            match self
+                .ctx
+                .rpc_timeout::<_, Envelope<()>>(dst, &envelope, timeout)
+                .await
+            {
+                Ok(_) => return Ok(()),
+                Err(e) if deadline::retryable(&e, &self.ctx) => {}
+                Err(e) => return Err(e),
+            }
+            if self.policy.max_attempts.is_some_and(|max| attempts >= max) {
+                return Err(MaelstromError::new(
+                    ErrorCode::TEMPORARILY_UNAVAILABLE,
+                    format!("{} did not ack seq {} after {} attempts", dst, seq, attempts),
+                )
+                .into());
+            }
+        }
+    }
+}
+
+/// Marks a send as done with when dropped.
+struct Outstanding<'a> {
+    links: &'a Links,
+    dst: &'a str,
+    seq: u64,
+}
+
+impl Drop for Outstanding<'_> {
+    fn drop(&mut self) {
+        self.links.done(self.dst, self.seq);
+    }
+}
@@ -0,0 +1,309 @@
+//! Mutual exclusion across nodes over lin-kv, with fencing tokens.
+//!
+//! Locks use the same heartbeat scheme as [`Lease`](crate::lease::Lease): the
+//! holder renews the record every renew interval and a waiter only takes it
+//! over once it has stayed unchanged for a whole TTL on the waiter's clock.
+//! Every acquisition also bumps the record's token, so a holder that stalled
+//! past its TTL carries a smaller token than its successor. Resources that
+//! check tokens with a [`Fence`] reject the stale holder's writes even if it
+//! hasn't noticed that it lost the lock.
+//!
+//! Nodes can also fence each other directly: messages sent through
+//! [`Ctx::fenced`] carry the holder's token in a `fencing_token` body field,
+//! and every node checks such messages against its own [`Ctx::fence`]. One
+//! carrying a token older than the highest the node has seen is answered with
+//! [`ErrorCode::PRECONDITION_FAILED`] and surfaces as
+//! [`Event::StaleLeader`](crate::Event::StaleLeader) instead of reaching the
+//! node's handlers, so a deposed primary can't keep replicating writes.
+
+use crate::deadline;
+use crate::error::{ErrorCode, MaelstromError};
+use crate::kv::Kv;
+use crate::lease::LeaseOptions;
+use crate::{Body, Ctx, Message, SystemPayload};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Increases with every acquisition of a lock.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FencingToken(pub u64);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct LockRecord {
+    holder: Option<String>,
+    token: FencingToken,
+    beat: u64,
+}
+
+/// Acquires named locks. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct DistLock {
+    ctx: Ctx,
+    kv: Kv,
+    options: LeaseOptions,
+}
+
+impl DistLock {
+    pub fn new(ctx: &Ctx, options: LeaseOptions) -> Self {
+        Self {
+            ctx: ctx.clone(),
+            kv: Kv::lin(ctx).timeout(options.renew_interval),
+            options,
+        }
+    }
+
+    /// Waits until `name` is free (or its holder has stopped renewing for a
+    /// TTL), then takes it. The guard renews the lock in the background.
+    /// Gives up with a timeout once the context's deadline passes.
+    pub async fn acquire(&self, name: &str) -> anyhow::Result<LockGuard> {
+        let key = format!("lock/{}", name);
+        let me = Some(self.ctx.node_id().to_string());
+        // a foreign record and when we first saw it in that exact form
+        let mut observed: Option<(LockRecord, Instant)> = None;
+        loop {
+            let now = self.ctx.now();
+            let claim = match self.kv.read::<LockRecord>(&key).await {
+                Ok(None) => Some((LockRecord::default(), true)),
+                Ok(Some(record)) if record.holder.is_none() => Some((record, false)),
+                Ok(Some(record)) => match &observed {
+                    Some((seen, since)) if *seen == record => {
+                        (now.duration_since(*since) >= self.options.ttl).then_some((record, false))
+                    }
+                    _ => {
+                        observed = Some((record, now));
+                        None
+                    }
+                },
+                Err(e) if deadline::retryable(&e, &self.ctx) => None,
+                Err(e) => return Err(e),
+            };
+
+            if let Some((from, create)) = claim {
+                let next = LockRecord {
+                    holder: me.clone(),
+                    token: FencingToken(from.token.0 + 1),
+                    beat: from.beat + 1,
+                };
+                let sent_at = self.ctx.now();
+                if let Ok(true) = self.kv.cas(&key, from, next.clone(), create).await {
+                    return Ok(LockGuard::start(self, key, next, sent_at));
+                }
+            }
+            self.ctx.sleep(self.options.renew_interval).await;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct GuardState {
+    /// The record we last wrote, while we hold the lock.
+    record: Option<LockRecord>,
+    valid_until: Option<Instant>,
+}
+
+/// A held lock. Renews in the background until released; dropping it stops
+/// renewing without releasing, so others get the lock after the TTL.
+#[derive(Debug)]
+pub struct LockGuard {
+    key: String,
+    token: FencingToken,
+    ctx: Ctx,
+    kv: Kv,
+    state: Arc<Mutex<GuardState>>,
+    task: JoinHandle<()>,
+}
+
+impl LockGuard {
+    fn start(lock: &DistLock, key: String, record: LockRecord, sent_at: Instant) -> Self {
+        let token = record.token;
+        let state = Arc::new(Mutex::new(GuardState {
+            record: Some(record),
+            valid_until: Some(sent_at + lock.options.ttl),
+        }));
+        let task = tokio::spawn(renew(lock.clone(), key.clone(), state.clone()));
+        Self {
+            key,
+            token,
+            ctx: lock.ctx.clone(),
+            kv: lock.kv.clone(),
+            state,
+            task,
+        }
+    }
+
+    /// The token to present to fenced resources.
+    pub fn token(&self) -> FencingToken {
+        self.token
+    }
+
+    /// Whether the lock is still held. Turns false as soon as the validity
+    /// window lapses, even before a renewal fails.
+    pub fn is_held(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.valid_until.is_some_and(|until| self.ctx.now() < until)
+    }
+
+    /// The token, or [`ErrorCode::ABORT`] if the lock has been lost.
+    pub fn check(&self) -> anyhow::Result<FencingToken> {
+        if !self.is_held() {
+            return Err(MaelstromError::new(ErrorCode::ABORT, format!("lost {}", self.key)).into());
+        }
+        Ok(self.token)
+    }
+
+    /// Stops renewing and marks the record free so a waiter can take it
+    /// without waiting out the TTL.
+    pub async fn release(self) -> anyhow::Result<()> {
+        self.task.abort();
+        let record = {
+            let mut state = self.state.lock().unwrap();
+            state.valid_until = None;
+            state.record.take()
+        };
+        let Some(mut record) = record else {
+            return Ok(());
+        };
+        loop {
+            let free = LockRecord {
+                holder: None,
+                token: record.token,
+                beat: record.beat + 1,
+            };
+            if self.kv.cas(&self.key, record.clone(), free, false).await? {
+                return Ok(());
+            }
+            // a renewal cut short by the abort may have landed anyway; free
+            // the record it wrote, unless someone else has taken over
+            match self.kv.read::<LockRecord>(&self.key).await? {
+                Some(current) if current.holder == record.holder && current.token == record.token => record = current,
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn renew(lock: DistLock, key: String, state: Arc<Mutex<GuardState>>) {
+    loop {
+        lock.ctx.sleep(lock.options.renew_interval).await;
+        let Some(current) = state.lock().unwrap().record.clone() else {
+            return;
+        };
+        let next = LockRecord {
+            beat: current.beat + 1,
+            ..current.clone()
+        };
+        let sent_at = lock.ctx.now();
+        let renewed = lock.kv.cas(&key, current, next.clone(), false).await;
+        let mut st = state.lock().unwrap();
+        let lost = match renewed {
+            Ok(true) => {
+                st.record = Some(next);
+                st.valid_until = Some(sent_at + lock.options.ttl);
+                false
+            }
+            Ok(false) => true,
+            // indeterminate; keep trying while the lock is still valid
+            Err(_) => st.valid_until.is_none_or(|until| lock.ctx.now() >= until),
+        };
+        if lost {
+            st.record = None;
+            st.valid_until = None;
+            return;
+        }
+    }
+}
+
+/// The highest token a fenced resource has accepted. Requests carrying an
+/// older token come from a holder that has since been superseded.
+#[derive(Debug, Default)]
+pub struct Fence {
+    highest: Mutex<Option<FencingToken>>,
+}
+
+impl Fence {
+    /// Accepts `token` if it is at least as new as every token accepted so
+    /// far, and fails with [`ErrorCode::PRECONDITION_FAILED`] otherwise.
+    pub fn admit(&self, token: FencingToken) -> Result<(), MaelstromError> {
+        let mut highest = self.highest.lock().unwrap();
+        match *highest {
+            Some(highest) if token < highest => Err(MaelstromError::new(
+                ErrorCode::PRECONDITION_FAILED,
+                format!("fencing token {} superseded by {}", token.0, highest.0),
+            )),
+            _ => {
+                *highest = Some(token);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn highest(&self) -> Option<FencingToken> {
+        *self.highest.lock().unwrap()
+    }
+}
+
+/// A message turned away because its fencing token was older than one this
+/// node had already seen. The runtime has already answered it, if it was a
+/// request, with [`ErrorCode::PRECONDITION_FAILED`].
+#[derive(Debug, Clone)]
+pub struct StaleLeader {
+    pub src: String,
+    /// The message's `type`.
+    pub kind: Option<String>,
+    pub token: FencingToken,
+    pub highest: FencingToken,
+}
+
+/// Adds `token` to a message's body if it is bound for another node.
+pub(crate) fn stamp(msg: &mut serde_json::Value, token: FencingToken) {
+    if msg["dest"].as_str().is_some_and(crate::is_node_id)
+        && let Some(body) = msg["body"].as_object_mut()
+    {
+        body.insert("fencing_token".to_string(), token.0.into());
+    }
+}
+
+/// Strips the fencing token off an inbound message and checks it against the
+/// node's fence. Unstamped messages pass through untouched.
+pub(crate) fn check(mut msg: serde_json::Value, ctx: &Ctx) -> anyhow::Result<Result<serde_json::Value, StaleLeader>> {
+    let Some(token) = msg["body"]
+        .as_object_mut()
+        .and_then(|body| body.remove("fencing_token"))
+    else {
+        return Ok(Ok(msg));
+    };
+    let token: FencingToken = serde_json::from_value(token).context("parse fencing token")?;
+    let Err(err) = ctx.fence.admit(token) else {
+        return Ok(Ok(msg));
+    };
+
+    let src = msg["src"].as_str().unwrap_or_default().to_string();
+    if let Some(msg_id) = msg["body"]["msg_id"].as_u64() {
+        let reply = Message {
+            src: ctx.node_id().to_string(),
+            dst: src.clone(),
+            body: Body {
+                id: Some(ctx.next_msg_id()),
+                in_reply_to: Some(msg_id as usize),
+                payload: SystemPayload::Error(err),
+            },
+        };
+        reply.send(ctx).context("reject stale leader")?;
+    }
+    Ok(Err(StaleLeader {
+        src,
+        kind: msg["body"]["type"].as_str().map(str::to_string),
+        token,
+        highest: ctx.fence.highest().unwrap_or(token),
+    }))
+}
@@ -0,0 +1,54 @@
+//! Messages that reach a node but are addressed to another.
+//!
+//! Maelstrom only ever delivers a node its own messages, but a replayed
+//! capture, a test harness or a hand-written script can get it wrong, and a
+//! message handled by the wrong node does quiet damage: a gossip from n2 to
+//! n3 handled by n1 teaches n1 that n2 knows things it told n3. Every message
+//! whose `dest` isn't the node is therefore stopped before any of the runtime
+//! sees it, and is dropped or passed on as [`Runtime::misrouted`] says.
+//!
+//! [`Runtime::misrouted`]: crate::Runtime::misrouted
+
+use crate::Ctx;
+use anyhow::Context;
+
+/// What to do with a message addressed to another node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Misrouted {
+    /// Drop it, with a warning on stderr.
+    #[default]
+    Drop,
+    /// Send it on to its `dest`, unchanged, with a warning on stderr.
+    Forward,
+}
+
+/// `msg` if it is addressed to this node or to nobody in particular; `None`
+/// once a message for someone else has been dealt with.
+pub(crate) fn check(msg: serde_json::Value, policy: Misrouted, ctx: &Ctx) -> anyhow::Result<Option<serde_json::Value>> {
+    let Some(dest) = msg["dest"].as_str() else {
+        return Ok(Some(msg));
+    };
+    if dest == ctx.node_id() {
+        return Ok(Some(msg));
+    }
+    let action = match policy {
+        Misrouted::Drop => "dropped",
+        Misrouted::Forward => "forwarded",
+    };
+    let warning = serde_json::json!({
+        "event": "misrouted",
+        "node": ctx.node_id(),
+        "src": msg["src"],
+        "dest": dest,
+        "type": msg["body"]["type"],
+        "msg_id": msg["body"]["msg_id"],
+        "action": action,
+    });
+    eprintln!("{}", warning);
+    if policy == Misrouted::Forward {
+        let mut line = serde_json::to_vec(&msg).context("serialize misrouted message")?;
+        line.push(b'\n');
+        ctx.output.write_line(dest, line).with_context(|| format!("forward message to {}", dest))?;
+    }
+    Ok(None)
+}
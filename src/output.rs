@@ -0,0 +1,377 @@
+use crate::capture::{Direction, Tap};
+use crate::chaos::{ChaosLayer, Fate};
+#[cfg(feature = "compression")]
+use crate::compress::Compressor;
+use crate::emulate::Emulator;
+use crate::rate::RateLimiter;
+use crate::replies::ReplyCache;
+use crate::sizes::{self, MessageSizes, SizeStats};
+use crate::stall::{StallStats, Stalls};
+use crate::time::Clock;
+use anyhow::Context;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+pub(crate) enum OutputCmd {
+    Line(Vec<u8>),
+    /// Acknowledged once every line queued before it has been written and
+    /// the writer flushed.
+    Flush(oneshot::Sender<()>),
+    Shutdown,
+}
+
+enum LaneCmd {
+    Line {
+        line: Vec<u8>,
+        copies: usize,
+        delay: Duration,
+    },
+    Barrier(oneshot::Sender<()>),
+}
+
+/// Handle to the node's outbound message stream.
+///
+/// Cloning is cheap; every clone feeds the same writer task, which owns the
+/// underlying `AsyncWrite` and writes lines in the order they were sent.
+///
+/// Messages to other nodes that may have to be held back (chaos delays, rate
+/// limits) go through a FIFO lane per peer instead, so they can reorder
+/// across peers but never overtake each other on the way to the same peer.
+/// Replies to clients and requests to services are written directly; the
+/// set of peers is fixed, so lanes are never torn down.
+#[derive(Debug, Clone)]
+pub(crate) struct Output {
+    tx: mpsc::UnboundedSender<OutputCmd>,
+    lanes: Arc<Mutex<HashMap<String, mpsc::UnboundedSender<LaneCmd>>>>,
+    clock: Arc<dyn Clock>,
+    chaos: Option<Arc<ChaosLayer>>,
+    limiter: Option<Arc<RateLimiter>>,
+    #[cfg(feature = "compression")]
+    compressor: Option<Arc<Compressor>>,
+    emulator: Option<(Arc<Emulator>, Arc<dyn Clock>)>,
+    /// Lines to be processed as if they had arrived on the input.
+    loopback: mpsc::UnboundedSender<String>,
+    /// The node's own id, if messages it sends itself are looped back.
+    own_id: Option<String>,
+    sizes: Arc<MessageSizes>,
+    size_warning: usize,
+    stalls: Arc<Stalls>,
+    /// Keeps replies to clients, if repeated requests are answered from it.
+    replies: Option<Arc<ReplyCache>>,
+}
+
+impl Output {
+    pub(crate) fn spawn<W>(
+        writer: W,
+        clock: Arc<dyn Clock>,
+        loopback: mpsc::UnboundedSender<String>,
+        tap: Option<Arc<Tap>>,
+        stalls: Stalls,
+    ) -> (Self, JoinHandle<anyhow::Result<()>>)
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let stalls = Arc::new(stalls);
+        let jh = tokio::spawn(write_loop(writer, rx, tap, stalls.clone()));
+        let output = Self {
+            tx,
+            lanes: Arc::default(),
+            clock,
+            chaos: None,
+            limiter: None,
+            #[cfg(feature = "compression")]
+            compressor: None,
+            emulator: None,
+            loopback,
+            own_id: None,
+            sizes: Arc::default(),
+            size_warning: sizes::DEFAULT_WARNING,
+            stalls,
+            replies: None,
+        };
+        (output, jh)
+    }
+
+    /// Answers messages to emulated services in-process instead of writing
+    /// them out.
+    pub(crate) fn set_emulator(&mut self, emulator: Emulator, clock: Arc<dyn Clock>) {
+        self.emulator = Some((Arc::new(emulator), clock));
+    }
+
+    /// Hands messages to `own_id` straight back to the input instead of
+    /// writing them out.
+    pub(crate) fn set_loopback(&mut self, own_id: String) {
+        self.own_id = Some(own_id);
+    }
+
+    /// Holds every line written from now on to `limiter`'s rates.
+    pub(crate) fn set_rate_limit(&mut self, limiter: RateLimiter) {
+        self.limiter = Some(Arc::new(limiter));
+    }
+
+    /// Compresses lines to nodes that accept it from now on.
+    #[cfg(feature = "compression")]
+    pub(crate) fn set_compressor(&mut self, compressor: Compressor) {
+        self.compressor = Some(Arc::new(compressor));
+    }
+
+    /// Undoes the compressor's work on an inbound message.
+    #[cfg(feature = "compression")]
+    pub(crate) fn decompress(&self, msg: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+        match &self.compressor {
+            Some(compressor) => compressor.inbound(msg),
+            None => Ok(msg),
+        }
+    }
+
+    /// Keeps every reply to a client written from now on in `replies`.
+    pub(crate) fn set_reply_cache(&mut self, replies: Arc<ReplyCache>) {
+        self.replies = Some(replies);
+    }
+
+    /// Tells the reply cache, if any, that the handler for `client`'s request
+    /// `msg_id` has finished.
+    pub(crate) fn handled(&self, client: &str, msg_id: u64) {
+        if let Some(replies) = &self.replies {
+            replies.finished(client, msg_id);
+        }
+    }
+
+    /// Warns of lines written from now on that are longer than `bytes`.
+    pub(crate) fn set_size_warning(&mut self, bytes: usize) {
+        self.size_warning = bytes;
+    }
+
+    /// The sizes of every line written so far, by message type.
+    pub(crate) fn sizes(&self) -> BTreeMap<String, SizeStats> {
+        self.sizes.stats()
+    }
+
+    pub(crate) fn stalls(&self) -> StallStats {
+        self.stalls.stats()
+    }
+
+    /// Routes every line written from now on through `chaos`.
+    pub(crate) fn set_chaos(&mut self, chaos: ChaosLayer) {
+        self.chaos = Some(Arc::new(chaos));
+    }
+
+    pub(crate) fn write_line(&self, dst: &str, line: Vec<u8>) -> anyhow::Result<()> {
+        self.sizes.record(dst, &line, self.size_warning);
+        if let Some(replies) = &self.replies
+            && !crate::is_node_id(dst)
+        {
+            replies.reply(dst, &line);
+        }
+        if self.own_id.as_deref() == Some(dst) {
+            let line = String::from_utf8(line).context("loop back message")?;
+            let _ = self.loopback.send(line.trim_end().to_string());
+            return Ok(());
+        }
+        if let Some((emulator, clock)) = &self.emulator
+            && emulator.handles(dst)
+        {
+            let request = serde_json::from_slice(&line).context("parse emulated request")?;
+            if let Some((reply, latency)) = emulator.handle(&request) {
+                if latency.is_zero() {
+                    let _ = self.loopback.send(reply);
+                } else {
+                    let sleep = clock.sleep(latency);
+                    let loopback = self.loopback.clone();
+                    tokio::spawn(async move {
+                        sleep.await;
+                        let _ = loopback.send(reply);
+                    });
+                }
+            }
+            return Ok(());
+        }
+
+        #[cfg(feature = "compression")]
+        let line = match &self.compressor {
+            Some(compressor) => compressor.outbound(dst, line)?,
+            None => line,
+        };
+
+        if !crate::is_node_id(dst) {
+            return self.enqueue(dst, line);
+        }
+        let (copies, delay) = match &self.chaos {
+            None if self.limiter.is_none() => return self.enqueue(dst, line),
+            None => (1, Duration::ZERO),
+            Some(chaos) => match chaos.decide() {
+                Fate::Drop => return Ok(()),
+                Fate::Deliver { copies, delay } => (copies, delay),
+            },
+        };
+        self.lane(dst)
+            .send(LaneCmd::Line { line, copies, delay })
+            .map_err(|_| anyhow::anyhow!("output closed"))
+    }
+
+    /// The FIFO lane for peer `dst`, started on first use.
+    fn lane(&self, dst: &str) -> mpsc::UnboundedSender<LaneCmd> {
+        let mut lanes = self.lanes.lock().unwrap();
+        if let Some(lane) = lanes.get(dst) {
+            return lane.clone();
+        }
+        let (lane_tx, mut lane_rx) = mpsc::unbounded_channel();
+        let tx = self.tx.clone();
+        let stalls = self.stalls.clone();
+        let clock = self.clock.clone();
+        let limiter = self.limiter.clone();
+        let peer = dst.to_string();
+        tokio::spawn(async move {
+            let peer_bucket = limiter.as_ref().and_then(|limiter| limiter.peer_bucket(&*clock));
+            while let Some(cmd) = lane_rx.recv().await {
+                match cmd {
+                    LaneCmd::Line { line, copies, delay } => {
+                        if !delay.is_zero() {
+                            clock.sleep(delay).await;
+                        }
+                        for _ in 0..copies {
+                            if let Some(limiter) = &limiter {
+                                limiter.admit(peer_bucket.as_ref(), &clock).await;
+                            }
+                            if stalls.admit(&peer, line.len()) {
+                                let _ = tx.send(OutputCmd::Line(line.clone()));
+                            }
+                        }
+                    }
+                    LaneCmd::Barrier(ack) => {
+                        let _ = ack.send(());
+                    }
+                }
+            }
+        });
+        lanes.insert(dst.to_string(), lane_tx.clone());
+        lane_tx
+    }
+
+    /// Waits until every message sent before the call (to `dst` only, if
+    /// given) has been handed to the writer and the writer has flushed.
+    pub(crate) async fn flush(&self, dst: Option<&str>) -> anyhow::Result<()> {
+        let barriers: Vec<_> = {
+            let lanes = self.lanes.lock().unwrap();
+            lanes
+                .iter()
+                .filter(|(lane_dst, _)| dst.is_none_or(|dst| dst == lane_dst.as_str()))
+                .filter_map(|(_, lane)| {
+                    let (ack, done) = oneshot::channel();
+                    lane.send(LaneCmd::Barrier(ack)).ok().map(|_| done)
+                })
+                .collect()
+        };
+        for barrier in barriers {
+            barrier.await.context("output lane stopped")?;
+        }
+        let (ack, done) = oneshot::channel();
+        self.tx
+            .send(OutputCmd::Flush(ack))
+            .map_err(|_| anyhow::anyhow!("output closed"))?;
+        done.await.context("output closed before flushing")
+    }
+
+    fn enqueue(&self, dst: &str, line: Vec<u8>) -> anyhow::Result<()> {
+        if !self.stalls.admit(dst, line.len()) {
+            return Ok(());
+        }
+        self.tx
+            .send(OutputCmd::Line(line))
+            .map_err(|_| anyhow::anyhow!("output closed"))
+    }
+
+    pub(crate) fn shutdown(&self) {
+        let _ = self.tx.send(OutputCmd::Shutdown);
+    }
+}
+
+async fn write_loop<W>(
+    mut writer: W,
+    mut rx: mpsc::UnboundedReceiver<OutputCmd>,
+    tap: Option<Arc<Tap>>,
+    stalls: Arc<Stalls>,
+) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    while let Some(cmd) = rx.recv().await {
+        match cmd {
+            OutputCmd::Line(line) => {
+                if let Some(tap) = &tap {
+                    tap.record(Direction::Send, &line);
+                }
+                let write = async {
+                    writer.write_all(&line).await.context("write output")?;
+                    // only flush once the queue is drained so bursts go out in one write
+                    if rx.is_empty() {
+                        writer.flush().await.context("flush output")?;
+                    }
+                    anyhow::Ok(())
+                };
+                stalls.watch(line.len(), write).await?;
+            }
+            OutputCmd::Flush(ack) => {
+                stalls.watch(0, writer.flush()).await.context("flush output")?;
+                let _ = ack.send(());
+            }
+            OutputCmd::Shutdown => break,
+        }
+    }
+    writer.flush().await.context("flush output")?;
+    writer.shutdown().await.context("shutdown output")?;
+    Ok(())
+}
+
+/// A serialized message waiting in an [`Outbox`].
+#[derive(Debug)]
+struct Staged {
+    dst: String,
+    line: Vec<u8>,
+}
+
+/// Messages staged by one handler invocation, written only once the handler
+/// has returned `Ok`.
+#[derive(Debug, Default)]
+pub(crate) struct Outbox {
+    /// `None` once the outbox has been flushed or discarded.
+    staged: std::sync::Mutex<Option<Vec<Staged>>>,
+}
+
+impl Outbox {
+    pub(crate) fn new() -> Self {
+        Self {
+            staged: std::sync::Mutex::new(Some(Vec::new())),
+        }
+    }
+
+    pub(crate) fn stage(&self, dst: &str, line: Vec<u8>) -> anyhow::Result<()> {
+        match &mut *self.staged.lock().unwrap() {
+            Some(staged) => {
+                staged.push(Staged {
+                    dst: dst.to_string(),
+                    line,
+                });
+                Ok(())
+            }
+            None => anyhow::bail!("outbox already closed; the handler that owned it has returned"),
+        }
+    }
+
+    /// Writes everything staged, in staging order, or drops it all if the
+    /// handler failed. Either way the outbox rejects further staging.
+    pub(crate) fn close(&self, output: &Output, commit: bool) -> anyhow::Result<()> {
+        let staged = self.staged.lock().unwrap().take().unwrap_or_default();
+        if commit {
+            for Staged { dst, line } in staged {
+                output.write_line(&dst, line)?;
+            }
+        }
+        Ok(())
+    }
+}
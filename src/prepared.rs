@@ -0,0 +1,102 @@
+//! Sending one large payload many times without serializing it each time.
+//!
+//! A `read_ok` carrying every message seen, or a snapshot shipped to a
+//! lagging follower, can take longer to serialize than to send, and a node
+//! that sends it to each of its peers, or resends it until acknowledged,
+//! pays that every time. A [`PreparedMessage`] serializes the payload once;
+//! each send then only writes the envelope (src, dest, a fresh msg_id and
+//! whatever [`Runtime`] stamps on messages to other nodes) around the same
+//! bytes:
+//!
+//! ```ignore
+//! let snapshot = PreparedMessage::new(&Payload::Snapshot { values })?;
+//! for peer in ctx.neighbors() {
+//!     snapshot.send_to(&peer, &ctx)?;
+//! }
+//! ```
+//!
+//! [`Runtime`]: crate::Runtime
+
+use crate::{Ctx, Message};
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::json;
+use std::sync::Arc;
+
+/// A payload serialized once, to be sent any number of times to any
+/// destinations. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct PreparedMessage {
+    /// The payload as a JSON object.
+    payload: Arc<[u8]>,
+}
+
+impl PreparedMessage {
+    /// Serializes `payload`, which has to serialize to a JSON object, as
+    /// the `#[serde(tag = "type")]` payload enums do.
+    pub fn new<Payload: Serialize>(payload: &Payload) -> anyhow::Result<Self> {
+        let payload = serde_json::to_vec(payload).context("serialize prepared payload")?;
+        anyhow::ensure!(
+            payload.first() == Some(&b'{'),
+            "a prepared payload has to be a JSON object"
+        );
+        Ok(Self {
+            payload: payload.into(),
+        })
+    }
+
+    /// Sends the payload to `dst` under a fresh msg_id, returning the id so a
+    /// resend can be matched to its acknowledgement.
+    pub fn send_to(&self, dst: &str, ctx: &Ctx) -> anyhow::Result<usize> {
+        let msg_id = ctx.next_msg_id();
+        self.send(dst, msg_id, None, ctx)?;
+        Ok(msg_id)
+    }
+
+    /// Answers `request` with the payload; does nothing if it isn't a
+    /// request, i.e. carries no msg_id.
+    pub fn reply_to<Request>(&self, request: &Message<Request>, ctx: &Ctx) -> anyhow::Result<()> {
+        let Some(in_reply_to) = request.body.id else {
+            return Ok(());
+        };
+        self.send(&request.src, ctx.next_msg_id(), Some(in_reply_to), ctx)
+    }
+
+    /// The size of the serialized payload, in bytes.
+    pub fn len(&self) -> usize {
+        self.payload.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        // `{}`, the least a prepared payload can be
+        self.payload.len() <= 2
+    }
+
+    fn send(&self, dst: &str, msg_id: usize, in_reply_to: Option<usize>, ctx: &Ctx) -> anyhow::Result<()> {
+        let mut envelope = json!({
+            "src": ctx.node_id(),
+            "dest": dst,
+            "body": {"msg_id": msg_id, "in_reply_to": in_reply_to},
+        });
+        ctx.stamp(&mut envelope, in_reply_to.is_none())
+            .context("stamp prepared message")?;
+        let body = serde_json::to_vec(&envelope["body"])?;
+
+        // {"src":..,"dest":..,"body":{<envelope fields>,<payload fields>}}
+        let mut line = Vec::with_capacity(dst.len() + body.len() + self.payload.len() + 64);
+        line.extend_from_slice(b"{\"src\":");
+        serde_json::to_writer(&mut line, &envelope["src"])?;
+        line.extend_from_slice(b",\"dest\":");
+        serde_json::to_writer(&mut line, &envelope["dest"])?;
+        line.extend_from_slice(b",\"body\":");
+        line.extend_from_slice(&body[..body.len() - 1]);
+        if !self.is_empty() {
+            line.push(b',');
+            line.extend_from_slice(&self.payload[1..]);
+        } else {
+            line.push(b'}');
+        }
+        line.extend_from_slice(b"}\n");
+        ctx.output.write_line(dst, line)
+    }
+}
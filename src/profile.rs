@@ -0,0 +1,164 @@
+//! Timing of every handler, for finding the ones that stall.
+//!
+//! With [`Runtime::profile`](crate::Runtime::profile) set, each
+//! [`Node::step`](crate::Node::step) is timed from start to finish, along with
+//! how long its polls took: time spent inside a poll is time the handler held
+//! its thread, e.g. blocked on a `std::sync::Mutex` another handler keeps
+//! locked across an await. When handlers of one type overrun the budget
+//! several times in a row, a report goes to stderr as one JSON object: the
+//! last overrun's timings and every handler still running, with its age and
+//! polls so far, which is usually enough to tell who is holding up whom.
+//!
+//! Tokio's own task dumps need `--cfg tokio_unstable`, so the report lists
+//! the runtime's handlers rather than their stacks.
+
+use crate::Event;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub(crate) struct Profiler {
+    budget: Duration,
+    repeats: usize,
+    next_id: AtomicU64,
+    running: Mutex<HashMap<u64, Running>>,
+    /// Consecutive overruns, by handler label.
+    overruns: Mutex<HashMap<String, usize>>,
+}
+
+#[derive(Debug)]
+struct Running {
+    label: String,
+    started: Instant,
+    polls: u64,
+    /// Time spent inside polls.
+    busy: Duration,
+    longest_poll: Duration,
+}
+
+impl Running {
+    fn report(&self) -> serde_json::Value {
+        serde_json::json!({
+            "handler": self.label,
+            "elapsed_ms": self.started.elapsed().as_secs_f64() * 1000.0,
+            "polls": self.polls,
+            "busy_ms": self.busy.as_secs_f64() * 1000.0,
+            "longest_poll_ms": self.longest_poll.as_secs_f64() * 1000.0,
+        })
+    }
+}
+
+impl Profiler {
+    pub(crate) fn new(budget: Duration, repeats: usize) -> Self {
+        Self {
+            budget,
+            repeats: repeats.max(1),
+            next_id: AtomicU64::new(0),
+            running: Mutex::default(),
+            overruns: Mutex::default(),
+        }
+    }
+
+    /// `step`, timed as a handler called `label`.
+    pub(crate) fn wrap<F: Future>(self: &Arc<Self>, label: String, step: F) -> Profiled<F> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let running = Running {
+            label,
+            started: Instant::now(),
+            polls: 0,
+            busy: Duration::ZERO,
+            longest_poll: Duration::ZERO,
+        };
+        self.running.lock().unwrap().insert(id, running);
+        Profiled {
+            profiler: self.clone(),
+            id,
+            step: Box::pin(step),
+        }
+    }
+
+    fn finished(&self, id: u64) {
+        let Some(done) = self.running.lock().unwrap().remove(&id) else {
+            return;
+        };
+        let took = done.started.elapsed();
+        let streak = {
+            let mut overruns = self.overruns.lock().unwrap();
+            let streak = overruns.entry(done.label.clone()).or_default();
+            *streak = if took > self.budget { *streak + 1 } else { 0 };
+            if *streak < self.repeats {
+                return;
+            }
+            // start counting afresh, so a handler that is always slow is
+            // reported every `repeats` calls rather than on every one
+            std::mem::take(streak)
+        };
+        let mut others: Vec<_> = self.running.lock().unwrap().values().map(Running::report).collect();
+        others.sort_by(|a, b| b["elapsed_ms"].as_f64().partial_cmp(&a["elapsed_ms"].as_f64()).unwrap());
+        let report = serde_json::json!({
+            "event": "slow_handler",
+            "budget_ms": self.budget.as_secs_f64() * 1000.0,
+            "overruns": streak,
+            "slow": done.report(),
+            "running": others,
+        });
+        eprintln!("{}", report);
+    }
+}
+
+/// A handler's future, timed; see [`Profiler::wrap`].
+pub(crate) struct Profiled<F> {
+    profiler: Arc<Profiler>,
+    id: u64,
+    step: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Profiled<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let start = Instant::now();
+        let poll = self.step.as_mut().poll(cx);
+        let took = start.elapsed();
+        if let Some(running) = self.profiler.running.lock().unwrap().get_mut(&self.id) {
+            running.polls += 1;
+            running.busy += took;
+            running.longest_poll = running.longest_poll.max(took);
+        }
+        if poll.is_ready() {
+            self.profiler.finished(self.id);
+        }
+        poll
+    }
+}
+
+impl<F> Drop for Profiled<F> {
+    fn drop(&mut self) {
+        // a handler cancelled or panicking mid-poll isn't timed
+        self.profiler.running.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// What to call the handler for `event`: the message's type, or the kind of
+/// event it is.
+pub(crate) fn label<P, SP, IP>(event: &Event<P, SP, IP>, kind: Option<&str>) -> String {
+    if let Some(kind) = kind {
+        return kind.to_string();
+    }
+    let label = match event {
+        Event::Message(_) => "message",
+        Event::ServiceMessage(_) => "service message",
+        Event::Injected(_) => "injected",
+        Event::TopologyChanged(_) => "topology",
+        Event::StaleLeader(_) => "stale leader",
+        Event::PeerRestarted(_) => "peer restarted",
+        Event::Signal(_) => "signal",
+        Event::EOF => "eof",
+    };
+    label.to_string()
+}
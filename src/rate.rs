@@ -0,0 +1,101 @@
+//! Token-bucket rate limiting on the outbound path, for keeping chatty
+//! protocols under a message budget.
+
+use crate::time::Clock;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A sustained rate with room for bursts: up to `burst` messages go out
+/// back to back, after which they are spaced `1 / per_sec` seconds apart.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub per_sec: f64,
+    pub burst: u32,
+}
+
+/// Limits on messages to other nodes, across all peers and for each one.
+/// Messages over the limit wait in their peer's queue, so they are delayed
+/// rather than dropped and still go out in order per peer.
+///
+/// Enabled through [`Runtime::rate_limit`](crate::Runtime::rate_limit);
+/// replies to clients and requests to services are never limited.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimit {
+    pub global: Option<Rate>,
+    pub per_peer: Option<Rate>,
+}
+
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    rate: Rate,
+    tokens: f64,
+    refilled: tokio::time::Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(rate: Rate, clock: &dyn Clock) -> Self {
+        assert!(rate.per_sec > 0.0 && rate.burst > 0, "rate must allow some messages");
+        Self {
+            rate,
+            tokens: f64::from(rate.burst),
+            refilled: clock.now(),
+        }
+    }
+
+    /// Takes a token, or says how long until one is available.
+    fn try_take(&mut self, clock: &dyn Clock) -> Result<(), Duration> {
+        let now = clock.now();
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate.per_sec).min(f64::from(self.rate.burst));
+        self.refilled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate.per_sec))
+        }
+    }
+}
+
+/// Waits for a token from `bucket`.
+async fn take(bucket: &Mutex<TokenBucket>, clock: &Arc<dyn Clock>) {
+    loop {
+        let wait = bucket.lock().unwrap().try_take(&**clock);
+        match wait {
+            Ok(()) => return,
+            Err(wait) => clock.sleep(wait).await,
+        }
+    }
+}
+
+/// The runtime's side of a [`RateLimit`]: the bucket shared by every
+/// destination, and the rate each destination's own bucket starts with.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    global: Option<Mutex<TokenBucket>>,
+    per_peer: Option<Rate>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit, clock: &dyn Clock) -> Self {
+        Self {
+            global: limit.global.map(|rate| Mutex::new(TokenBucket::new(rate, clock))),
+            per_peer: limit.per_peer,
+        }
+    }
+
+    pub(crate) fn peer_bucket(&self, clock: &dyn Clock) -> Option<Mutex<TokenBucket>> {
+        self.per_peer.map(|rate| Mutex::new(TokenBucket::new(rate, clock)))
+    }
+
+    /// Waits until one more message may go to the destination owning
+    /// `peer_bucket`.
+    pub(crate) async fn admit(&self, peer_bucket: Option<&Mutex<TokenBucket>>, clock: &Arc<dyn Clock>) {
+        if let Some(bucket) = peer_bucket {
+            take(bucket, clock).await;
+        }
+        if let Some(bucket) = &self.global {
+            take(bucket, clock).await;
+        }
+    }
+}
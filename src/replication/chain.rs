@@ -0,0 +1,406 @@
+//! Chain replication: the replicas form a chain, the head orders commands and
+//! passes them down it, and a command commits once it reaches the tail.
+//!
+//! Each replica passes new entries to its successor in the background, and
+//! the tail acknowledges what it has back up the chain, each replica applying
+//! commands as the acknowledgement passes. The head answers a
+//! [`Chain::submit`] once its command is acknowledged. Every replica holds a
+//! prefix of its predecessor's log, so the tail's state only ever reflects
+//! committed commands and [`Chain::query`] reads it without going through the
+//! chain.
+//!
+//! The chain's membership is a [`ChainConfig`] kept in `lin-kv` under
+//! [`key`], starting from the replicas in the order given. A replica whose
+//! successor stops answering removes that successor and carries on with the
+//! next one, which is told what it lacks. Replicas only learn of a change
+//! when it is in the way: a request carrying an older version is turned away
+//! with [`ErrorCode::PRECONDITION_FAILED`] and the sender re-reads the
+//! configuration. A failed head has no predecessor to notice it, so it has to
+//! be removed with [`Chain::remove`], and like any chain replication, reads at
+//! the tail assume a tail is only removed once it has actually stopped.
+//!
+//! The replicas answer each other themselves: their payload enum carries
+//! `ChainForward(ForwardRequest<C>)` and `ChainAck(ChainAck)` variants, the
+//! former answered with `ChainForwardOk(..)` from [`Chain::forward`] and the
+//! latter handed to [`Chain::ack`].
+
+use super::{Replica, StateMachine};
+use crate::error::{ErrorCode, MaelstromError};
+use crate::kv::Kv;
+use crate::supervise::RestartPolicy;
+use crate::{Body, Ctx, Message};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Notify, oneshot};
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// How long a replica waits on its successor before removing it from the
+    /// chain.
+    pub timeout: Duration,
+    /// How often a replica re-sends to its successor while it has entries the
+    /// tail hasn't acknowledged.
+    pub retry_interval: Duration,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(500),
+            retry_interval: Duration::from_millis(100),
+        }
+    }
+}
+
+/// The chain's members, head first. `version` goes up with every change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainConfig {
+    pub version: u64,
+    pub nodes: Vec<String>,
+}
+
+impl ChainConfig {
+    pub fn head(&self) -> Option<&str> {
+        self.nodes.first().map(String::as_str)
+    }
+
+    pub fn tail(&self) -> Option<&str> {
+        self.nodes.last().map(String::as_str)
+    }
+
+    pub fn successor(&self, node: &str) -> Option<&str> {
+        let i = self.nodes.iter().position(|n| n == node)?;
+        self.nodes.get(i + 1).map(String::as_str)
+    }
+
+    pub fn predecessor(&self, node: &str) -> Option<&str> {
+        let i = self.nodes.iter().position(|n| n == node)?;
+        self.nodes.get(i.checked_sub(1)?).map(String::as_str)
+    }
+}
+
+/// The `lin-kv` key holding the configuration of the chain `name`.
+pub fn key(name: &str) -> String {
+    format!("chain/{}", name)
+}
+
+/// Log entries from position `from` on, sent under configuration `version`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardRequest<C> {
+    pub version: u64,
+    pub from: usize,
+    pub entries: Vec<C>,
+}
+
+/// How much of the log the successor has, and how much of it the tail has
+/// acknowledged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardResponse {
+    pub len: usize,
+    pub committed: usize,
+}
+
+/// The tail has the first `committed` log entries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainAck {
+    pub committed: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
+enum ChainPayload<C> {
+    ChainForward(ForwardRequest<C>),
+    ChainForwardOk(ForwardResponse),
+    ChainAck(ChainAck),
+}
+
+struct State<M: StateMachine> {
+    replica: Replica<M>,
+    /// The successor and how much of the log it is known to have.
+    sent: Option<(String, usize)>,
+    /// At the head, submitters waiting on their command, by log position.
+    waiting: HashMap<usize, oneshot::Sender<M::Output>>,
+}
+
+/// One replica of a chain.
+pub struct Chain<M: StateMachine> {
+    ctx: Ctx,
+    name: String,
+    kv: Kv,
+    options: Options,
+    /// The configuration until the first change is stored.
+    initial: ChainConfig,
+    config: Mutex<ChainConfig>,
+    state: tokio::sync::Mutex<State<M>>,
+    /// Wakes the task that passes entries down the chain.
+    appended: Notify,
+}
+
+impl<M: StateMachine> Chain<M> {
+    /// Starts replicating `machine` along `replicas`, head first, which
+    /// include this node. Every replica must be given them in the same order.
+    pub fn start(ctx: &Ctx, name: impl Into<String>, replicas: &[String], machine: M, options: Options) -> Arc<Self> {
+        let name = name.into();
+        let initial = ChainConfig {
+            version: 0,
+            nodes: replicas.to_vec(),
+        };
+        let chain = Arc::new(Self {
+            ctx: ctx.clone(),
+            kv: Kv::lin(ctx),
+            options,
+            config: Mutex::new(initial.clone()),
+            initial,
+            state: tokio::sync::Mutex::new(State {
+                replica: Replica::new(machine),
+                sent: None,
+                waiting: HashMap::new(),
+            }),
+            appended: Notify::new(),
+            name: name.clone(),
+        });
+        let pass_down = chain.clone();
+        ctx.spawn_supervised(format!("chain/{}", name), RestartPolicy::default(), move |ctx| {
+            pass_down.clone().pass_down(ctx)
+        });
+        chain
+    }
+
+    /// The configuration as this replica last heard of it.
+    pub fn config(&self) -> ChainConfig {
+        self.config.lock().unwrap().clone()
+    }
+
+    fn install(&self, config: ChainConfig) -> ChainConfig {
+        let mut current = self.config.lock().unwrap();
+        if config.version > current.version {
+            *current = config;
+        }
+        current.clone()
+    }
+
+    /// Re-reads the configuration from `lin-kv`.
+    pub async fn refresh(&self) -> anyhow::Result<ChainConfig> {
+        let stored = self.kv.read(&key(&self.name)).await?;
+        Ok(self.install(stored.unwrap_or_else(|| self.initial.clone())))
+    }
+
+    /// Takes `node` out of the chain.
+    pub async fn remove(&self, node: &str) -> anyhow::Result<ChainConfig> {
+        let key = key(&self.name);
+        loop {
+            let stored: Option<ChainConfig> = self.kv.read(&key).await?;
+            let current = stored.clone().unwrap_or_else(|| self.initial.clone());
+            if !current.nodes.iter().any(|n| n == node) {
+                return Ok(self.install(current));
+            }
+            let next = ChainConfig {
+                version: current.version + 1,
+                nodes: current.nodes.iter().filter(|n| *n != node).cloned().collect(),
+            };
+            anyhow::ensure!(!next.nodes.is_empty(), "cannot remove {}, the last node of chain {}", node, self.name);
+            if self.kv.cas(&key, current, next.clone(), stored.is_none()).await? {
+                eprintln!("removed {} from chain {}: {:?}", node, self.name, next.nodes);
+                return Ok(self.install(next));
+            }
+        }
+    }
+
+    /// The configuration, re-read if this replica doesn't play `role` in it.
+    async fn config_where(&self, role: impl Fn(&ChainConfig) -> Option<&str>) -> anyhow::Result<ChainConfig> {
+        let config = self.config();
+        if role(&config) == Some(self.ctx.node_id()) {
+            return Ok(config);
+        }
+        let config = self.refresh().await?;
+        if role(&config) == Some(self.ctx.node_id()) {
+            return Ok(config);
+        }
+        Err(MaelstromError::new(
+            ErrorCode::TEMPORARILY_UNAVAILABLE,
+            format!("{} cannot serve this for chain {}: {:?}", self.ctx.node_id(), self.name, config.nodes),
+        )
+        .into())
+    }
+
+    /// Orders `command` after every earlier one and returns its output once
+    /// the tail has it. Fails with [`ErrorCode::TEMPORARILY_UNAVAILABLE`] on a
+    /// replica that isn't the head, and with [`ErrorCode::CRASH`], which
+    /// leaves the outcome unknown, if the tail doesn't acknowledge it in time.
+    pub async fn submit(&self, command: M::Command) -> anyhow::Result<M::Output> {
+        let config = self.config_where(ChainConfig::head).await?;
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = self.state.lock().await;
+            state.replica.log.push(command);
+            let position = state.replica.log.len() - 1;
+            state.waiting.insert(position, tx);
+            self.settle(&mut state, &config);
+        }
+        self.appended.notify_one();
+
+        // every hop may take up to a timeout, plus a removal on the way
+        let patience = self.options.timeout * 2 * config.nodes.len() as u32;
+        tokio::select! {
+            output = rx => output.map_err(|_| MaelstromError::new(ErrorCode::CRASH, "command dropped").into()),
+            _ = self.ctx.sleep(patience) => Err(MaelstromError::new(
+                ErrorCode::CRASH,
+                format!("chain {} did not acknowledge the command within {:?}", self.name, patience),
+            )
+            .into()),
+        }
+    }
+
+    /// Runs `read` against the tail's state. Fails with
+    /// [`ErrorCode::TEMPORARILY_UNAVAILABLE`] on a replica that isn't the
+    /// tail.
+    pub async fn query<R>(&self, read: impl FnOnce(&M) -> R) -> anyhow::Result<R> {
+        self.config_where(ChainConfig::tail).await?;
+        Ok(read(&self.state.lock().await.replica.machine))
+    }
+
+    /// Applies the log up to `committed` and answers the submitters waiting
+    /// on it.
+    fn commit(&self, state: &mut State<M>, committed: usize) {
+        let State { replica, waiting, .. } = state;
+        replica.apply_to(committed, |position, output| {
+            if let Some(tx) = waiting.remove(&position) {
+                let _ = tx.send(output);
+            }
+        });
+    }
+
+    /// At the tail, commits everything and acknowledges it upstream.
+    fn settle(&self, state: &mut State<M>, config: &ChainConfig) {
+        if config.tail() == Some(self.ctx.node_id()) && state.replica.applied < state.replica.log.len() {
+            let len = state.replica.log.len();
+            self.commit(state, len);
+            self.ack_upstream(config, len);
+        }
+    }
+
+    fn ack_upstream(&self, config: &ChainConfig, committed: usize) {
+        let Some(predecessor) = config.predecessor(self.ctx.node_id()) else {
+            return;
+        };
+        let ack = Message {
+            src: self.ctx.node_id().to_string(),
+            dst: predecessor.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                payload: ChainPayload::<M::Command>::ChainAck(ChainAck { committed }),
+            },
+        };
+        // a lost ack is made up for by the next forward's response
+        if let Err(e) = ack.send(&self.ctx) {
+            eprintln!("chain {}: ack to {} failed: {:#}", self.name, predecessor, e);
+        }
+    }
+
+    /// Answers the predecessor's [`ForwardRequest`].
+    pub async fn forward(&self, from: &str, request: ForwardRequest<M::Command>) -> anyhow::Result<ForwardResponse> {
+        let mut config = self.config();
+        if request.version > config.version {
+            config = self.refresh().await?;
+        }
+        if request.version < config.version || config.predecessor(self.ctx.node_id()) != Some(from) {
+            return Err(MaelstromError::new(
+                ErrorCode::PRECONDITION_FAILED,
+                format!("chain {} is at version {}: {:?}", self.name, config.version, config.nodes),
+            )
+            .into());
+        }
+        let response = {
+            let mut state = self.state.lock().await;
+            state.replica.append(request.from, request.entries);
+            self.settle(&mut state, &config);
+            ForwardResponse {
+                len: state.replica.log.len(),
+                committed: state.replica.applied,
+            }
+        };
+        self.appended.notify_one();
+        Ok(response)
+    }
+
+    /// Takes the successor's [`ChainAck`].
+    pub async fn ack(&self, from: &str, ack: ChainAck) {
+        let config = self.config();
+        if config.successor(self.ctx.node_id()) != Some(from) {
+            return;
+        }
+        let mut state = self.state.lock().await;
+        if ack.committed > state.replica.applied {
+            self.commit(&mut state, ack.committed);
+            self.ack_upstream(&config, state.replica.applied);
+        }
+    }
+
+    /// Passes entries to the successor for as long as the node runs, whenever
+    /// some are appended and every retry interval while some are
+    /// unacknowledged.
+    async fn pass_down(self: Arc<Self>, ctx: Ctx) -> anyhow::Result<()> {
+        loop {
+            tokio::select! {
+                _ = self.appended.notified() => {}
+                _ = ctx.sleep(self.options.retry_interval) => {}
+            }
+            let config = self.config();
+            let Some(next) = config.successor(ctx.node_id()) else {
+                self.settle(&mut *self.state.lock().await, &config);
+                continue;
+            };
+            let request = {
+                let state = self.state.lock().await;
+                let len = state.replica.log.len();
+                // a new successor is first asked how much it has
+                let from = match &state.sent {
+                    Some((to, sent)) if to == next => {
+                        if *sent >= len && state.replica.applied >= len {
+                            continue;
+                        }
+                        (*sent).min(len)
+                    }
+                    _ => len,
+                };
+                ForwardRequest {
+                    version: config.version,
+                    from,
+                    entries: state.replica.log[from..].to_vec(),
+                }
+            };
+            let through = request.from + request.entries.len();
+            let request = ChainPayload::ChainForward(request);
+            match ctx.rpc_timeout(next, request, self.options.timeout).await {
+                Ok(ChainPayload::<M::Command>::ChainForwardOk(response)) => {
+                    let mut state = self.state.lock().await;
+                    state.sent = Some((next.to_string(), response.len));
+                    if response.committed > state.replica.applied {
+                        self.commit(&mut state, response.committed);
+                        self.ack_upstream(&config, state.replica.applied);
+                    }
+                    if response.len < through {
+                        // it lacks earlier entries; send those straight away
+                        self.appended.notify_one();
+                    }
+                }
+                Ok(_) => eprintln!("chain {}: {} answered forward with something else", self.name, next),
+                Err(e) if MaelstromError::code_of(&e) == Some(ErrorCode::PRECONDITION_FAILED) => {
+                    if let Err(e) = self.refresh().await {
+                        eprintln!("chain {}: refreshing the configuration failed: {:#}", self.name, e);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("chain {}: {} did not answer, removing it: {:#}", self.name, next, e);
+                    if let Err(e) = self.remove(next).await {
+                        eprintln!("chain {}: removing {} failed: {:#}", self.name, next, e);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! Replicating a deterministic state machine across nodes.
+//!
+//! A workload describes its state as a [`StateMachine`] and hands commands to
+//! a replication driver, which puts them in one order on every replica before
+//! applying them. Two drivers are available: [`primary_backup`] and
+//! [`chain`].
+
+pub mod chain;
+pub mod primary_backup;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// State changed only by applying commands, one at a time. Replicas that
+/// apply the same commands in the same order end up in the same state, so
+/// `apply` must not depend on anything else (clocks, randomness, which node
+/// it runs on).
+pub trait StateMachine: Send + 'static {
+    type Command: Clone + Serialize + DeserializeOwned + Send + Sync + 'static;
+    type Output: Send + 'static;
+
+    fn apply(&mut self, command: &Self::Command) -> Self::Output;
+}
+
+/// A replica's copy of the command log and the state it has applied.
+struct Replica<M: StateMachine> {
+    machine: M,
+    log: Vec<M::Command>,
+    /// How many log entries have been applied to `machine`.
+    applied: usize,
+}
+
+impl<M: StateMachine> Replica<M> {
+    fn new(machine: M) -> Self {
+        Self {
+            machine,
+            log: Vec::new(),
+            applied: 0,
+        }
+    }
+
+    /// Takes the entries from log position `from` on, unless that would leave
+    /// a gap. Both drivers keep replicas' logs from ever disagreeing, so
+    /// entries already here are the same ones and are skipped.
+    fn append(&mut self, from: usize, entries: Vec<M::Command>) {
+        if from <= self.log.len() {
+            let have = self.log.len() - from;
+            self.log.extend(entries.into_iter().skip(have));
+        }
+    }
+
+    /// Applies the log up to `commit`, handing each output to `done` with
+    /// the position of the command that produced it.
+    fn apply_to(&mut self, commit: usize, mut done: impl FnMut(usize, M::Output)) {
+        while self.applied < commit.min(self.log.len()) {
+            done(self.applied, self.machine.apply(&self.log[self.applied]));
+            self.applied += 1;
+        }
+    }
+}
@@ -0,0 +1,362 @@
+//! Primary-backup replication: one node orders every command and waits for
+//! every backup to acknowledge it before applying it and answering.
+//!
+//! The primary is whichever replica holds the group's [`DistLock`], and it
+//! sends everything under the lock's fencing token, so backups turn away a
+//! deposed primary as soon as they have heard from its successor (see
+//! [`lock`](crate::lock)). A new primary first fetches every backup's log and
+//! adopts the longest: a command is only acknowledged once every replica has
+//! it, so the longest log holds every acknowledged command, and since every
+//! takeover does this, replicas' logs never disagree, only lag. Commands that
+//! reached some replicas but were never acknowledged may take effect later,
+//! which is why a failed [`PrimaryBackup::submit`] is reported as
+//! indeterminate.
+//!
+//! Backups apply commands once the primary reports them committed, which it
+//! does with the next command it replicates. A backup that falls behind or
+//! restarts empty is sent the part of the log it lacks.
+//!
+//! Every replica must be reachable for commands to commit or a primary to
+//! take over: this buys a much smaller protocol than consensus, not
+//! availability under partitions.
+//!
+//! [`Options::learners`] are nodes that follow the log without being
+//! replicas: the primary passes them committed commands in the background,
+//! never waits on them, never fetches from them, and they never contend for
+//! the primary role. They are for observing the state from more nodes and for
+//! warming up a node before it is restarted as a replica.
+//!
+//! The replicas answer each other's requests themselves: their payload enum
+//! carries `Replicate(ReplicateRequest<C>)` and `Fetch(FetchRequest)`
+//! variants, answered with `ReplicateOk(..)` from [`PrimaryBackup::replicate`]
+//! and `FetchOk(..)` from [`PrimaryBackup::fetch`].
+
+use super::{Replica, StateMachine};
+use crate::Ctx;
+use crate::error::{ErrorCode, MaelstromError};
+use crate::fanout;
+use crate::lease::LeaseOptions;
+use crate::lock::{DistLock, LockGuard};
+use crate::supervise::RestartPolicy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Timing of the lock that elects the primary.
+    pub lock: LeaseOptions,
+    /// How long the primary waits on each request to a backup.
+    pub timeout: Duration,
+    /// Nodes outside the replicas that follow the log. A learner starts the
+    /// group with itself in this list, and the same replicas as everyone
+    /// else.
+    pub learners: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            lock: LeaseOptions::default(),
+            timeout: Duration::from_millis(500),
+            learners: Vec::new(),
+        }
+    }
+}
+
+/// Log entries from position `from` on, and how much of the log is
+/// committed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicateRequest<C> {
+    pub from: usize,
+    pub entries: Vec<C>,
+    pub commit: usize,
+}
+
+/// The length of the backup's log once it has taken the entries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicateResponse {
+    pub len: usize,
+}
+
+/// Asks for a replica's log from position `from` on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FetchRequest {
+    pub from: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FetchResponse<C> {
+    pub entries: Vec<C>,
+    pub len: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PrimaryBackupPayload<C> {
+    Replicate(ReplicateRequest<C>),
+    ReplicateOk(ReplicateResponse),
+    Fetch(FetchRequest),
+    FetchOk(FetchResponse<C>),
+}
+
+struct State<M: StateMachine> {
+    replica: Replica<M>,
+    /// While primary, how much of the log each backup is known to have.
+    matched: HashMap<String, usize>,
+    /// While primary, how much of the log each learner is known to have, and
+    /// how much of it it was last told is committed.
+    learned: HashMap<String, (usize, usize)>,
+}
+
+/// One replica of a primary-backup group. Contends for the primary role in
+/// the background from the moment it is started.
+pub struct PrimaryBackup<M: StateMachine> {
+    ctx: Ctx,
+    name: String,
+    backups: Vec<String>,
+    options: Options,
+    state: tokio::sync::Mutex<State<M>>,
+    /// The lock, while this replica is the primary.
+    primary: Mutex<Option<Arc<LockGuard>>>,
+    /// Wakes the task that passes commands to the learners.
+    committed: Notify,
+}
+
+impl<M: StateMachine> PrimaryBackup<M> {
+    /// Starts replicating `machine` among `replicas`, which include this node
+    /// unless it is one of [`Options::learners`]. The primary is elected
+    /// through the lock named `name`.
+    pub fn start(ctx: &Ctx, name: impl Into<String>, replicas: &[String], machine: M, options: Options) -> Arc<Self> {
+        let name = name.into();
+        let group = Arc::new(Self {
+            ctx: ctx.clone(),
+            backups: replicas.iter().filter(|r| *r != ctx.node_id()).cloned().collect(),
+            options,
+            state: tokio::sync::Mutex::new(State {
+                replica: Replica::new(machine),
+                matched: HashMap::new(),
+                learned: HashMap::new(),
+            }),
+            primary: Mutex::new(None),
+            committed: Notify::new(),
+            name: name.clone(),
+        });
+        if group.options.learners.iter().any(|l| l == ctx.node_id()) {
+            return group;
+        }
+        let lead = group.clone();
+        ctx.spawn_supervised(format!("primary-backup/{}", name), RestartPolicy::default(), move |ctx| {
+            lead.clone().lead(ctx)
+        });
+        if !group.options.learners.is_empty() {
+            let teach = group.clone();
+            ctx.spawn_supervised(format!("primary-backup/{}/learners", name), RestartPolicy::default(), move |ctx| {
+                teach.clone().teach(ctx)
+            });
+        }
+        group
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.held().is_some()
+    }
+
+    fn held(&self) -> Option<Arc<LockGuard>> {
+        self.primary.lock().unwrap().clone().filter(|guard| guard.is_held())
+    }
+
+    /// Runs `read` against this replica's state, which on a backup may lag
+    /// the primary's.
+    pub async fn state<R>(&self, read: impl FnOnce(&M) -> R) -> R {
+        read(&self.state.lock().await.replica.machine)
+    }
+
+    /// Orders `command` after every earlier one and returns its output once
+    /// every backup has it. Fails with [`ErrorCode::TEMPORARILY_UNAVAILABLE`]
+    /// on a replica that isn't the primary, and with [`ErrorCode::CRASH`],
+    /// which leaves the outcome unknown, if a backup didn't acknowledge it.
+    pub async fn submit(&self, command: M::Command) -> anyhow::Result<M::Output> {
+        let Some(guard) = self.held() else {
+            return Err(MaelstromError::new(
+                ErrorCode::TEMPORARILY_UNAVAILABLE,
+                format!("{} is not the primary of {}", self.ctx.node_id(), self.name),
+            )
+            .into());
+        };
+        let ctx = self.ctx.fenced(guard.token());
+        let mut state = self.state.lock().await;
+        state.replica.log.push(command);
+        let len = state.replica.log.len();
+
+        let pushes = self.backups.iter().map(|backup| {
+            let matched = state.matched.get(backup).copied().unwrap_or(0);
+            let pushed = self.push(&ctx, backup, &state.replica.log, state.replica.applied, matched);
+            async move { Ok::<_, anyhow::Error>((backup, pushed.await)) }
+        });
+        let pushed = fanout::all(pushes).await?;
+        let mut failed = None;
+        for (backup, result) in pushed {
+            match result {
+                Ok(len) => {
+                    state.matched.insert(backup.clone(), len);
+                }
+                Err(e) => failed = Some(e.context(format!("replicate to {}", backup))),
+            }
+        }
+        if let Some(e) = failed {
+            return Err(MaelstromError::new(ErrorCode::CRASH, format!("{:#}", e)).into());
+        }
+        let mut output = None;
+        state.replica.apply_to(len, |_, applied| output = Some(applied));
+        self.committed.notify_one();
+        Ok(output.expect("the submitted command is past every applied one"))
+    }
+
+    /// Brings `backup`'s log, thought to be `matched` entries long, up to
+    /// `log`. Returns its new length.
+    async fn push(&self, ctx: &Ctx, backup: &str, log: &[M::Command], commit: usize, matched: usize) -> anyhow::Result<usize> {
+        let mut from = matched.min(log.len());
+        loop {
+            let request = PrimaryBackupPayload::Replicate(ReplicateRequest {
+                from,
+                entries: log[from..].to_vec(),
+                commit,
+            });
+            let response = ctx.rpc_timeout(backup, request, self.options.timeout).await?;
+            let PrimaryBackupPayload::<M::Command>::ReplicateOk(ReplicateResponse { len }) = response else {
+                anyhow::bail!("{} answered replicate with something else", backup);
+            };
+            if len >= log.len() {
+                return Ok(len);
+            }
+            // the backup lost entries, e.g. by restarting; resend from there
+            anyhow::ensure!(len < from, "{} has {} entries after taking entries up to {}", backup, len, log.len());
+            from = len;
+        }
+    }
+
+    /// Answers the primary's [`ReplicateRequest`].
+    pub async fn replicate(&self, request: ReplicateRequest<M::Command>) -> ReplicateResponse {
+        let replica = &mut self.state.lock().await.replica;
+        replica.append(request.from, request.entries);
+        replica.apply_to(request.commit, |_, _| {});
+        ReplicateResponse { len: replica.log.len() }
+    }
+
+    /// Answers a new primary's [`FetchRequest`].
+    pub async fn fetch(&self, request: FetchRequest) -> FetchResponse<M::Command> {
+        let replica = &self.state.lock().await.replica;
+        FetchResponse {
+            entries: replica.log.get(request.from..).unwrap_or_default().to_vec(),
+            len: replica.log.len(),
+        }
+    }
+
+    /// Contends for the lock for as long as the node runs, taking over as
+    /// primary whenever this replica gets it.
+    async fn lead(self: Arc<Self>, ctx: Ctx) -> anyhow::Result<()> {
+        let lock = DistLock::new(&ctx, self.options.lock.clone());
+        loop {
+            let guard = Arc::new(lock.acquire(&self.name).await?);
+            while guard.is_held() && !self.is_primary() {
+                match self.take_over(&ctx.fenced(guard.token())).await {
+                    Ok(()) => *self.primary.lock().unwrap() = Some(guard.clone()),
+                    Err(e) => {
+                        eprintln!("taking over {} failed, retrying: {:#}", self.name, e);
+                        ctx.sleep(self.options.lock.renew_interval).await;
+                    }
+                }
+            }
+            while guard.is_held() {
+                ctx.sleep(self.options.lock.renew_interval).await;
+            }
+            *self.primary.lock().unwrap() = None;
+        }
+    }
+
+    /// Passes committed commands to the learners for as long as the node
+    /// runs, whenever some are committed and every renew interval, while this
+    /// replica is the primary.
+    async fn teach(self: Arc<Self>, ctx: Ctx) -> anyhow::Result<()> {
+        loop {
+            tokio::select! {
+                _ = self.committed.notified() => {}
+                _ = ctx.sleep(self.options.lock.renew_interval) => {}
+            }
+            let Some(guard) = self.held() else {
+                continue;
+            };
+            let ctx = ctx.fenced(guard.token());
+            let requests: Vec<_> = {
+                let state = self.state.lock().await;
+                // learners only ever get committed commands
+                let len = state.replica.applied;
+                self.options
+                    .learners
+                    .iter()
+                    .filter_map(|learner| {
+                        // a learner not heard from yet is first asked how much it has
+                        let from = match state.learned.get(learner) {
+                            Some(&(has, told)) if has >= len && told >= len => return None,
+                            Some(&(has, _)) => has.min(len),
+                            None => len,
+                        };
+                        let entries = state.replica.log[from..len].to_vec();
+                        Some((learner, ReplicateRequest { from, entries, commit: len }))
+                    })
+                    .collect()
+            };
+            let (ctx, timeout) = (&ctx, self.options.timeout);
+            let pushes = requests.into_iter().map(|(learner, request)| {
+                async move {
+                    let commit = request.commit;
+                    let request = PrimaryBackupPayload::Replicate(request);
+                    let response = ctx.rpc_timeout(learner, request, timeout).await;
+                    Ok::<_, anyhow::Error>((learner, commit, response))
+                }
+            });
+            let pushed = fanout::all(pushes).await?;
+            let mut state = self.state.lock().await;
+            for (learner, commit, response) in pushed {
+                match response {
+                    Ok(PrimaryBackupPayload::<M::Command>::ReplicateOk(ReplicateResponse { len })) => {
+                        state.learned.insert(learner.clone(), (len, commit));
+                    }
+                    Ok(_) => eprintln!("{}: learner {} answered replicate with something else", self.name, learner),
+                    // a learner is never waited on; it is tried again next time
+                    Err(e) => eprintln!("{}: learner {} did not answer: {:#}", self.name, learner, e),
+                }
+            }
+        }
+    }
+
+    /// Adopts the longest log among the replicas and commits all of it.
+    async fn take_over(&self, ctx: &Ctx) -> anyhow::Result<()> {
+        let mut state = self.state.lock().await;
+        let from = state.replica.log.len();
+        let fetches = self.backups.iter().map(|backup| async move {
+            let request = PrimaryBackupPayload::<M::Command>::Fetch(FetchRequest { from });
+            match ctx.rpc_timeout(backup, request, self.options.timeout).await? {
+                PrimaryBackupPayload::FetchOk(response) => Ok((backup, response)),
+                _ => anyhow::bail!("{} answered fetch with something else", backup),
+            }
+        });
+        let fetched = fanout::all(fetches).await?;
+        state.matched.clear();
+        state.learned.clear();
+        let mut longest = Vec::new();
+        for (backup, response) in fetched {
+            state.matched.insert(backup.clone(), response.len);
+            if response.entries.len() > longest.len() {
+                longest = response.entries;
+            }
+        }
+        state.replica.log.extend(longest);
+        let len = state.replica.log.len();
+        state.replica.apply_to(len, |_, _| {});
+        Ok(())
+    }
+}
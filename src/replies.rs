@@ -0,0 +1,117 @@
+//! Answering repeated client requests from the first answer.
+//!
+//! A client that never heard back, or a network that duplicated its request,
+//! can deliver the same request (same client, same msg_id) to a node twice.
+//! Handling it twice is harmless for a read but not for, say, a kafka `send`,
+//! which would append the message again. With [`Runtime::reply_cache`] set,
+//! the runtime keeps each reply it sends a client and answers a repeat of
+//! the request by resending it, without the node seeing the request again; a
+//! repeat of one still being handled is dropped, as its reply is on the way.
+//! A request whose handler finished without replying, by failing or by
+//! leaving the reply to a later handler, is forgotten, so a repeat of it is
+//! handled again rather than dropped until the TTL runs out.
+//! Requests are remembered for the cache's TTL from when they first
+//! arrived, after which a repeat counts as a new request.
+//!
+//! [`Runtime::reply_cache`]: crate::Runtime::reply_cache
+
+use crate::time::Clock;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug)]
+pub(crate) struct ReplyCache {
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// Replies by client and the msg_id of its request; `None` while the
+    /// request is being handled.
+    replies: HashMap<(String, u64), Option<Vec<u8>>>,
+    /// When each request arrived, oldest first.
+    arrived: VecDeque<(Instant, (String, u64))>,
+}
+
+/// What to do with a client request.
+#[derive(Debug)]
+pub(crate) enum Seen {
+    /// Handle it.
+    New,
+    /// Drop it: the same request is still being handled.
+    InFlight,
+    /// Send this, the reply to the same request, instead.
+    Answered(Vec<u8>),
+}
+
+/// Just enough of a message to tell what it answers.
+#[derive(Deserialize)]
+struct Probe {
+    body: ProbeBody,
+}
+
+#[derive(Deserialize)]
+struct ProbeBody {
+    in_reply_to: Option<u64>,
+}
+
+impl ReplyCache {
+    pub(crate) fn new(ttl: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            ttl,
+            clock,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Notes a request `msg_id` from `client`, saying whether it has been
+    /// seen before.
+    pub(crate) fn request(&self, client: &str, msg_id: u64) -> Seen {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        while let Some((arrived, _)) = entries.arrived.front()
+            && now.saturating_duration_since(*arrived) >= self.ttl
+        {
+            let (_, key) = entries.arrived.pop_front().unwrap();
+            entries.replies.remove(&key);
+        }
+        let key = (client.to_string(), msg_id);
+        match entries.replies.get(&key) {
+            Some(Some(reply)) => Seen::Answered(reply.clone()),
+            Some(None) => Seen::InFlight,
+            None => {
+                entries.replies.insert(key.clone(), None);
+                entries.arrived.push_back((now, key));
+                Seen::New
+            }
+        }
+    }
+
+    /// Keeps `line`, a message to `client`, if it is the first reply to a
+    /// request being handled.
+    pub(crate) fn reply(&self, client: &str, line: &[u8]) {
+        let Some(msg_id) = serde_json::from_slice::<Probe>(line).ok().and_then(|probe| probe.body.in_reply_to) else {
+            return;
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(reply @ None) = entries.replies.get_mut(&(client.to_string(), msg_id)) {
+            *reply = Some(line.to_vec());
+        }
+    }
+
+    /// Forgets request `msg_id` from `client` if its handler has finished
+    /// without replying.
+    pub(crate) fn finished(&self, client: &str, msg_id: u64) {
+        let key = (client.to_string(), msg_id);
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(None) = entries.replies.get(&key) {
+            entries.replies.remove(&key);
+            entries.arrived.retain(|(_, arrived)| *arrived != key);
+        }
+    }
+}
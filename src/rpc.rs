@@ -0,0 +1,118 @@
+//! Request/reply correlation for RPCs issued through [`Ctx::rpc`](crate::Ctx::rpc).
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::{Notify, oneshot};
+
+/// Replies the runtime is waiting for, keyed by the peer they were sent to
+/// and the request's msg_id. The input task consults this before handing a
+/// message to the node, so RPC replies never reach `Node::step`.
+///
+/// Entries are spread over [`SHARDS`] maps by msg_id, so concurrent RPCs
+/// rarely contend for the same lock.
+#[derive(Debug)]
+pub(crate) struct PendingReplies {
+    shards: Box<[Mutex<Waiting>]>,
+    /// Woken whenever an entry leaves `shards`.
+    finished: Notify,
+}
+
+type Waiting = HashMap<(String, usize), oneshot::Sender<Value>>;
+
+const SHARDS: usize = 16;
+
+impl Default for PendingReplies {
+    fn default() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+            finished: Notify::new(),
+        }
+    }
+}
+
+impl PendingReplies {
+    fn shard(&self, msg_id: usize) -> &Mutex<Waiting> {
+        &self.shards[msg_id % SHARDS]
+    }
+
+    pub(crate) fn register(&self, peer: &str, msg_id: usize) -> oneshot::Receiver<Value> {
+        let (tx, rx) = oneshot::channel();
+        self.shard(msg_id)
+            .lock()
+            .unwrap()
+            .insert((peer.to_string(), msg_id), tx);
+        rx
+    }
+
+    pub(crate) fn cancel(&self, peer: &str, msg_id: usize) {
+        let removed = self
+            .shard(msg_id)
+            .lock()
+            .unwrap()
+            .remove(&(peer.to_string(), msg_id));
+        if removed.is_some() {
+            self.finished.notify_waiters();
+        }
+    }
+
+    /// Waits until every RPC registered before the call has been answered or
+    /// cancelled.
+    pub(crate) async fn settle(&self) {
+        let issued: Vec<_> = self
+            .shards
+            .iter()
+            .flat_map(|shard| shard.lock().unwrap().keys().cloned().collect::<Vec<_>>())
+            .collect();
+        loop {
+            let finished = self.finished.notified();
+            tokio::pin!(finished);
+            finished.as_mut().enable();
+            let outstanding = issued
+                .iter()
+                .any(|key| self.shard(key.1).lock().unwrap().contains_key(key));
+            if !outstanding {
+                return;
+            }
+            finished.await;
+        }
+    }
+
+    /// Delivers `msg` to the RPC waiting on it, if any. Returns the message
+    /// back if nobody was waiting.
+    pub(crate) fn resolve(&self, msg: Value) -> Option<Value> {
+        let (Some(src), Some(in_reply_to)) = (
+            msg.get("src").and_then(Value::as_str),
+            msg["body"].get("in_reply_to").and_then(Value::as_u64),
+        ) else {
+            return Some(msg);
+        };
+        let in_reply_to = in_reply_to as usize;
+        let tx = self
+            .shard(in_reply_to)
+            .lock()
+            .unwrap()
+            .remove(&(src.to_string(), in_reply_to));
+        match tx {
+            Some(tx) => {
+                self.finished.notify_waiters();
+                let _ = tx.send(msg);
+                None
+            }
+            None => Some(msg),
+        }
+    }
+}
+
+/// Cancels an RPC's pending entry when dropped.
+pub(crate) struct PendingGuard<'a> {
+    pub(crate) pending: &'a PendingReplies,
+    pub(crate) dst: &'a str,
+    pub(crate) msg_id: usize,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.pending.cancel(self.dst, self.msg_id);
+    }
+}
@@ -0,0 +1,121 @@
+//! A built-in sanity check for node binaries, run with `--selftest` before
+//! spending minutes on a Maelstrom run.
+//!
+//! A [`Script`] is a list of requests and the replies they should get. [`run`]
+//! drives the node in-process as `n1` of a one-node cluster, with every
+//! Maelstrom service emulated, sends each request from `c1` in turn and
+//! checks the reply. An expected reply only needs to list the fields worth
+//! checking: objects match if every expected field matches, so
+//! `{"type": "generate_ok"}` accepts any id.
+
+use crate::client::MaelstromClient;
+use crate::emulate::Emulation;
+use crate::{Coalescible, Node, Runtime};
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::time::Duration;
+use tokio::io::{BufReader, DuplexStream};
+
+/// The argument that selects the self-test.
+pub const FLAG: &str = "--selftest";
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether the process was started with [`FLAG`].
+pub fn requested() -> bool {
+    std::env::args().skip(1).any(|arg| arg == FLAG)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    Exchange(Value, Value),
+    Send(String, Value),
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends the body `request` and expects a reply whose body matches
+    /// `reply`. `msg_id` and `in_reply_to` are filled in by the test.
+    pub fn exchange(mut self, request: Value, reply: Value) -> Self {
+        self.steps.push(Step::Exchange(request, reply));
+        self
+    }
+
+    /// Sends the body `body` as if from `src`, e.g. a service answering a
+    /// request the node has stopped waiting for, and expects no reply. Later
+    /// exchanges check the node survived it.
+    pub fn send(mut self, src: impl Into<String>, body: Value) -> Self {
+        self.steps.push(Step::Send(src.into(), body));
+        self
+    }
+}
+
+/// Runs `script` against the node and reports the outcome on stderr; fails on
+/// the first reply that doesn't match or doesn't arrive.
+pub async fn run<S, N, P, SP, IP>(runtime: Runtime, init_state: S, script: Script) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + Send + 'static,
+    SP: DeserializeOwned + Send + 'static,
+    N: Node<S, P, SP, IP> + Send + 'static,
+    IP: Coalescible + Send + 'static,
+{
+    let (input, node_input) = tokio::io::duplex(64 * 1024);
+    let (node_output, output) = tokio::io::duplex(64 * 1024);
+    let node = runtime.emulate(Emulation::all()).run_with_io::<S, N, P, SP, IP>(
+        init_state,
+        BufReader::new(node_input),
+        node_output,
+    );
+    // the node keeps running (and its timers firing) until the script is done
+    let result = tokio::select! {
+        result = drive(input, output, script) => result,
+        result = node => match result {
+            Ok(()) => Err(anyhow::anyhow!("node exited before the script finished")),
+            Err(e) => Err(e.context("node failed")),
+        },
+    };
+    if result.is_ok() {
+        eprintln!("selftest passed");
+    }
+    result
+}
+
+/// [`run`] on a tokio runtime of its own, of the kind the runtime's
+/// [`flavor`](Runtime::flavor) picks, like [`Runtime::start`] does for a real
+/// run. For a `main` without `#[tokio::main]`.
+pub fn start<S, N, P, SP, IP>(runtime: Runtime, init_state: S, script: Script) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + Send + 'static,
+    SP: DeserializeOwned + Send + 'static,
+    N: Node<S, P, SP, IP> + Send + 'static,
+    IP: Coalescible + Send + 'static,
+{
+    runtime.flavor.main(run::<S, N, P, SP, IP>(runtime, init_state, script))
+}
+
+async fn drive(input: DuplexStream, output: DuplexStream, script: Script) -> anyhow::Result<()> {
+    let mut client = MaelstromClient::new("c1", "n1", input, BufReader::new(output)).with_timeout(REPLY_TIMEOUT);
+    let init = json!({"type": "init", "node_id": "n1", "node_ids": ["n1"]});
+    let steps = std::iter::once(Step::Exchange(init, json!({"type": "init_ok"}))).chain(script.steps);
+    for step in steps {
+        match step {
+            Step::Exchange(request, expected) => {
+                let reply = client.expect(request.clone(), expected).await?;
+                eprintln!("selftest: {} -> {}", request["type"], reply);
+            }
+            Step::Send(src, body) => {
+                eprintln!("selftest: {} from {}", body["type"], src);
+                client.send_from(&src, body).await?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,86 @@
+//! Which Maelstrom service a node uses for each role.
+//!
+//! A node asks for a service by the role it plays, e.g. `ctx.service("kv")`,
+//! rather than naming `seq-kv` outright, so a run can point it at `lin-kv`
+//! instead without a code change. Roles are mapped, lowest precedence first:
+//!
+//! * by [`Runtime::default_service`](crate::Runtime::default_service);
+//! * by a `services` object in the `init` body, e.g.
+//!   `{"type": "init", ..., "services": {"kv": "lin-kv"}}`;
+//! * by the knob `service-<role>`, i.e. `--service-kv lin-kv` or
+//!   `DIST_SYS_SERVICE_KV=lin-kv`, for a runtime built by
+//!   [`Runtime::from_env`](crate::Runtime::from_env) or
+//!   [`Runtime::from_config`](crate::Runtime::from_config); see
+//!   [`config`](crate::config).
+//!
+//! A role that isn't mapped is taken as the service's own name.
+
+use crate::Ctx;
+use crate::config::NodeConfig;
+use anyhow::Context;
+use std::collections::HashMap;
+
+/// Prefix of the knobs that map roles to services.
+pub const KNOB_PREFIX: &str = "service-";
+
+/// The field of the `init` body that maps roles to services.
+pub const INIT_FIELD: &str = "services";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Services {
+    names: HashMap<String, String>,
+}
+
+impl Services {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps `role` to `service`.
+    pub fn with(mut self, role: impl Into<String>, service: impl Into<String>) -> Self {
+        self.names.insert(role.into(), service.into());
+        self
+    }
+
+    /// The `service-<role>` knobs in `config`.
+    pub fn from_config(config: &NodeConfig) -> Self {
+        let names = config
+            .prefixed(KNOB_PREFIX)
+            .map(|(role, service)| (role.to_string(), service.to_string()))
+            .collect();
+        Self { names }
+    }
+
+    /// The [`INIT_FIELD`] of an `init` message's body, if it has one.
+    pub fn from_init(body: &serde_json::Value) -> anyhow::Result<Self> {
+        let Some(field) = body.get(INIT_FIELD) else {
+            return Ok(Self::default());
+        };
+        let names = serde_json::from_value(field.clone())
+            .with_context(|| format!("init field {:?} should map roles to service names", INIT_FIELD))?;
+        Ok(Self { names })
+    }
+
+    /// The mappings of `self`, overridden by those of `other`.
+    pub fn merge(mut self, other: Services) -> Self {
+        self.names.extend(other.names);
+        self
+    }
+
+    /// The service `role` is mapped to, if it is.
+    pub fn get(&self, role: &str) -> Option<&str> {
+        self.names.get(role).map(String::as_str)
+    }
+
+    /// The service `role` is mapped to, or `role` itself.
+    pub fn resolve<'a>(&'a self, role: &'a str) -> &'a str {
+        self.get(role).unwrap_or(role)
+    }
+}
+
+impl Ctx {
+    /// The Maelstrom service to send `role`'s requests to; see [`services`](crate::services).
+    pub fn service<'a>(&'a self, role: &'a str) -> &'a str {
+        self.services.resolve(role)
+    }
+}
@@ -0,0 +1,63 @@
+//! Unix signals, delivered to the node as events.
+//!
+//! A node run over stdio by [`Runtime::run`], or any run after
+//! [`Runtime::signals`], hears of `SIGUSR1` as an
+//! [`Event::Signal`]`(`[`Signal::DumpStats`]`)`, for `kill -USR1` to make it
+//! report on itself partway through a long local run. `SIGTERM` arrives as
+//! [`Signal::Terminate`] and then shuts the node down as the end of its
+//! input would: no more messages are read, the handlers running finish and
+//! the output is flushed, so whatever they send still goes out. Elsewhere
+//! than on Unix, no signals are delivered.
+//!
+//! [`Runtime::run`]: crate::Runtime::run
+//! [`Runtime::signals`]: crate::Runtime::signals
+//! [`Event::Signal`]: crate::Event::Signal
+
+/// A signal the process received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// `SIGTERM`: the node is about to shut down.
+    Terminate,
+    /// `SIGUSR1`: report statistics or other diagnostics, e.g. on stderr.
+    DumpStats,
+}
+
+/// The signals the runtime listens for, if any.
+pub(crate) struct Signals {
+    #[cfg(unix)]
+    streams: Option<(tokio::signal::unix::Signal, tokio::signal::unix::Signal)>,
+}
+
+impl Signals {
+    /// Listens for signals if `enabled`. From then on, the process no longer
+    /// dies of them.
+    pub(crate) fn new(enabled: bool) -> std::io::Result<Self> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{SignalKind, signal};
+            let streams = match enabled {
+                true => Some((signal(SignalKind::terminate())?, signal(SignalKind::user_defined1())?)),
+                false => None,
+            };
+            Ok(Self { streams })
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = enabled;
+            Ok(Self {})
+        }
+    }
+
+    /// The next signal; never returns when not listening.
+    pub(crate) async fn recv(&mut self) -> Signal {
+        #[cfg(unix)]
+        if let Some((terminate, dump_stats)) = &mut self.streams {
+            tokio::select! {
+                Some(()) = terminate.recv() => return Signal::Terminate,
+                Some(()) = dump_stats.recv() => return Signal::DumpStats,
+                else => {}
+            }
+        }
+        std::future::pending().await
+    }
+}
@@ -0,0 +1,171 @@
+//! Linearizability checking over recorded histories.
+//!
+//! This is the Wing & Gong search with Lowe's memoization of already-explored
+//! (linearized set, model state) pairs: repeatedly pick an operation that was
+//! invoked before every remaining operation returned, apply it to the model,
+//! and backtrack when the model rejects the observed output.
+
+use super::Operation;
+use std::collections::HashSet;
+use std::hash::Hash;
+
+/// A sequential specification the history is checked against.
+pub trait Model {
+    type State: Clone + Eq + Hash;
+    type Input;
+    type Output;
+
+    fn init(&self) -> Self::State;
+
+    /// Applies `input` to `state`. `output` is `None` for operations that never
+    /// completed, in which case any output is acceptable. Returns `None` if the
+    /// observed output is impossible from `state`.
+    fn step(
+        &self,
+        state: &Self::State,
+        input: &Self::Input,
+        output: Option<&Self::Output>,
+    ) -> Option<Self::State>;
+}
+
+/// Returns a valid linearization of `history` (as indices into it), or `None`
+/// if there isn't one. Incomplete operations may be left out of the order.
+pub fn linearize<M: Model>(model: &M, history: &[Operation<M::Input, M::Output>]) -> Option<Vec<usize>> {
+    let mut search = Search {
+        model,
+        history,
+        done: vec![false; history.len()],
+        order: Vec::with_capacity(history.len()),
+        seen: HashSet::new(),
+    };
+    let state = model.init();
+    search.explore(&state).then_some(search.order)
+}
+
+pub fn is_linearizable<M: Model>(model: &M, history: &[Operation<M::Input, M::Output>]) -> bool {
+    linearize(model, history).is_some()
+}
+
+struct Search<'a, M: Model> {
+    model: &'a M,
+    history: &'a [Operation<M::Input, M::Output>],
+    done: Vec<bool>,
+    order: Vec<usize>,
+    seen: HashSet<(Vec<bool>, M::State)>,
+}
+
+impl<M: Model> Search<'_, M> {
+    fn explore(&mut self, state: &M::State) -> bool {
+        // an operation can go next only if it was invoked before the earliest
+        // return among the operations that are still outstanding
+        let horizon = self
+            .history
+            .iter()
+            .zip(&self.done)
+            .filter(|(_, done)| !**done)
+            .filter_map(|(op, _)| op.ret)
+            .min();
+        let Some(horizon) = horizon else {
+            // only incomplete operations remain; they are allowed to never take effect
+            return true;
+        };
+
+        if !self.seen.insert((self.done.clone(), state.clone())) {
+            return false;
+        }
+
+        for i in 0..self.history.len() {
+            let op = &self.history[i];
+            if self.done[i] || op.call > horizon {
+                continue;
+            }
+            let Some(next) = self.model.step(state, &op.input, op.output.as_ref()) else {
+                continue;
+            };
+            self.done[i] = true;
+            self.order.push(i);
+            if self.explore(&next) {
+                return true;
+            }
+            self.order.pop();
+            self.done[i] = false;
+        }
+        false
+    }
+}
+
+/// Grow-only counter: `Add(delta)` always succeeds, `Read` returns the sum.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CounterModel;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CounterOp {
+    Add(usize),
+    Read,
+}
+
+impl Model for CounterModel {
+    type State = usize;
+    type Input = CounterOp;
+    type Output = Option<usize>;
+
+    fn init(&self) -> usize {
+        0
+    }
+
+    fn step(&self, state: &usize, input: &CounterOp, output: Option<&Option<usize>>) -> Option<usize> {
+        match input {
+            CounterOp::Add(delta) => Some(state + delta),
+            CounterOp::Read => match output {
+                Some(Some(value)) if value != state => None,
+                _ => Some(*state),
+            },
+        }
+    }
+}
+
+/// Single lin-kv style register supporting read, write and compare-and-set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RegisterModel;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegisterOp {
+    Read,
+    Write(u64),
+    Cas { from: u64, to: u64 },
+}
+
+/// `Read` outputs the value (`None` if unset); `Write` and `Cas` output
+/// whether they succeeded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegisterResult {
+    Value(Option<u64>),
+    Ok(bool),
+}
+
+impl Model for RegisterModel {
+    type State = Option<u64>;
+    type Input = RegisterOp;
+    type Output = RegisterResult;
+
+    fn init(&self) -> Option<u64> {
+        None
+    }
+
+    fn step(&self, state: &Option<u64>, input: &RegisterOp, output: Option<&RegisterResult>) -> Option<Option<u64>> {
+        match (input, output) {
+            (RegisterOp::Read, None) => Some(*state),
+            (RegisterOp::Read, Some(RegisterResult::Value(v))) => (v == state).then_some(*state),
+            (RegisterOp::Write(v), None | Some(RegisterResult::Ok(true))) => Some(Some(*v)),
+            (RegisterOp::Write(_), Some(RegisterResult::Ok(false))) => Some(*state),
+            (RegisterOp::Cas { from, to }, None) => Some(if *state == Some(*from) { Some(*to) } else { *state }),
+            (RegisterOp::Cas { from, to }, Some(RegisterResult::Ok(true))) => {
+                (*state == Some(*from)).then_some(Some(*to))
+            }
+            (RegisterOp::Cas { from, .. }, Some(RegisterResult::Ok(false))) => {
+                (*state != Some(*from)).then_some(*state)
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,309 @@
+//! A cluster of nodes run in-process over a simulated network, for
+//! exercising partitions, crashes and clock skew and drift without
+//! Maelstrom.
+//!
+//! Everything a node writes goes through the [`Cluster`]'s router: to another
+//! node unless a partition separates the two, to a Maelstrom service emulated
+//! once for the whole cluster (see [`emulate`](crate::emulate)), or back to
+//! the client waiting on it. Nodes are `n1`, `n2`, ... and clients may use
+//! any other name.
+
+use crate::emulate::{Emulation, Emulator};
+use crate::time::{SkewedClock, TokioClock};
+use crate::{Coalescible, Node, Runtime};
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+/// The client that initializes nodes.
+const ADMIN: &str = "c0";
+const INIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+type NodeFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+type Launch = Box<dyn Fn(SkewedClock, BufReader<DuplexStream>, DuplexStream) -> NodeFuture + Send + Sync>;
+
+pub struct Cluster {
+    node_ids: Vec<String>,
+    launch: Launch,
+    net: Arc<Network>,
+    members: Mutex<HashMap<String, Member>>,
+}
+
+#[derive(Debug)]
+struct Member {
+    /// Outlives restarts, like a machine's clock.
+    clock: SkewedClock,
+    /// While the node runs.
+    task: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for Cluster {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cluster").field("node_ids", &self.node_ids).finish_non_exhaustive()
+    }
+}
+
+impl Cluster {
+    /// `nodes` nodes, each run by the runtime and with the initial state
+    /// `node` returns, with every Maelstrom service emulated. The runtime's
+    /// clock and emulation are replaced with the cluster's. Nothing runs
+    /// until [`Cluster::start`].
+    pub fn new<S, N, P, SP, IP>(nodes: usize, node: impl Fn() -> (Runtime, S) + Send + Sync + 'static) -> Self
+    where
+        S: Send + 'static,
+        P: DeserializeOwned + Send + 'static,
+        SP: DeserializeOwned + Send + 'static,
+        N: Node<S, P, SP, IP> + Send + 'static,
+        IP: Coalescible + Send + 'static,
+    {
+        let node_ids: Vec<String> = (1..=nodes).map(|i| format!("n{}", i)).collect();
+        let launch: Launch = Box::new(move |clock, input, output| {
+            let (mut runtime, state) = node();
+            runtime.emulation = None;
+            Box::pin(runtime.clock(clock).run_with_io::<S, N, P, SP, IP>(state, input, output))
+        });
+        let members = node_ids
+            .iter()
+            .map(|id| {
+                let member = Member {
+                    clock: SkewedClock::new(TokioClock),
+                    task: None,
+                };
+                (id.clone(), member)
+            })
+            .collect();
+        Self {
+            net: Arc::new(Network {
+                node_ids: node_ids.clone(),
+                emulator: Emulator::new(Emulation::all()),
+                state: Mutex::default(),
+            }),
+            node_ids,
+            launch,
+            members: Mutex::new(members),
+        }
+    }
+
+    pub fn node_ids(&self) -> &[String] {
+        &self.node_ids
+    }
+
+    /// Starts and initializes every node.
+    pub async fn start(&self) -> anyhow::Result<()> {
+        for id in &self.node_ids {
+            self.restart(id).await?;
+        }
+        Ok(())
+    }
+
+    /// Whether `node` is running, i.e. started and not since killed.
+    pub fn is_up(&self, node: &str) -> bool {
+        self.members.lock().unwrap().get(node).is_some_and(|member| member.task.is_some())
+    }
+
+    /// Stops `node` at once, losing whatever it hadn't persisted. Messages to
+    /// it are dropped until it restarts.
+    pub fn kill(&self, node: &str) {
+        self.net.state.lock().unwrap().inputs.remove(node);
+        let task = self.members.lock().unwrap().get_mut(node).and_then(|member| member.task.take());
+        if let Some(task) = task {
+            task.abort();
+        }
+    }
+
+    /// Starts `node` afresh, killing it first if it is running, and waits
+    /// for it to acknowledge `init`.
+    pub async fn restart(&self, node: &str) -> anyhow::Result<()> {
+        self.kill(node);
+        let clock = match self.members.lock().unwrap().get(node) {
+            Some(member) => member.clock.clone(),
+            None => anyhow::bail!("no node {} in the cluster", node),
+        };
+        let (input, node_input) = tokio::io::duplex(64 * 1024);
+        let (node_output, output) = tokio::io::duplex(64 * 1024);
+        let run = (self.launch)(clock, BufReader::new(node_input), node_output);
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.net.state.lock().unwrap().inputs.insert(node.to_string(), tx);
+        let task = tokio::spawn(drive(node.to_string(), run, input, rx, output, self.net.clone()));
+        if let Some(member) = self.members.lock().unwrap().get_mut(node) {
+            member.task = Some(task);
+        }
+
+        let init = json!({"type": "init", "node_id": node, "node_ids": self.node_ids});
+        let reply = self.request(ADMIN, node, init, INIT_TIMEOUT).await;
+        anyhow::ensure!(
+            reply.as_ref().is_some_and(|body| body["type"] == "init_ok"),
+            "{} did not acknowledge init: {:?}",
+            node,
+            reply
+        );
+        Ok(())
+    }
+
+    /// Cuts the network into `groups`: nodes only reach nodes in the same
+    /// group, and nodes in no group reach nobody. Clients and services still
+    /// reach everyone. Replaces any earlier partition.
+    pub fn partition(&self, groups: &[Vec<String>]) {
+        let mut state = self.net.state.lock().unwrap();
+        state.components = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(i, group)| group.iter().map(move |node| (node.clone(), i)))
+            .collect();
+        state.partitioned = true;
+    }
+
+    /// Lifts any partition.
+    pub fn heal(&self) {
+        let mut state = self.net.state.lock().unwrap();
+        state.components.clear();
+        state.partitioned = false;
+    }
+
+    /// Sets how far ahead of true time `node`'s clock reads, in milliseconds;
+    /// negative if behind. Any drift continues from there. Kept across
+    /// restarts.
+    pub fn skew(&self, node: &str, offset_ms: i64) {
+        if let Some(member) = self.members.lock().unwrap().get(node) {
+            member.clock.set_offset_ms(offset_ms);
+        }
+    }
+
+    /// Makes `node`'s clock run fast by `drift_ppm` parts per million, or
+    /// slow if negative, from what it reads now. Kept across restarts.
+    pub fn drift(&self, node: &str, drift_ppm: i64) {
+        if let Some(member) = self.members.lock().unwrap().get(node) {
+            member.clock.set_drift_ppm(drift_ppm);
+        }
+    }
+
+    /// Sends the body `body` from `client` to `node` and returns the reply's
+    /// body, or `None` if none came within `timeout`, e.g. because `node` is
+    /// down. A `msg_id` is filled in.
+    pub async fn request(&self, client: &str, node: &str, mut body: Value, timeout: Duration) -> Option<Value> {
+        let (tx, rx) = oneshot::channel();
+        let msg_id = {
+            let mut state = self.net.state.lock().unwrap();
+            state.next_msg_id += 1;
+            let msg_id = state.next_msg_id;
+            body["msg_id"] = msg_id.into();
+            state.waiting.insert((client.to_string(), msg_id), tx);
+            if let Some(input) = state.inputs.get(node) {
+                let _ = input.send(json!({"src": client, "dest": node, "body": body}).to_string());
+            }
+            msg_id
+        };
+        let reply = tokio::time::timeout(timeout, rx).await.ok().and_then(Result::ok);
+        self.net.state.lock().unwrap().waiting.remove(&(client.to_string(), msg_id));
+        reply
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        for member in self.members.get_mut().unwrap().values_mut() {
+            if let Some(task) = member.task.take() {
+                task.abort();
+            }
+        }
+    }
+}
+
+/// Runs a node, feeding it what the network delivers and routing what it
+/// writes, until it exits.
+async fn drive(
+    id: String,
+    run: NodeFuture,
+    mut input: DuplexStream,
+    mut deliveries: mpsc::UnboundedReceiver<String>,
+    output: DuplexStream,
+    net: Arc<Network>,
+) {
+    let feed = async move {
+        while let Some(line) = deliveries.recv().await {
+            if input.write_all(format!("{}\n", line).as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    };
+    let route = async {
+        let mut lines = BufReader::new(output).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            net.route(&line);
+        }
+    };
+    let run = async {
+        if let Err(e) = run.await {
+            eprintln!("{} stopped: {:#}", id, e);
+        }
+    };
+    // the node only sees EOF when it is killed, which drops all of this
+    tokio::join!(feed, route, run);
+}
+
+#[derive(Debug)]
+struct Network {
+    node_ids: Vec<String>,
+    emulator: Emulator,
+    state: Mutex<NetState>,
+}
+
+#[derive(Debug, Default)]
+struct NetState {
+    /// Running nodes' inputs.
+    inputs: HashMap<String, mpsc::UnboundedSender<String>>,
+    partitioned: bool,
+    /// Which group of the partition each node is in.
+    components: HashMap<String, usize>,
+    /// Clients' requests awaiting replies, by client and msg_id.
+    waiting: HashMap<(String, u64), oneshot::Sender<Value>>,
+    next_msg_id: u64,
+}
+
+impl NetState {
+    fn separated(&self, a: &str, b: &str) -> bool {
+        let apart = match (self.components.get(a), self.components.get(b)) {
+            (Some(a), Some(b)) => a != b,
+            _ => true,
+        };
+        self.partitioned && a != b && apart
+    }
+}
+
+impl Network {
+    fn route(&self, line: &str) {
+        let Ok(msg) = serde_json::from_str::<Value>(line) else {
+            return;
+        };
+        let (Some(src), Some(dest)) = (msg["src"].as_str(), msg["dest"].as_str()) else {
+            return;
+        };
+        if self.emulator.handles(dest) {
+            if let Some((reply, _)) = self.emulator.handle(&msg)
+                && let Some(input) = self.state.lock().unwrap().inputs.get(src)
+            {
+                let _ = input.send(reply);
+            }
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if self.node_ids.iter().any(|id| id == dest) {
+            if !state.separated(src, dest)
+                && let Some(input) = state.inputs.get(dest)
+            {
+                let _ = input.send(line.to_string());
+            }
+        } else if let Some(in_reply_to) = msg["body"]["in_reply_to"].as_u64()
+            && let Some(waiting) = state.waiting.remove(&(dest.to_string(), in_reply_to))
+        {
+            let _ = waiting.send(msg["body"].clone());
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Histories in Jepsen's EDN form, for checking with Elle or Knossos.
+//!
+//! Jepsen records each operation as an `:invoke` event when it starts and an
+//! `:ok`, `:fail` or `:info` event when it ends:
+//!
+//! ```text
+//! {:index 0, :type :invoke, :process 0, :time 0, :f :txn, :value [[:append 3 1] [:r 4 nil]]}
+//! {:index 1, :type :ok, :process 0, :time 1, :f :txn, :value [[:append 3 1] [:r 4 [2]]]}
+//! ```
+//!
+//! `:fail` means the operation certainly didn't take effect and `:info` that
+//! it may have; operations that never completed end with an `:info`.
+//! [`history`] renders a recorded [`History`](super::History) this way, one
+//! event per line, given how each input and output reads in Jepsen's terms.
+
+use super::Operation;
+use serde_json::Value;
+use std::fmt;
+
+/// An EDN value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edn {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Keyword(String),
+    Vector(Vec<Edn>),
+    Map(Vec<(Edn, Edn)>),
+}
+
+impl Edn {
+    pub fn keyword(name: impl Into<String>) -> Self {
+        Self::Keyword(name.into())
+    }
+}
+
+impl fmt::Display for Edn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Edn::Nil => f.write_str("nil"),
+            Edn::Bool(b) => write!(f, "{}", b),
+            Edn::Int(n) => write!(f, "{}", n),
+            Edn::Float(x) => write!(f, "{:?}", x),
+            // JSON string escapes are valid EDN ones
+            Edn::String(s) => write!(f, "{}", Value::String(s.clone())),
+            Edn::Keyword(k) => write!(f, ":{}", k),
+            Edn::Vector(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str("]")
+            }
+            Edn::Map(entries) => {
+                f.write_str("{")?;
+                for (i, (k, v)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{} {}", k, v)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+/// JSON as EDN: arrays become vectors and objects maps with keyword keys.
+impl From<&Value> for Edn {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => Edn::Nil,
+            Value::Bool(b) => Edn::Bool(*b),
+            Value::Number(n) => match n.as_i64() {
+                Some(n) => Edn::Int(n),
+                None => Edn::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            Value::String(s) => Edn::String(s.clone()),
+            Value::Array(items) => Edn::Vector(items.iter().map(Edn::from).collect()),
+            Value::Object(entries) => Edn::Map(entries.iter().map(|(k, v)| (Edn::keyword(k), Edn::from(v))).collect()),
+        }
+    }
+}
+
+/// How an operation ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    Fail,
+    Info,
+}
+
+impl Outcome {
+    fn keyword(self) -> Edn {
+        Edn::keyword(match self {
+            Outcome::Ok => "ok",
+            Outcome::Fail => "fail",
+            Outcome::Info => "info",
+        })
+    }
+}
+
+/// Renders `ops` as a Jepsen history, one event per line in time order.
+/// `invoke` gives an input's `:f` and `:value`, and `complete` an output's
+/// outcome and `:value`. Operations that never completed end with an `:info`
+/// after everything else, carrying their invocation's value.
+pub fn history<I, O>(
+    ops: &[Operation<I, O>],
+    invoke: impl Fn(&I) -> (String, Edn),
+    complete: impl Fn(&I, &O) -> (Outcome, Edn),
+) -> String {
+    let end = ops.iter().filter_map(|op| op.ret).chain(ops.iter().map(|op| op.call)).max().unwrap_or(0) + 1;
+    let mut events = Vec::with_capacity(ops.len() * 2);
+    for op in ops {
+        let (f, value) = invoke(&op.input);
+        let (outcome, completed) = match &op.output {
+            Some(output) => complete(&op.input, output),
+            None => (Outcome::Info, value.clone()),
+        };
+        events.push((op.call, op.process, Edn::keyword("invoke"), f.clone(), value));
+        events.push((op.ret.unwrap_or(end), op.process, outcome.keyword(), f, completed));
+    }
+    events.sort_by_key(|(time, ..)| *time);
+
+    let mut out = String::new();
+    for (index, (time, process, kind, f, value)) in events.into_iter().enumerate() {
+        let event = Edn::Map(vec![
+            (Edn::keyword("index"), Edn::Int(index as i64)),
+            (Edn::keyword("type"), kind),
+            (Edn::keyword("process"), Edn::Int(process as i64)),
+            (Edn::keyword("time"), Edn::Int(time as i64)),
+            (Edn::keyword("f"), Edn::keyword(f)),
+            (Edn::keyword("value"), value),
+        ]);
+        out.push_str(&event.to_string());
+        out.push('\n');
+    }
+    out
+}
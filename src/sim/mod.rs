@@ -0,0 +1,74 @@
+//! Building blocks for checking node behaviour in-process, without Maelstrom:
+//! a simulated [`cluster`] to run nodes in, [`scenario`]s to drive it from
+//! data, histories and [`check`]ers for them, and [`edn`] to hand histories
+//! to Jepsen's checkers instead.
+
+pub mod check;
+pub mod cluster;
+pub mod edn;
+pub mod scenario;
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// One client operation in a history: what was asked, what came back, and the
+/// logical times at which it was invoked and completed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operation<I, O> {
+    pub process: usize,
+    pub input: I,
+    /// `None` if the operation never completed (e.g. timed out); it may or may
+    /// not have taken effect.
+    pub output: Option<O>,
+    pub call: u64,
+    pub ret: Option<u64>,
+}
+
+/// Records a concurrent history. Safe to share between client tasks; the
+/// logical clock orders every invocation and completion it sees.
+#[derive(Debug)]
+pub struct History<I, O> {
+    clock: AtomicU64,
+    ops: Mutex<Vec<Operation<I, O>>>,
+}
+
+impl<I, O> Default for History<I, O> {
+    fn default() -> Self {
+        Self {
+            clock: AtomicU64::new(0),
+            ops: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<I, O> History<I, O> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the invocation of `input` by `process`, returning a handle to
+    /// pass to [`History::complete`].
+    pub fn invoke(&self, process: usize, input: I) -> usize {
+        let call = self.clock.fetch_add(1, Ordering::SeqCst);
+        let mut ops = self.ops.lock().unwrap();
+        ops.push(Operation {
+            process,
+            input,
+            output: None,
+            call,
+            ret: None,
+        });
+        ops.len() - 1
+    }
+
+    pub fn complete(&self, op: usize, output: O) {
+        let ret = self.clock.fetch_add(1, Ordering::SeqCst);
+        let mut ops = self.ops.lock().unwrap();
+        ops[op].output = Some(output);
+        ops[op].ret = Some(ret);
+    }
+
+    pub fn into_operations(self) -> Vec<Operation<I, O>> {
+        self.ops.into_inner().unwrap()
+    }
+}
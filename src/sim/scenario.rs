@@ -0,0 +1,496 @@
+//! Scenarios: a cluster, a workload to run against it, faults to inject
+//! along the way and what must hold afterwards, written down as data so a
+//! test case can be a file rather than code.
+//!
+//! ```json
+//! {
+//!   "nodes": 3,
+//!   "workload": {"kind": "broadcast", "rate": 50},
+//!   "duration_ms": 2000,
+//!   "nemesis": [
+//!     {"at_ms": 500, "fault": "partition", "groups": [["n1"], ["n2", "n3"]]},
+//!     {"at_ms": 800, "fault": "kill", "node": "n3"},
+//!     {"at_ms": 1000, "fault": "restart", "node": "n3"},
+//!     {"at_ms": 1200, "fault": "heal"},
+//!     {"at_ms": 1200, "fault": "skew", "node": "n2", "offset_ms": -300},
+//!     {"at_ms": 1200, "fault": "drift", "node": "n1", "ppm": 50000}
+//!   ],
+//!   "assertions": ["converged", {"min_ok_fraction": 0.5}]
+//! }
+//! ```
+//!
+//! [`Scenario::run`] starts a [`Cluster`] of the given node, runs the
+//! workload's clients for `duration_ms` while applying the nemesis schedule,
+//! then heals the network, waits `settle_ms` and reads the final state of
+//! every node still running before checking the assertions. The same
+//! scenario can be built in code with [`Scenario::new`].
+
+use super::cluster::Cluster;
+use super::{History, Operation};
+use crate::{Coalescible, Node, Runtime};
+use anyhow::Context;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The client that sends the topology and the final reads.
+const ADMIN: &str = "c0";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scenario {
+    pub nodes: usize,
+    pub workload: Workload,
+    /// How long clients keep starting operations.
+    #[serde(default = "default_duration_ms")]
+    pub duration_ms: u64,
+    /// How long the healed cluster gets to converge before the final reads.
+    #[serde(default = "default_settle_ms")]
+    pub settle_ms: u64,
+    /// Faults and when to inject them, counted from when the clients start.
+    #[serde(default)]
+    pub nemesis: Vec<Fault>,
+    #[serde(default)]
+    pub assertions: Vec<Assertion>,
+    /// Seeds the clients' choice of operations and nodes.
+    #[serde(default)]
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WorkloadKind {
+    /// `echo` requests.
+    Echo,
+    /// Unique `broadcast` messages and the odd `read`, after a `topology`
+    /// connecting every node to every other.
+    Broadcast,
+    /// `add`s of small positive deltas and the odd `read`.
+    Counter,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Workload {
+    pub kind: WorkloadKind,
+    /// Operations started per second, across all clients.
+    #[serde(default = "default_rate")]
+    pub rate: f64,
+    /// Clients, each with one operation in flight at a time, sending to
+    /// nodes at random. One per node unless set.
+    #[serde(default)]
+    pub clients: Option<usize>,
+    /// How long a client waits for a reply before moving on, leaving the
+    /// operation indeterminate.
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+/// A fault and when to inject it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fault {
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub nemesis: Nemesis,
+}
+
+/// What the nemesis can do to the cluster; see [`Cluster`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "fault", rename_all = "kebab-case")]
+pub enum Nemesis {
+    /// Nodes only reach nodes in their own group; nodes in no group are
+    /// cut off entirely.
+    Partition { groups: Vec<Vec<String>> },
+    Heal,
+    Kill { node: String },
+    /// Starts a node afresh, whether or not it was killed.
+    Restart { node: String },
+    /// Sets how far ahead the node's clock reads; negative if behind.
+    Skew { node: String, offset_ms: i64 },
+    /// Makes the node's clock gain `ppm` microseconds a second, or lose
+    /// them if negative.
+    Drift { node: String, ppm: i64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Assertion {
+    /// Every operation succeeded.
+    AllOk,
+    /// At least this fraction of operations succeeded.
+    MinOkFraction(f64),
+    /// The final reads reflect exactly what the operations could have done:
+    /// every acknowledged broadcast is read everywhere and nothing never
+    /// broadcast is, or every node's counter reads the same value, at least
+    /// the acknowledged adds and at most all of them. For echo, every reply
+    /// echoes its request.
+    Converged,
+}
+
+fn default_duration_ms() -> u64 {
+    1000
+}
+
+fn default_settle_ms() -> u64 {
+    1000
+}
+
+fn default_rate() -> f64 {
+    20.0
+}
+
+fn default_timeout_ms() -> u64 {
+    1000
+}
+
+impl Workload {
+    pub fn new(kind: WorkloadKind) -> Self {
+        Self {
+            kind,
+            rate: default_rate(),
+            clients: None,
+            timeout_ms: default_timeout_ms(),
+        }
+    }
+
+    pub fn rate(mut self, rate: f64) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    pub fn clients(mut self, clients: usize) -> Self {
+        self.clients = Some(clients);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout_ms = timeout.as_millis() as u64;
+        self
+    }
+}
+
+impl Scenario {
+    pub fn new(nodes: usize, workload: Workload) -> Self {
+        Self {
+            nodes,
+            workload,
+            duration_ms: default_duration_ms(),
+            settle_ms: default_settle_ms(),
+            nemesis: Vec::new(),
+            assertions: Vec::new(),
+            seed: 0,
+        }
+    }
+
+    /// Reads a scenario from a JSON file.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path).with_context(|| format!("read scenario {}", path.display()))?;
+        serde_json::from_str(&json).with_context(|| format!("parse scenario {}", path.display()))
+    }
+
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration_ms = duration.as_millis() as u64;
+        self
+    }
+
+    pub fn settle(mut self, settle: Duration) -> Self {
+        self.settle_ms = settle.as_millis() as u64;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Injects `nemesis` `at` after the clients start.
+    pub fn at(mut self, at: Duration, nemesis: Nemesis) -> Self {
+        self.nemesis.push(Fault {
+            at_ms: at.as_millis() as u64,
+            nemesis,
+        });
+        self
+    }
+
+    pub fn assert(mut self, assertion: Assertion) -> Self {
+        self.assertions.push(assertion);
+        self
+    }
+
+    fn node_ids(&self) -> Vec<String> {
+        (1..=self.nodes).map(|i| format!("n{}", i)).collect()
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.nodes > 0, "a scenario needs at least one node");
+        anyhow::ensure!(self.workload.rate > 0.0, "workload rate must be positive");
+        anyhow::ensure!(self.workload.clients != Some(0), "a scenario needs at least one client");
+        let node_ids = self.node_ids();
+        for fault in &self.nemesis {
+            let named: Vec<&String> = match &fault.nemesis {
+                Nemesis::Partition { groups } => groups.iter().flatten().collect(),
+                Nemesis::Heal => Vec::new(),
+                Nemesis::Kill { node }
+                | Nemesis::Restart { node }
+                | Nemesis::Skew { node, .. }
+                | Nemesis::Drift { node, .. } => vec![node],
+            };
+            for node in named {
+                anyhow::ensure!(node_ids.contains(node), "fault at {}ms names {}, which isn't one of {:?}", fault.at_ms, node, node_ids);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the scenario against nodes run by the runtime and with the
+    /// initial state `node` returns (see [`Cluster::new`]), and fails
+    /// listing every assertion that doesn't hold.
+    pub async fn run<S, N, P, SP, IP>(&self, node: impl Fn() -> (Runtime, S) + Send + Sync + 'static) -> anyhow::Result<Report>
+    where
+        S: Send + 'static,
+        P: DeserializeOwned + Send + 'static,
+        SP: DeserializeOwned + Send + 'static,
+        N: Node<S, P, SP, IP> + Send + 'static,
+        IP: Coalescible + Send + 'static,
+    {
+        self.validate()?;
+        let cluster = Arc::new(Cluster::new::<S, N, P, SP, IP>(self.nodes, node));
+        cluster.start().await?;
+        let node_ids = self.node_ids();
+        let timeout = Duration::from_millis(self.workload.timeout_ms);
+        if self.workload.kind == WorkloadKind::Broadcast {
+            let topology: HashMap<&String, Vec<&String>> = node_ids
+                .iter()
+                .map(|id| (id, node_ids.iter().filter(|peer| *peer != id).collect()))
+                .collect();
+            for id in &node_ids {
+                let body = json!({"type": "topology", "topology": topology});
+                cluster.request(ADMIN, id, body, timeout).await.with_context(|| format!("{} did not acknowledge topology", id))?;
+            }
+        }
+
+        let started = Instant::now();
+        let stop_at = started + Duration::from_millis(self.duration_ms);
+        let history = Arc::new(History::new());
+        let next_message = Arc::new(AtomicU64::new(0));
+        let clients = self.workload.clients.unwrap_or(self.nodes);
+        let period = Duration::from_secs_f64(clients as f64 / self.workload.rate);
+        let tasks: Vec<_> = (0..clients)
+            .map(|c| {
+                let client = Client {
+                    id: format!("c{}", c + 1),
+                    process: c,
+                    clients,
+                    kind: self.workload.kind,
+                    rng: StdRng::seed_from_u64(self.seed.wrapping_add(c as u64)),
+                    next_message: next_message.clone(),
+                };
+                // spread the clients' operations over each period
+                let first = started + period.mul_f64(c as f64 / clients as f64);
+                tokio::spawn(client.run(cluster.clone(), history.clone(), first, period, stop_at, timeout))
+            })
+            .collect();
+
+        let mut faults = self.nemesis.clone();
+        faults.sort_by_key(|fault| fault.at_ms);
+        for fault in faults {
+            tokio::time::sleep_until(started + Duration::from_millis(fault.at_ms)).await;
+            match &fault.nemesis {
+                Nemesis::Partition { groups } => cluster.partition(groups),
+                Nemesis::Heal => cluster.heal(),
+                Nemesis::Kill { node } => cluster.kill(node),
+                Nemesis::Restart { node } => cluster.restart(node).await?,
+                Nemesis::Skew { node, offset_ms } => cluster.skew(node, *offset_ms),
+                Nemesis::Drift { node, ppm } => cluster.drift(node, *ppm),
+            }
+        }
+        for task in tasks {
+            task.await?;
+        }
+
+        cluster.heal();
+        tokio::time::sleep(Duration::from_millis(self.settle_ms)).await;
+        let mut final_reads = BTreeMap::new();
+        if self.workload.kind != WorkloadKind::Echo {
+            for id in node_ids.iter().filter(|id| cluster.is_up(id)) {
+                let read = cluster.request(ADMIN, id, json!({"type": "read"}), timeout).await;
+                final_reads.insert(id.clone(), read);
+            }
+        }
+
+        let history = Arc::try_unwrap(history).expect("clients are done").into_operations();
+        let report = Report { history, final_reads };
+        let failures = report.failures(self);
+        anyhow::ensure!(failures.is_empty(), "scenario failed:\n  {}", failures.join("\n  "));
+        Ok(report)
+    }
+}
+
+/// One of the workload's clients.
+struct Client {
+    id: String,
+    /// The Jepsen process it acts as; a new one after each operation that
+    /// got no reply, since that one may yet take effect.
+    process: usize,
+    clients: usize,
+    kind: WorkloadKind,
+    rng: StdRng,
+    next_message: Arc<AtomicU64>,
+}
+
+impl Client {
+    async fn run(
+        mut self,
+        cluster: Arc<Cluster>,
+        history: Arc<History<Value, Value>>,
+        mut next: Instant,
+        period: Duration,
+        stop_at: Instant,
+        timeout: Duration,
+    ) {
+        while next < stop_at {
+            tokio::time::sleep_until(next).await;
+            next += period;
+            let node = cluster.node_ids()[self.rng.random_range(0..cluster.node_ids().len())].clone();
+            let body = self.operation();
+            let op = history.invoke(self.process, body.clone());
+            match cluster.request(&self.id, &node, body, timeout).await {
+                Some(reply) => history.complete(op, reply),
+                None => self.process += self.clients,
+            }
+            // skip the slots missed waiting for a reply rather than catch up
+            next = next.max(Instant::now());
+        }
+    }
+
+    fn operation(&mut self) -> Value {
+        let read = self.rng.random_ratio(1, 10);
+        match self.kind {
+            WorkloadKind::Echo => json!({"type": "echo", "echo": format!("{}-{}", self.id, self.rng.random::<u32>())}),
+            WorkloadKind::Broadcast if read => json!({"type": "read"}),
+            WorkloadKind::Broadcast => {
+                json!({"type": "broadcast", "message": self.next_message.fetch_add(1, Ordering::SeqCst)})
+            }
+            WorkloadKind::Counter if read => json!({"type": "read"}),
+            WorkloadKind::Counter => json!({"type": "add", "delta": self.rng.random_range(1..=5)}),
+        }
+    }
+}
+
+/// What happened during a scenario.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Every client operation: its request body and, if it got one, the
+    /// reply's body.
+    pub history: Vec<Operation<Value, Value>>,
+    /// The reply to the final read of each node running at the end, if it
+    /// answered. Empty for echo.
+    pub final_reads: BTreeMap<String, Option<Value>>,
+}
+
+fn succeeded(op: &Operation<Value, Value>) -> bool {
+    op.output.as_ref().is_some_and(|reply| reply["type"] != "error")
+}
+
+impl Report {
+    /// How many operations succeeded.
+    pub fn ok_count(&self) -> usize {
+        self.history.iter().filter(|op| succeeded(op)).count()
+    }
+
+    /// Why each of `scenario`'s assertions that doesn't hold fails.
+    fn failures(&self, scenario: &Scenario) -> Vec<String> {
+        let total = self.history.len();
+        let ok = self.ok_count();
+        let mut failures = Vec::new();
+        for assertion in &scenario.assertions {
+            match assertion {
+                Assertion::AllOk if ok < total => {
+                    failures.push(format!("all_ok: {} of {} operations did not succeed", total - ok, total));
+                }
+                Assertion::MinOkFraction(fraction) if total == 0 || (ok as f64) < fraction * total as f64 => {
+                    failures.push(format!(
+                        "min_ok_fraction: {} of {} operations succeeded, fewer than {}",
+                        ok, total, fraction
+                    ));
+                }
+                Assertion::Converged => {
+                    failures.extend(self.divergence(scenario.workload.kind).into_iter().map(|e| format!("converged: {}", e)));
+                }
+                _ => {}
+            }
+        }
+        failures
+    }
+
+    fn divergence(&self, kind: WorkloadKind) -> Vec<String> {
+        let mut problems = Vec::new();
+        let of_type = |t: &'static str| self.history.iter().filter(move |op| op.input["type"] == t);
+        if kind == WorkloadKind::Echo {
+            for op in of_type("echo").filter(|op| succeeded(op)) {
+                let reply = op.output.as_ref().unwrap();
+                if reply["echo"] != op.input["echo"] {
+                    problems.push(format!("{} was echoed as {}", op.input["echo"], reply["echo"]));
+                }
+            }
+            return problems;
+        }
+        if self.final_reads.is_empty() {
+            problems.push("no node was running at the end".to_string());
+        }
+        let mut reads = Vec::new();
+        for (node, read) in &self.final_reads {
+            match read {
+                Some(read) if read["type"] != "error" => reads.push((node, read)),
+                _ => problems.push(format!("{} did not answer the final read: {:?}", node, read)),
+            }
+        }
+
+        if kind == WorkloadKind::Broadcast {
+            let attempted: BTreeSet<u64> = of_type("broadcast").filter_map(|op| op.input["message"].as_u64()).collect();
+            let acked: BTreeSet<u64> = of_type("broadcast")
+                .filter(|op| succeeded(op))
+                .filter_map(|op| op.input["message"].as_u64())
+                .collect();
+            for (node, read) in reads {
+                let seen: BTreeSet<u64> = read["messages"].as_array().into_iter().flatten().filter_map(Value::as_u64).collect();
+                let missing: Vec<_> = acked.difference(&seen).collect();
+                if !missing.is_empty() {
+                    problems.push(format!("{} lacks {} acknowledged messages: {:?}", node, missing.len(), missing));
+                }
+                let invented: Vec<_> = seen.difference(&attempted).collect();
+                if !invented.is_empty() {
+                    problems.push(format!("{} read messages nobody broadcast: {:?}", node, invented));
+                }
+            }
+        } else {
+            let delta = |op: &Operation<Value, Value>| op.input["delta"].as_i64().unwrap_or(0);
+            let acked: i64 = of_type("add").filter(|op| succeeded(op)).map(delta).sum();
+            let unsure: i64 = of_type("add").filter(|op| !succeeded(op)).map(delta).sum();
+            let values: BTreeSet<i64> = reads.iter().filter_map(|(_, read)| read["value"].as_i64()).collect();
+            if values.len() > 1 {
+                problems.push(format!("nodes read different values: {:?}", values));
+            }
+            for (node, read) in reads {
+                match read["value"].as_i64() {
+                    Some(value) if (acked..=acked + unsure).contains(&value) => {}
+                    value => problems.push(format!(
+                        "{} read {:?}, but the adds sum to between {} and {}",
+                        node,
+                        value,
+                        acked,
+                        acked + unsure
+                    )),
+                }
+            }
+        }
+        problems
+    }
+}
@@ -0,0 +1,115 @@
+//! How large the messages a node sends are, by payload type.
+//!
+//! Every outbound message is measured as serialized, before any compression,
+//! and [`Ctx::message_sizes`] reports the distribution for each `type`. One
+//! larger than [`Runtime::message_size_warning`] and than any before it of
+//! its type is logged, so a `read_ok` or snapshot that grows with the run
+//! doesn't go unnoticed.
+//!
+//! [`Ctx::message_sizes`]: crate::Ctx::message_sizes
+//! [`Runtime::message_size_warning`]: crate::Runtime::message_size_warning
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// How many of the latest sizes of each type the percentiles are taken over.
+pub const WINDOW: usize = 1024;
+
+/// The default for [`Runtime::message_size_warning`](crate::Runtime::message_size_warning).
+pub const DEFAULT_WARNING: usize = 1024 * 1024;
+
+/// The sizes of one type of message, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SizeStats {
+    pub count: u64,
+    pub total_bytes: u64,
+    /// Over the latest [`WINDOW`] messages.
+    pub p50: usize,
+    /// Over the latest [`WINDOW`] messages.
+    pub p99: usize,
+    /// Over all of them.
+    pub max: usize,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct MessageSizes {
+    by_type: Mutex<HashMap<String, Sizes>>,
+}
+
+#[derive(Debug, Default)]
+struct Sizes {
+    count: u64,
+    total: u64,
+    max: usize,
+    recent: VecDeque<usize>,
+}
+
+/// Just enough of a message to tell its type.
+#[derive(Deserialize)]
+struct Probe<'a> {
+    #[serde(borrow)]
+    body: ProbeBody<'a>,
+}
+
+#[derive(Deserialize)]
+struct ProbeBody<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: Option<Cow<'a, str>>,
+}
+
+impl MessageSizes {
+    /// Counts `line`, a message to `dst`, warning if it is over `warn_above`
+    /// bytes and the largest of its type so far.
+    pub(crate) fn record(&self, dst: &str, line: &[u8], warn_above: usize) {
+        let size = line.strip_suffix(b"\n").unwrap_or(line).len();
+        let kind = serde_json::from_slice::<Probe>(line).ok().and_then(|probe| probe.body.kind);
+        let kind = kind.as_deref().unwrap_or("untyped");
+        let mut by_type = self.by_type.lock().unwrap();
+        let sizes = match by_type.get_mut(kind) {
+            Some(sizes) => sizes,
+            None => by_type.entry(kind.to_string()).or_default(),
+        };
+        if size > warn_above && size > sizes.max {
+            eprintln!(
+                "{} message to {} is {} bytes, over the {}-byte warning threshold",
+                kind, dst, size, warn_above
+            );
+        }
+        sizes.count += 1;
+        sizes.total += size as u64;
+        sizes.max = sizes.max.max(size);
+        if sizes.recent.len() == WINDOW {
+            sizes.recent.pop_front();
+        }
+        sizes.recent.push_back(size);
+    }
+
+    pub(crate) fn stats(&self) -> BTreeMap<String, SizeStats> {
+        self.by_type
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(kind, sizes)| {
+                let mut recent: Vec<_> = sizes.recent.iter().copied().collect();
+                recent.sort_unstable();
+                let stats = SizeStats {
+                    count: sizes.count,
+                    total_bytes: sizes.total,
+                    p50: percentile(&recent, 0.50),
+                    p99: percentile(&recent, 0.99),
+                    max: sizes.max,
+                };
+                (kind.clone(), stats)
+            })
+            .collect()
+    }
+}
+
+fn percentile(sorted: &[usize], p: f64) -> usize {
+    if sorted.is_empty() {
+        return 0;
+    }
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
@@ -0,0 +1,130 @@
+//! Noticing when the node's output stops draining.
+//!
+//! Maelstrom reads every node's stdout through a pipe, and when it falls
+//! behind the pipe fills and writes block. The writer task takes the block
+//! alone, but everything sent meanwhile piles up behind it. A write that
+//! takes longer than [`Runtime::write_stall`]'s threshold is a stall: it is
+//! logged when it starts and when it ends, and counted in
+//! [`Ctx::write_stalls`]. While stalled, messages to other nodes are queued
+//! only up to the buffer limit and dropped past it, as an overloaded network
+//! would drop them, rather than growing the queue for as long as the stall
+//! lasts. Replies to clients and requests to services are always queued:
+//! protocols between nodes already retry lost messages, but a client would
+//! only see its request time out.
+//!
+//! [`Runtime::write_stall`]: crate::Runtime::write_stall
+//! [`Ctx::write_stalls`]: crate::Ctx::write_stalls
+
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The default for how long a write can take before it counts as a stall.
+pub const DEFAULT_THRESHOLD: Duration = Duration::from_millis(100);
+
+/// The default for how many bytes may wait to be written during a stall.
+pub const DEFAULT_BUFFER: usize = 16 * 1024 * 1024;
+
+/// Stalls of the node's output so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StallStats {
+    pub stalls: u64,
+    /// Whether a write is stalled right now.
+    pub stalled: bool,
+    pub longest_ms: u64,
+    pub total_ms: u64,
+    /// Messages to other nodes dropped because the buffer was full during a
+    /// stall.
+    pub dropped: u64,
+    /// Bytes waiting to be written.
+    pub queued_bytes: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct Stalls {
+    threshold: Duration,
+    buffer: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    stats: StallStats,
+    /// Messages dropped during the current stall.
+    dropped_now: u64,
+}
+
+impl Stalls {
+    pub(crate) fn new(threshold: Duration, buffer: usize) -> Self {
+        Self {
+            threshold,
+            buffer,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Whether a line of `len` bytes to `dst` may be queued: always, unless
+    /// `dst` is another node, the output is stalled and the queue is full.
+    pub(crate) fn admit(&self, dst: &str, len: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.stats.stalled && state.stats.queued_bytes + len > self.buffer && crate::is_node_id(dst) {
+            if state.dropped_now == 0 {
+                eprintln!(
+                    "output stalled with {} bytes queued; dropping messages to other nodes until it drains",
+                    state.stats.queued_bytes
+                );
+            }
+            state.dropped_now += 1;
+            state.stats.dropped += 1;
+            return false;
+        }
+        state.stats.queued_bytes += len;
+        true
+    }
+
+    /// Runs `write`, which takes a line of `len` bytes off the queue,
+    /// reporting it if it stalls.
+    pub(crate) async fn watch<F: Future>(&self, len: usize, write: F) -> F::Output {
+        let started = Instant::now();
+        tokio::pin!(write);
+        let done = tokio::select! {
+            done = &mut write => Some(done),
+            _ = tokio::time::sleep(self.threshold) => None,
+        };
+        let done = match done {
+            Some(done) => done,
+            None => {
+                {
+                    let mut state = self.state.lock().unwrap();
+                    eprintln!(
+                        "output stalled: a write has been blocked for {:?} with {} bytes queued",
+                        self.threshold, state.stats.queued_bytes
+                    );
+                    state.stats.stalled = true;
+                    state.stats.stalls += 1;
+                }
+                let done = write.await;
+                let took = started.elapsed();
+                let mut state = self.state.lock().unwrap();
+                eprintln!(
+                    "output resumed after stalling for {:?}; {} messages dropped",
+                    took, state.dropped_now
+                );
+                let took_ms = u64::try_from(took.as_millis()).unwrap_or(u64::MAX);
+                state.stats.stalled = false;
+                state.stats.longest_ms = state.stats.longest_ms.max(took_ms);
+                state.stats.total_ms += took_ms;
+                state.dropped_now = 0;
+                done
+            }
+        };
+        let mut state = self.state.lock().unwrap();
+        state.stats.queued_bytes = state.stats.queued_bytes.saturating_sub(len);
+        done
+    }
+
+    pub(crate) fn stats(&self) -> StallStats {
+        self.state.lock().unwrap().stats
+    }
+}
@@ -0,0 +1,62 @@
+//! Node state that can't be held locked across an await.
+//!
+//! A handler that awaits while holding a `std::sync::Mutex` guard stalls
+//! every other handler that wants the lock for as long as the await takes,
+//! and deadlocks outright if what it awaits needs one of them to run. A
+//! [`StateCell`] never hands out a guard: its state is only reachable inside
+//! a synchronous closure, so there is nothing to await with the lock held,
+//! and code that tries doesn't compile.
+//!
+//! ```ignore
+//! let (messages, peers) = self.state.with(|state| {
+//!     state.messages.insert(message);
+//!     (state.messages.clone(), state.peers.clone())
+//! });
+//! gossip(&ctx, peers, messages).await?;
+//! ```
+
+use std::sync::Mutex;
+
+#[derive(Default)]
+pub struct StateCell<T> {
+    inner: Mutex<T>,
+}
+
+impl<T> StateCell<T> {
+    pub fn new(state: T) -> Self {
+        Self { inner: Mutex::new(state) }
+    }
+
+    /// Runs `f` on the state, with the lock held until it returns. Whatever
+    /// `f` returns has to be owned, e.g. a clone of what it looked up.
+    /// Calling `with` again from inside `f` deadlocks.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.inner.lock().unwrap())
+    }
+
+    /// A copy of the whole state.
+    pub fn get(&self) -> T
+    where
+        T: Clone,
+    {
+        self.with(|state| state.clone())
+    }
+
+    /// Replaces the state, returning the old one.
+    pub fn replace(&self, state: T) -> T {
+        self.with(|old| std::mem::replace(old, state))
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner().unwrap()
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for StateCell<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.inner.try_lock() {
+            Ok(state) => f.debug_tuple("StateCell").field(&*state).finish(),
+            Err(_) => f.write_str("StateCell(<locked>)"),
+        }
+    }
+}
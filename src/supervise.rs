@@ -0,0 +1,193 @@
+//! Background tasks the runtime knows about.
+//!
+//! A task started with [`Ctx::spawn_supervised`] is restarted when it panics
+//! or fails, as its [`RestartPolicy`] allows, and cancelled once the node's
+//! input has ended. [`Ctx::supervised`] reports every such task, so timers
+//! and refreshers aren't invisible the way bare `tokio::spawn`ed tasks are.
+
+use crate::Ctx;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::task::{AbortHandle, JoinSet};
+
+/// How often a supervised task is restarted after a panic or error, and how
+/// long to wait before each restart. Defaults to 5 restarts, 100ms apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    pub max_restarts: usize,
+    pub backoff: Duration,
+}
+
+impl RestartPolicy {
+    /// Never restart; a failure is only reported.
+    pub const NEVER: Self = Self {
+        max_restarts: 0,
+        backoff: Duration::ZERO,
+    };
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: 5,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TaskStatus {
+    Running,
+    /// Waiting out the backoff after a failure.
+    Restarting { error: String },
+    Finished,
+    /// Failed with no restarts left.
+    Failed { error: String },
+    Cancelled,
+}
+
+/// A supervised task as [`Ctx::supervised`] reports it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TaskReport {
+    pub name: String,
+    #[serde(flatten)]
+    pub status: TaskStatus,
+    pub restarts: usize,
+}
+
+#[derive(Debug)]
+struct Task {
+    abort: AbortHandle,
+    report: TaskReport,
+}
+
+#[derive(Debug)]
+pub(crate) struct Supervisor {
+    tasks: Mutex<HashMap<String, Task>>,
+    /// Unnamed tasks, such as [`timer`](crate::timer)s, that are only
+    /// cancelled at shutdown; `None` once that has happened.
+    others: Mutex<Option<Vec<AbortHandle>>>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self {
+            tasks: Mutex::default(),
+            others: Mutex::new(Some(Vec::new())),
+        }
+    }
+}
+
+impl Supervisor {
+    fn update(&self, name: &str, restarts: usize, status: TaskStatus) {
+        if let Some(task) = self.tasks.lock().unwrap().get_mut(name) {
+            task.report.restarts = restarts;
+            task.report.status = status;
+        }
+    }
+
+    pub(crate) fn reports(&self) -> Vec<TaskReport> {
+        let mut reports: Vec<_> = self
+            .tasks
+            .lock()
+            .unwrap()
+            .values()
+            .map(|task| task.report.clone())
+            .collect();
+        reports.sort_by(|a, b| a.name.cmp(&b.name));
+        reports
+    }
+
+    /// Cancels `task` at shutdown, or at once if that has already happened.
+    pub(crate) fn track(&self, task: AbortHandle) {
+        match &mut *self.others.lock().unwrap() {
+            Some(others) => {
+                others.retain(|other| !other.is_finished());
+                others.push(task);
+            }
+            None => task.abort(),
+        }
+    }
+
+    /// Cancels every task still running.
+    pub(crate) fn shutdown(&self) {
+        for task in self.others.lock().unwrap().take().into_iter().flatten() {
+            task.abort();
+        }
+        for task in self.tasks.lock().unwrap().values_mut() {
+            task.abort.abort();
+            if matches!(task.report.status, TaskStatus::Running | TaskStatus::Restarting { .. }) {
+                task.report.status = TaskStatus::Cancelled;
+            }
+        }
+    }
+}
+
+impl Ctx {
+    /// Runs the future `make` returns as a background task named `name`,
+    /// calling `make` again for each restart `policy` allows. `make` gets a
+    /// context of its own to send with. A task already running under the same
+    /// name is cancelled and replaced.
+    pub fn spawn_supervised<F, Fut>(&self, name: impl Into<String>, policy: RestartPolicy, mut make: F)
+    where
+        F: FnMut(Ctx) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let name = name.into();
+        let ctx = Ctx {
+            outbox: None,
+            ..self.clone()
+        };
+        let supervisor = self.supervisor.clone();
+        let mut tasks = supervisor.tasks.lock().unwrap();
+        let task_name = name.clone();
+        let abort = tokio::spawn(async move {
+            let mut restarts = 0;
+            loop {
+                // dropping the set when this task is aborted aborts the attempt
+                let mut attempt = JoinSet::new();
+                attempt.spawn(make(ctx.clone()));
+                let error = match attempt.join_next().await.expect("one task was spawned") {
+                    Ok(Ok(())) => {
+                        ctx.supervisor.update(&task_name, restarts, TaskStatus::Finished);
+                        return;
+                    }
+                    Ok(Err(e)) => format!("{:#}", e),
+                    Err(e) if e.is_panic() => format!("panicked: {}", crate::panic_message(&*e.into_panic())),
+                    Err(_) => return,
+                };
+                if restarts >= policy.max_restarts {
+                    eprintln!("supervised task {} failed for good: {}", task_name, error);
+                    ctx.supervisor.update(&task_name, restarts, TaskStatus::Failed { error });
+                    return;
+                }
+                eprintln!("supervised task {} failed, restarting: {}", task_name, error);
+                ctx.supervisor.update(&task_name, restarts, TaskStatus::Restarting { error });
+                ctx.sleep(policy.backoff).await;
+                restarts += 1;
+                ctx.supervisor.update(&task_name, restarts, TaskStatus::Running);
+            }
+        })
+        .abort_handle();
+        let task = Task {
+            abort,
+            report: TaskReport {
+                name: name.clone(),
+                status: TaskStatus::Running,
+                restarts: 0,
+            },
+        };
+        if let Some(old) = tasks.insert(name, task) {
+            old.abort.abort();
+        }
+    }
+
+    /// Every task started with [`Ctx::spawn_supervised`], by name.
+    pub fn supervised(&self) -> Vec<TaskReport> {
+        self.supervisor.reports()
+    }
+}
@@ -0,0 +1,290 @@
+//! Time source used by the runtime and by nodes for timers and sleeps.
+//!
+//! Nodes should sleep through [`Ctx::sleep`](crate::Ctx::sleep) rather
+//! than calling `tokio::time` directly so tests can swap in a [`ManualClock`]
+//! and fast-forward through gossip intervals and retry delays.
+
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+pub trait Clock: Send + Sync + std::fmt::Debug + 'static {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration) -> Sleep;
+
+    /// Milliseconds since the Unix epoch, as this clock reads them now. The
+    /// system's wall-clock time unless overridden.
+    fn wall_ms(&self) -> u64 {
+        system_ms()
+    }
+}
+
+fn system_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Wall-clock time via `tokio::time`. Honors `tokio::time::pause`/`advance`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Virtual time that only moves when [`ManualClock::advance`] is called.
+///
+/// Sleepers whose deadline is reached are woken in deadline order, so a test
+/// advancing by a full gossip interval deterministically fires every tick that
+/// falls inside it.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    inner: Arc<Mutex<ManualState>>,
+}
+
+#[derive(Debug)]
+struct ManualState {
+    start: Instant,
+    /// Wall-clock time at `start`, in Unix milliseconds.
+    start_wall_ms: u64,
+    elapsed: Duration,
+    sleepers: Vec<(Duration, oneshot::Sender<()>)>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(ManualState {
+                start: Instant::now(),
+                start_wall_ms: system_ms(),
+                elapsed: Duration::ZERO,
+                sleepers: Vec::new(),
+            })),
+        }
+    }
+
+    /// Moves time forward by `by`, waking every sleeper that is now due.
+    pub fn advance(&self, by: Duration) {
+        let due = {
+            let mut state = self.inner.lock().unwrap();
+            state.elapsed += by;
+            let now = state.elapsed;
+            let (mut due, pending): (Vec<_>, Vec<_>) =
+                state.sleepers.drain(..).partition(|(deadline, _)| *deadline <= now);
+            state.sleepers = pending;
+            due.sort_by_key(|(deadline, _)| *deadline);
+            due
+        };
+        for (_, tx) in due {
+            let _ = tx.send(());
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.inner.lock().unwrap().elapsed
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        let state = self.inner.lock().unwrap();
+        state.start + state.elapsed
+    }
+
+    fn wall_ms(&self) -> u64 {
+        let state = self.inner.lock().unwrap();
+        state.start_wall_ms + state.elapsed.as_millis() as u64
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = self.inner.lock().unwrap();
+            if duration.is_zero() {
+                let _ = tx.send(());
+            } else {
+                let deadline = state.elapsed + duration;
+                state.sleepers.push((deadline, tx));
+            }
+        }
+        Box::pin(async move {
+            let _ = rx.await;
+        })
+    }
+}
+
+/// Another clock read with an offset and a drift, as a node whose clock is
+/// wrong would read it. Both can be changed while the clock is in use, e.g.
+/// by a simulated clock nemesis; sleeps are unaffected, since they only
+/// measure durations.
+#[derive(Debug, Clone)]
+pub struct SkewedClock {
+    inner: Arc<dyn Clock>,
+    skew: Arc<Mutex<Skew>>,
+}
+
+#[derive(Debug)]
+struct Skew {
+    /// Milliseconds ahead of `inner` at `since`; negative if behind.
+    offset_ms: i64,
+    /// Microseconds gained per second of `inner`'s time; negative if lost.
+    drift_ppm: i64,
+    since: Instant,
+}
+
+impl Skew {
+    fn ahead_ms(&self, now: Instant) -> i64 {
+        let drifted = now.saturating_duration_since(self.since).as_micros() as i64 * self.drift_ppm / 1_000_000_000;
+        self.offset_ms + drifted
+    }
+}
+
+impl SkewedClock {
+    pub fn new(inner: impl Clock) -> Self {
+        let skew = Skew {
+            offset_ms: 0,
+            drift_ppm: 0,
+            since: inner.now(),
+        };
+        Self {
+            inner: Arc::new(inner),
+            skew: Arc::new(Mutex::new(skew)),
+        }
+    }
+
+    /// Jumps the clock to read `offset_ms` ahead of the inner clock, from
+    /// which it keeps drifting.
+    pub fn set_offset_ms(&self, offset_ms: i64) {
+        let mut skew = self.skew.lock().unwrap();
+        skew.offset_ms = offset_ms;
+        skew.since = self.inner.now();
+    }
+
+    /// Makes the clock run fast by `drift_ppm` parts per million of the
+    /// inner clock's rate, or slow if negative, from its current reading.
+    pub fn set_drift_ppm(&self, drift_ppm: i64) {
+        let now = self.inner.now();
+        let mut skew = self.skew.lock().unwrap();
+        skew.offset_ms = skew.ahead_ms(now);
+        skew.drift_ppm = drift_ppm;
+        skew.since = now;
+    }
+
+    /// How far ahead of the inner clock this one reads now, in
+    /// milliseconds; negative if behind.
+    pub fn offset_ms(&self) -> i64 {
+        self.skew.lock().unwrap().ahead_ms(self.inner.now())
+    }
+}
+
+impl Clock for SkewedClock {
+    fn now(&self) -> Instant {
+        let now = self.inner.now();
+        let ahead = self.skew.lock().unwrap().ahead_ms(now);
+        let by = Duration::from_millis(ahead.unsigned_abs());
+        if ahead >= 0 {
+            now + by
+        } else {
+            // an Instant can't go back past whatever the platform counts from
+            now.checked_sub(by).unwrap_or(now)
+        }
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        self.inner.sleep(duration)
+    }
+
+    fn wall_ms(&self) -> u64 {
+        self.inner.wall_ms().saturating_add_signed(self.offset_ms())
+    }
+}
+
+/// A hybrid logical clock timestamp: milliseconds of physical time, and a
+/// counter that orders events within the same millisecond. Ordered first by
+/// `wall`, then by `logical`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Hlc {
+    pub wall: u64,
+    pub logical: u32,
+}
+
+/// The node's hybrid logical clock, read through
+/// [`Ctx::now_hlc`](crate::Ctx::now_hlc).
+///
+/// Physical time is the node's [`Clock::wall_ms`] at startup plus however
+/// far its [`Clock`] has moved since, so a [`ManualClock`] drives it too and
+/// a [`SkewedClock`] skews it. Every timestamp it hands out is greater than
+/// any it handed out or observed before, so if one event causally precedes
+/// another, the first gets the smaller timestamp.
+#[derive(Debug)]
+pub(crate) struct HybridClock {
+    clock: Arc<dyn Clock>,
+    /// Unix time in milliseconds at `started`.
+    epoch_ms: u64,
+    started: Instant,
+    last: Mutex<Hlc>,
+}
+
+impl HybridClock {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Self {
+        let epoch_ms = clock.wall_ms();
+        let started = clock.now();
+        Self {
+            clock,
+            epoch_ms,
+            started,
+            last: Mutex::default(),
+        }
+    }
+
+    fn physical(&self) -> u64 {
+        self.epoch_ms + self.clock.now().saturating_duration_since(self.started).as_millis() as u64
+    }
+
+    /// A timestamp for a local or send event.
+    pub(crate) fn tick(&self) -> Hlc {
+        let physical = self.physical();
+        let mut last = self.last.lock().unwrap();
+        *last = if physical > last.wall {
+            Hlc { wall: physical, logical: 0 }
+        } else {
+            Hlc {
+                wall: last.wall,
+                logical: last.logical + 1,
+            }
+        };
+        *last
+    }
+
+    /// A timestamp for receiving a message stamped `remote`.
+    pub(crate) fn observe(&self, remote: Hlc) -> Hlc {
+        let physical = self.physical();
+        let mut last = self.last.lock().unwrap();
+        let wall = physical.max(last.wall).max(remote.wall);
+        let logical = match (wall == last.wall, wall == remote.wall) {
+            (true, true) => last.logical.max(remote.logical) + 1,
+            (true, false) => last.logical + 1,
+            (false, true) => remote.logical + 1,
+            (false, false) => 0,
+        };
+        *last = Hlc { wall, logical };
+        *last
+    }
+}
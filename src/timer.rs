@@ -0,0 +1,129 @@
+//! One-shot timers that fire as injected events.
+//!
+//! [`Ctx::after`] hands the node an [`Event::Injected`] once a delay has
+//! passed on the node's clock, and returns a [`TimerHandle`] to push the
+//! moment back or call it off: an election timeout reset on every heartbeat,
+//! a lease renewal cancelled when the lease is given up, a retransmission
+//! cancelled by its ack. Timers are cancelled once the node's input has
+//! ended, like supervised tasks.
+
+use crate::time::{Clock, Sleep};
+use crate::{Ctx, Event};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Notify, mpsc};
+use tokio::time::Instant;
+
+/// Controls a timer started with [`Ctx::after`]. Dropping it leaves the
+/// timer running.
+#[derive(Debug, Clone)]
+pub struct TimerHandle {
+    clock: Arc<dyn Clock>,
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    armed: Mutex<Armed>,
+    changed: Notify,
+}
+
+#[derive(Default)]
+struct Armed {
+    /// When the timer fires; `None` once it has fired or been cancelled.
+    deadline: Option<Instant>,
+    /// The sleep for a new deadline, for the timer's task to take up.
+    reset: Option<Sleep>,
+}
+
+impl std::fmt::Debug for Armed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Armed").field("deadline", &self.deadline).finish_non_exhaustive()
+    }
+}
+
+impl TimerHandle {
+    /// Stops the timer from firing. Returns whether it was still pending.
+    pub fn cancel(&self) -> bool {
+        let mut armed = self.shared.armed.lock().unwrap();
+        armed.reset = None;
+        let pending = armed.deadline.take().is_some();
+        self.shared.changed.notify_one();
+        pending
+    }
+
+    /// Makes the timer fire `delay` from now instead of when it would have,
+    /// earlier or later. Returns whether it was still pending; a timer that
+    /// has fired or been cancelled stays that way.
+    pub fn reset(&self, delay: Duration) -> bool {
+        let mut armed = self.shared.armed.lock().unwrap();
+        if armed.deadline.is_none() {
+            return false;
+        }
+        armed.deadline = Some(self.clock.now() + delay);
+        armed.reset = Some(self.clock.sleep(delay));
+        self.shared.changed.notify_one();
+        true
+    }
+
+    /// Whether the timer has yet to fire and hasn't been cancelled.
+    pub fn is_pending(&self) -> bool {
+        self.shared.armed.lock().unwrap().deadline.is_some()
+    }
+
+    /// When the timer will fire, if it is pending.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.shared.armed.lock().unwrap().deadline
+    }
+}
+
+impl Ctx {
+    /// Injects `payload` through `inject`, the sender the node got in
+    /// [`Node::from_init`](crate::Node::from_init), once `delay` has passed.
+    pub fn after<P, SP, IP>(&self, delay: Duration, inject: &mpsc::UnboundedSender<Event<P, SP, IP>>, payload: IP) -> TimerHandle
+    where
+        P: Send + 'static,
+        SP: Send + 'static,
+        IP: Send + 'static,
+    {
+        let shared = Arc::new(Shared::default());
+        shared.armed.lock().unwrap().deadline = Some(self.now() + delay);
+        // taken now rather than in the task, so a manual clock advanced right
+        // after this call still fires the timer
+        let mut sleep = self.sleep(delay);
+        let task_shared = shared.clone();
+        let inject = inject.clone();
+        let task = tokio::spawn(async move {
+            let shared = task_shared;
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => {
+                        let mut armed = shared.armed.lock().unwrap();
+                        if let Some(reset) = armed.reset.take() {
+                            sleep = reset;
+                            continue;
+                        }
+                        if armed.deadline.take().is_some() {
+                            let _ = inject.send(Event::Injected(payload));
+                        }
+                        return;
+                    }
+                    _ = shared.changed.notified() => {
+                        let mut armed = shared.armed.lock().unwrap();
+                        if armed.deadline.is_none() {
+                            return;
+                        }
+                        if let Some(reset) = armed.reset.take() {
+                            sleep = reset;
+                        }
+                    }
+                }
+            }
+        });
+        self.supervisor.track(task.abort_handle());
+        TimerHandle {
+            clock: self.clock.clone(),
+            shared,
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! A write-ahead log: records appended to a file, for state that has to
+//! survive the node's process being killed.
+//!
+//! Each record is framed as
+//!
+//! ```text
+//! len: u32 LE | crc: u32 LE | payload: len bytes
+//! ```
+//!
+//! where the payload is the record as JSON and `crc` is its CRC-32 (IEEE). A
+//! kill can leave the last record half written; [`Wal::open`] reads records
+//! up to the first one that is cut short or fails its checksum, truncates the
+//! file there, and appends after it. Nothing past a bad record is trusted,
+//! since its length may be the part that is wrong.
+//!
+//! How often appended records are flushed to disk is the log's
+//! [`Durability`], trading how much a machine crash can lose against the
+//! latency of each append. [`Wal::sync_stats`] reports what the flushes cost.
+
+use anyhow::Context;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+const HEADER_LEN: usize = 8;
+
+/// When appended records are flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// Before every append returns.
+    #[default]
+    Always,
+    /// From a background thread at this interval, while there are unflushed
+    /// records; a machine crash loses at most about this much.
+    Every(Duration),
+    /// When the log is cleared for a snapshot, and on [`Wal::sync`].
+    OnSnapshot,
+    /// Only on [`Wal::sync`]. Records still survive the process being
+    /// killed, just not the machine going down.
+    Never,
+}
+
+/// What flushing the log has cost so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncStats {
+    pub syncs: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl SyncStats {
+    pub fn mean(&self) -> Duration {
+        self.total.checked_div(self.syncs as u32).unwrap_or_default()
+    }
+}
+
+/// The file, shared with the thread that flushes it for
+/// [`Durability::Every`].
+#[derive(Debug)]
+struct Synced {
+    file: File,
+    /// Records were appended since the last flush.
+    dirty: AtomicBool,
+    stats: Mutex<SyncStats>,
+}
+
+impl Synced {
+    fn sync(&self) -> std::io::Result<()> {
+        self.dirty.store(false, Ordering::SeqCst);
+        let started = Instant::now();
+        let synced = self.file.sync_data();
+        let took = started.elapsed();
+        let mut stats = self.stats.lock().unwrap();
+        stats.syncs += 1;
+        stats.total += took;
+        stats.max = stats.max.max(took);
+        if synced.is_err() {
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+        synced
+    }
+}
+
+/// An open log of `T` records.
+#[derive(Debug)]
+pub struct Wal<T> {
+    path: PathBuf,
+    synced: Arc<Synced>,
+    durability: Durability,
+    /// Bytes of good records in the file.
+    len: u64,
+    _records: PhantomData<fn(T)>,
+}
+
+impl<T: Serialize + DeserializeOwned> Wal<T> {
+    /// Opens the log at `path` with [`Durability::Always`]; see
+    /// [`Wal::open_with`].
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<(Self, Vec<T>)> {
+        Self::open_with(path, Durability::default())
+    }
+
+    /// Opens the log at `path`, creating it if it doesn't exist, and returns
+    /// the records already in it, oldest first.
+    pub fn open_with(path: impl AsRef<Path>, durability: Durability) -> anyhow::Result<(Self, Vec<T>)> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("open wal {}", path.display()))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).with_context(|| format!("read wal {}", path.display()))?;
+
+        let mut records = Vec::new();
+        let mut at = 0;
+        while let Some((payload, next)) = frame(&bytes, at) {
+            let Ok(record) = serde_json::from_slice(payload) else {
+                break;
+            };
+            records.push(record);
+            at = next;
+        }
+        if at < bytes.len() {
+            eprintln!("wal {}: dropping {} bytes of torn or corrupt records", path.display(), bytes.len() - at);
+            file.set_len(at as u64).with_context(|| format!("truncate wal {}", path.display()))?;
+            file.sync_data()?;
+        }
+        file.seek(SeekFrom::Start(at as u64))?;
+        let synced = Arc::new(Synced {
+            file,
+            dirty: AtomicBool::new(false),
+            stats: Mutex::new(SyncStats::default()),
+        });
+        if let Durability::Every(interval) = durability {
+            let synced = Arc::downgrade(&synced);
+            std::thread::Builder::new()
+                .name(format!("wal-sync {}", path.display()))
+                .spawn(move || flush_every(synced, interval))?;
+        }
+        let wal = Self {
+            path,
+            synced,
+            durability,
+            len: at as u64,
+            _records: PhantomData,
+        };
+        Ok((wal, records))
+    }
+
+    /// Appends `record`. It reaches the file before this returns, and disk as
+    /// the log's [`Durability`] says.
+    pub fn append(&mut self, record: &T) -> anyhow::Result<()> {
+        let payload = serde_json::to_vec(record)?;
+        let len = u32::try_from(payload.len()).context("wal record longer than 4 GiB")?;
+        let mut buf = Vec::with_capacity(HEADER_LEN + payload.len());
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(&crc32(&payload).to_le_bytes());
+        buf.extend_from_slice(&payload);
+        let mut file = &self.synced.file;
+        if let Err(e) = file.write_all(&buf) {
+            // don't leave part of a record for the next one to follow
+            let _ = file.set_len(self.len);
+            let _ = file.seek(SeekFrom::Start(self.len));
+            return Err(anyhow::Error::new(e).context(format!("append to wal {}", self.path.display())));
+        }
+        self.len += buf.len() as u64;
+        self.synced.dirty.store(true, Ordering::SeqCst);
+        if self.durability == Durability::Always {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes appended records to disk, whatever the log's [`Durability`].
+    pub fn sync(&self) -> anyhow::Result<()> {
+        self.synced.sync().with_context(|| format!("sync wal {}", self.path.display()))
+    }
+
+    /// Empties the log, e.g. once its records are folded into a snapshot.
+    pub fn clear(&mut self) -> anyhow::Result<()> {
+        let mut file = &self.synced.file;
+        file.set_len(0).with_context(|| format!("truncate wal {}", self.path.display()))?;
+        file.seek(SeekFrom::Start(0))?;
+        self.len = 0;
+        if self.durability != Durability::Never {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    pub fn sync_stats(&self) -> SyncStats {
+        *self.synced.stats.lock().unwrap()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Flushes the file every `interval` while there is something to flush,
+/// until the log is dropped.
+fn flush_every(synced: Weak<Synced>, interval: Duration) {
+    loop {
+        std::thread::sleep(interval);
+        let Some(synced) = synced.upgrade() else {
+            return;
+        };
+        if synced.dirty.load(Ordering::SeqCst)
+            && let Err(e) = synced.sync()
+        {
+            eprintln!("wal sync failed: {}", e);
+        }
+    }
+}
+
+/// The payload of the record starting at `at`, and where the next one
+/// starts, if the record is whole and its checksum matches.
+fn frame(bytes: &[u8], at: usize) -> Option<(&[u8], usize)> {
+    let header = bytes.get(at..at + HEADER_LEN)?;
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+    let start = at + HEADER_LEN;
+    let payload = bytes.get(start..start.checked_add(len)?)?;
+    (crc32(payload) == crc).then_some((payload, start + len))
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 with the IEEE polynomial, as used by zlib and Ethernet.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |c, &b| CRC_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8))
+}
@@ -0,0 +1,64 @@
+use dist_sys::annotate::FIELD;
+use dist_sys::client::MaelstromClient;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Read,
+    ReadOk { value: u64 },
+}
+
+/// Answers reads with how many it has answered, noting whether the answer
+/// came from its "cache".
+struct Counting {
+    reads: AtomicU64,
+}
+
+impl Node<(), Payload> for Counting {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Counting { reads: AtomicU64::new(0) })
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let value = self.reads.fetch_add(1, Ordering::Relaxed);
+        let reply = input.reply_with(Payload::ReadOk { value }, &ctx);
+        reply.send_annotated(&ctx, || json!({"term": 2, "cache": if value > 0 { "hit" } else { "miss" }}))
+    }
+}
+
+#[tokio::test]
+async fn annotated_replies_carry_the_note() {
+    let runtime = Runtime::new().annotate();
+    let (mut client, node) = MaelstromClient::in_process::<_, Counting, _, (), ()>(runtime, (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+    let first = client.request(json!({"type": "read"})).await.unwrap();
+    assert_eq!(first["value"], 0);
+    assert_eq!(first[FIELD], json!({"term": 2, "cache": "miss"}));
+    let second = client.request(json!({"type": "read"})).await.unwrap();
+    assert_eq!(second[FIELD]["cache"], "hit");
+    node.abort();
+}
+
+#[tokio::test]
+async fn replies_are_plain_unless_asked() {
+    let (mut client, node) = MaelstromClient::in_process::<_, Counting, _, (), ()>(Runtime::new(), (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+    let reply = client.request(json!({"type": "read"})).await.unwrap();
+    assert_eq!(reply["value"], 0);
+    assert!(reply.get(FIELD).is_none());
+    node.abort();
+}
@@ -0,0 +1,110 @@
+use dist_sys::anti_entropy::{self, SyncRequest};
+use dist_sys::client::MaelstromClient;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    CatchUp { from: String },
+    CatchUpOk { received: usize, items: BTreeSet<u64> },
+}
+
+/// Pulls a peer's state into its own when asked to.
+struct Puller {
+    items: Mutex<BTreeSet<u64>>,
+}
+
+impl Node<(), Payload> for Puller {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Puller {
+            items: Mutex::new(BTreeSet::from([1])),
+        })
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (Payload::CatchUp { from }, request) = input.take_payload() else {
+            return Ok(());
+        };
+        let received = anti_entropy::pull(&ctx, &from, 2, Duration::from_secs(1), |chunk: Vec<u64>| {
+            self.items.lock().unwrap().extend(chunk)
+        })
+        .await?;
+        let items = self.items.lock().unwrap().clone();
+        request.reply_with(Payload::CatchUpOk { received, items }, &ctx).send(&ctx)
+    }
+}
+
+#[tokio::test]
+async fn pulls_pick_up_after_the_last_item_of_each_chunk() {
+    let (client, node) = MaelstromClient::in_process::<_, Puller, _, (), ()>(Runtime::new(), (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2"]).await.unwrap();
+
+    // n2, played by the client, holds 3, 5, 8 and 13
+    let peer: BTreeSet<u64> = BTreeSet::from([3, 5, 8, 13]);
+    let asked = client.send(json!({"type": "catch_up", "from": "n2"})).await.unwrap();
+    let mut afters = Vec::new();
+    loop {
+        let sync = client.next_other().await.unwrap();
+        assert_eq!(sync["dest"], "n2");
+        assert_eq!(sync["body"]["type"], "sync");
+        let request: SyncRequest<u64> = serde_json::from_value(sync["body"].clone()).unwrap();
+        assert_eq!(request.limit, 2);
+        afters.push(request.after);
+        let response = anti_entropy::serve(&peer, request);
+        let done = response.done;
+        let body = json!({
+            "type": "sync_ok",
+            "in_reply_to": sync["body"]["msg_id"],
+            "items": response.items,
+            "done": response.done,
+        });
+        client.send_from("n2", body).await.unwrap();
+        if done {
+            break;
+        }
+    }
+    // the last full chunk doesn't say it is the last, so one more comes back empty
+    assert_eq!(afters, [None, Some(5), Some(13)]);
+
+    let reply = client.reply_to("c1", asked).await.unwrap();
+    assert_eq!(reply["body"]["received"], 4);
+    assert_eq!(reply["body"]["items"], json!([1, 3, 5, 8, 13]));
+    node.abort();
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_peer_that_never_answers_fails_the_pull() {
+    let (client, node) = MaelstromClient::in_process::<_, Puller, _, (), ()>(Runtime::new(), (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(5));
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2"]).await.unwrap();
+
+    client.send(json!({"type": "catch_up", "from": "n2"})).await.unwrap();
+    assert_eq!(client.next_other().await.unwrap()["body"]["type"], "sync");
+    // the handler's error takes the node down once the round trip times out
+    assert!(node.await.unwrap().is_err());
+}
+
+#[test]
+fn serving_marks_the_first_short_chunk_done() {
+    let state: BTreeSet<u64> = (0..5).collect();
+    let first = anti_entropy::serve(&state, SyncRequest { after: None, limit: 3 });
+    assert_eq!((first.items, first.done), (vec![0, 1, 2], false));
+    let rest = anti_entropy::serve(&state, SyncRequest { after: Some(2), limit: 3 });
+    assert_eq!((rest.items, rest.done), (vec![3, 4], true));
+}
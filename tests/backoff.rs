@@ -0,0 +1,50 @@
+use dist_sys::backoff::{self, Backoff, Policy};
+use dist_sys::time::TokioClock;
+use std::time::Duration;
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[test]
+fn schedules_grow_and_cap() {
+    let exponential = Backoff::Exponential { base: ms(10), max: ms(50) };
+    assert_eq!(exponential.delays().take(5).collect::<Vec<_>>(), [ms(10), ms(20), ms(40), ms(50), ms(50)]);
+
+    let fibonacci = Backoff::Fibonacci { base: ms(10), max: ms(70) };
+    assert_eq!(
+        fibonacci.delays().take(7).collect::<Vec<_>>(),
+        [ms(10), ms(10), ms(20), ms(30), ms(50), ms(70), ms(70)]
+    );
+
+    let jitter = Backoff::DecorrelatedJitter { base: ms(5), max: ms(100) };
+    let mut previous = ms(5);
+    for delay in jitter.delays().take(200) {
+        assert!(delay >= ms(5) && delay <= (previous * 3).min(ms(100)), "{:?} after {:?}", delay, previous);
+        previous = delay;
+    }
+
+    // the first attempt needs no delay
+    let policy = Policy::new(Backoff::Constant(ms(1))).max_attempts(3);
+    assert_eq!(policy.delays().count(), 2);
+    assert_eq!(backoff::CONTENTION.delays().take(1000).count(), 1000);
+}
+
+#[tokio::test]
+async fn retry_until_done_or_out_of_attempts() {
+    let policy = Policy::new(Backoff::Exponential { base: ms(1), max: ms(4) }).max_attempts(5);
+    let done = backoff::retry(&TokioClock, &policy, |attempt| async move { Ok((attempt == 2).then_some(attempt)) }).await;
+    assert_eq!(done.unwrap(), Some(2));
+
+    let mut attempts = 0;
+    let exhausted = backoff::retry(&TokioClock, &policy, |_| {
+        attempts += 1;
+        async { Ok(None::<()>) }
+    })
+    .await;
+    assert_eq!(exhausted.unwrap(), None);
+    assert_eq!(attempts, 5);
+
+    let failed = backoff::retry(&TokioClock, &policy, |_| async { anyhow::bail!("broken") as anyhow::Result<Option<()>> }).await;
+    assert!(failed.is_err());
+}
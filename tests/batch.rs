@@ -0,0 +1,121 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::emulate::Emulation;
+use dist_sys::kv::{Kv, Outcome};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Go,
+    GoOk { outcomes: Vec<Value> },
+    MismatchedOk { outcomes: Vec<Value>, written: Option<u64> },
+}
+
+/// Runs one batch against the emulated lin-kv and reports how it went.
+struct Batching;
+
+impl Node<(), Payload> for Batching {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Batching)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let kv = Kv::lin(&ctx);
+        kv.write("a", 1).await?;
+        kv.write("d", 4).await?;
+        // the operations may land in any order, so each has a key of its own
+        let batch = kv.batch::<u64>().read("a").read("b").write("c", 3).cas("d", 4, 5, false).cas("e", 5, 6, false);
+        assert_eq!(batch.len(), 5);
+        let outcomes = batch
+            .run()
+            .await
+            .into_iter()
+            .map(|outcome| match outcome.unwrap() {
+                Outcome::Read(value) => json!({"read": value}),
+                Outcome::Written => json!("written"),
+                Outcome::Cas(applied) => json!({"cas": applied}),
+            })
+            .collect();
+        let reads = kv.batch::<u64>().read("c").read("d").run().await;
+        let values: Vec<_> = reads.into_iter().map(|read| read.unwrap().value()).collect();
+        assert_eq!(values, [Some(3), Some(5)]);
+        input.reply_with(Payload::GoOk { outcomes }, &ctx).send(&ctx)
+    }
+}
+
+/// Runs a batch one of whose operations fails, and reports how the others
+/// went.
+struct Mismatching;
+
+impl Node<(), Payload> for Mismatching {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Mismatching)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let kv = Kv::lin(&ctx);
+        // not a u64, so reading it in a batch of u64s fails
+        kv.write("name", "kafka".to_string()).await?;
+        let batch = kv.batch::<u64>().read("name").write("w", 7).cas("missing", 1, 2, false);
+        let outcomes = batch
+            .run()
+            .await
+            .into_iter()
+            .map(|outcome| match outcome {
+                Ok(Outcome::Read(value)) => json!({"read": value}),
+                Ok(Outcome::Written) => json!("written"),
+                Ok(Outcome::Cas(applied)) => json!({"cas": applied}),
+                Err(_) => json!("failed"),
+            })
+            .collect();
+        let written = kv.read("w").await?;
+        input.reply_with(Payload::MismatchedOk { outcomes, written }, &ctx).send(&ctx)
+    }
+}
+
+#[tokio::test]
+async fn a_batch_reports_each_operation_in_order() {
+    let runtime = Runtime::new().emulate(Emulation::all());
+    let (mut client, node) = MaelstromClient::in_process::<_, Batching, _, (), ()>(runtime, (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+    let expected = json!({
+        "type": "go_ok",
+        "outcomes": [{"read": 1}, {"read": null}, "written", {"cas": true}, {"cas": false}],
+    });
+    client.expect(json!({"type": "go"}), expected).await.unwrap();
+    node.abort();
+}
+
+#[tokio::test]
+async fn one_failed_operation_leaves_the_rest_of_the_batch_alone() {
+    let runtime = Runtime::new().emulate(Emulation::all());
+    let (mut client, node) = MaelstromClient::in_process::<_, Mismatching, _, (), ()>(runtime, (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+    let expected = json!({
+        "type": "mismatched_ok",
+        "outcomes": ["failed", "written", {"cas": false}],
+        "written": 7,
+    });
+    client.expect(json!({"type": "go"}), expected).await.unwrap();
+    node.abort();
+}
@@ -0,0 +1,144 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::emulate::Emulation;
+use dist_sys::kv::{BlobStore, Kv};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Put { data: String },
+    PutOk { hash: String },
+    /// Gets through the node's store, or a new one with nothing cached.
+    Get { hash: String, fresh: bool },
+    GetOk { data: Option<String>, ms: u64 },
+    /// Writes `value` to `key` in `seq-kv` `after_ms` from now.
+    Write { key: String, value: String, after_ms: u64 },
+    WriteOk,
+    Failed { error: String },
+}
+
+struct Blobs {
+    store: BlobStore,
+}
+
+fn store(ctx: &Ctx) -> BlobStore {
+    BlobStore::new(Kv::seq(ctx), "blobs").retry(Duration::from_millis(10), 3)
+}
+
+impl Node<(), Payload> for Blobs {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Blobs { store: store(ctx) })
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        let result = match payload {
+            Payload::Put { data } => self
+                .store
+                .put(data.into_bytes())
+                .await
+                .map(|hash| Payload::PutOk { hash }),
+            Payload::Get { hash, fresh } => {
+                let start = ctx.now();
+                let got = if fresh { store(&ctx).get(&hash).await } else { self.store.get(&hash).await };
+                got.map(|bytes| Payload::GetOk {
+                    data: bytes.map(|bytes| String::from_utf8(bytes.to_vec()).unwrap()),
+                    ms: start.elapsed().as_millis() as u64,
+                })
+            }
+            Payload::Write { key, value, after_ms } => {
+                ctx.sleep(Duration::from_millis(after_ms)).await;
+                Kv::seq(&ctx).write(&key, value).await.map(|()| Payload::WriteOk)
+            }
+            _ => return Ok(()),
+        };
+        let reply = result.unwrap_or_else(|e| Payload::Failed { error: format!("{:#}", e) });
+        request.reply_with(reply, &ctx).send(&ctx)
+    }
+}
+
+async fn blobs() -> (MaelstromClient, tokio::task::JoinHandle<anyhow::Result<()>>) {
+    let runtime = Runtime::new().emulate(Emulation::all());
+    let (client, node) = MaelstromClient::in_process::<_, Blobs, _, (), ()>(runtime, (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+    (client, node)
+}
+
+async fn put(client: &mut MaelstromClient, data: &str) -> String {
+    let reply = client.request(json!({"type": "put", "data": data})).await.unwrap();
+    reply["hash"].as_str().unwrap().to_string()
+}
+
+async fn get(client: &mut MaelstromClient, hash: &str, fresh: bool) -> Value {
+    client.request(json!({"type": "get", "hash": hash, "fresh": fresh})).await.unwrap()
+}
+
+async fn write(client: &mut MaelstromClient, key: &str, value: &str, after_ms: u64) -> u64 {
+    client
+        .send(json!({"type": "write", "key": key, "value": value, "after_ms": after_ms}))
+        .await
+        .unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn blobs_are_stored_under_their_hash() {
+    let (mut client, node) = blobs().await;
+    let hash = put(&mut client, "hello").await;
+    assert_eq!(hash, BlobStore::hash(b"hello"));
+    assert_eq!(put(&mut client, "hello").await, hash);
+    assert_ne!(put(&mut client, "world").await, hash);
+
+    // readable by a store that never saw the put
+    assert_eq!(get(&mut client, &hash, true).await["data"], "hello");
+    node.abort();
+}
+
+// paused, so the retries show exactly in how long a get takes
+#[tokio::test(start_paused = true)]
+async fn a_missing_blob_is_reread_before_get_gives_up() {
+    let (mut client, node) = blobs().await;
+    let missing = BlobStore::hash(b"later");
+    let reply = get(&mut client, &missing, true).await;
+    assert_eq!((reply["data"].clone(), reply["ms"].clone()), (Value::Null, json!(20)));
+
+    // one that turns up between the retries is found
+    write(&mut client, &format!("blobs/{}", missing), "6c61746572", 15).await;
+    let reply = get(&mut client, &missing, true).await;
+    assert_eq!((reply["data"].clone(), reply["ms"].clone()), (json!("later"), json!(20)));
+    node.abort();
+}
+
+#[tokio::test(start_paused = true)]
+async fn blobs_are_checked_against_their_hash_and_cached_once_fetched() {
+    let (mut client, node) = blobs().await;
+    let hash = put(&mut client, "hello").await;
+    let forged = write(&mut client, &format!("blobs/{}", hash), "776f726c64", 0).await;
+    client.reply_to("c1", forged).await.unwrap();
+
+    let reply = get(&mut client, &hash, true).await;
+    let error = reply["error"].as_str().expect("forged blob was accepted");
+    assert!(error.contains("does not match its hash"), "{}", error);
+    let garbled = BlobStore::hash(b"garbled");
+    let garbling = write(&mut client, &format!("blobs/{}", garbled), "not hex", 0).await;
+    client.reply_to("c1", garbling).await.unwrap();
+    let error = get(&mut client, &garbled, true).await["error"].as_str().unwrap().to_string();
+    assert!(error.contains("corrupt blob"), "{}", error);
+
+    // the store that put it never reads it back
+    let reply = get(&mut client, &hash, false).await;
+    assert_eq!((reply["data"].clone(), reply["ms"].clone()), (json!("hello"), json!(0)));
+    node.abort();
+}
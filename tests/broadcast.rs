@@ -0,0 +1,72 @@
+use serde_json::{Value, json};
+use std::collections::BTreeSet;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// What n1, gossiping to n2 and n3, sends each of them once it has
+/// everything, after n2 told it of 7, n3 of 9 and a client broadcast 8.
+fn gossip_once_caught_up(args: &[&str]) -> [BTreeSet<u64>; 2] {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_broadcast"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else { break };
+            if tx.send(serde_json::from_str::<Value>(&line).unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let inputs = [
+        json!({"src": "c0", "dest": "n1", "body": {"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2", "n3"]}}),
+        json!({"src": "n2", "dest": "n1", "body": {"type": "gossip", "seen": [7]}}),
+        json!({"src": "n3", "dest": "n1", "body": {"type": "gossip", "seen": [9]}}),
+        json!({"src": "c1", "dest": "n1", "body": {"type": "broadcast", "msg_id": 1, "message": 8}}),
+    ];
+    for input in inputs {
+        writeln!(stdin, "{}", input).unwrap();
+    }
+    stdin.flush().unwrap();
+
+    let everything = BTreeSet::from([7, 8, 9]);
+    let mut caught_up = [None, None];
+    while caught_up.iter().any(Option::is_none) {
+        let msg = rx.recv_timeout(Duration::from_secs(5)).expect("no gossip with everything in it");
+        if msg["body"]["type"] != "gossip" {
+            continue;
+        }
+        let seen: BTreeSet<u64> = serde_json::from_value(msg["body"]["seen"].clone()).unwrap();
+        let (slot, from_it) = match msg["dest"].as_str().unwrap() {
+            "n2" => (0, 7),
+            "n3" => (1, 9),
+            other => panic!("gossip to {}", other),
+        };
+        // all that the peer didn't tell n1 itself
+        let news: BTreeSet<u64> = everything.iter().copied().filter(|&m| m != from_it).collect();
+        if seen.is_superset(&news) {
+            caught_up[slot].get_or_insert(seen);
+        }
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+    caught_up.map(Option::unwrap)
+}
+
+#[test]
+fn messages_are_never_resent_to_the_neighbour_they_came_from() {
+    // resending as many known messages as there are new ones resends all
+    // of them, bar those
+    let [to_n2, to_n3] = gossip_once_caught_up(&["--resend-percent", "100"]);
+    assert_eq!(to_n2, BTreeSet::from([8, 9]));
+    assert_eq!(to_n3, BTreeSet::from([7, 8]));
+}
@@ -0,0 +1,99 @@
+use dist_sys::capture::node_path;
+use dist_sys::client::MaelstromClient;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Echo { echo: String },
+    EchoOk { echo: String },
+}
+
+struct Echo;
+
+impl Node<(), Payload> for Echo {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Echo)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        let Payload::Echo { echo } = payload else {
+            return Ok(());
+        };
+        request.reply_with(Payload::EchoOk { echo }, &ctx).send(&ctx)
+    }
+}
+
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dist-sys-capture-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs `node_id` capturing into `path`, and has it echo `echo`.
+async fn echo(path: &Path, node_id: &'static str, echo: &str) {
+    let runtime = Runtime::new().capture(path);
+    let (mut client, node) = MaelstromClient::in_process::<_, Echo, _, (), ()>(runtime, (), node_id);
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2"]).await.unwrap();
+    client.request(json!({"type": "echo", "echo": echo})).await.unwrap();
+    node.abort();
+}
+
+/// The direction, type and echo of each record in the file at `path`.
+fn records(path: &Path) -> Vec<(String, String, Value)> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let record: Value = serde_json::from_str(line).unwrap();
+            assert!(record["at_us"].is_u64(), "{}", record);
+            let body = &record["msg"]["body"];
+            (
+                record["dir"].as_str().unwrap().to_string(),
+                body["type"].as_str().unwrap().to_string(),
+                body["echo"].clone(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn paths_name_the_node() {
+    assert_eq!(node_path(Path::new("/tmp/{node}/cap.jsonl"), "n1"), Path::new("/tmp/n1/cap.jsonl"));
+    assert_eq!(node_path(Path::new("/tmp/cap.jsonl"), "n1"), Path::new("/tmp/cap.n1.jsonl"));
+    assert_eq!(node_path(Path::new("/tmp/cap"), "n2"), Path::new("/tmp/cap.n2"));
+}
+
+#[tokio::test]
+async fn nodes_sharing_a_path_each_capture_into_their_own_file() {
+    let dir = dir("shared");
+    let path = dir.join("cap-{node}.jsonl");
+    echo(&path, "n1", "one").await;
+    echo(&path, "n2", "two").await;
+
+    for (node, said) in [("n1", "one"), ("n2", "two")] {
+        let expected = vec![
+            ("recv".to_string(), "init".to_string(), Value::Null),
+            ("send".to_string(), "init_ok".to_string(), Value::Null),
+            ("recv".to_string(), "echo".to_string(), json!(said)),
+            ("send".to_string(), "echo_ok".to_string(), json!(said)),
+        ];
+        assert_eq!(records(&dir.join(format!("cap-{}.jsonl", node))), expected, "{}", node);
+    }
+    assert!(!dir.join("cap-{node}.jsonl").exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,191 @@
+use dist_sys::emulate::Emulation;
+use dist_sys::error::MaelstromError;
+use dist_sys::replication::StateMachine;
+use dist_sys::replication::chain::{Chain, ChainAck, ForwardRequest, ForwardResponse, Options};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines};
+
+struct Sum(i64);
+
+impl StateMachine for Sum {
+    type Command = i64;
+    type Output = i64;
+
+    fn apply(&mut self, delta: &i64) -> i64 {
+        self.0 += delta;
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Add { delta: i64 },
+    AddOk { total: i64 },
+    Read,
+    ReadOk { total: i64 },
+    ChainForward(ForwardRequest<i64>),
+    ChainForwardOk(ForwardResponse),
+    ChainAck(ChainAck),
+}
+
+struct Summing {
+    chain: Arc<Chain<Sum>>,
+}
+
+fn answer(reply: Message<Payload>, result: anyhow::Result<Payload>, ctx: &Ctx) -> anyhow::Result<()> {
+    let Message { src, dst, body } = reply;
+    match result {
+        Ok(payload) => Message {
+            src,
+            dst,
+            body: Body { payload, ..body },
+        }
+        .send(ctx),
+        Err(e) => {
+            let body = Body {
+                id: body.id,
+                in_reply_to: body.in_reply_to,
+                payload: SystemPayload::Error(e.downcast::<MaelstromError>()?),
+            };
+            Message { src, dst, body }.send(ctx)
+        }
+    }
+}
+
+impl Node<(), Payload> for Summing {
+    async fn from_init(
+        _state: (),
+        init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        let options = Options {
+            timeout: Duration::from_millis(100),
+            ..Options::default()
+        };
+        let chain = Chain::start(ctx, "sum", &init.node_ids, Sum(0), options);
+        Ok(Summing { chain })
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let from = input.src.clone();
+        let mut reply = input.into_reply(None);
+        let payload = std::mem::replace(&mut reply.body.payload, Payload::Read);
+        let result = match payload {
+            Payload::Add { delta } => self.chain.submit(delta).await.map(|total| Payload::AddOk { total }),
+            Payload::Read => self.chain.query(|sum| sum.0).await.map(|total| Payload::ReadOk { total }),
+            Payload::ChainForward(request) => self.chain.forward(&from, request).await.map(Payload::ChainForwardOk),
+            Payload::ChainAck(ack) => {
+                self.chain.ack(&from, ack).await;
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        answer(reply, result, &ctx)
+    }
+}
+
+struct Cluster {
+    input: DuplexStream,
+    output: Lines<BufReader<DuplexStream>>,
+    next_msg_id: u64,
+}
+
+impl Cluster {
+    async fn start(node_ids: &[&str]) -> Self {
+        let (input, node_input) = tokio::io::duplex(64 * 1024);
+        let (node_output, output) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(
+            Runtime::new()
+                .emulate(Emulation::all())
+                .run_with_io::<_, Summing, _, (), ()>((), BufReader::new(node_input), node_output),
+        );
+        let mut cluster = Self {
+            input,
+            output: BufReader::new(output).lines(),
+            next_msg_id: 1,
+        };
+        let init = json!({"type": "init", "node_id": "n1", "node_ids": node_ids});
+        cluster.send("c0", init).await;
+        cluster
+    }
+
+    async fn send(&mut self, src: &str, mut body: Value) -> u64 {
+        let msg_id = self.next_msg_id;
+        self.next_msg_id += 1;
+        body["msg_id"] = msg_id.into();
+        let msg = json!({"src": src, "dest": "n1", "body": body});
+        self.input.write_all(format!("{}\n", msg).as_bytes()).await.unwrap();
+        msg_id
+    }
+
+    async fn next(&mut self) -> Value {
+        let line = tokio::time::timeout(Duration::from_secs(5), self.output.next_line())
+            .await
+            .expect("no message")
+            .unwrap()
+            .unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    async fn request(&mut self, src: &str, body: Value) -> Value {
+        let msg_id = self.send(src, body).await;
+        loop {
+            let msg = self.next().await;
+            if msg["dest"] == src && msg["body"]["in_reply_to"] == msg_id {
+                return msg["body"].clone();
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn a_lone_replica_is_head_and_tail() {
+    let mut cluster = Cluster::start(&["n1"]).await;
+
+    assert_eq!(cluster.request("c1", json!({"type": "add", "delta": 5})).await["total"], 5);
+    assert_eq!(cluster.request("c1", json!({"type": "add", "delta": 2})).await["total"], 7);
+    assert_eq!(cluster.request("c1", json!({"type": "read"})).await["total"], 7);
+}
+
+#[tokio::test]
+async fn the_tail_commits_what_its_predecessor_forwards() {
+    let mut cluster = Cluster::start(&["n2", "n1"]).await;
+
+    let forward = json!({"type": "chain_forward", "version": 0, "from": 0, "entries": [1, 2]});
+    cluster.send("n2", forward).await;
+    let (mut reply, mut ack) = (None, None);
+    while reply.is_none() || ack.is_none() {
+        let msg = cluster.next().await;
+        match msg["body"]["type"].as_str() {
+            Some("chain_forward_ok") => reply = Some(msg["body"].clone()),
+            Some("chain_ack") => ack = Some(msg),
+            _ => {}
+        }
+    }
+    let (reply, ack) = (reply.unwrap(), ack.unwrap());
+    assert_eq!((reply["len"].clone(), reply["committed"].clone()), (json!(2), json!(2)));
+    assert_eq!((ack["dest"].clone(), ack["body"]["committed"].clone()), (json!("n2"), json!(2)));
+
+    // a gap is refused; the predecessor resends from where the tail is
+    let forward = json!({"type": "chain_forward", "version": 0, "from": 5, "entries": [9]});
+    assert_eq!(cluster.request("n2", forward).await["len"], 2);
+
+    // entries the tail already has are skipped, not applied twice
+    let forward = json!({"type": "chain_forward", "version": 0, "from": 1, "entries": [2, 3]});
+    assert_eq!(cluster.request("n2", forward).await["committed"], 3);
+    assert_eq!(cluster.request("c1", json!({"type": "read"})).await["total"], 6);
+
+    // only the predecessor may forward, and only the head takes commands
+    let forward = json!({"type": "chain_forward", "version": 0, "from": 3, "entries": [4]});
+    assert_eq!(cluster.request("n3", forward).await["code"], 22);
+    assert_eq!(cluster.request("c1", json!({"type": "add", "delta": 1})).await["code"], 11);
+}
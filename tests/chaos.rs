@@ -0,0 +1,122 @@
+use dist_sys::chaos::Chaos;
+use dist_sys::client::MaelstromClient;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Flood { count: u64 },
+    FloodOk,
+    Flooded { index: u64 },
+}
+
+struct Flooder;
+
+impl Node<(), Payload> for Flooder {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Flooder)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        let Payload::Flood { count } = payload else {
+            return Ok(());
+        };
+        for index in 0..count {
+            let flooded = Message {
+                src: ctx.node_id().to_string(),
+                dst: "n2".to_string(),
+                body: Body {
+                    id: None,
+                    in_reply_to: None,
+                    payload: Payload::Flooded { index },
+                },
+            };
+            flooded.send(&ctx)?;
+        }
+        request.reply_with(Payload::FloodOk, &ctx).send(&ctx)?;
+        Ok(())
+    }
+}
+
+/// Which of 50 messages to n2 come out under `chaos`, in order, and how
+/// long after the request each does.
+async fn outcome(chaos: Chaos) -> Vec<(u64, Duration)> {
+    let (client, node) = MaelstromClient::in_process::<_, Flooder, _, (), ()>(Runtime::new().chaos(chaos), (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2"]).await.unwrap();
+
+    let start = Instant::now();
+    client.send(json!({"type": "flood", "count": 50})).await.unwrap();
+    let mut seen = Vec::new();
+    while let Ok(msg) = client.next_other().await {
+        seen.push((msg["body"]["index"].as_u64().unwrap(), start.elapsed()));
+    }
+    node.abort();
+    seen
+}
+
+fn chaos(seed: u64) -> Chaos {
+    Chaos {
+        drop_prob: 0.2,
+        delay_range: Duration::from_millis(1)..Duration::from_millis(20),
+        dup_prob: 0.2,
+        seed,
+    }
+}
+
+// paused, so delays show exactly in when messages come out
+#[tokio::test(start_paused = true)]
+async fn the_same_seed_makes_the_same_decisions() {
+    let first = outcome(chaos(3)).await;
+    assert_eq!(first, outcome(chaos(3)).await);
+    assert_ne!(first, outcome(chaos(4)).await);
+
+    // some dropped, some duplicated
+    let indices: Vec<u64> = first.iter().map(|(index, _)| *index).collect();
+    assert!((0..50).any(|i| !indices.contains(&i)), "{:?}", indices);
+    assert!(indices.windows(2).any(|pair| pair[0] == pair[1]), "{:?}", indices);
+}
+
+#[tokio::test(start_paused = true)]
+async fn replies_to_clients_are_left_alone() {
+    let chaos = Chaos {
+        drop_prob: 0.5,
+        delay_range: Duration::from_millis(50)..Duration::from_millis(100),
+        ..Chaos::default()
+    };
+    let (client, node) = MaelstromClient::in_process::<_, Flooder, _, (), ()>(Runtime::new().chaos(chaos), (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2"]).await.unwrap();
+
+    for _ in 0..20 {
+        let start = Instant::now();
+        let reply = client.request(json!({"type": "flood", "count": 1})).await.unwrap();
+        assert_eq!(reply["type"], "flood_ok");
+        assert_eq!(start.elapsed(), Duration::ZERO, "neither dropped nor delayed");
+    }
+    node.abort();
+}
+
+#[test]
+#[should_panic(expected = "finite")]
+fn probabilities_must_be_finite() {
+    let _ = Runtime::new().chaos(Chaos {
+        drop_prob: f64::NAN,
+        ..Chaos::default()
+    });
+}
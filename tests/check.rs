@@ -0,0 +1,54 @@
+use dist_sys::sim::History;
+use dist_sys::sim::check::{CounterModel, CounterOp, RegisterModel, RegisterOp, RegisterResult, is_linearizable, linearize};
+
+#[test]
+fn concurrent_counter_reads_may_observe_either_side_of_an_add() {
+    let history = History::new();
+    let add = history.invoke(0, CounterOp::Add(5));
+    let read = history.invoke(1, CounterOp::Read);
+    history.complete(read, Some(5));
+    history.complete(add, None);
+    let read = history.invoke(1, CounterOp::Read);
+    history.complete(read, Some(5));
+
+    let ops = history.into_operations();
+    assert_eq!(linearize(&CounterModel, &ops), Some(vec![0, 1, 2]));
+}
+
+#[test]
+fn stale_counter_read_after_completed_add_is_rejected() {
+    let history = History::new();
+    let add = history.invoke(0, CounterOp::Add(5));
+    history.complete(add, None);
+    let read = history.invoke(1, CounterOp::Read);
+    history.complete(read, Some(0));
+
+    assert!(!is_linearizable(&CounterModel, &history.into_operations()));
+}
+
+#[test]
+fn incomplete_register_write_may_never_take_effect() {
+    let history = History::new();
+    let write = history.invoke(0, RegisterOp::Write(1));
+    history.complete(write, RegisterResult::Ok(true));
+    let _lost = history.invoke(1, RegisterOp::Write(2));
+    let read = history.invoke(2, RegisterOp::Read);
+    history.complete(read, RegisterResult::Value(Some(1)));
+    let cas = history.invoke(2, RegisterOp::Cas { from: 1, to: 3 });
+    history.complete(cas, RegisterResult::Ok(true));
+
+    assert!(is_linearizable(&RegisterModel, &history.into_operations()));
+}
+
+#[test]
+fn register_read_cannot_go_back_in_time() {
+    let history = History::new();
+    let write = history.invoke(0, RegisterOp::Write(1));
+    history.complete(write, RegisterResult::Ok(true));
+    let write = history.invoke(0, RegisterOp::Write(2));
+    history.complete(write, RegisterResult::Ok(true));
+    let read = history.invoke(1, RegisterOp::Read);
+    history.complete(read, RegisterResult::Value(Some(1)));
+
+    assert!(!is_linearizable(&RegisterModel, &history.into_operations()));
+}
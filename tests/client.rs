@@ -0,0 +1,94 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::error::{ErrorCode, MaelstromError};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Echo { echo: String },
+    EchoOk { echo: String },
+    Add { delta: i64 },
+    AddOk,
+    Read,
+    ReadOk { value: i64 },
+    /// Asks n2 for its value and adds it.
+    Pull,
+    PullOk { value: i64 },
+    Get,
+    GetOk { value: i64 },
+}
+
+struct Summing {
+    total: AtomicI64,
+}
+
+impl Node<(), Payload> for Summing {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Summing { total: AtomicI64::new(0) })
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let payload = match &input.body.payload {
+            Payload::Echo { echo } => Payload::EchoOk { echo: echo.clone() },
+            Payload::Add { delta } if *delta < 0 => {
+                bail_with_error_reply!(&ctx, input, ErrorCode::PRECONDITION_FAILED, "only adds")
+            }
+            Payload::Add { delta } => {
+                self.total.fetch_add(*delta, Ordering::SeqCst);
+                Payload::AddOk
+            }
+            Payload::Read => Payload::ReadOk {
+                value: self.total.load(Ordering::SeqCst),
+            },
+            Payload::Pull => {
+                let Payload::GetOk { value } = ctx.rpc("n2", Payload::Get).await? else {
+                    anyhow::bail!("get answered with something else");
+                };
+                Payload::PullOk {
+                    value: self.total.fetch_add(value, Ordering::SeqCst) + value,
+                }
+            }
+            _ => return Ok(()),
+        };
+        let mut reply = input.into_reply(None);
+        reply.body.payload = payload;
+        reply.send(&ctx)
+    }
+}
+
+#[tokio::test]
+async fn drives_a_node_and_plays_its_peers() {
+    let (mut client, node) = MaelstromClient::in_process::<_, Summing, _, (), ()>(Runtime::new(), (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2"]).await.unwrap();
+
+    assert_eq!(client.echo("hi").await.unwrap(), "hi");
+    client.add(5).await.unwrap();
+    client.expect(json!({"type": "read"}), json!({"type": "read_ok", "value": 5})).await.unwrap();
+
+    let err = client.add(-1).await.unwrap_err();
+    assert_eq!(MaelstromError::code_of(&err), Some(ErrorCode::PRECONDITION_FAILED));
+    assert!(client.expect(json!({"type": "read"}), json!({"value": 4})).await.is_err());
+
+    // answer the node's request to n2 while a pull waits on it
+    let pull = client.send(json!({"type": "pull"})).await.unwrap();
+    let get = client.next_other().await.unwrap();
+    assert_eq!((get["dest"].clone(), get["body"]["type"].clone()), (json!("n2"), json!("get")));
+    let get_ok = json!({"type": "get_ok", "value": 2, "in_reply_to": get["body"]["msg_id"]});
+    client.send_from("n2", get_ok).await.unwrap();
+    assert_eq!(client.reply_to("c1", pull).await.unwrap()["body"]["value"], 7);
+
+    drop(client);
+    node.await.unwrap().unwrap();
+}
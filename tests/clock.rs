@@ -0,0 +1,124 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::time::{Clock, ManualClock, SkewedClock};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[test]
+fn skewed_clock_offsets_and_drifts() {
+    let manual = ManualClock::new();
+    let skewed = SkewedClock::new(manual.clone());
+    let wall = manual.wall_ms();
+    assert_eq!(skewed.wall_ms(), wall);
+
+    skewed.set_offset_ms(-500);
+    assert_eq!(skewed.wall_ms(), wall - 500);
+    assert_eq!(manual.now() - skewed.now(), Duration::from_millis(500));
+
+    // 10% fast: gains 100ms a second
+    skewed.set_drift_ppm(100_000);
+    manual.advance(Duration::from_secs(1));
+    assert_eq!(skewed.offset_ms(), -400);
+    assert_eq!(skewed.wall_ms(), wall + 1000 - 400);
+
+    // a jump keeps the drift
+    skewed.set_offset_ms(0);
+    manual.advance(Duration::from_secs(2));
+    assert_eq!(skewed.offset_ms(), 200);
+    assert_eq!(skewed.now() - manual.now(), Duration::from_millis(200));
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Stamp,
+    StampOk { wall: u64 },
+    Nap { ms: u64 },
+    NapOk,
+}
+
+struct Stamper;
+
+impl Node<(), Payload> for Stamper {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Stamper)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        let reply = match payload {
+            Payload::Stamp => Payload::StampOk {
+                wall: ctx.now_hlc().wall,
+            },
+            Payload::Nap { ms } => {
+                ctx.sleep(Duration::from_millis(ms)).await;
+                Payload::NapOk
+            }
+            Payload::StampOk { .. } | Payload::NapOk => return Ok(()),
+        };
+        request.reply_with(reply, &ctx).send(&ctx)
+    }
+}
+
+#[tokio::test]
+async fn hlc_reads_the_nodes_clock() {
+    let hour_behind = SkewedClock::new(time::TokioClock);
+    hour_behind.set_offset_ms(-3_600_000);
+    let (mut client, node) =
+        MaelstromClient::in_process::<_, Stamper, _, (), ()>(Runtime::new().clock(hour_behind), (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+
+    let stamped = client.request(json!({"type": "stamp"})).await.unwrap()["wall"].as_u64().unwrap();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+    let behind = now - stamped;
+    assert!((3_600_000..3_660_000).contains(&behind), "stamped {}ms behind", behind);
+    node.abort();
+}
+
+#[tokio::test]
+async fn manual_sleepers_wake_once_time_reaches_them() {
+    let clock = ManualClock::new();
+    let start = clock.now();
+    let short = tokio::spawn(clock.sleep(Duration::from_millis(10)));
+    let long = tokio::spawn(clock.sleep(Duration::from_millis(30)));
+    tokio::task::yield_now().await;
+    assert!(!short.is_finished() && !long.is_finished(), "time hasn't moved");
+
+    clock.advance(Duration::from_millis(20));
+    short.await.unwrap();
+    tokio::task::yield_now().await;
+    assert!(!long.is_finished());
+    assert_eq!(clock.now() - start, Duration::from_millis(20));
+    assert_eq!(clock.elapsed(), Duration::from_millis(20));
+
+    clock.advance(Duration::from_millis(10));
+    long.await.unwrap();
+    // nothing to wait for
+    clock.sleep(Duration::ZERO).await;
+}
+
+#[tokio::test]
+async fn nodes_sleep_on_the_runtimes_clock() {
+    let clock = ManualClock::new();
+    let runtime = Runtime::new().clock(clock.clone());
+    let (client, node) = MaelstromClient::in_process::<_, Stamper, _, (), ()>(runtime, (), "n1");
+    let mut client = client.with_timeout(Duration::from_millis(100));
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+
+    let nap = client.send(json!({"type": "nap", "ms": 60_000})).await.unwrap();
+    assert!(client.reply_to("c1", nap).await.is_err(), "woke without the clock moving");
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(client.reply_to("c1", nap).await.unwrap()["body"]["type"], "nap_ok");
+    node.abort();
+}
@@ -0,0 +1,100 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    /// Injects `events` all at once.
+    Inject { events: Vec<String> },
+    InjectOk,
+    Handled,
+    HandledOk { events: Vec<String> },
+}
+
+/// Ticks make later queued ticks redundant; anything else is kept.
+#[derive(Debug)]
+struct Queued(String);
+
+impl Coalescible for Queued {
+    fn coalesces(&self, queued: &Self) -> bool {
+        self.0.starts_with("tick") && queued.0.starts_with("tick")
+    }
+}
+
+struct Lagging {
+    inject: UnboundedSender<Event<Payload, (), Queued>>,
+    handled: Mutex<Vec<String>>,
+}
+
+impl Node<(), Payload, (), Queued> for Lagging {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        inject: UnboundedSender<Event<Payload, (), Queued>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Lagging {
+            inject,
+            handled: Mutex::default(),
+        })
+    }
+
+    async fn step(&self, input: Event<Payload, (), Queued>, ctx: Ctx) -> anyhow::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            Event::Injected(Queued(event)) => {
+                self.handled.lock().unwrap().push(event);
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        let (payload, request) = input.take_payload();
+        let reply = match payload {
+            Payload::Inject { events } => {
+                for event in events {
+                    self.inject.send(Event::Injected(Queued(event)))?;
+                }
+                Payload::InjectOk
+            }
+            Payload::Handled => Payload::HandledOk {
+                events: self.handled.lock().unwrap().clone(),
+            },
+            _ => return Ok(()),
+        };
+        request.reply_with(reply, &ctx).send(&ctx)
+    }
+}
+
+/// What the node handled, given `batches` of events each injected all at
+/// once, and each after the last was handled.
+async fn handled(batches: &[&[&str]]) -> serde_json::Value {
+    let (client, node) = MaelstromClient::in_process::<_, Lagging, _, (), Queued>(Runtime::new(), (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+    for events in batches {
+        client.request(json!({"type": "inject", "events": events})).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let reply = client.request(json!({"type": "handled"})).await.unwrap();
+    node.abort();
+    reply["events"].clone()
+}
+
+#[tokio::test(start_paused = true)]
+async fn queued_events_are_dropped_when_an_earlier_one_coalesces_them() {
+    let events = handled(&[&["tick 1", "note 1", "tick 2", "note 2", "tick 3"]]).await;
+    // the other events keep their order
+    assert_eq!(events, json!(["tick 1", "note 1", "note 2"]));
+}
+
+#[tokio::test(start_paused = true)]
+async fn events_handled_before_the_next_is_queued_are_not_coalesced() {
+    let events = handled(&[&["tick 1"], &["tick 2"]]).await;
+    assert_eq!(events, json!(["tick 1", "tick 2"]));
+}
@@ -0,0 +1,121 @@
+use dist_sys::codec::{Codec, FrameReader, FrameWriter, Json, MAX_FRAME_LEN};
+use dist_sys::{Body, Message};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Gossip { seen: Vec<u64> },
+    GossipOk,
+}
+
+fn messages() -> Vec<Message<Payload>> {
+    let gossip = Message {
+        src: "n1".to_string(),
+        dst: "n2".to_string(),
+        body: Body {
+            id: Some(7),
+            in_reply_to: None,
+            payload: Payload::Gossip {
+                seen: (0..1000).collect(),
+            },
+        },
+    };
+    let ok = Message {
+        src: "n2".to_string(),
+        dst: "n1".to_string(),
+        body: Body {
+            id: None,
+            in_reply_to: Some(7),
+            payload: Payload::GossipOk,
+        },
+    };
+    vec![gossip, ok]
+}
+
+fn same(a: &Message<Payload>, b: &Message<Payload>) -> bool {
+    a.src == b.src
+        && a.dst == b.dst
+        && a.body.id == b.body.id
+        && a.body.in_reply_to == b.body.in_reply_to
+        && a.body.payload == b.body.payload
+}
+
+/// Writes every message as a frame on one stream and reads them all back.
+async fn round_trip<C: Codec + Copy + 'static>(codec: C) {
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let sent = messages();
+    let writing = {
+        let sent = sent.clone();
+        tokio::spawn(async move {
+            let mut writer = FrameWriter::new(client, codec);
+            for msg in &sent {
+                writer.write(msg).await.unwrap();
+            }
+        })
+    };
+    let mut reader = FrameReader::new(server, codec);
+    for msg in &sent {
+        let read: Message<Payload> = reader.read().await.unwrap().expect("a frame per message");
+        assert!(same(msg, &read), "sent {:?}, read {:?}", msg, read);
+    }
+    writing.await.unwrap();
+    assert!(reader.read::<Message<Payload>>().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn json_frames_round_trip() {
+    round_trip(Json).await;
+}
+
+#[cfg(feature = "msgpack")]
+#[tokio::test]
+async fn msgpack_frames_round_trip() {
+    round_trip(dist_sys::codec::MessagePack).await;
+}
+
+#[cfg(feature = "cbor")]
+#[tokio::test]
+async fn cbor_frames_round_trip() {
+    round_trip(dist_sys::codec::Cbor).await;
+}
+
+#[cfg(all(feature = "msgpack", feature = "cbor"))]
+#[test]
+fn binary_encodings_are_smaller_for_integer_heavy_payloads() {
+    let gossip = &messages()[0];
+    let json = Json.encode(gossip).unwrap().len();
+    assert!(dist_sys::codec::MessagePack.encode(gossip).unwrap().len() < json);
+    assert!(dist_sys::codec::Cbor.encode(gossip).unwrap().len() < json);
+}
+
+#[tokio::test]
+async fn oversized_frames_are_rejected() {
+    let (mut client, server) = tokio::io::duplex(64);
+    let len = u32::try_from(MAX_FRAME_LEN + 1).unwrap();
+    client.write_all(&len.to_be_bytes()).await.unwrap();
+    let mut reader = FrameReader::new(server, Json);
+    assert!(reader.read::<Message<Payload>>().await.is_err());
+}
+
+#[tokio::test]
+async fn oversized_frames_are_not_written() {
+    let (client, server) = tokio::io::duplex(64);
+    let mut writer = FrameWriter::new(client, Json);
+    // two bytes over once quoted
+    let huge = "a".repeat(MAX_FRAME_LEN);
+    assert!(writer.write(&huge).await.is_err());
+    drop(writer);
+    let mut reader = FrameReader::new(server, Json);
+    assert!(reader.read::<String>().await.unwrap().is_none(), "nothing reached the stream");
+}
+
+#[tokio::test]
+async fn a_stream_ending_inside_a_frame_length_is_an_error() {
+    let (mut client, server) = tokio::io::duplex(64);
+    client.write_all(&[0, 0]).await.unwrap();
+    drop(client);
+    let mut reader = FrameReader::new(server, Json);
+    assert!(reader.read::<Message<Payload>>().await.is_err());
+}
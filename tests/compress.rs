@@ -0,0 +1,152 @@
+#![cfg(feature = "compression")]
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use dist_sys::client::MaelstromClient;
+use dist_sys::compress::Compression;
+use dist_sys::*;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::io::{Read, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Forward { to: String, data: String },
+    Data { data: String },
+    Received,
+    ReceivedOk { data: Vec<String> },
+}
+
+/// Forwards data to other nodes, and says what data it was sent.
+struct Forwarder {
+    received: Mutex<Vec<String>>,
+}
+
+impl Node<(), Payload> for Forwarder {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Forwarder {
+            received: Mutex::default(),
+        })
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        let reply = match payload {
+            Payload::Forward { to, data } => {
+                let forwarded = Message {
+                    src: ctx.node_id().to_string(),
+                    dst: to,
+                    body: Body {
+                        id: None,
+                        in_reply_to: None,
+                        payload: Payload::Data { data },
+                    },
+                };
+                return forwarded.send(&ctx);
+            }
+            Payload::Data { data } => {
+                self.received.lock().unwrap().push(data);
+                return Ok(());
+            }
+            Payload::Received => Payload::ReceivedOk {
+                data: self.received.lock().unwrap().clone(),
+            },
+            _ => return Ok(()),
+        };
+        request.reply_with(reply, &ctx).send(&ctx)
+    }
+}
+
+/// A client playing `n2` to `n1`, which compresses bodies of 100 bytes or
+/// more.
+async fn peer() -> (MaelstromClient, tokio::task::JoinHandle<anyhow::Result<()>>) {
+    let runtime = Runtime::new().compress(Compression { min_bytes: 100 });
+    let (client, node) = MaelstromClient::in_process::<_, Forwarder, _, (), ()>(runtime, (), "n1");
+    let mut client = client.with_timeout(Duration::from_millis(300));
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2"]).await.unwrap();
+    (client, node)
+}
+
+/// The body of the next message `n1` sends `n2`.
+async fn forwarded(client: &mut MaelstromClient, data: &str) -> Value {
+    client.send(json!({"type": "forward", "to": "n2", "data": data})).await.unwrap();
+    let msg = client.next_other().await.unwrap();
+    assert_eq!(msg["dest"], "n2", "{}", msg);
+    msg["body"].clone()
+}
+
+fn gzip(body: &Value) -> String {
+    let mut gz = GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    gz.write_all(&serde_json::to_vec(body).unwrap()).unwrap();
+    BASE64.encode(gz.finish().unwrap())
+}
+
+fn gunzip(body: &Value) -> Value {
+    assert_eq!(body["encoding"], "gzip", "{}", body);
+    let gz = BASE64.decode(body["data"].as_str().unwrap()).unwrap();
+    let mut json = Vec::new();
+    GzDecoder::new(&gz[..]).read_to_end(&mut json).unwrap();
+    serde_json::from_slice(&json).unwrap()
+}
+
+#[tokio::test]
+async fn large_bodies_are_compressed_once_the_peer_accepts_gzip() {
+    let (mut client, node) = peer().await;
+    let large = "x".repeat(500);
+
+    // n2 hasn't said it accepts gzip yet
+    let body = forwarded(&mut client, &large).await;
+    assert_eq!(body["type"], "data", "{}", body);
+    assert_eq!(body["accept_encoding"], "gzip");
+
+    let ad = json!({"type": "data", "data": "hello", "accept_encoding": "gzip"});
+    client.send_from("n2", ad).await.unwrap();
+    let body = forwarded(&mut client, &large).await;
+    assert_eq!(body["type"], "compressed", "{}", body);
+    let body = gunzip(&body);
+    assert_eq!((&body["type"], &body["data"]), (&json!("data"), &json!(large)));
+
+    // small bodies go as they are, still advertising until n2 stops
+    let body = forwarded(&mut client, "small").await;
+    assert_eq!(body["type"], "data", "{}", body);
+    assert_eq!(body["accept_encoding"], "gzip");
+    client.send_from("n2", json!({"type": "data", "data": "heard"})).await.unwrap();
+    let body = forwarded(&mut client, "small").await;
+    assert_eq!(body["type"], "data", "{}", body);
+    assert!(body.get("accept_encoding").is_none(), "{}", body);
+    node.abort();
+}
+
+#[tokio::test]
+async fn compressed_bodies_are_unwrapped_before_the_node_sees_them() {
+    let (mut client, node) = peer().await;
+    let large = "y".repeat(500);
+
+    let data = gzip(&json!({"type": "data", "data": large}));
+    let compressed = json!({"type": "compressed", "encoding": "gzip", "data": data});
+    client.send_from("n2", compressed).await.unwrap();
+
+    // clients are never sent compressed bodies, however large
+    let reply = client.request(json!({"type": "received"})).await.unwrap();
+    assert_eq!(reply["data"], json!([large]));
+    assert!(reply.get("accept_encoding").is_none(), "{}", reply);
+
+    // and sending compressed showed n2 accepts it
+    let body = forwarded(&mut client, &large).await;
+    assert_eq!(body["type"], "compressed", "{}", body);
+    node.abort();
+}
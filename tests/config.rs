@@ -0,0 +1,106 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::config::NodeConfig;
+use dist_sys::error::ErrorCode;
+use dist_sys::kv::Kv;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+#[test]
+fn arguments_override_the_environment() {
+    let config = NodeConfig::from_parts(
+        args(&["efficient-1", "--gossip-interval", "150ms", "--retries=3"]),
+        env(&[
+            ("DIST_SYS_GOSSIP_INTERVAL", "2s"),
+            ("DIST_SYS_BATCH_SIZE", "64"),
+            ("DIST_SYS_MODE", "multi"),
+            ("PATH", "/bin"),
+        ]),
+    )
+    .unwrap();
+
+    assert_eq!(config.mode(), Some("efficient-1"));
+    assert_eq!(config.duration("gossip-interval").unwrap(), Some(Duration::from_millis(150)));
+    assert_eq!(config.get::<usize>("retries").unwrap(), Some(3));
+    assert_eq!(config.get::<usize>("batch-size").unwrap(), Some(64));
+    assert_eq!(config.get_or("missing", 7usize).unwrap(), 7);
+    assert_eq!(config.get::<String>("path").unwrap(), None);
+}
+
+#[test]
+fn malformed_knobs_are_reported() {
+    assert!(NodeConfig::from_parts(args(&["--retries"]), env(&[])).is_err());
+    assert!(NodeConfig::from_parts(args(&["single", "multi"]), env(&[])).is_err());
+
+    let config = NodeConfig::from_parts(args(&["--retries", "many", "--timeout", "3h"]), env(&[])).unwrap();
+    assert!(config.get::<usize>("retries").is_err());
+    assert!(config.duration("timeout").is_err());
+    assert_eq!(config.mode(), None);
+}
+
+#[test]
+fn flags_are_on_unless_empty_or_zero() {
+    let config = NodeConfig::from_parts(args(&["--a", "1", "--b", "0", "--c", "", "--d", "yes"]), env(&[])).unwrap();
+    assert!(config.flag("a"));
+    assert!(!config.flag("b"));
+    assert!(!config.flag("c"));
+    assert!(config.flag("d"));
+    assert!(!config.flag("e"));
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Store,
+    StoreOk,
+}
+
+/// Stores a value in lin-kv when asked.
+struct Storing;
+
+impl Node<(), Payload> for Storing {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Storing)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        Kv::lin(&ctx).write("k", 1).await?;
+        input.reply_with(Payload::StoreOk, &ctx).send(&ctx)
+    }
+}
+
+#[tokio::test]
+async fn runtimes_take_their_knobs_from_the_config() {
+    // as Runtime::from_env would see them in the environment
+    let config = NodeConfig::from_parts(args(&[]), env(&[("DIST_SYS_EMULATE", "all"), ("DIST_SYS_STRICT", "1")])).unwrap();
+    let runtime = Runtime::from_config(&config);
+    let (client, node) = MaelstromClient::in_process::<_, Storing, _, (), ()>(runtime, (), "n1");
+    let mut client = client.with_timeout(Duration::from_millis(500));
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+
+    // lin-kv answered in-process
+    client.expect(json!({"type": "store"}), json!({"type": "store_ok"})).await.unwrap();
+    // and a request the node can't parse answered with an error
+    let id = client.send(json!({"type": "fetch"})).await.unwrap();
+    let reply = client.reply_to("c1", id).await.unwrap();
+    assert_eq!(reply["body"]["code"], ErrorCode::MALFORMED_REQUEST.0, "{}", reply);
+    node.abort();
+}
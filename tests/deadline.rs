@@ -0,0 +1,122 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::error::{ErrorCode, MaelstromError};
+use dist_sys::kv::Kv;
+use dist_sys::lease::LeaseOptions;
+use dist_sys::link::ReliableLink;
+use dist_sys::lock::DistLock;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Relay,
+    RelayOk,
+    Ask,
+    AskOk { remaining_ms: Option<u64> },
+    Deliver,
+    Watch,
+    Lock,
+    Done,
+}
+
+/// Relays to n2 with half its budget, and says how much budget it was given.
+struct Relay;
+
+impl Node<(), Payload> for Relay {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Relay)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let mut reply = input.into_reply(None);
+        reply.body.payload = match reply.body.payload {
+            Payload::Relay => {
+                let _: Payload = ctx.share(2).rpc("n2", Payload::Ask).await?;
+                Payload::RelayOk
+            }
+            Payload::Ask => Payload::AskOk {
+                remaining_ms: ctx.remaining().map(|remaining| remaining.as_millis() as u64),
+            },
+            Payload::Deliver => {
+                ReliableLink::new(&ctx).retry(Duration::from_millis(30), None).send_reliable("n2", Payload::Ask).await?;
+                Payload::Done
+            }
+            Payload::Watch => {
+                Kv::lin(&ctx).watch::<u64>("k").changed().await?;
+                Payload::Done
+            }
+            Payload::Lock => {
+                DistLock::new(&ctx, LeaseOptions::default()).acquire("l").await?;
+                Payload::Done
+            }
+            _ => return Ok(()),
+        };
+        reply.send(&ctx)
+    }
+}
+
+#[tokio::test]
+async fn budgets_shrink_along_the_chain_and_overruns_are_answered() {
+    let runtime = Runtime::new().client_budget(Duration::from_millis(200)).propagate_deadlines();
+    let (mut client, node) = MaelstromClient::in_process::<_, Relay, _, (), ()>(runtime, (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2"]).await.unwrap();
+
+    let relay = client.send(json!({"type": "relay"})).await.unwrap();
+    let ask = client.next_other().await.unwrap();
+    assert_eq!(ask["dest"], "n2");
+    let budget = ask["body"]["budget_ms"].as_u64().unwrap();
+    assert!((50..=100).contains(&budget), "n2 was given {}ms", budget);
+
+    // n2 never answers
+    let reply = client.reply_to("c1", relay).await.unwrap();
+    let err: MaelstromError = serde_json::from_value(reply["body"].clone()).unwrap();
+    assert_eq!(err.code, ErrorCode::TIMEOUT, "{}", err);
+
+    // still up, and a peer's budget travels with its request
+    let asked = client.send_from("n2", json!({"type": "ask", "budget_ms": 30})).await.unwrap();
+    let remaining = client.reply_to("n2", asked).await.unwrap()["body"]["remaining_ms"].as_u64().unwrap();
+    assert!(remaining <= 30, "{}ms left", remaining);
+    let remaining = client.request(json!({"type": "ask"})).await.unwrap()["remaining_ms"].as_u64().unwrap();
+    assert!((100..=200).contains(&remaining), "{}ms left", remaining);
+    node.abort();
+}
+
+#[tokio::test]
+async fn without_a_budget_nothing_has_a_deadline() {
+    let (mut client, node) = MaelstromClient::in_process::<_, Relay, _, (), ()>(Runtime::new(), (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2"]).await.unwrap();
+
+    let asked = client.send_from("n2", json!({"type": "ask", "budget_ms": 30})).await.unwrap();
+    assert_eq!(client.reply_to("n2", asked).await.unwrap()["body"]["remaining_ms"], json!(null));
+    node.abort();
+}
+
+#[tokio::test]
+async fn retrying_operations_give_up_at_the_deadline() {
+    let runtime = Runtime::new().client_budget(Duration::from_millis(100));
+    let (client, node) = MaelstromClient::in_process::<_, Relay, _, (), ()>(runtime, (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2"]).await.unwrap();
+
+    // neither n2 nor lin-kv ever answers
+    for request in ["deliver", "watch", "lock"] {
+        let err = client.request(json!({"type": request})).await.unwrap_err();
+        let err = err.downcast::<MaelstromError>().unwrap_or_else(|e| panic!("{}: {:#}", request, e));
+        assert_eq!(err.code, ErrorCode::TIMEOUT, "{}: {}", request, err);
+    }
+    node.abort();
+}
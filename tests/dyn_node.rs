@@ -0,0 +1,81 @@
+use dist_sys::dyn_node::{DynInit, DynNode};
+use dist_sys::selftest::{self, Script};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Echo { echo: String },
+    EchoOk { echo: String },
+}
+
+/// `Quiet`'s initial state, so the two nodes differ in state type as well.
+struct Prefixing(&'static str);
+
+struct Loud;
+
+struct Quiet;
+
+async fn echo(prefix: &str, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+    let Event::Message(input) = input else {
+        return Ok(());
+    };
+    let mut reply = input.into_reply(None);
+    if let Payload::Echo { echo } = reply.body.payload {
+        reply.body.payload = Payload::EchoOk {
+            echo: format!("{}{}", prefix, echo),
+        };
+        reply.send(&ctx)?;
+    }
+    Ok(())
+}
+
+impl Node<(), Payload> for Loud {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Loud)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        echo("LOUD ", input, ctx).await
+    }
+}
+
+impl Node<Prefixing, Payload> for Quiet {
+    async fn from_init(
+        state: Prefixing,
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        assert_eq!(state.0, "quiet ");
+        Ok(Quiet)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        echo("quiet ", input, ctx).await
+    }
+}
+
+#[tokio::test]
+async fn runs_nodes_chosen_at_runtime_through_one_type() {
+    let nodes = [
+        (DynInit::new::<_, Loud>(()), "LOUD hi"),
+        (DynInit::new::<_, Quiet>(Prefixing("quiet ")), "quiet hi"),
+    ];
+    for (init, expected) in nodes {
+        let script = Script::new().exchange(
+            json!({"type": "echo", "echo": "hi"}),
+            json!({"type": "echo_ok", "echo": expected}),
+        );
+        selftest::run::<_, Box<dyn DynNode<Payload>>, _, _, _>(Runtime::new(), init, script)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,51 @@
+use dist_sys::sim::History;
+use dist_sys::sim::edn::{self, Edn, Outcome};
+use serde_json::json;
+
+#[derive(Debug)]
+enum Op {
+    Add(i64),
+    Read,
+}
+
+#[test]
+fn history_renders_ok_fail_and_incomplete_operations() {
+    let history = History::new();
+    let add = history.invoke(0, Op::Add(3));
+    let read = history.invoke(1, Op::Read);
+    history.complete(add, Ok(None));
+    // never completes
+    history.invoke(0, Op::Add(4));
+    history.complete(read, Err("unavailable"));
+
+    let out = edn::history(
+        &history.into_operations(),
+        |op| match op {
+            Op::Add(delta) => ("add".to_string(), Edn::Int(*delta)),
+            Op::Read => ("read".to_string(), Edn::Nil),
+        },
+        |op, result: &Result<Option<i64>, &str>| match (op, result) {
+            (Op::Add(delta), Ok(_)) => (Outcome::Ok, Edn::Int(*delta)),
+            (Op::Read, Ok(value)) => (Outcome::Ok, value.map_or(Edn::Nil, Edn::Int)),
+            (_, Err(_)) => (Outcome::Fail, Edn::Nil),
+        },
+    );
+    let expected = [
+        "{:index 0, :type :invoke, :process 0, :time 0, :f :add, :value 3}",
+        "{:index 1, :type :invoke, :process 1, :time 1, :f :read, :value nil}",
+        "{:index 2, :type :ok, :process 0, :time 2, :f :add, :value 3}",
+        "{:index 3, :type :invoke, :process 0, :time 3, :f :add, :value 4}",
+        "{:index 4, :type :fail, :process 1, :time 4, :f :read, :value nil}",
+        "{:index 5, :type :info, :process 0, :time 5, :f :add, :value 4}",
+    ];
+    assert_eq!(out.lines().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn json_converts_to_edn() {
+    let value = json!({"txn": [["append", 1, 2.5], ["r", 1, null]], "ok": true, "s": "a \"b\""});
+    assert_eq!(
+        Edn::from(&value).to_string(),
+        r#"{:ok true, :s "a \"b\"", :txn [["append" 1 2.5] ["r" 1 nil]]}"#
+    );
+}
@@ -0,0 +1,137 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::emulate::{Emulation, LIN_TSO};
+use dist_sys::error::{ErrorCode, MaelstromError};
+use dist_sys::kv::{LIN_KV, SEQ_KV};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    /// Sends `body` to `service` and answers with its reply.
+    Call { service: String, body: Value },
+    CallOk { reply: Value },
+}
+
+struct Proxy;
+
+impl Node<(), Payload> for Proxy {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Proxy)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (Payload::Call { service, body }, request) = input.take_payload() else {
+            return Ok(());
+        };
+        match ctx.rpc::<_, Value>(&service, body).await {
+            Ok(reply) => request.reply_with(Payload::CallOk { reply }, &ctx).send(&ctx),
+            Err(e) => request.reply_error(e.downcast::<MaelstromError>()?, &ctx),
+        }
+    }
+}
+
+async fn proxy(emulation: Emulation) -> (MaelstromClient, tokio::task::JoinHandle<anyhow::Result<()>>) {
+    let runtime = Runtime::new().emulate(emulation);
+    let (client, node) = MaelstromClient::in_process::<_, Proxy, _, (), ()>(runtime, (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+    (client, node)
+}
+
+/// `service`'s reply to `body`.
+async fn call(client: &mut MaelstromClient, service: &str, body: Value) -> Value {
+    let reply = client.request(json!({"type": "call", "service": service, "body": body})).await.unwrap();
+    reply["reply"].clone()
+}
+
+/// The code of the error `service` answers `body` with.
+async fn failure(client: &mut MaelstromClient, service: &str, body: Value) -> ErrorCode {
+    let err = client.request(json!({"type": "call", "service": service, "body": body})).await.unwrap_err();
+    MaelstromError::code_of(&err).unwrap_or_else(|| panic!("{:#}", err))
+}
+
+#[tokio::test]
+async fn lin_kv_reads_writes_and_compares_and_sets() {
+    let (mut client, node) = proxy(Emulation::all()).await;
+
+    assert_eq!(failure(&mut client, LIN_KV, json!({"type": "read", "key": "k"})).await, ErrorCode::KEY_DOES_NOT_EXIST);
+    assert_eq!(failure(&mut client, LIN_KV, json!({"type": "cas", "key": "k", "from": 1, "to": 2})).await, ErrorCode::KEY_DOES_NOT_EXIST);
+
+    let created = json!({"type": "cas", "key": "k", "from": 0, "to": 1, "create_if_not_exists": true});
+    assert_eq!(call(&mut client, LIN_KV, created).await["type"], "cas_ok");
+    assert_eq!(failure(&mut client, LIN_KV, json!({"type": "cas", "key": "k", "from": 0, "to": 2})).await, ErrorCode::PRECONDITION_FAILED);
+    assert_eq!(call(&mut client, LIN_KV, json!({"type": "cas", "key": "k", "from": 1, "to": 2})).await["type"], "cas_ok");
+    assert_eq!(call(&mut client, LIN_KV, json!({"type": "write", "key": "other", "value": [1, 2]})).await["type"], "write_ok");
+
+    assert_eq!(call(&mut client, LIN_KV, json!({"type": "read", "key": "k"})).await["value"], 2);
+    assert_eq!(call(&mut client, LIN_KV, json!({"type": "read", "key": "other"})).await["value"], json!([1, 2]));
+    // each service keeps keys of its own
+    assert_eq!(failure(&mut client, SEQ_KV, json!({"type": "read", "key": "k"})).await, ErrorCode::KEY_DOES_NOT_EXIST);
+    node.abort();
+}
+
+#[tokio::test]
+async fn lin_tso_hands_out_increasing_timestamps() {
+    let (mut client, node) = proxy(Emulation::all()).await;
+    let first = call(&mut client, LIN_TSO, json!({"type": "ts"})).await["ts"].as_u64().unwrap();
+    let second = call(&mut client, LIN_TSO, json!({"type": "ts"})).await["ts"].as_u64().unwrap();
+    assert!(second > first, "{} then {}", first, second);
+    assert_eq!(failure(&mut client, LIN_TSO, json!({"type": "read", "key": "k"})).await, ErrorCode::NOT_SUPPORTED);
+    node.abort();
+}
+
+#[tokio::test]
+async fn stale_seq_kv_reads_never_go_back_past_the_nodes_own_writes() {
+    let emulation = Emulation {
+        seq_kv_stale_reads: 1.0,
+        ..Emulation::all()
+    };
+    let (mut client, node) = proxy(emulation).await;
+    for value in 1..=5 {
+        call(&mut client, SEQ_KV, json!({"type": "write", "key": "k", "value": value})).await;
+        let read = call(&mut client, SEQ_KV, json!({"type": "read", "key": "k"})).await;
+        assert_eq!(read["value"], value);
+    }
+    node.abort();
+}
+
+// paused, so the latency shows exactly in when replies arrive
+#[tokio::test(start_paused = true)]
+async fn replies_are_held_back_for_the_latency() {
+    let emulation = Emulation {
+        latency: Duration::from_millis(50)..Duration::from_millis(60),
+        ..Emulation::all()
+    };
+    let (mut client, node) = proxy(emulation).await;
+    let start = Instant::now();
+    call(&mut client, LIN_TSO, json!({"type": "ts"})).await;
+    let took = start.elapsed();
+    assert!((Duration::from_millis(50)..Duration::from_millis(60)).contains(&took), "{:?}", took);
+    node.abort();
+}
+
+#[tokio::test]
+async fn services_not_emulated_are_left_to_maelstrom() {
+    let (mut client, node) = proxy(Emulation::from_spec("lin-kv").unwrap()).await;
+    client
+        .send(json!({"type": "call", "service": SEQ_KV, "body": {"type": "read", "key": "k"}}))
+        .await
+        .unwrap();
+    let out = client.next_other().await.unwrap();
+    assert_eq!(out["dest"], SEQ_KV);
+    assert_eq!(out["body"]["type"], "read");
+    node.abort();
+}
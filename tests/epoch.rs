@@ -0,0 +1,90 @@
+use dist_sys::emulate::Emulation;
+use dist_sys::epoch::{Epoch, PeerRestarted};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+static RESTARTS: Mutex<Vec<PeerRestarted>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Ping,
+    Pong,
+}
+
+struct Pinged;
+
+impl Node<(), Payload> for Pinged {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Pinged)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        match input {
+            Event::PeerRestarted(restarted) => RESTARTS.lock().unwrap().push(restarted),
+            Event::Message(msg) if matches!(msg.body.payload, Payload::Ping) => {
+                let mut reply = msg.into_reply(None);
+                reply.body.payload = Payload::Pong;
+                reply.send(&ctx)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn ping(msg_id: u64, epoch: u64) -> String {
+    let msg = json!({"src": "n2", "dest": "n1", "body": {"type": "ping", "msg_id": msg_id, "epoch": epoch}});
+    format!("{}\n", msg)
+}
+
+#[tokio::test]
+async fn stamps_its_epoch_and_reports_peers_that_restarted() {
+    let (mut input, node_input) = tokio::io::duplex(4096);
+    let (node_output, output) = tokio::io::duplex(4096);
+    let init = json!({"src": "c0", "dest": "n1", "body": {"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]}});
+    input.write_all(format!("{}\n", init).as_bytes()).await.unwrap();
+    let node = tokio::spawn(
+        Runtime::new()
+            .emulate(Emulation::all())
+            .epochs()
+            .run_with_io::<_, Pinged, _, (), ()>((), BufReader::new(node_input), node_output),
+    );
+
+    // leave the node time to bump its epoch in the emulated lin-kv
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    for (msg_id, epoch) in [(2, 1), (3, 3), (4, 2), (5, 3)] {
+        input.write_all(ping(msg_id, epoch).as_bytes()).await.unwrap();
+    }
+
+    let mut lines = BufReader::new(output).lines();
+    let mut pongs = Vec::new();
+    while pongs.len() < 4 {
+        let line = tokio::time::timeout(Duration::from_secs(5), lines.next_line()).await.unwrap().unwrap().unwrap();
+        let msg: Value = serde_json::from_str(&line).unwrap();
+        if msg["body"]["type"] == "pong" {
+            pongs.push(msg);
+        }
+    }
+    drop(input);
+    node.await.unwrap().unwrap();
+
+    assert!(pongs.iter().all(|pong| pong["body"]["epoch"] == 1), "{:?}", pongs);
+    assert_eq!(
+        *RESTARTS.lock().unwrap(),
+        [PeerRestarted {
+            peer: "n2".to_string(),
+            previous: Epoch(1),
+            epoch: Epoch(3),
+        }]
+    );
+}
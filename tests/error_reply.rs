@@ -0,0 +1,58 @@
+use dist_sys::error::{ErrorCode, MaelstromError};
+use dist_sys::selftest::{self, Script};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Div { a: i64, b: i64 },
+    DivOk { quotient: i64 },
+}
+
+struct Dividing;
+
+impl Node<(), Payload> for Dividing {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Dividing)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let Payload::Div { a, b } = input.body.payload else {
+            return Ok(());
+        };
+        if b == 0 {
+            bail_with_error_reply!(&ctx, input, ErrorCode::MALFORMED_REQUEST, "cannot divide {} by zero", a);
+        }
+        if a < 0 {
+            bail_with_error_reply!(&ctx, input, MaelstromError::new(ErrorCode::NOT_SUPPORTED, "negative"));
+        }
+        let mut reply = input.into_reply(None);
+        reply.body.payload = Payload::DivOk { quotient: a / b };
+        reply.send(&ctx)
+    }
+}
+
+#[tokio::test]
+async fn bailing_answers_the_request_and_keeps_the_node_running() {
+    let script = Script::new()
+        .exchange(
+            json!({"type": "div", "a": 1, "b": 0}),
+            json!({"type": "error", "code": 12, "text": "cannot divide 1 by zero"}),
+        )
+        .exchange(
+            json!({"type": "div", "a": -1, "b": 1}),
+            json!({"type": "error", "code": 10, "text": "negative"}),
+        )
+        .exchange(json!({"type": "div", "a": 6, "b": 3}), json!({"type": "div_ok", "quotient": 2}));
+    selftest::run::<_, Dividing, _, _, _>(Runtime::new(), (), script).await.unwrap();
+}
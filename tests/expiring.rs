@@ -0,0 +1,32 @@
+use dist_sys::collections::ExpiringMap;
+use dist_sys::time::ManualClock;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn entries_expire_on_their_own_schedules() {
+    let clock = ManualClock::new();
+    let secs = Duration::from_secs;
+    let mut map = ExpiringMap::new(Arc::new(clock.clone()));
+    map.insert("lease", 1, secs(10));
+    map.insert("hint", 2, secs(5));
+    assert_eq!(map.len(), 2);
+
+    clock.advance(secs(5));
+    assert_eq!(map.get("hint"), None);
+    assert_eq!(map.get("lease"), Some(&1));
+    // an expired value isn't handed back when overwritten
+    assert_eq!(map.insert("hint", 3, secs(5)), None);
+    assert!(map.touch("lease", secs(10)));
+
+    clock.advance(secs(6));
+    assert_eq!(map.iter().collect::<Vec<_>>(), [(&"lease", &1)]);
+    assert!(!map.touch("hint", secs(10)));
+    assert_eq!(map.remove("hint"), None);
+    map.insert("dedup", 4, secs(1));
+
+    clock.advance(secs(10));
+    assert!(map.is_empty());
+    assert_eq!(map.purge(), 2);
+    assert_eq!(map.purge(), 0);
+}
@@ -0,0 +1,67 @@
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Echo { echo: u64 },
+    EchoOk { echo: u64 },
+}
+
+struct Echo;
+
+impl Node<(), Payload> for Echo {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Echo)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (Payload::Echo { echo }, request) = input.take_payload() else {
+            return Ok(());
+        };
+        request.reply_with(Payload::EchoOk { echo }, &ctx).send(&ctx)
+    }
+}
+
+/// A burst of requests already waiting when the node starts, all answered
+/// however small the budget.
+#[tokio::test]
+async fn a_burst_is_answered_in_turns() {
+    const REQUESTS: u64 = 500;
+    let (mut input, node_input) = tokio::io::duplex(1024 * 1024);
+    let (node_output, output) = tokio::io::duplex(1024 * 1024);
+    let init = json!({"src": "c1", "dest": "n1", "body": {"type": "init", "msg_id": 0, "node_id": "n1", "node_ids": ["n1"]}});
+    input.write_all(format!("{}\n", init).as_bytes()).await.unwrap();
+    for echo in 1..=REQUESTS {
+        let line = json!({"src": "c1", "dest": "n1", "body": {"type": "echo", "msg_id": echo, "echo": echo}});
+        input.write_all(format!("{}\n", line).as_bytes()).await.unwrap();
+    }
+    drop(input);
+
+    let runtime = Runtime::new().event_budget(1);
+    runtime
+        .run_with_io::<_, Echo, _, (), ()>((), BufReader::new(node_input), node_output)
+        .await
+        .unwrap();
+
+    let mut answered = Vec::new();
+    let mut output = BufReader::new(output).lines();
+    while let Some(line) = output.next_line().await.unwrap() {
+        let msg: Value = serde_json::from_str(&line).unwrap();
+        if msg["body"]["type"] == "echo_ok" {
+            answered.push(msg["body"]["echo"].as_u64().unwrap());
+        }
+    }
+    answered.sort_unstable();
+    assert_eq!(answered, (1..=REQUESTS).collect::<Vec<_>>());
+}
@@ -0,0 +1,38 @@
+use dist_sys::fanout;
+use std::time::Duration;
+
+async fn reply(after_ms: u64, result: Result<u32, &'static str>) -> anyhow::Result<u32> {
+    tokio::time::sleep(Duration::from_millis(after_ms)).await;
+    result.map_err(anyhow::Error::msg)
+}
+
+#[tokio::test]
+async fn all_keeps_request_order() {
+    let replies = fanout::all([reply(30, Ok(1)), reply(10, Ok(2)), reply(20, Ok(3))]).await.unwrap();
+    assert_eq!(replies, vec![1, 2, 3]);
+
+    let err = fanout::all([reply(30, Ok(1)), reply(10, Err("down"))]).await.unwrap_err();
+    assert_eq!(err.to_string(), "down");
+}
+
+#[tokio::test]
+async fn quorum_and_first_ok_take_the_fastest_successes() {
+    let started = tokio::time::Instant::now();
+    let replies = fanout::quorum(
+        [reply(10, Ok(1)), reply(5000, Ok(2)), reply(5, Err("down")), reply(20, Ok(3))],
+        2,
+    )
+    .await
+    .unwrap();
+    assert_eq!(replies, vec![1, 3]);
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    let err = fanout::quorum([reply(10, Ok(1)), reply(5, Err("down")), reply(20, Err("gone"))], 2)
+        .await
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("gone"));
+
+    let first = fanout::first_ok([reply(5, Err("down")), reply(20, Ok(7)), reply(30, Ok(8))]).await.unwrap();
+    assert_eq!(first, 7);
+    assert!(fanout::first_ok([reply(5, Err("down"))]).await.is_err());
+}
@@ -0,0 +1,56 @@
+use dist_sys::flavor::Flavor;
+use serde_json::{Value, json};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+#[test]
+fn flavors_parse_by_name() {
+    assert_eq!("current-thread".parse::<Flavor>().unwrap(), Flavor::CurrentThread);
+    assert_eq!("multi-thread".parse::<Flavor>().unwrap(), Flavor::MultiThread);
+    assert!("threaded".parse::<Flavor>().is_err());
+}
+
+/// Runs `bin` with `args` through `init` and checks it answers.
+fn answers_init(bin: &str, args: &[&str]) {
+    let mut child = Command::new(bin)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let init = json!({"src": "c0", "dest": "n1", "body": {"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1"]}});
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(stdin, "{}", init).unwrap();
+    drop(stdin);
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).unwrap();
+    let reply: Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(reply["body"]["type"], "init_ok", "{} {:?}", bin, args);
+    assert!(child.wait().unwrap().success(), "{} {:?}", bin, args);
+}
+
+/// Each binary starts its node through `Runtime::start` on the flavor it
+/// picked.
+#[test]
+fn binaries_start_on_their_own_flavor() {
+    answers_init(env!("CARGO_BIN_EXE_echo"), &[]);
+    answers_init(env!("CARGO_BIN_EXE_unique-ids"), &[]);
+    answers_init(env!("CARGO_BIN_EXE_broadcast"), &[]);
+    answers_init(env!("CARGO_BIN_EXE_maelstrom-node"), &["--workload", "echo"]);
+}
+
+/// Echo runs on a current-thread runtime unless told otherwise.
+#[test]
+fn the_knob_overrides_the_binary() {
+    answers_init(env!("CARGO_BIN_EXE_echo"), &["--runtime", "multi-thread"]);
+    answers_init(env!("CARGO_BIN_EXE_maelstrom-node"), &["--workload", "broadcast", "--runtime", "current-thread"]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_echo"))
+        .args(["--runtime", "threaded"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown runtime"));
+}
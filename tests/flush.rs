@@ -0,0 +1,87 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::rate::{Rate, RateLimit};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    /// Sends three notes to n2 and one to n3, then flushes n3 and then
+    /// everything, saying how long each flush took to return.
+    Burst,
+    BurstOk { n3_ms: u64, all_ms: u64 },
+    Note,
+}
+
+struct Burster;
+
+impl Node<(), Payload> for Burster {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Burster)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (Payload::Burst, request) = input.take_payload() else {
+            return Ok(());
+        };
+        for dst in ["n2", "n2", "n2", "n3"] {
+            let note = Message {
+                src: ctx.node_id().to_string(),
+                dst: dst.to_string(),
+                body: Body {
+                    id: None,
+                    in_reply_to: None,
+                    payload: Payload::Note,
+                },
+            };
+            note.send(&ctx)?;
+        }
+        let start = ctx.now();
+        ctx.flush_to("n3").await?;
+        let n3_ms = start.elapsed().as_millis() as u64;
+        ctx.flush().await?;
+        let all_ms = start.elapsed().as_millis() as u64;
+        request.reply_with(Payload::BurstOk { n3_ms, all_ms }, &ctx).send(&ctx)
+    }
+}
+
+// paused, so the rate limit's spacing shows exactly in how long flushes wait
+#[tokio::test(start_paused = true)]
+async fn flushes_wait_for_messages_held_back_on_their_way_out() {
+    let limit = RateLimit {
+        per_peer: Some(Rate { per_sec: 10.0, burst: 1 }),
+        ..RateLimit::default()
+    };
+    let runtime = Runtime::new().rate_limit(limit);
+    let (client, node) = MaelstromClient::in_process::<_, Burster, _, (), ()>(runtime, (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2", "n3"]).await.unwrap();
+
+    let start = Instant::now();
+    let reply = client.request(json!({"type": "burst"})).await.unwrap();
+    // n3's one note isn't held back, whatever is queued for n2
+    assert_eq!(reply["n3_ms"], 0);
+    // n2's last note goes out two intervals in
+    assert_eq!(reply["all_ms"], 200);
+    assert_eq!(start.elapsed(), Duration::from_millis(200));
+
+    let mut notes = Vec::new();
+    while let Ok(msg) = client.next_other().await {
+        notes.push(msg["dest"].as_str().unwrap().to_string());
+    }
+    notes.sort();
+    assert_eq!(notes, ["n2", "n2", "n2", "n3"]);
+    node.abort();
+}
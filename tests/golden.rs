@@ -0,0 +1,122 @@
+//! Golden-transcript tests for the bundled node binaries.
+//!
+//! A transcript is a sequence of lines, each prefixed with `>` (a line fed to
+//! the node's stdin) or `<` (a line the node must emit next). Blank lines and
+//! lines starting with `#` are ignored. Outputs are compared as JSON with
+//! `body.msg_id` normalized: the first time an expected msg_id is matched it is
+//! bound to the actual one, and later `in_reply_to` fields on input lines are
+//! rewritten through that binding so service replies reach the right request.
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+const LINE_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn run_transcript(bin: &str, transcript: &str) {
+    let path = format!("{}/tests/transcripts/{}", env!("CARGO_MANIFEST_DIR"), transcript);
+    let script = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("read {}: {}", path, e));
+
+    let mut child = Command::new(bin)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .unwrap_or_else(|e| panic!("spawn {}: {}", bin, e));
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else { break };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut ids: HashMap<u64, u64> = HashMap::new();
+    for (lineno, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (dir, json) = line.split_at(1);
+        let mut msg: Value = serde_json::from_str(json.trim())
+            .unwrap_or_else(|e| panic!("{}:{}: bad json: {}", transcript, lineno + 1, e));
+
+        match dir {
+            ">" => {
+                if let Some(irt) = msg["body"]["in_reply_to"].as_u64()
+                    && let Some(actual) = ids.get(&irt)
+                {
+                    msg["body"]["in_reply_to"] = (*actual).into();
+                }
+                writeln!(stdin, "{}", msg).unwrap();
+                stdin.flush().unwrap();
+            }
+            "<" => {
+                let got = rx.recv_timeout(LINE_TIMEOUT).unwrap_or_else(|_| {
+                    let _ = child.kill();
+                    panic!("{}:{}: timed out waiting for {}", transcript, lineno + 1, msg)
+                });
+                let mut got: Value = serde_json::from_str(&got)
+                    .unwrap_or_else(|e| panic!("{}:{}: node emitted bad json {:?}: {}", transcript, lineno + 1, got, e));
+
+                let expected_id = take_msg_id(&mut msg);
+                let actual_id = take_msg_id(&mut got);
+                if msg != got || expected_id.is_some() != actual_id.is_some() {
+                    let _ = child.kill();
+                    panic!(
+                        "{}:{}: output mismatch\nexpected: {}\n     got: {}",
+                        transcript,
+                        lineno + 1,
+                        msg,
+                        got
+                    );
+                }
+                if let (Some(expected), Some(actual)) = (expected_id, actual_id) {
+                    ids.insert(expected, actual);
+                }
+            }
+            _ => panic!("{}:{}: line must start with '>' or '<'", transcript, lineno + 1),
+        }
+    }
+
+    drop(stdin);
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn take_msg_id(msg: &mut Value) -> Option<u64> {
+    msg["body"].as_object_mut()?.remove("msg_id")?.as_u64()
+}
+
+#[test]
+fn echo() {
+    run_transcript(env!("CARGO_BIN_EXE_echo"), "echo.txt");
+}
+
+#[test]
+fn unique_ids() {
+    run_transcript(env!("CARGO_BIN_EXE_unique-ids"), "unique-ids.txt");
+}
+
+#[test]
+fn broadcast() {
+    run_transcript(env!("CARGO_BIN_EXE_broadcast"), "broadcast.txt");
+}
+
+#[test]
+fn counter() {
+    run_transcript(env!("CARGO_BIN_EXE_counter"), "counter.txt");
+}
+
+#[test]
+fn txn_list_append() {
+    run_transcript(env!("CARGO_BIN_EXE_txn-list-append"), "txn-list-append.txt");
+}
@@ -0,0 +1,63 @@
+use dist_sys::gossip::{GossipStats, PeerSampler, PeerSampling, RangeDigest};
+
+fn peers() -> Vec<String> {
+    ["n2", "n3", "n4"].map(String::from).to_vec()
+}
+
+#[test]
+fn parses_strategies() {
+    assert_eq!("all".parse::<PeerSampling>().unwrap(), PeerSampling::All);
+    assert_eq!("random:2".parse::<PeerSampling>().unwrap(), PeerSampling::Random(2));
+    assert_eq!("round-robin:1".parse::<PeerSampling>().unwrap(), PeerSampling::RoundRobin(1));
+    assert!("random:0".parse::<PeerSampling>().is_err());
+    assert!("random".parse::<PeerSampling>().is_err());
+}
+
+#[test]
+fn samples_peers() {
+    let peers = peers();
+    assert_eq!(PeerSampler::new(PeerSampling::All).pick(&peers), peers);
+
+    let random = PeerSampler::new(PeerSampling::Random(2)).pick(&peers);
+    assert_eq!(random.len(), 2);
+    assert!(random.iter().all(|p| peers.contains(p)) && random[0] != random[1]);
+    assert_eq!(PeerSampler::new(PeerSampling::Random(5)).pick(&peers).len(), 3);
+
+    let rotation = PeerSampler::new(PeerSampling::RoundRobin(2));
+    assert_eq!(rotation.pick(&peers), ["n2", "n3"]);
+    assert_eq!(rotation.pick(&peers), ["n4", "n2"]);
+    assert!(rotation.pick(&[]).is_empty());
+}
+
+#[test]
+fn digest_finds_what_the_sender_is_missing() {
+    let digest = RangeDigest::new([5, 1, 2, 3, 9, 2]);
+    assert_eq!(serde_json::to_string(&digest).unwrap(), "[[1,3],[5,5],[9,9]]");
+    assert!(digest.contains(2) && digest.contains(9));
+    assert!(!digest.contains(0) && !digest.contains(4) && !digest.contains(10));
+    assert_eq!(digest.missing([1, 4, 5, 8]), vec![4, 8]);
+    assert_eq!(RangeDigest::new([]).missing([u64::MAX]), vec![u64::MAX]);
+}
+
+#[test]
+fn stats_track_cost_and_novelty_per_peer() {
+    let stats = GossipStats::default();
+    stats.sent("n2", 3, 100);
+    stats.sent("n2", 1, 40);
+    stats.received("n2", 4, 1);
+    stats.received("n3", 2, 2);
+
+    let n2 = stats.peer("n2");
+    assert_eq!((n2.messages_sent, n2.bytes_sent, n2.entries_sent), (2, 140, 4));
+    assert_eq!((n2.messages_received, n2.entries_received, n2.novel_received), (1, 4, 1));
+    assert_eq!(n2.redundancy, 0.75);
+    assert_eq!(stats.peer("n3").redundancy, 0.0);
+    assert_eq!(stats.all().keys().collect::<Vec<_>>(), ["n2", "n3"]);
+
+    // resends shrink with the peer's redundancy, and not before it has any
+    assert_eq!(stats.resend_percent("n2", 20), 5);
+    assert_eq!(stats.resend_percent("n3", 20), 20);
+    assert_eq!(stats.resend_percent("n4", 20), 20);
+    stats.forget("n2");
+    assert_eq!(stats.peer("n2").messages_sent, 0);
+}
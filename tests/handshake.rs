@@ -0,0 +1,138 @@
+use dist_sys::handshake::{EXIT_CODE, HandshakeError};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Echo { echo: String },
+    EchoOk { echo: String },
+}
+
+struct Echo;
+
+impl Node<(), Payload> for Echo {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Echo)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (Payload::Echo { echo }, request) = input.take_payload() else {
+            return Ok(());
+        };
+        request.reply_with(Payload::EchoOk { echo }, &ctx).send(&ctx)
+    }
+}
+
+/// Runs the node on `input`, returning how it ended and what it wrote.
+async fn run(input: &str) -> (anyhow::Result<()>, Vec<Value>) {
+    run_on(Runtime::new(), input).await
+}
+
+/// Like [`run`], with `runtime`.
+async fn run_on(runtime: Runtime, input: &str) -> (anyhow::Result<()>, Vec<Value>) {
+    let (mut writer, node_input) = tokio::io::duplex(64 * 1024);
+    let (node_output, output) = tokio::io::duplex(64 * 1024);
+    writer.write_all(input.as_bytes()).await.unwrap();
+    drop(writer);
+    let result = runtime
+        .run_with_io::<_, Echo, _, (), ()>((), BufReader::new(node_input), node_output)
+        .await;
+    let mut written = Vec::new();
+    let mut output = BufReader::new(output).lines();
+    while let Some(line) = output.next_line().await.unwrap() {
+        written.push(serde_json::from_str(&line).unwrap());
+    }
+    (result, written)
+}
+
+fn init() -> Value {
+    json!({"src": "c0", "dest": "n1", "body": {"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1"]}})
+}
+
+#[tokio::test]
+async fn blank_lines_and_a_bom_come_before_init() {
+    let echo = json!({"src": "c1", "dest": "n1", "body": {"type": "echo", "msg_id": 2, "echo": "hi"}});
+    let (result, written) = run(&format!("\u{feff}\n  \n\u{feff}{}\n{}\n", init(), echo)).await;
+    result.unwrap();
+    assert_eq!(written.len(), 2, "{:?}", written);
+    assert_eq!(written[0]["body"]["type"], "init_ok");
+    assert_eq!(written[1]["body"]["echo"], "hi");
+}
+
+#[tokio::test]
+async fn blank_lines_after_init_are_skipped_too() {
+    let echo = json!({"src": "c1", "dest": "n1", "body": {"type": "echo", "msg_id": 2, "echo": "hi"}});
+    let (result, written) = run(&format!("{}\n\n  \n\u{feff}\n{}\n\n", init(), echo)).await;
+    result.unwrap();
+    assert_eq!(written.len(), 2, "{:?}", written);
+    assert_eq!(written[1]["body"]["echo"], "hi");
+}
+
+#[tokio::test]
+async fn input_that_isnt_json_is_skipped_in_strict_mode() {
+    let fetch = json!({"src": "c1", "dest": "n1", "body": {"type": "fetch", "msg_id": 2}});
+    let echo = json!({"src": "c1", "dest": "n1", "body": {"type": "echo", "msg_id": 3, "echo": "hi"}});
+    let input = format!("{}\n{{\"src\": \"c1\", \"dest\n{}\nnot json\n{}\n", init(), fetch, echo);
+    let (result, written) = run_on(Runtime::new().strict(), &input).await;
+    result.unwrap();
+    assert_eq!(written.len(), 3, "{:?}", written);
+    // what parses but isn't the node's is still answered
+    assert_eq!(written[1]["body"]["in_reply_to"], 2);
+    assert_eq!(written[1]["body"]["code"], 12);
+    assert_eq!(written[2]["body"]["echo"], "hi");
+}
+
+#[tokio::test]
+async fn a_request_before_init_is_answered_as_malformed() {
+    let echo = json!({"src": "c1", "dest": "n1", "body": {"type": "echo", "msg_id": 2, "echo": "hi"}});
+    let (result, written) = run(&format!("{}\n{}\n", echo, init())).await;
+    let err = result.unwrap_err();
+    let err = err.downcast_ref::<HandshakeError>().unwrap();
+    assert_eq!(err.line(), Some(echo.to_string().as_str()));
+    assert_eq!(written.len(), 1, "{:?}", written);
+    assert_eq!(written[0]["dest"], "c1");
+    assert_eq!(written[0]["body"]["type"], "error");
+    assert_eq!(written[0]["body"]["code"], 12);
+    assert_eq!(written[0]["body"]["in_reply_to"], 2);
+}
+
+#[tokio::test]
+async fn garbage_and_silence_fail_the_handshake() {
+    let (result, written) = run("hello\n").await;
+    let err = result.unwrap_err();
+    assert_eq!(err.downcast_ref::<HandshakeError>().unwrap().line(), Some("hello"));
+    assert!(written.is_empty());
+
+    let (result, _) = run("\n\n").await;
+    let err = result.unwrap_err();
+    assert!(err.downcast_ref::<HandshakeError>().unwrap().line().is_none());
+}
+
+#[test]
+fn a_bad_init_exits_with_its_own_code() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_echo"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(stdin, "not json").unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(EXIT_CODE));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("bad_init"));
+}
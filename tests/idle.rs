@@ -0,0 +1,99 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    /// The milliseconds since init at which `on_idle` was called.
+    Idled,
+    IdledOk { at: Vec<u64> },
+    Busy { ms: u64 },
+    BusyOk,
+}
+
+struct Compactor {
+    start: Instant,
+    idled: Mutex<Vec<u64>>,
+}
+
+impl Node<(), Payload> for Compactor {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Compactor {
+            start: ctx.now(),
+            idled: Mutex::default(),
+        })
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        let reply = match payload {
+            Payload::Idled => Payload::IdledOk {
+                at: self.idled.lock().unwrap().clone(),
+            },
+            Payload::Busy { ms } => {
+                ctx.sleep(Duration::from_millis(ms)).await;
+                Payload::BusyOk
+            }
+            _ => return Ok(()),
+        };
+        request.reply_with(reply, &ctx).send(&ctx)
+    }
+
+    async fn on_idle(&self, ctx: Ctx) -> anyhow::Result<()> {
+        let at = ctx.now().duration_since(self.start).as_millis() as u64;
+        self.idled.lock().unwrap().push(at);
+        Ok(())
+    }
+}
+
+async fn compactor(runtime: Runtime) -> (MaelstromClient, tokio::task::JoinHandle<anyhow::Result<()>>) {
+    let (client, node) = MaelstromClient::in_process::<_, Compactor, _, (), ()>(runtime, (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+    (client, node)
+}
+
+async fn idled(client: &mut MaelstromClient) -> Value {
+    client.request(json!({"type": "idled"})).await.unwrap()["at"].clone()
+}
+
+// paused, so when on_idle runs is exact
+#[tokio::test(start_paused = true)]
+async fn a_quiet_node_idles_once_every_interval() {
+    let (mut client, node) = compactor(Runtime::new().idle(Duration::from_millis(100))).await;
+    tokio::time::sleep(Duration::from_millis(350)).await;
+    assert_eq!(idled(&mut client).await, json!([100, 200, 300]));
+    node.abort();
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_busy_node_idles_once_its_handlers_are_done() {
+    let (mut client, node) = compactor(Runtime::new().idle(Duration::from_millis(100))).await;
+    client.request(json!({"type": "busy", "ms": 250})).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    // overdue when the handler finished, then back on the interval
+    assert_eq!(idled(&mut client).await, json!([250, 350]));
+    node.abort();
+}
+
+#[tokio::test(start_paused = true)]
+async fn nodes_only_idle_when_asked_to() {
+    let (mut client, node) = compactor(Runtime::new()).await;
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    assert_eq!(idled(&mut client).await, json!([]));
+    node.abort();
+}
@@ -0,0 +1,69 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::emulate::Emulation;
+use dist_sys::kv::Kv;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Go,
+    GoOk,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Lease {
+    holder: String,
+    term: u64,
+}
+
+/// Stores values of several types through one handle and reads them back.
+struct Mixed;
+
+impl Node<(), Payload> for Mixed {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Mixed)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let kv = Kv::lin(&ctx);
+        let lease = Lease {
+            holder: "n1".into(),
+            term: 3,
+        };
+        kv.write("lease", lease.clone()).await?;
+        kv.write("offsets", vec![0u64, 4, 9]).await?;
+        kv.write("name", "kafka".to_string()).await?;
+
+        assert_eq!(kv.read::<Lease>("lease").await?, Some(lease.clone()));
+        assert_eq!(kv.read::<Vec<u64>>("offsets").await?, Some(vec![0, 4, 9]));
+        assert_eq!(kv.read::<String>("name").await?.as_deref(), Some("kafka"));
+
+        // the escape hatch reads whatever is there
+        assert_eq!(kv.read::<Value>("lease").await?, Some(json!({"holder": "n1", "term": 3})));
+        assert!(kv.read::<u64>("name").await.is_err());
+        assert!(kv.cas("offsets", json!([0, 4, 9]), json!([0, 4, 9, 12]), false).await?);
+        assert_eq!(kv.read::<Vec<u64>>("offsets").await?, Some(vec![0, 4, 9, 12]));
+
+        input.reply_with(Payload::GoOk, &ctx).send(&ctx)
+    }
+}
+
+#[tokio::test]
+async fn values_of_any_serializable_type() {
+    let runtime = Runtime::new().emulate(Emulation::all());
+    let (mut client, node) = MaelstromClient::in_process::<_, Mixed, _, (), ()>(runtime, (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+    client.expect(json!({"type": "go"}), json!({"type": "go_ok"})).await.unwrap();
+    node.abort();
+}
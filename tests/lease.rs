@@ -0,0 +1,135 @@
+use dist_sys::lease::{Lease, LeaseEvent, LeaseOptions};
+use dist_sys::sim::cluster::Cluster;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Holding,
+    HoldingOk { held: bool, events: Vec<String> },
+    Release,
+    ReleaseOk,
+}
+
+#[derive(Debug)]
+struct Leased(LeaseEvent);
+
+impl Coalescible for Leased {}
+
+/// Contends for the lease "leader" from init on, and keeps a log of what the
+/// lease told it.
+struct Contender {
+    lease: Lease,
+    events: Mutex<Vec<String>>,
+}
+
+impl Node<(), Payload, (), Leased> for Contender {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        tx: tokio::sync::mpsc::UnboundedSender<Event<Payload, (), Leased>>,
+        ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        let options = LeaseOptions {
+            ttl: Duration::from_millis(1000),
+            renew_interval: Duration::from_millis(50),
+        };
+        Ok(Contender {
+            lease: Lease::spawn(ctx, "leader", options, tx, Leased),
+            events: Mutex::default(),
+        })
+    }
+
+    async fn step(&self, input: Event<Payload, (), Leased>, ctx: Ctx) -> anyhow::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            Event::Injected(Leased(event)) => {
+                let event = match event {
+                    LeaseEvent::Acquired { .. } => "acquired",
+                    LeaseEvent::Lost { .. } => "lost",
+                };
+                self.events.lock().unwrap().push(event.to_string());
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        let (payload, request) = input.take_payload();
+        let reply = match payload {
+            Payload::Holding => Payload::HoldingOk {
+                held: self.lease.is_held(),
+                events: self.events.lock().unwrap().clone(),
+            },
+            Payload::Release => {
+                self.lease.release().await?;
+                Payload::ReleaseOk
+            }
+            _ => return Ok(()),
+        };
+        request.reply_with(reply, &ctx).send(&ctx)
+    }
+}
+
+async fn cluster(nodes: usize) -> Cluster {
+    let cluster = Cluster::new::<_, Contender, Payload, (), Leased>(nodes, || (Runtime::new(), ()));
+    cluster.start().await.unwrap();
+    cluster
+}
+
+async fn ask(cluster: &Cluster, node: &str, body: Value) -> Value {
+    cluster.request("c1", node, body, Duration::from_secs(5)).await.unwrap()
+}
+
+/// The nodes that hold the lease right now, among those still up.
+async fn holders(cluster: &Cluster) -> Vec<String> {
+    let mut holders = Vec::new();
+    for node in cluster.node_ids() {
+        if cluster.is_up(node) && ask(cluster, node, json!({"type": "holding"})).await["held"] == true {
+            holders.push(node.clone());
+        }
+    }
+    holders
+}
+
+// paused, so waiting out a TTL takes no time
+#[tokio::test(start_paused = true)]
+async fn exactly_one_node_takes_the_lease() {
+    let cluster = cluster(3).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let holders = holders(&cluster).await;
+    assert_eq!(holders.len(), 1, "{:?}", holders);
+    let holding = ask(&cluster, &holders[0], json!({"type": "holding"})).await;
+    assert_eq!(holding["events"], json!(["acquired"]), "renewals raise no events");
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_released_lease_is_taken_without_waiting_out_the_ttl() {
+    let cluster = cluster(2).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let [first] = <[String; 1]>::try_from(holders(&cluster).await).unwrap();
+
+    ask(&cluster, &first, json!({"type": "release"})).await;
+    // a couple of polls, well under the TTL
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    let holders = holders(&cluster).await;
+    assert_eq!(holders.len(), 1, "{:?}", holders);
+    assert_ne!(holders[0], first);
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_dead_holders_lease_passes_on_once_it_goes_unrenewed_for_a_ttl() {
+    let cluster = cluster(2).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let [first] = <[String; 1]>::try_from(holders(&cluster).await).unwrap();
+
+    cluster.kill(&first);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(holders(&cluster).await.is_empty(), "taken before the TTL ran out");
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let holders = holders(&cluster).await;
+    assert_eq!(holders.len(), 1, "{:?}", holders);
+    assert_ne!(holders[0], first);
+}
@@ -0,0 +1,120 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    /// Takes 100ms, answering with how many were running at its busiest.
+    Slow,
+    SlowOk { busiest: usize },
+    Quick,
+    QuickOk,
+}
+
+#[derive(Default)]
+struct Running {
+    now: usize,
+    busiest: usize,
+}
+
+struct Worker {
+    running: Mutex<Running>,
+}
+
+impl Node<(), Payload> for Worker {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Worker {
+            running: Mutex::default(),
+        })
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        let reply = match payload {
+            Payload::Slow => {
+                {
+                    let mut running = self.running.lock().unwrap();
+                    running.now += 1;
+                    running.busiest = running.busiest.max(running.now);
+                }
+                ctx.sleep(Duration::from_millis(100)).await;
+                let mut running = self.running.lock().unwrap();
+                running.now -= 1;
+                Payload::SlowOk {
+                    busiest: running.busiest,
+                }
+            }
+            Payload::Quick => Payload::QuickOk,
+            _ => return Ok(()),
+        };
+        request.reply_with(reply, &ctx).send(&ctx)
+    }
+}
+
+// paused, so how long the handlers queue up shows exactly
+#[tokio::test(start_paused = true)]
+async fn handlers_of_a_limited_kind_wait_for_a_slot() {
+    let runtime = Runtime::new().limit("slow", 2);
+    let (client, node) = MaelstromClient::in_process::<_, Worker, _, (), ()>(runtime, (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+
+    let start = Instant::now();
+    let mut sent = Vec::new();
+    for _ in 0..5 {
+        sent.push(client.send(json!({"type": "slow"})).await.unwrap());
+    }
+    // other kinds don't wait behind them
+    client.request(json!({"type": "quick"})).await.unwrap();
+    assert_eq!(start.elapsed(), Duration::ZERO);
+
+    for msg_id in sent {
+        let reply = client.reply_to("c1", msg_id).await.unwrap();
+        assert_eq!(reply["body"]["busiest"], 2, "{}", reply);
+    }
+    // two at a time, in three rounds
+    assert_eq!(start.elapsed(), Duration::from_millis(300));
+    node.abort();
+}
+
+#[tokio::test(start_paused = true)]
+async fn kinds_without_a_limit_all_run_at_once() {
+    let (client, node) = MaelstromClient::in_process::<_, Worker, _, (), ()>(Runtime::new(), (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+
+    let start = Instant::now();
+    let mut sent = Vec::new();
+    for _ in 0..5 {
+        sent.push(client.send(json!({"type": "slow"})).await.unwrap());
+    }
+    let mut busiest = 0;
+    for msg_id in sent {
+        let reply = client.reply_to("c1", msg_id).await.unwrap();
+        busiest = reply["body"]["busiest"].as_u64().unwrap();
+    }
+    assert_eq!(busiest, 5);
+    assert_eq!(start.elapsed(), Duration::from_millis(100));
+    node.abort();
+}
+
+#[test]
+#[should_panic(expected = "limit must allow at least one handler")]
+fn a_limit_of_zero_is_refused() {
+    let _ = Runtime::new().limit("slow", 0);
+}
@@ -0,0 +1,286 @@
+use dist_sys::chaos::Chaos;
+use dist_sys::client::MaelstromClient;
+use dist_sys::error::{ErrorCode, MaelstromError};
+use dist_sys::link::ReliableLink;
+use dist_sys::sim::cluster::Cluster;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use dist_sys::time::{Clock, ManualClock, SkewedClock, TokioClock};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Deliver { to: String, value: u64 },
+    DeliverOk,
+    Value { value: u64 },
+    Delivered,
+    DeliveredOk { values: Vec<u64> },
+}
+
+/// How [`Linked`] sets up its link.
+#[derive(Debug, Clone)]
+struct Options {
+    window: usize,
+    interval: Duration,
+    max_attempts: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            window: 32,
+            interval: Duration::from_millis(20),
+            max_attempts: None,
+        }
+    }
+}
+
+/// Delivers values to peers over a reliable link, and says what it was
+/// delivered.
+struct Linked {
+    link: ReliableLink,
+    delivered: Mutex<Vec<u64>>,
+}
+
+impl Node<Options, Payload> for Linked {
+    async fn from_init(
+        options: Options,
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Linked {
+            link: ReliableLink::new(ctx)
+                .window(options.window)
+                .retry(options.interval, options.max_attempts),
+            delivered: Mutex::default(),
+        })
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        let reply = match payload {
+            Payload::Deliver { to, value } => {
+                if let Err(e) = self.link.send_reliable(&to, Payload::Value { value }).await {
+                    return request.reply_error(e.downcast::<MaelstromError>()?, &ctx);
+                }
+                Payload::DeliverOk
+            }
+            Payload::Value { value } => {
+                self.delivered.lock().unwrap().push(value);
+                return Ok(());
+            }
+            Payload::Delivered => Payload::DeliveredOk {
+                values: self.delivered.lock().unwrap().clone(),
+            },
+            _ => return Ok(()),
+        };
+        request.reply_with(reply, &ctx).send(&ctx)
+    }
+}
+
+fn cluster(chaos: Option<Chaos>, options: Options) -> Cluster {
+    Cluster::new::<_, Linked, Payload, (), ()>(2, move || {
+        let runtime = match &chaos {
+            Some(chaos) => Runtime::new().chaos(chaos.clone()),
+            None => Runtime::new(),
+        };
+        (runtime, options.clone())
+    })
+}
+
+/// Asks `node` to deliver each of `values` to `to`. Replies may be lost to
+/// chaos, so they aren't waited on for long.
+async fn deliver(cluster: &Cluster, node: &str, to: &str, values: impl IntoIterator<Item = u64>) {
+    for value in values {
+        let body = json!({"type": "deliver", "to": to, "value": value});
+        cluster.request("c1", node, body, Duration::from_millis(300)).await;
+    }
+}
+
+/// What `node` was delivered, sorted, once it has `count` values or a few
+/// seconds have passed.
+async fn delivered(cluster: &Cluster, node: &str, count: usize) -> Vec<u64> {
+    let mut values = Vec::new();
+    for _ in 0..50 {
+        let reply = cluster.request("c1", node, json!({"type": "delivered"}), Duration::from_millis(100)).await;
+        if let Some(reply) = reply {
+            values = serde_json::from_value::<Vec<u64>>(reply["values"].clone()).unwrap();
+            if values.len() >= count {
+                break;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    values.sort();
+    values
+}
+
+#[tokio::test]
+async fn sends_are_delivered_once_through_drops_and_duplicates() {
+    let chaos = Chaos {
+        drop_prob: 0.3,
+        dup_prob: 0.3,
+        seed: 7,
+        ..Chaos::default()
+    };
+    let cluster = cluster(Some(chaos), Options::default());
+    cluster.start().await.unwrap();
+
+    deliver(&cluster, "n1", "n2", 1..=20).await;
+    assert_eq!(delivered(&cluster, "n2", 20).await, (1..=20).collect::<Vec<_>>());
+    // retransmissions still in flight don't add to it
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(delivered(&cluster, "n2", 20).await, (1..=20).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn a_restarted_sender_is_not_taken_for_a_duplicate() {
+    let cluster = cluster(None, Options::default());
+    cluster.start().await.unwrap();
+
+    deliver(&cluster, "n1", "n2", 1..=3).await;
+    cluster.restart("n1").await.unwrap();
+    // numbered from 1 again
+    deliver(&cluster, "n1", "n2", 4..=6).await;
+    assert_eq!(delivered(&cluster, "n2", 6).await, (1..=6).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn a_send_given_up_on_does_not_hold_back_later_ones() {
+    let options = Options {
+        max_attempts: Some(2),
+        ..Options::default()
+    };
+    let cluster = cluster(None, options);
+    cluster.start().await.unwrap();
+
+    cluster.partition(&[vec!["n1".to_string()], vec!["n2".to_string()]]);
+    let body = json!({"type": "deliver", "to": "n2", "value": 1});
+    let reply = cluster.request("c1", "n1", body, Duration::from_secs(1)).await.unwrap();
+    let err: MaelstromError = serde_json::from_value(reply).unwrap();
+    assert_eq!(err.code, ErrorCode::TEMPORARILY_UNAVAILABLE, "{}", err);
+    cluster.heal();
+
+    deliver(&cluster, "n1", "n2", 2..=4).await;
+    assert_eq!(delivered(&cluster, "n2", 3).await, vec![2, 3, 4]);
+}
+
+/// A client playing `n2` to `n1`, which links to it with `options`.
+async fn held_peer(options: Options) -> (MaelstromClient, tokio::task::JoinHandle<anyhow::Result<()>>) {
+    held_peer_on(Runtime::new(), options).await
+}
+
+async fn held_peer_on(runtime: Runtime, options: Options) -> (MaelstromClient, tokio::task::JoinHandle<anyhow::Result<()>>) {
+    let (client, node) = MaelstromClient::in_process::<_, Linked, _, (), ()>(runtime, options, "n1");
+    let mut client = client.with_timeout(Duration::from_millis(300));
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2"]).await.unwrap();
+    (client, node)
+}
+
+/// Sends `n1` a deliver request for `value` to `n2`, returning its msg_id.
+async fn request_delivery(client: &mut MaelstromClient, value: u64) -> u64 {
+    client.send(json!({"type": "deliver", "to": "n2", "value": value})).await.unwrap()
+}
+
+/// The next envelope `n1` sends, and the value it carries.
+async fn next_envelope(client: &mut MaelstromClient) -> (Value, u64) {
+    let envelope = client.next_other().await.unwrap();
+    assert_eq!(envelope["body"]["type"], "reliable", "{}", envelope);
+    let value = envelope["body"]["payload"]["value"].as_u64().unwrap();
+    (envelope, value)
+}
+
+/// Answers `envelope` as `n2` with `body`.
+async fn answer(client: &mut MaelstromClient, envelope: &Value, mut body: Value) {
+    body["in_reply_to"] = envelope["body"]["msg_id"].clone();
+    client.send_from("n2", body).await.unwrap();
+}
+
+#[tokio::test]
+async fn sends_past_the_window_wait_for_an_ack() {
+    let options = Options {
+        window: 2,
+        interval: Duration::from_secs(5),
+        ..Options::default()
+    };
+    let (mut client, node) = held_peer(options).await;
+
+    let first = request_delivery(&mut client, 1).await;
+    request_delivery(&mut client, 2).await;
+    request_delivery(&mut client, 3).await;
+    let (envelope, value) = next_envelope(&mut client).await;
+    assert_eq!(value, 1);
+    assert_eq!(next_envelope(&mut client).await.1, 2);
+    assert!(client.next_other().await.is_err(), "sent past the window");
+
+    let seq = envelope["body"]["seq"].clone();
+    answer(&mut client, &envelope, json!({"type": "reliable_ok", "seq": seq})).await;
+    assert_eq!(client.reply_to("c1", first).await.unwrap()["body"]["type"], "deliver_ok");
+    assert_eq!(next_envelope(&mut client).await.1, 3);
+    node.abort();
+}
+
+#[tokio::test]
+async fn sends_that_fail_free_their_place_in_the_window() {
+    let options = Options {
+        window: 1,
+        interval: Duration::from_millis(100),
+        max_attempts: Some(1),
+    };
+    let (mut client, node) = held_peer(options).await;
+
+    // never acked
+    let timed_out = request_delivery(&mut client, 1).await;
+    assert_eq!(next_envelope(&mut client).await.1, 1);
+    let reply = client.reply_to("c1", timed_out).await.unwrap();
+    assert_eq!(reply["body"]["code"], ErrorCode::TEMPORARILY_UNAVAILABLE.0, "{}", reply);
+
+    // rejected
+    let rejected = request_delivery(&mut client, 2).await;
+    let (envelope, value) = next_envelope(&mut client).await;
+    assert_eq!(value, 2);
+    answer(&mut client, &envelope, json!({"type": "error", "code": ErrorCode::CRASH.0, "text": "rejected"})).await;
+    assert_eq!(client.reply_to("c1", rejected).await.unwrap()["body"]["code"], ErrorCode::CRASH.0);
+
+    request_delivery(&mut client, 3).await;
+    assert_eq!(next_envelope(&mut client).await.1, 3);
+    node.abort();
+}
+
+#[tokio::test]
+async fn incarnations_come_from_the_nodes_clock() {
+    let day_ms = 24 * 60 * 60 * 1000;
+    let clock = SkewedClock::new(TokioClock);
+    clock.set_offset_ms(day_ms);
+    let started = clock.wall_ms();
+    let (mut client, node) = held_peer_on(Runtime::new().clock(clock.clone()), Options::default()).await;
+
+    request_delivery(&mut client, 1).await;
+    let (envelope, _) = next_envelope(&mut client).await;
+    let incarnation = envelope["body"]["incarnation"].as_u64().unwrap();
+    // a day ahead of the system clock, in milliseconds
+    assert!((started..clock.wall_ms() + 1000).contains(&incarnation), "{} from {}", incarnation, started);
+    node.abort();
+}
+
+#[tokio::test]
+async fn a_run_started_at_the_same_reading_is_still_a_later_incarnation() {
+    let clock = ManualClock::new();
+    let mut incarnations = Vec::new();
+    for _ in 0..2 {
+        let (mut client, node) = held_peer_on(Runtime::new().clock(clock.clone()), Options::default()).await;
+        request_delivery(&mut client, 1).await;
+        let (envelope, _) = next_envelope(&mut client).await;
+        incarnations.push(envelope["body"]["incarnation"].as_u64().unwrap());
+        node.abort();
+    }
+    assert!(incarnations[1] > incarnations[0], "{:?}", incarnations);
+}
@@ -0,0 +1,88 @@
+use std::process::Command;
+
+/// Runs loadgen with `args` against `node`, returning whether it
+/// succeeded and what it printed to stdout and stderr.
+fn loadgen(args: &[&str], node: &str) -> (bool, String, String) {
+    let out = Command::new(env!("CARGO_BIN_EXE_loadgen"))
+        .args(args)
+        .arg("--")
+        .arg(node)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    (out.status.success(), stdout, stderr)
+}
+
+/// The ok, failed, timed out and skipped counts from the summary line.
+fn counts(stdout: &str) -> [usize; 4] {
+    let summary = stdout.lines().next().unwrap();
+    let (_, rest) = summary.split_once(": ").unwrap();
+    let numbers: Vec<usize> = rest
+        .split(", ")
+        .take(4)
+        .map(|part| part.split(' ').next().unwrap().parse().unwrap())
+        .collect();
+    numbers.try_into().unwrap()
+}
+
+#[test]
+fn operations_start_at_the_rate_given() {
+    let (ok, stdout, stderr) = loadgen(&["--rate", "100", "--duration-ms", "300"], env!("CARGO_BIN_EXE_echo"));
+    assert!(ok, "{}", stderr);
+    assert!(stdout.starts_with("echo: "), "{}", stdout);
+    let [done, failed, timed_out, skipped] = counts(&stdout);
+    assert!((20..=31).contains(&done), "{}", stdout);
+    assert_eq!([failed, timed_out, skipped], [0, 0, 0], "{}", stdout);
+    assert!(stdout.contains("echo       n="), "{}", stdout);
+}
+
+#[test]
+fn operations_past_the_concurrency_are_skipped() {
+    // the counter answers no echo, so the first one never finishes
+    let args = ["--rate", "100", "--duration-ms", "300", "--concurrency", "1", "--timeout-ms", "100"];
+    let (ok, stdout, stderr) = loadgen(&args, env!("CARGO_BIN_EXE_counter"));
+    assert!(ok, "{}", stderr);
+    let [done, failed, timed_out, skipped] = counts(&stdout);
+    assert_eq!([done, failed, timed_out], [0, 0, 1], "{}", stdout);
+    assert!(skipped >= 20, "{}", stdout);
+}
+
+#[test]
+fn histories_are_written_as_edn() {
+    let path = std::env::temp_dir().join(format!("loadgen-history-{}.edn", std::process::id()));
+    let args = [
+        "--workload",
+        "txn-list-append",
+        "--rate",
+        "50",
+        "--duration-ms",
+        "200",
+        "--keys",
+        "2",
+        "--history",
+        path.to_str().unwrap(),
+    ];
+    let (ok, stdout, stderr) = loadgen(&args, env!("CARGO_BIN_EXE_txn-list-append"));
+    assert!(ok, "{}", stderr);
+    let [done, ..] = counts(&stdout);
+    let history = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let lines: Vec<&str> = history.lines().collect();
+    assert_eq!(lines.len(), 2 * done, "{}", history);
+    assert!(lines[0].contains(":type :invoke, :process 0"), "{}", lines[0]);
+    assert_eq!(lines.iter().filter(|line| line.contains(":type :ok")).count(), done);
+    assert!(lines.iter().all(|line| line.contains(":f :txn")), "{}", history);
+    assert!(lines.iter().any(|line| line.contains("[[:append ")), "{}", history);
+}
+
+#[test]
+fn unknown_workloads_are_refused() {
+    let (ok, _, stderr) = loadgen(&["--workload", "nope"], env!("CARGO_BIN_EXE_echo"));
+    assert!(!ok);
+    assert!(stderr.contains("unknown workload \"nope\""), "{}", stderr);
+    let (ok, _, stderr) = loadgen(&["--mix", "read:1"], env!("CARGO_BIN_EXE_echo"));
+    assert!(!ok);
+    assert!(stderr.contains("echo has no \"read\" operation"), "{}", stderr);
+}
@@ -0,0 +1,160 @@
+use dist_sys::error::{ErrorCode, MaelstromError};
+use dist_sys::lease::LeaseOptions;
+use dist_sys::lock::{DistLock, FencingToken, LockGuard};
+use dist_sys::sim::cluster::Cluster;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Acquire,
+    AcquireOk { token: FencingToken },
+    Release,
+    ReleaseOk,
+    /// Stops renewing the lock without releasing it, as a crashed or
+    /// stalled holder would.
+    Abandon,
+    AbandonOk,
+    Held,
+    HeldOk { held: bool },
+    /// Stores on `to`, fenced with the last token acquired.
+    Write { to: String },
+    WriteOk,
+    Store,
+    StoreOk,
+}
+
+/// Takes the lock "l" when asked, and keeps the last token it was given
+/// after letting go.
+struct Locker {
+    lock: DistLock,
+    guard: Mutex<Option<LockGuard>>,
+    token: Mutex<Option<FencingToken>>,
+}
+
+impl Node<(), Payload> for Locker {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        let options = LeaseOptions {
+            ttl: Duration::from_millis(1000),
+            renew_interval: Duration::from_millis(50),
+        };
+        Ok(Locker {
+            lock: DistLock::new(ctx, options),
+            guard: Mutex::default(),
+            token: Mutex::default(),
+        })
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        let reply = match payload {
+            Payload::Acquire => {
+                let guard = self.lock.acquire("l").await?;
+                let token = guard.token();
+                *self.token.lock().unwrap() = Some(token);
+                *self.guard.lock().unwrap() = Some(guard);
+                Payload::AcquireOk { token }
+            }
+            Payload::Release => {
+                let guard = self.guard.lock().unwrap().take();
+                if let Some(guard) = guard {
+                    guard.release().await?;
+                }
+                Payload::ReleaseOk
+            }
+            Payload::Abandon => {
+                self.guard.lock().unwrap().take();
+                Payload::AbandonOk
+            }
+            Payload::Held => Payload::HeldOk {
+                held: self.guard.lock().unwrap().as_ref().is_some_and(LockGuard::is_held),
+            },
+            Payload::Write { to } => {
+                let token = self.token.lock().unwrap().expect("acquired before writing");
+                if let Err(e) = ctx.fenced(token).rpc::<_, Payload>(&to, Payload::Store).await {
+                    return request.reply_error(e.downcast::<MaelstromError>()?, &ctx);
+                }
+                Payload::WriteOk
+            }
+            Payload::Store => Payload::StoreOk,
+            _ => return Ok(()),
+        };
+        request.reply_with(reply, &ctx).send(&ctx)
+    }
+}
+
+async fn cluster(nodes: usize) -> Cluster {
+    let cluster = Cluster::new::<_, Locker, Payload, (), ()>(nodes, || (Runtime::new(), ()));
+    cluster.start().await.unwrap();
+    cluster
+}
+
+async fn ask(cluster: &Cluster, node: &str, body: Value) -> Value {
+    cluster.request("c1", node, body, Duration::from_secs(5)).await.unwrap()
+}
+
+async fn acquire(cluster: &Cluster, node: &str) -> u64 {
+    let reply = ask(cluster, node, json!({"type": "acquire"})).await;
+    reply["token"].as_u64().unwrap()
+}
+
+// paused, so waiting out a TTL takes no time
+#[tokio::test(start_paused = true)]
+async fn a_released_lock_passes_to_a_waiter_with_a_newer_token() {
+    let cluster = cluster(2).await;
+    assert_eq!(acquire(&cluster, "n1").await, 1);
+
+    let start = Instant::now();
+    let release = async {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        ask(&cluster, "n1", json!({"type": "release"})).await;
+        start.elapsed()
+    };
+    let waiter = async {
+        let token = acquire(&cluster, "n2").await;
+        (token, start.elapsed())
+    };
+    let (released, (token, acquired)) = tokio::join!(release, waiter);
+    assert_eq!(token, 2);
+    assert!(acquired >= released, "acquired after {:?}, released after {:?}", acquired, released);
+    // without waiting out the TTL
+    assert!(acquired < Duration::from_millis(1000), "acquired after {:?}", acquired);
+
+    let reply = ask(&cluster, "n1", json!({"type": "held"})).await;
+    assert_eq!(reply["held"], false);
+    let reply = ask(&cluster, "n2", json!({"type": "held"})).await;
+    assert_eq!(reply["held"], true);
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_holder_that_stops_renewing_is_fenced_off() {
+    let cluster = cluster(3).await;
+    assert_eq!(acquire(&cluster, "n1").await, 1);
+    let reply = ask(&cluster, "n1", json!({"type": "write", "to": "n3"})).await;
+    assert_eq!(reply["type"], "write_ok", "{}", reply);
+    ask(&cluster, "n1", json!({"type": "abandon"})).await;
+
+    let start = Instant::now();
+    assert_eq!(acquire(&cluster, "n2").await, 2);
+    assert!(start.elapsed() >= Duration::from_millis(1000), "taken over after {:?}", start.elapsed());
+    let reply = ask(&cluster, "n2", json!({"type": "write", "to": "n3"})).await;
+    assert_eq!(reply["type"], "write_ok", "{}", reply);
+
+    // n1 still thinks token 1 is good
+    let reply = ask(&cluster, "n1", json!({"type": "write", "to": "n3"})).await;
+    let err: MaelstromError = serde_json::from_value(reply).unwrap();
+    assert_eq!(err.code, ErrorCode::PRECONDITION_FAILED, "{}", err);
+}
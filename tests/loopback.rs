@@ -0,0 +1,79 @@
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Quadruple { x: i64 },
+    QuadrupleOk { x: i64 },
+    Double { x: i64 },
+    DoubleOk { x: i64 },
+}
+
+/// Quadruples by asking itself to double twice.
+struct Doubling;
+
+impl Node<(), Payload> for Doubling {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Doubling)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let mut reply = input.into_reply(None);
+        reply.body.payload = match reply.body.payload {
+            Payload::Quadruple { mut x } => {
+                for _ in 0..2 {
+                    let Payload::DoubleOk { x: doubled } = ctx.rpc(ctx.node_id(), Payload::Double { x }).await? else {
+                        anyhow::bail!("double answered with something else");
+                    };
+                    x = doubled;
+                }
+                Payload::QuadrupleOk { x }
+            }
+            Payload::Double { x } => Payload::DoubleOk { x: 2 * x },
+            _ => return Ok(()),
+        };
+        reply.send(&ctx)
+    }
+}
+
+#[tokio::test]
+async fn messages_to_itself_never_leave_the_node() {
+    let (mut input, node_input) = tokio::io::duplex(4096);
+    let (node_output, output) = tokio::io::duplex(4096);
+    for msg in [
+        json!({"src": "c0", "dest": "n1", "body": {"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1"]}}),
+        json!({"src": "c1", "dest": "n1", "body": {"type": "quadruple", "msg_id": 2, "x": 3}}),
+    ] {
+        input.write_all(format!("{}\n", msg).as_bytes()).await.unwrap();
+    }
+    let node = tokio::spawn(
+        Runtime::new()
+            .loopback()
+            .run_with_io::<_, Doubling, _, (), ()>((), BufReader::new(node_input), node_output),
+    );
+
+    let mut lines = BufReader::new(output).lines();
+    let mut written = Vec::new();
+    while written.len() < 2 {
+        let line = tokio::time::timeout(Duration::from_secs(5), lines.next_line()).await.unwrap().unwrap().unwrap();
+        written.push(serde_json::from_str::<Value>(&line).unwrap());
+    }
+    drop(input);
+    node.await.unwrap().unwrap();
+    assert!(lines.next_line().await.unwrap().is_none());
+
+    assert_eq!(written[0]["body"]["type"], "init_ok");
+    assert_eq!((written[1]["dest"].clone(), written[1]["body"]["x"].clone()), (json!("c1"), json!(12)));
+}
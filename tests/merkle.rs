@@ -0,0 +1,45 @@
+use dist_sys::collections::MerkleTree;
+
+/// Walks two trees from the root the way two nodes would over the network,
+/// returning the leaves that differ.
+fn differing_leaves(ours: &MerkleTree, theirs: &MerkleTree) -> Vec<usize> {
+    let mut frontier = ours.differing(0, &[(0, theirs.root())]);
+    for level in 0..ours.depth() {
+        let their_children: Vec<_> = frontier.iter().flat_map(|&index| theirs.children(level, index)).collect();
+        frontier = ours.differing(level + 1, &their_children);
+    }
+    frontier
+}
+
+#[test]
+fn identical_sets_have_identical_roots_regardless_of_insert_order() {
+    let mut a = MerkleTree::new(8);
+    let mut b = MerkleTree::new(8);
+    for i in 0..100 {
+        a.insert(&i);
+        b.insert(&(99 - i));
+    }
+    assert_eq!(a.root(), b.root());
+    assert!(differing_leaves(&a, &b).is_empty());
+}
+
+#[test]
+fn reconciliation_narrows_down_to_the_buckets_of_missing_items() {
+    let mut a = MerkleTree::new(8);
+    let mut b = MerkleTree::new(8);
+    for i in 0..1000u64 {
+        a.insert(&i);
+        if i != 17 && i != 600 {
+            b.insert(&i);
+        }
+    }
+
+    let mut expected = vec![a.leaf_of(&17u64), a.leaf_of(&600u64)];
+    expected.sort_unstable();
+    expected.dedup();
+    assert_eq!(differing_leaves(&a, &b), expected);
+
+    b.insert(&17u64);
+    b.insert(&600u64);
+    assert_eq!(a, b);
+}
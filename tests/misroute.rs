@@ -0,0 +1,78 @@
+use dist_sys::misroute::Misrouted;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Echo { echo: String },
+    EchoOk { echo: String },
+}
+
+struct Echo;
+
+impl Node<(), Payload> for Echo {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Echo)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (Payload::Echo { echo }, request) = input.take_payload() else {
+            return Ok(());
+        };
+        request.reply_with(Payload::EchoOk { echo }, &ctx).send(&ctx)
+    }
+}
+
+/// Everything `n1` writes when sent an echo meant for it and one meant for n2.
+async fn run(misrouted: Misrouted) -> Vec<Value> {
+    let (mut input, node_input) = tokio::io::duplex(64 * 1024);
+    let (node_output, output) = tokio::io::duplex(64 * 1024);
+    let runtime = Runtime::new().misrouted(misrouted);
+    let node = tokio::spawn(runtime.run_with_io::<_, Echo, _, (), ()>((), BufReader::new(node_input), node_output));
+    let lines = [
+        json!({"src": "c1", "dest": "n1", "body": {"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]}}),
+        json!({"src": "c1", "dest": "n2", "body": {"type": "echo", "msg_id": 2, "echo": "theirs"}}),
+        json!({"src": "c1", "dest": "n1", "body": {"type": "echo", "msg_id": 3, "echo": "ours"}}),
+    ];
+    for line in lines {
+        input.write_all(format!("{}\n", line).as_bytes()).await.unwrap();
+    }
+    drop(input);
+    node.await.unwrap().unwrap();
+
+    let mut written = Vec::new();
+    let mut output = BufReader::new(output).lines();
+    while let Some(line) = output.next_line().await.unwrap() {
+        written.push(serde_json::from_str(&line).unwrap());
+    }
+    written
+}
+
+#[tokio::test]
+async fn messages_for_other_nodes_are_dropped() {
+    let written = run(Misrouted::Drop).await;
+    assert_eq!(written.len(), 2, "{:?}", written);
+    assert_eq!(written[1]["body"]["echo"], "ours");
+}
+
+#[tokio::test]
+async fn messages_for_other_nodes_can_be_forwarded() {
+    let written = run(Misrouted::Forward).await;
+    assert_eq!(written.len(), 3, "{:?}", written);
+    assert_eq!(
+        written[1],
+        json!({"src": "c1", "dest": "n2", "body": {"type": "echo", "msg_id": 2, "echo": "theirs"}})
+    );
+    assert_eq!(written[2]["body"]["echo"], "ours");
+}
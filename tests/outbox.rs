@@ -0,0 +1,122 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::error::ErrorCode;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    /// Stages notes 1 and 2 to n2 around sending note 3 right away, then
+    /// fails if asked to.
+    Commit { fail: bool },
+    CommitOk,
+    /// Stages a note from a task spawned by the handler, after it returned.
+    Late,
+    LateOk,
+    Note { index: u64 },
+    Staged { error: Option<String> },
+}
+
+struct Committer;
+
+fn note(ctx: &Ctx, index: u64) -> Message<Payload> {
+    Message {
+        src: ctx.node_id().to_string(),
+        dst: "n2".to_string(),
+        body: Body {
+            id: None,
+            in_reply_to: None,
+            payload: Payload::Note { index },
+        },
+    }
+}
+
+impl Node<(), Payload> for Committer {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Committer)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        match payload {
+            Payload::Commit { fail } => {
+                note(&ctx, 1).stage(&ctx)?;
+                note(&ctx, 3).send(&ctx)?;
+                note(&ctx, 2).stage(&ctx)?;
+                request.reply_with(Payload::CommitOk, &ctx).stage(&ctx)?;
+                anyhow::ensure!(!fail, "failed after staging");
+                Ok(())
+            }
+            Payload::Late => {
+                let late = ctx.clone();
+                tokio::spawn(async move {
+                    late.sleep(Duration::from_millis(10)).await;
+                    let error = note(&late, 4).stage(&late).err().map(|e| e.to_string());
+                    let mut staged = note(&late, 0);
+                    staged.body.payload = Payload::Staged { error };
+                    staged.send(&late).unwrap();
+                });
+                request.reply_with(Payload::LateOk, &ctx).send(&ctx)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+async fn committer() -> (MaelstromClient, tokio::task::JoinHandle<anyhow::Result<()>>) {
+    let (client, node) = MaelstromClient::in_process::<_, Committer, _, (), ()>(Runtime::new(), (), "n1");
+    let mut client = client.with_timeout(Duration::from_millis(200));
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2"]).await.unwrap();
+    (client, node)
+}
+
+#[tokio::test]
+async fn staged_messages_go_out_in_order_once_the_handler_returns() {
+    let (mut client, node) = committer().await;
+    let reply = client.request(json!({"type": "commit", "fail": false})).await.unwrap();
+    assert_eq!(reply["type"], "commit_ok");
+    let mut indices = Vec::new();
+    while let Ok(msg) = client.next_other().await {
+        indices.push(msg["body"]["index"].as_u64().unwrap());
+    }
+    // the one sent directly didn't wait for the handler
+    assert_eq!(indices, [3, 1, 2]);
+    node.abort();
+}
+
+#[tokio::test]
+async fn a_failing_handler_sends_nothing_it_staged() {
+    let (mut client, node) = committer().await;
+    let msg_id = client.send(json!({"type": "commit", "fail": true})).await.unwrap();
+    let reply = client.reply_to("c1", msg_id).await.unwrap();
+    assert_eq!(reply["body"]["code"], ErrorCode::CRASH.0, "{}", reply);
+    let mut indices = Vec::new();
+    while let Ok(msg) = client.next_other().await {
+        indices.push(msg["body"]["index"].as_u64().unwrap());
+    }
+    assert_eq!(indices, [3]);
+    assert!(node.await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn staging_after_the_handler_returned_is_an_error() {
+    let (mut client, node) = committer().await;
+    assert_eq!(client.request(json!({"type": "late"})).await.unwrap()["type"], "late_ok");
+    let staged = client.next_other().await.unwrap();
+    assert_eq!(staged["body"]["type"], "staged");
+    let error = staged["body"]["error"].as_str().expect("staging failed");
+    assert!(error.contains("outbox already closed"), "{}", error);
+    assert!(client.next_other().await.is_err(), "nothing else was sent");
+    node.abort();
+}
@@ -0,0 +1,86 @@
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Set { value: i64 },
+    SetOk,
+    Read,
+    ReadOk { value: Option<i64> },
+}
+
+/// Ready once it has been given a value.
+struct Register {
+    value: Mutex<Option<i64>>,
+}
+
+impl Node<(), Payload> for Register {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Register { value: Mutex::new(None) })
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let mut reply = input.into_reply(None);
+        reply.body.payload = match reply.body.payload {
+            Payload::Set { value } => {
+                *self.value.lock().unwrap() = Some(value);
+                ctx.ready();
+                Payload::SetOk
+            }
+            Payload::Read => Payload::ReadOk {
+                value: *self.value.lock().unwrap(),
+            },
+            _ => return Ok(()),
+        };
+        reply.send(&ctx)
+    }
+}
+
+#[tokio::test]
+async fn parked_messages_are_replayed_in_order_once_ready() {
+    let (mut input, node_input) = tokio::io::duplex(4096);
+    let (node_output, output) = tokio::io::duplex(4096);
+    let init = json!({"src": "c0", "dest": "n1", "body": {"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1"]}});
+    input.write_all(format!("{}\n", init).as_bytes()).await.unwrap();
+    let node = tokio::spawn(
+        Runtime::new()
+            .park_until_ready(|msg| msg["body"]["type"] == "read")
+            .run_with_io::<_, Register, _, (), ()>((), BufReader::new(node_input), node_output),
+    );
+
+    for body in [
+        json!({"type": "read", "msg_id": 2}),
+        json!({"type": "read", "msg_id": 3}),
+        json!({"type": "set", "msg_id": 4, "value": 5}),
+    ] {
+        let msg = json!({"src": "c1", "dest": "n1", "body": body});
+        input.write_all(format!("{}\n", msg).as_bytes()).await.unwrap();
+    }
+
+    let mut lines = BufReader::new(output).lines();
+    let mut replies = Vec::new();
+    while replies.len() < 3 {
+        let line = tokio::time::timeout(Duration::from_secs(5), lines.next_line()).await.unwrap().unwrap().unwrap();
+        let msg: Value = serde_json::from_str(&line).unwrap();
+        if msg["body"]["type"] != "init_ok" {
+            replies.push((msg["body"]["in_reply_to"].clone(), msg["body"]["value"].clone()));
+        }
+    }
+    drop(input);
+    node.await.unwrap().unwrap();
+
+    assert_eq!(replies, [(json!(4), Value::Null), (json!(2), json!(5)), (json!(3), json!(5))]);
+}
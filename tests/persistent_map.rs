@@ -0,0 +1,38 @@
+use dist_sys::collections::PersistentMap;
+
+#[test]
+fn updates_leave_earlier_versions_untouched() {
+    let empty = PersistentMap::new();
+    let mut versions = vec![empty];
+    for i in 0..200u64 {
+        let next = versions.last().unwrap().insert(i, i * 10);
+        versions.push(next);
+    }
+    let full = versions.last().unwrap().clone();
+    let trimmed = (0..200u64).step_by(2).fold(full.clone(), |map, i| map.remove(&i));
+
+    for (n, version) in versions.iter().enumerate() {
+        assert_eq!(version.len(), n);
+        assert_eq!(version.get(&(n as u64)), None);
+        if n > 0 {
+            assert_eq!(version.get(&(n as u64 - 1)), Some(&((n as u64 - 1) * 10)));
+        }
+    }
+    assert_eq!(full.len(), 200);
+    assert_eq!(trimmed.len(), 100);
+    assert!(trimmed.iter().all(|(k, v)| k % 2 == 1 && *v == k * 10));
+    assert_eq!(full.remove(&1000).len(), 200);
+}
+
+#[test]
+fn equal_contents_iterate_the_same_regardless_of_history() {
+    let forward = (0..100u64).fold(PersistentMap::new(), |map, i| map.insert(i, i));
+    let backward = (0..150u64).rev().fold(PersistentMap::new(), |map, i| map.insert(i, 0));
+    let backward = (0..100u64).fold(backward, |map, i| map.insert(i, i));
+    let backward = (100..150u64).fold(backward, |map, i| map.remove(&i));
+    let collected: PersistentMap<u64, u64> = (0..100u64).map(|i| (i, i)).collect();
+
+    let entries = |map: &PersistentMap<u64, u64>| map.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+    assert_eq!(entries(&forward), entries(&backward));
+    assert_eq!(entries(&forward), entries(&collected));
+}
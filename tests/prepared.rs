@@ -0,0 +1,85 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::prepared::PreparedMessage;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Share,
+    ShareOk,
+    Read,
+    ReadOk { messages: Vec<u64> },
+}
+
+/// Keeps one prepared `read_ok`, answering every read and sharing it with
+/// its peers from the same bytes.
+struct Snapshot {
+    read_ok: PreparedMessage,
+}
+
+impl Node<(), Payload> for Snapshot {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        let read_ok = PreparedMessage::new(&Payload::ReadOk {
+            messages: (0..1000).collect(),
+        })?;
+        Ok(Snapshot { read_ok })
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        match input.body.payload {
+            Payload::Share => {
+                for peer in ["n2", "n3"] {
+                    self.read_ok.send_to(peer, &ctx)?;
+                }
+                input.reply_with(Payload::ShareOk, &ctx).send(&ctx)
+            }
+            Payload::Read => self.read_ok.reply_to(&input, &ctx),
+            Payload::ShareOk | Payload::ReadOk { .. } => Ok(()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn prepared_payload_answers_and_fans_out() {
+    let (mut client, node) = MaelstromClient::in_process::<_, Snapshot, _, (), ()>(Runtime::new(), (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2", "n3"]).await.unwrap();
+
+    let messages: Vec<u64> = (0..1000).collect();
+    for _ in 0..2 {
+        let reply = client.request(json!({"type": "read"})).await.unwrap();
+        assert_eq!(reply["type"], "read_ok");
+        assert_eq!(reply["messages"], json!(messages));
+    }
+
+    client.expect(json!({"type": "share"}), json!({"type": "share_ok"})).await.unwrap();
+    let first = client.next_other().await.unwrap();
+    let second = client.next_other().await.unwrap();
+    assert_eq!((first["dest"].as_str(), second["dest"].as_str()), (Some("n2"), Some("n3")));
+    for shared in [&first, &second] {
+        assert_eq!(shared["src"], "n1");
+        assert_eq!(shared["body"]["type"], "read_ok");
+        assert_eq!(shared["body"]["messages"], json!(messages));
+        assert!(shared["body"]["in_reply_to"].is_null());
+    }
+    assert_ne!(first["body"]["msg_id"], second["body"]["msg_id"]);
+    node.abort();
+}
+
+#[test]
+fn only_objects_can_be_prepared() {
+    assert!(PreparedMessage::new(&json!([1, 2, 3])).is_err());
+    let empty = PreparedMessage::new(&json!({})).unwrap();
+    assert!(empty.is_empty());
+    assert!(!PreparedMessage::new(&Payload::Read).unwrap().is_empty());
+}
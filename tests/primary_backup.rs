@@ -0,0 +1,213 @@
+use dist_sys::emulate::Emulation;
+use dist_sys::error::MaelstromError;
+use dist_sys::replication::StateMachine;
+use dist_sys::replication::primary_backup::{
+    FetchRequest, FetchResponse, Options, PrimaryBackup, ReplicateRequest, ReplicateResponse,
+};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines};
+
+struct Sum(i64);
+
+impl StateMachine for Sum {
+    type Command = i64;
+    type Output = i64;
+
+    fn apply(&mut self, delta: &i64) -> i64 {
+        self.0 += delta;
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Add { delta: i64 },
+    AddOk { total: i64 },
+    Read,
+    ReadOk { total: i64 },
+    Replicate(ReplicateRequest<i64>),
+    ReplicateOk(ReplicateResponse),
+    Fetch(FetchRequest),
+    FetchOk(FetchResponse<i64>),
+}
+
+struct Summing {
+    group: Arc<PrimaryBackup<Sum>>,
+}
+
+impl Node<(), Payload> for Summing {
+    async fn from_init(
+        _state: (),
+        init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        // nodes named l* follow the group as learners
+        let (learners, replicas): (Vec<_>, Vec<_>) = init.node_ids.into_iter().partition(|id| id.starts_with('l'));
+        let options = Options {
+            timeout: Duration::from_millis(100),
+            learners,
+            ..Options::default()
+        };
+        let group = PrimaryBackup::start(ctx, "sum", &replicas, Sum(0), options);
+        Ok(Summing { group })
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let mut reply = input.into_reply(None);
+        reply.body.payload = match reply.body.payload {
+            Payload::Add { delta } => match self.group.submit(delta).await {
+                Ok(total) => Payload::AddOk { total },
+                Err(e) => {
+                    let err = e.downcast::<MaelstromError>()?;
+                    let Message { src, dst, body } = reply;
+                    let body = Body {
+                        id: body.id,
+                        in_reply_to: body.in_reply_to,
+                        payload: SystemPayload::Error(err),
+                    };
+                    return Message { src, dst, body }.send(&ctx);
+                }
+            },
+            Payload::Read => Payload::ReadOk {
+                total: self.group.state(|sum| sum.0).await,
+            },
+            Payload::Replicate(request) => Payload::ReplicateOk(self.group.replicate(request).await),
+            Payload::Fetch(request) => Payload::FetchOk(self.group.fetch(request).await),
+            _ => return Ok(()),
+        };
+        reply.send(&ctx)
+    }
+}
+
+struct Cluster {
+    input: DuplexStream,
+    output: Lines<BufReader<DuplexStream>>,
+    next_msg_id: u64,
+}
+
+impl Cluster {
+    async fn start(node_ids: &[&str]) -> Self {
+        let (input, node_input) = tokio::io::duplex(64 * 1024);
+        let (node_output, output) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(
+            Runtime::new()
+                .emulate(Emulation::all())
+                .run_with_io::<_, Summing, _, (), ()>((), BufReader::new(node_input), node_output),
+        );
+        let mut cluster = Self {
+            input,
+            output: BufReader::new(output).lines(),
+            next_msg_id: 1,
+        };
+        let init = json!({"type": "init", "node_id": "n1", "node_ids": node_ids});
+        cluster.send("c0", init).await;
+        cluster
+    }
+
+    async fn send(&mut self, src: &str, mut body: Value) -> u64 {
+        let msg_id = self.next_msg_id;
+        self.next_msg_id += 1;
+        body["msg_id"] = msg_id.into();
+        let msg = json!({"src": src, "dest": "n1", "body": body});
+        self.input.write_all(format!("{}\n", msg).as_bytes()).await.unwrap();
+        msg_id
+    }
+
+    async fn next(&mut self) -> Value {
+        let line = tokio::time::timeout(Duration::from_secs(5), self.output.next_line())
+            .await
+            .expect("no message")
+            .unwrap()
+            .unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    async fn request(&mut self, src: &str, body: Value) -> Value {
+        let msg_id = self.send(src, body).await;
+        loop {
+            let msg = self.next().await;
+            if msg["dest"] == src && msg["body"]["in_reply_to"] == msg_id {
+                return msg["body"].clone();
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn backups_take_entries_in_order_and_apply_what_is_committed() {
+    let mut cluster = Cluster::start(&["n1", "n2"]).await;
+
+    let reply = cluster.request("n2", json!({"type": "replicate", "from": 0, "entries": [1, 2], "commit": 0})).await;
+    assert_eq!(reply["len"], 2);
+    assert_eq!(cluster.request("c1", json!({"type": "read"})).await["total"], 0);
+
+    // a gap is refused; the primary resends from where the backup is
+    let reply = cluster.request("n2", json!({"type": "replicate", "from": 5, "entries": [9], "commit": 6})).await;
+    assert_eq!(reply["len"], 2);
+
+    // entries the backup already has are skipped, not applied twice
+    let reply = cluster.request("n2", json!({"type": "replicate", "from": 1, "entries": [2, 3], "commit": 3})).await;
+    assert_eq!(reply["len"], 3);
+    assert_eq!(cluster.request("c1", json!({"type": "read"})).await["total"], 6);
+
+    let reply = cluster.request("n2", json!({"type": "fetch", "from": 1})).await;
+    assert_eq!(reply["entries"], json!([2, 3]));
+    assert_eq!(reply["len"], 3);
+}
+
+#[tokio::test]
+async fn a_lone_replica_becomes_primary_and_orders_commands() {
+    let mut cluster = Cluster::start(&["n1"]).await;
+
+    let mut reply = cluster.request("c1", json!({"type": "add", "delta": 5})).await;
+    while reply["type"] == "error" {
+        assert_eq!(reply["code"], 11, "{}", reply);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        reply = cluster.request("c1", json!({"type": "add", "delta": 5})).await;
+    }
+    assert_eq!(reply["total"], 5);
+    assert_eq!(cluster.request("c1", json!({"type": "add", "delta": 2})).await["total"], 7);
+}
+
+#[tokio::test]
+async fn learners_get_committed_commands_without_being_waited_on() {
+    let mut cluster = Cluster::start(&["n1", "l1"]).await;
+
+    // nothing answers for l1 yet, so the add can only succeed without it
+    let mut reply = cluster.request("c1", json!({"type": "add", "delta": 5})).await;
+    while reply["type"] == "error" {
+        assert_eq!(reply["code"], 11, "{}", reply);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        reply = cluster.request("c1", json!({"type": "add", "delta": 5})).await;
+    }
+    assert_eq!(reply["total"], 5);
+
+    // play the learner until it has the command and knows it is committed
+    let mut log = Vec::new();
+    loop {
+        let msg = cluster.next().await;
+        if msg["dest"] != "l1" || msg["body"]["type"] != "replicate" {
+            continue;
+        }
+        let body = &msg["body"];
+        let from = body["from"].as_u64().unwrap() as usize;
+        if from <= log.len() {
+            let entries = body["entries"].as_array().unwrap();
+            log.extend(entries.iter().skip(log.len() - from).cloned());
+        }
+        if log == [json!(5)] && body["commit"] == 1 {
+            break;
+        }
+        let ok = json!({"type": "replicate_ok", "in_reply_to": body["msg_id"], "len": log.len()});
+        cluster.send("l1", ok).await;
+    }
+}
@@ -0,0 +1,108 @@
+#![cfg(feature = "profile")]
+
+use dist_sys::client::MaelstromClient;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::process::Command;
+use std::time::Duration;
+
+/// Set for the copy of this test binary that runs the profiled node.
+const CHILD_ENV: &str = "DIST_SYS_PROFILE_TEST_CHILD";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    /// Holds its thread for 30ms.
+    Block,
+    BlockOk,
+    /// Waits 5s without holding its thread.
+    Wait,
+    WaitOk,
+    Quick,
+    QuickOk,
+}
+
+struct Stalling;
+
+impl Node<(), Payload> for Stalling {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Stalling)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        let reply = match payload {
+            Payload::Block => {
+                std::thread::sleep(Duration::from_millis(30));
+                Payload::BlockOk
+            }
+            Payload::Wait => {
+                ctx.sleep(Duration::from_secs(5)).await;
+                Payload::WaitOk
+            }
+            Payload::Quick => Payload::QuickOk,
+            _ => return Ok(()),
+        };
+        request.reply_with(reply, &ctx).send(&ctx)
+    }
+}
+
+/// Only does anything in the child process `slow_handlers_are_reported`
+/// starts, as the reports go to stderr.
+#[tokio::test]
+async fn profiled_node() {
+    if std::env::var_os(CHILD_ENV).is_none() {
+        return;
+    }
+    let runtime = Runtime::new().profile(Duration::from_millis(10), 2);
+    let (client, node) = MaelstromClient::in_process::<_, Stalling, _, (), ()>(runtime, (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+
+    client.send(json!({"type": "wait"})).await.unwrap();
+    for body in [json!({"type": "quick"}), json!({"type": "block"}), json!({"type": "quick"}), json!({"type": "block"})] {
+        client.request(body).await.unwrap();
+    }
+    node.abort();
+}
+
+#[test]
+fn slow_handlers_are_reported() {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["profiled_node", "--exact", "--nocapture"])
+        .env(CHILD_ENV, "1")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{}", stderr);
+    let reports: Vec<Value> = stderr
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|report| report["event"] == "slow_handler")
+        .collect();
+
+    // once, after the second overrun in a row; the quick ones in between
+    // don't count against the block handler
+    let [report] = <[Value; 1]>::try_from(reports).unwrap_or_else(|_| panic!("{}", stderr));
+    assert_eq!(report["budget_ms"], 10.0);
+    assert_eq!(report["overruns"], 2);
+    assert_eq!(report["slow"]["handler"], "block");
+    assert!(report["slow"]["busy_ms"].as_f64().unwrap() >= 30.0, "{}", report);
+    assert!(report["slow"]["longest_poll_ms"].as_f64().unwrap() >= 30.0, "{}", report);
+
+    let running = report["running"].as_array().unwrap();
+    assert_eq!(running.len(), 1, "{}", report);
+    assert_eq!(running[0]["handler"], "wait");
+    // parked on its timer, not holding the thread
+    assert!(running[0]["busy_ms"].as_f64().unwrap() < 10.0, "{}", report);
+}
@@ -0,0 +1,127 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::rate::{Rate, RateLimit};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Flood { to: Vec<String>, count: u64 },
+    Flooded { index: u64 },
+}
+
+struct Flooder;
+
+impl Node<(), Payload> for Flooder {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Flooder)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let Payload::Flood { to, count } = input.body.payload else {
+            return Ok(());
+        };
+        for index in 0..count {
+            for dst in &to {
+                let flooded = Message {
+                    src: ctx.node_id().to_string(),
+                    dst: dst.clone(),
+                    body: Body {
+                        id: None,
+                        in_reply_to: None,
+                        payload: Payload::Flooded { index },
+                    },
+                };
+                flooded.send(&ctx)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The destination and index of each message n1 floods `to` under `limit`,
+/// in the order they come out, and how long after the request each does.
+async fn flood(limit: RateLimit, to: &[&str], count: u64) -> Vec<(String, u64, Duration)> {
+    let runtime = Runtime::new().rate_limit(limit);
+    let (client, node) = MaelstromClient::in_process::<_, Flooder, _, (), ()>(runtime, (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2", "n3"]).await.unwrap();
+
+    let start = Instant::now();
+    client.send(json!({"type": "flood", "to": to, "count": count})).await.unwrap();
+    let mut seen = Vec::new();
+    while let Ok(msg) = client.next_other().await {
+        let dst = msg["dest"].as_str().unwrap().to_string();
+        seen.push((dst, msg["body"]["index"].as_u64().unwrap(), start.elapsed()));
+    }
+    node.abort();
+    seen
+}
+
+fn ms(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+}
+
+// paused, so the spacing shows exactly in when messages come out
+#[tokio::test(start_paused = true)]
+async fn a_burst_goes_out_at_once_and_the_rest_at_the_rate() {
+    let limit = RateLimit {
+        per_peer: Some(Rate { per_sec: 10.0, burst: 2 }),
+        ..RateLimit::default()
+    };
+    let seen = flood(limit, &["n2"], 5).await;
+    let indices: Vec<u64> = seen.iter().map(|(_, index, _)| *index).collect();
+    assert_eq!(indices, [0, 1, 2, 3, 4], "delayed, not dropped or reordered");
+    let times: Vec<Duration> = seen.iter().map(|(_, _, at)| *at).collect();
+    assert_eq!(times, [ms(0), ms(0), ms(100), ms(200), ms(300)]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn each_peer_has_a_bucket_of_its_own() {
+    let limit = RateLimit {
+        per_peer: Some(Rate { per_sec: 10.0, burst: 1 }),
+        ..RateLimit::default()
+    };
+    let seen = flood(limit, &["n2", "n3"], 2).await;
+    for peer in ["n2", "n3"] {
+        let times: Vec<Duration> = seen.iter().filter(|(dst, _, _)| dst == peer).map(|(_, _, at)| *at).collect();
+        assert_eq!(times, [ms(0), ms(100)], "{}", peer);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn the_global_limit_spans_every_peer() {
+    let limit = RateLimit {
+        global: Some(Rate { per_sec: 10.0, burst: 2 }),
+        ..RateLimit::default()
+    };
+    let seen = flood(limit, &["n2", "n3"], 2).await;
+    let mut times: Vec<Duration> = seen.iter().map(|(_, _, at)| *at).collect();
+    times.sort();
+    assert_eq!(times, [ms(0), ms(0), ms(100), ms(200)]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn messages_to_clients_are_not_limited() {
+    let limit = RateLimit {
+        global: Some(Rate { per_sec: 10.0, burst: 1 }),
+        per_peer: Some(Rate { per_sec: 10.0, burst: 1 }),
+    };
+    let seen = flood(limit, &["c1", "n2"], 3).await;
+    let to_client: Vec<Duration> = seen.iter().filter(|(dst, _, _)| dst == "c1").map(|(_, _, at)| *at).collect();
+    assert_eq!(to_client, [ms(0), ms(0), ms(0)]);
+    let to_peer: Vec<Duration> = seen.iter().filter(|(dst, _, _)| dst == "n2").map(|(_, _, at)| *at).collect();
+    assert_eq!(to_peer, [ms(0), ms(100), ms(200)], "nor counted against the global limit");
+}
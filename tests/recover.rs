@@ -0,0 +1,126 @@
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines};
+use tokio::sync::Notify;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Restore { value: i64 },
+    RestoreOk,
+    Read,
+    ReadOk { value: Option<i64> },
+}
+
+/// Recovers once a peer has sent it its value, or fails to then if started
+/// with `true`.
+struct Restoring {
+    fail: bool,
+    value: Mutex<Option<i64>>,
+    restored: Notify,
+}
+
+impl Node<bool, Payload> for Restoring {
+    async fn from_init(
+        fail: bool,
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Restoring {
+            fail,
+            value: Mutex::new(None),
+            restored: Notify::new(),
+        })
+    }
+
+    async fn recover(&self, _ctx: Ctx) -> anyhow::Result<()> {
+        self.restored.notified().await;
+        anyhow::ensure!(!self.fail, "the restored value is no good");
+        Ok(())
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let mut reply = input.into_reply(None);
+        reply.body.payload = match reply.body.payload {
+            Payload::Restore { value } => {
+                *self.value.lock().unwrap() = Some(value);
+                self.restored.notify_one();
+                Payload::RestoreOk
+            }
+            Payload::Read => Payload::ReadOk {
+                value: *self.value.lock().unwrap(),
+            },
+            _ => return Ok(()),
+        };
+        reply.send(&ctx)
+    }
+}
+
+async fn start(fail: bool) -> (DuplexStream, Lines<BufReader<DuplexStream>>, tokio::task::JoinHandle<anyhow::Result<()>>) {
+    let (mut input, node_input) = tokio::io::duplex(4096);
+    let (node_output, output) = tokio::io::duplex(4096);
+    let init = json!({"src": "c0", "dest": "n1", "body": {"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]}});
+    input.write_all(format!("{}\n", init).as_bytes()).await.unwrap();
+    let node = tokio::spawn(
+        Runtime::new().run_with_io::<_, Restoring, _, (), ()>(fail, BufReader::new(node_input), node_output),
+    );
+    let mut lines = BufReader::new(output).lines();
+    assert_eq!(next(&mut lines).await["body"]["type"], "init_ok");
+    (input, lines, node)
+}
+
+async fn next(lines: &mut Lines<BufReader<DuplexStream>>) -> Value {
+    let line = tokio::time::timeout(Duration::from_secs(5), lines.next_line()).await.unwrap().unwrap().unwrap();
+    serde_json::from_str(&line).unwrap()
+}
+
+#[tokio::test]
+async fn client_messages_wait_for_recovery_but_peers_do_not() {
+    let (mut input, mut lines, node) = start(false).await;
+
+    for msg in [
+        json!({"src": "c1", "dest": "n1", "body": {"type": "read", "msg_id": 2}}),
+        json!({"src": "c1", "dest": "n1", "body": {"type": "read", "msg_id": 3}}),
+        json!({"src": "n2", "dest": "n1", "body": {"type": "restore", "msg_id": 4, "value": 7}}),
+    ] {
+        input.write_all(format!("{}\n", msg).as_bytes()).await.unwrap();
+    }
+
+    let restored = next(&mut lines).await;
+    assert_eq!((restored["dest"].clone(), restored["body"]["type"].clone()), (json!("n2"), json!("restore_ok")));
+    for msg_id in [2, 3] {
+        let read = next(&mut lines).await;
+        assert_eq!(read["body"]["in_reply_to"], msg_id);
+        assert_eq!(read["body"]["value"], 7);
+    }
+    drop(input);
+    node.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn a_failed_recovery_stops_the_node_and_answers_held_messages() {
+    let (mut input, mut lines, node) = start(true).await;
+    for msg in [
+        json!({"src": "c1", "dest": "n1", "body": {"type": "read", "msg_id": 2}}),
+        json!({"src": "n2", "dest": "n1", "body": {"type": "restore", "msg_id": 3, "value": 7}}),
+    ] {
+        input.write_all(format!("{}\n", msg).as_bytes()).await.unwrap();
+    }
+
+    let read = loop {
+        let msg = next(&mut lines).await;
+        if msg["dest"] == "c1" {
+            break msg;
+        }
+    };
+    assert_eq!((read["body"]["in_reply_to"].clone(), read["body"]["code"].clone()), (json!(2), json!(13)));
+    let err = node.await.unwrap().unwrap_err();
+    assert!(format!("{:#}", err).contains("the restored value is no good"), "{:#}", err);
+}
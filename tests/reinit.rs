@@ -0,0 +1,123 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::error::{ErrorCode, MaelstromError};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Peers,
+    PeersOk { node_ids: Vec<String>, reinits: usize },
+}
+
+/// Takes the node ids of every init it is given, and refuses an init
+/// naming no nodes.
+struct Membership {
+    node_ids: Mutex<Vec<String>>,
+    reinits: Mutex<usize>,
+}
+
+impl Node<(), Payload> for Membership {
+    async fn from_init(
+        _state: (),
+        init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Membership {
+            node_ids: Mutex::new(init.node_ids),
+            reinits: Mutex::default(),
+        })
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (Payload::Peers, request) = input.take_payload() else {
+            return Ok(());
+        };
+        let reply = Payload::PeersOk {
+            node_ids: self.node_ids.lock().unwrap().clone(),
+            reinits: *self.reinits.lock().unwrap(),
+        };
+        request.reply_with(reply, &ctx).send(&ctx)
+    }
+
+    async fn on_reinit(&self, init: Init, _ctx: Ctx) -> anyhow::Result<()> {
+        *self.reinits.lock().unwrap() += 1;
+        if init.node_ids.is_empty() {
+            return Err(MaelstromError::new(ErrorCode::MALFORMED_REQUEST, "no nodes").into());
+        }
+        *self.node_ids.lock().unwrap() = init.node_ids;
+        Ok(())
+    }
+}
+
+/// Only has the default `on_reinit`.
+struct Fixed;
+
+impl Node<(), Payload> for Fixed {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Fixed)
+    }
+
+    async fn step(&self, _input: Event<Payload>, _ctx: Ctx) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+fn init(node_id: &str, node_ids: &[&str]) -> Value {
+    json!({"type": "init", "node_id": node_id, "node_ids": node_ids})
+}
+
+/// What the node answers a second `init` with: its type, or its error code.
+async fn reinit(client: &mut MaelstromClient, body: Value) -> Result<String, ErrorCode> {
+    match client.request(body).await {
+        Ok(reply) => Ok(reply["type"].as_str().unwrap().to_string()),
+        Err(e) => Err(MaelstromError::code_of(&e).unwrap_or_else(|| panic!("{:#}", e))),
+    }
+}
+
+#[tokio::test]
+async fn a_repeated_init_goes_to_on_reinit() {
+    let (client, node) = MaelstromClient::in_process::<_, Membership, _, (), ()>(Runtime::new(), (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1", "n2"]).await.unwrap();
+
+    assert_eq!(reinit(&mut client, init("n1", &["n1", "n2", "n3"])).await, Ok("init_ok".to_string()));
+    let peers = client.request(json!({"type": "peers"})).await.unwrap();
+    assert_eq!(peers["node_ids"], json!(["n1", "n2", "n3"]));
+
+    // the hook's own error goes back as is
+    assert_eq!(reinit(&mut client, init("n1", &[])).await, Err(ErrorCode::MALFORMED_REQUEST));
+    // and a different id is turned away before reaching it
+    assert_eq!(reinit(&mut client, init("n2", &["n1", "n2"])).await, Err(ErrorCode::NOT_SUPPORTED));
+    let peers = client.request(json!({"type": "peers"})).await.unwrap();
+    assert_eq!(peers["reinits"], 2);
+    assert_eq!(peers["node_ids"], json!(["n1", "n2", "n3"]));
+    node.abort();
+}
+
+#[tokio::test]
+async fn nodes_without_the_hook_refuse_to_be_reinitialized() {
+    let (client, node) = MaelstromClient::in_process::<_, Fixed, _, (), ()>(Runtime::new(), (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+
+    assert_eq!(reinit(&mut client, init("n1", &["n1"])).await, Err(ErrorCode::NOT_SUPPORTED));
+    // and keep running
+    assert_eq!(reinit(&mut client, init("n1", &["n1"])).await, Err(ErrorCode::NOT_SUPPORTED));
+    assert!(!node.is_finished());
+    node.abort();
+}
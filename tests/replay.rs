@@ -0,0 +1,40 @@
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+fn replay(bin: &str, log: &str, settle_ms: u64) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_replay"))
+        .args([bin, log, "--settle-ms", &settle_ms.to_string()])
+        .output()
+        .expect("run replay")
+}
+
+fn transcript(name: &str) -> String {
+    format!("{}/tests/transcripts/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+// the transcript's seq-kv requests carry msg_ids the node doesn't issue
+#[test]
+fn service_replies_reach_the_requests_the_replayed_node_sent() {
+    // long enough for a read's pause before it asks seq-kv
+    let output = replay(env!("CARGO_BIN_EXE_counter"), &transcript("counter.txt"), 1000);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+}
+
+#[test]
+fn captured_messages_are_fed_at_their_recorded_times() {
+    let log = std::env::temp_dir().join(format!("dist-sys-replay-{}.jsonl", std::process::id()));
+    let records = [
+        r#"{"at_us":0,"dir":"recv","msg":{"src":"c0","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1"]}}}"#,
+        r#"{"at_us":10,"dir":"send","msg":{"src":"n1","dest":"c0","body":{"type":"init_ok","msg_id":0,"in_reply_to":1}}}"#,
+        r#"{"at_us":400000,"dir":"recv","msg":{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"late"}}}"#,
+        r#"{"at_us":400010,"dir":"send","msg":{"src":"n1","dest":"c1","body":{"type":"echo_ok","msg_id":1,"in_reply_to":1,"echo":"late"}}}"#,
+    ];
+    std::fs::write(&log, records.join("\n")).unwrap();
+
+    let start = Instant::now();
+    let output = replay(env!("CARGO_BIN_EXE_echo"), log.to_str().unwrap(), 200);
+    let elapsed = start.elapsed();
+    let _ = std::fs::remove_file(&log);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    assert!(elapsed >= Duration::from_millis(600), "echo fed after 400ms, then 200ms to settle: {:?}", elapsed);
+}
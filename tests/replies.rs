@@ -0,0 +1,92 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::time::ManualClock;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Append,
+    AppendOk { offset: u64 },
+    /// Appends like `append`, but the first one is handled without a reply.
+    AppendLossy,
+}
+
+/// Appends to a log that only ever grows, so handling a request twice shows.
+struct Log {
+    len: AtomicU64,
+    lossy: AtomicU64,
+}
+
+impl Node<(), Payload> for Log {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Log {
+            len: AtomicU64::new(0),
+            lossy: AtomicU64::new(0),
+        })
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        match payload {
+            Payload::Append => {}
+            Payload::AppendLossy if self.lossy.fetch_add(1, Ordering::SeqCst) > 0 => {}
+            _ => return Ok(()),
+        }
+        let offset = self.len.fetch_add(1, Ordering::SeqCst);
+        request.reply_with(Payload::AppendOk { offset }, &ctx).send(&ctx)
+    }
+}
+
+#[tokio::test]
+async fn repeated_requests_get_the_first_reply_until_the_ttl_passes() {
+    let clock = ManualClock::new();
+    let runtime = Runtime::new().clock(clock.clone()).reply_cache(Duration::from_secs(10));
+    let (mut client, node) = MaelstromClient::in_process::<_, Log, _, (), ()>(runtime, (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+    let mut append = async |msg_id: u64| {
+        client.send(json!({"type": "append", "msg_id": msg_id})).await.unwrap();
+        let reply = client.reply_to("c1", msg_id).await.unwrap();
+        reply["body"]["offset"].as_u64().unwrap()
+    };
+
+    assert_eq!(append(5).await, 0);
+    assert_eq!(append(5).await, 0);
+    assert_eq!(append(6).await, 1);
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(append(5).await, 2);
+    assert_eq!(append(6).await, 3);
+    node.abort();
+}
+
+#[tokio::test]
+async fn a_request_handled_without_a_reply_is_handled_again_when_repeated() {
+    let runtime = Runtime::new().reply_cache(Duration::from_secs(10));
+    let (client, node) = MaelstromClient::in_process::<_, Log, _, (), ()>(runtime, (), "n1");
+    let mut client = client.with_timeout(Duration::from_millis(200));
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+
+    client.send(json!({"type": "append_lossy", "msg_id": 5})).await.unwrap();
+    assert!(client.reply_to("c1", 5).await.is_err(), "the first is never answered");
+    // not dropped as a repeat of a request still being handled
+    client.send(json!({"type": "append_lossy", "msg_id": 5})).await.unwrap();
+    let reply = client.reply_to("c1", 5).await.unwrap();
+    assert_eq!(reply["body"]["offset"], 0);
+    // and remembered now that it has been answered
+    client.send(json!({"type": "append_lossy", "msg_id": 5})).await.unwrap();
+    assert_eq!(client.reply_to("c1", 5).await.unwrap()["body"]["offset"], 0);
+    node.abort();
+}
@@ -0,0 +1,46 @@
+use dist_sys::selftest::{self, Script};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Double { n: i64 },
+}
+
+/// Replies are a type of their own, so a request can't be sent as one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    DoubleOk { n: i64 },
+}
+
+struct Doubler;
+
+impl Node<(), Request> for Doubler {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Request>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Doubler)
+    }
+
+    async fn step(&self, input: Event<Request>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (Request::Double { n }, request) = input.take_payload();
+        request.reply_with(Reply::DoubleOk { n: 2 * n }, &ctx).send(&ctx)
+    }
+}
+
+#[tokio::test]
+async fn replies_answer_the_request_they_were_built_from() {
+    let script = Script::new()
+        .exchange(json!({"type": "double", "n": 2}), json!({"type": "double_ok", "n": 4}))
+        .exchange(json!({"type": "double", "n": -3}), json!({"type": "double_ok", "n": -6}));
+    selftest::run::<_, Doubler, _, _, _>(Runtime::new(), (), script).await.unwrap();
+}
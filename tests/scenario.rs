@@ -0,0 +1,211 @@
+//! Runs every scenario under tests/scenarios against the matching node below.
+
+use dist_sys::kv::Kv;
+use dist_sys::sim::scenario::{Assertion, Nemesis, Report, Scenario, Workload, WorkloadKind};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BroadcastPayload {
+    Broadcast { message: u64 },
+    BroadcastOk,
+    Read,
+    ReadOk { messages: BTreeSet<u64> },
+    Gossip { messages: BTreeSet<u64> },
+}
+
+struct Tick;
+
+impl Coalescible for Tick {
+    fn coalesces(&self, _queued: &Self) -> bool {
+        true
+    }
+}
+
+/// Pushes everything it has to every peer on each tick.
+struct BroadcastNode {
+    peers: Vec<String>,
+    messages: Mutex<BTreeSet<u64>>,
+}
+
+impl Node<(), BroadcastPayload, (), Tick> for BroadcastNode {
+    async fn from_init(
+        _state: (),
+        init: Init,
+        tx: tokio::sync::mpsc::UnboundedSender<Event<BroadcastPayload, (), Tick>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                if tx.send(Event::Injected(Tick)).is_err() {
+                    break;
+                }
+            }
+        });
+        let peers = init.node_ids.into_iter().filter(|id| *id != init.node_id).collect();
+        Ok(Self {
+            peers,
+            messages: Mutex::default(),
+        })
+    }
+
+    async fn step(&self, input: Event<BroadcastPayload, (), Tick>, ctx: Ctx) -> anyhow::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            Event::Injected(Tick) => {
+                let messages = self.messages.lock().unwrap().clone();
+                for peer in &self.peers {
+                    let gossip = Message {
+                        src: ctx.node_id().to_string(),
+                        dst: peer.clone(),
+                        body: Body {
+                            id: None,
+                            in_reply_to: None,
+                            payload: BroadcastPayload::Gossip {
+                                messages: messages.clone(),
+                            },
+                        },
+                    };
+                    gossip.send(&ctx)?;
+                }
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        let mut reply = input.into_reply(None);
+        reply.body.payload = match reply.body.payload {
+            BroadcastPayload::Broadcast { message } => {
+                self.messages.lock().unwrap().insert(message);
+                BroadcastPayload::BroadcastOk
+            }
+            BroadcastPayload::Read => BroadcastPayload::ReadOk {
+                messages: self.messages.lock().unwrap().clone(),
+            },
+            BroadcastPayload::Gossip { messages } => {
+                self.messages.lock().unwrap().extend(messages);
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        reply.send(&ctx)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CounterPayload {
+    Add { delta: i64 },
+    AddOk,
+    Read,
+    ReadOk { value: i64 },
+}
+
+/// Keeps the count in `lin-kv`.
+struct CounterNode;
+
+impl Node<(), CounterPayload> for CounterNode {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<CounterPayload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(CounterNode)
+    }
+
+    async fn step(&self, input: Event<CounterPayload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let kv = Kv::lin(&ctx);
+        let mut reply = input.into_reply(None);
+        reply.body.payload = match reply.body.payload {
+            CounterPayload::Add { delta } => loop {
+                let value: i64 = kv.read("counter").await?.unwrap_or(0);
+                if kv.cas("counter", value, value + delta, true).await? {
+                    break CounterPayload::AddOk;
+                }
+            },
+            CounterPayload::Read => CounterPayload::ReadOk {
+                value: kv.read("counter").await?.unwrap_or(0),
+            },
+            _ => return Ok(()),
+        };
+        reply.send(&ctx)
+    }
+}
+
+async fn run(scenario: &Scenario) -> anyhow::Result<Report> {
+    match scenario.workload.kind {
+        WorkloadKind::Broadcast => {
+            scenario
+                .run::<_, BroadcastNode, _, (), _>(|| (Runtime::new(), ()))
+                .await
+        }
+        WorkloadKind::Counter => scenario.run::<_, CounterNode, _, (), ()>(|| (Runtime::new(), ())).await,
+        WorkloadKind::Echo => anyhow::bail!("no echo node here"),
+    }
+}
+
+#[tokio::test]
+async fn scenario_files() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenarios");
+    let mut runs = Vec::new();
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        let scenario = Scenario::load(&path).unwrap();
+        runs.push((path, tokio::spawn(async move { run(&scenario).await.map(|_| ()) })));
+    }
+    assert!(!runs.is_empty(), "no scenarios in {}", dir.display());
+    let mut failures = Vec::new();
+    for (path, run) in runs {
+        if let Err(e) = run.await.unwrap() {
+            failures.push(format!("{}: {:#}", path.display(), e));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn builder_matches_the_file_format() {
+    let built = Scenario::new(3, Workload::new(WorkloadKind::Broadcast).rate(40.0).timeout(Duration::from_millis(300)))
+        .duration(Duration::from_millis(1500))
+        .settle(Duration::from_millis(800))
+        .at(Duration::from_millis(400), Nemesis::Kill { node: "n3".into() })
+        .at(Duration::from_millis(900), Nemesis::Restart { node: "n3".into() })
+        .at(
+            Duration::from_millis(900),
+            Nemesis::Skew {
+                node: "n2".into(),
+                offset_ms: -2000,
+            },
+        )
+        .assert(Assertion::Converged)
+        .assert(Assertion::MinOkFraction(0.5))
+        .seed(7);
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenarios/broadcast-kill.json");
+    assert_eq!(Scenario::load(path).unwrap(), built);
+}
+
+#[tokio::test]
+async fn failed_assertions_are_reported() {
+    // n2 never hears of what n1 was told
+    let scenario = Scenario::new(2, Workload::new(WorkloadKind::Broadcast).rate(40.0).clients(1))
+        .duration(Duration::from_millis(300))
+        .settle(Duration::ZERO)
+        .at(Duration::ZERO, Nemesis::Kill { node: "n2".into() })
+        .assert(Assertion::AllOk)
+        .assert(Assertion::Converged);
+    let err = run(&scenario).await.unwrap_err().to_string();
+    assert!(err.contains("all_ok: "), "{}", err);
+
+    let unreachable = Scenario::new(2, Workload::new(WorkloadKind::Counter)).at(Duration::ZERO, Nemesis::Kill { node: "n9".into() });
+    let err = run(&unreachable).await.unwrap_err().to_string();
+    assert!(err.contains("n9"), "{}", err);
+}
@@ -0,0 +1,25 @@
+//! Every bundled binary passes its own `--selftest`.
+
+use std::process::Command;
+
+fn selftest(bin: &str) {
+    let output = Command::new(bin)
+        .arg("--selftest")
+        .output()
+        .unwrap_or_else(|e| panic!("spawn {}: {}", bin, e));
+    assert!(
+        output.status.success(),
+        "{} --selftest failed:\n{}",
+        bin,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn bundled_binaries() {
+    selftest(env!("CARGO_BIN_EXE_echo"));
+    selftest(env!("CARGO_BIN_EXE_unique-ids"));
+    selftest(env!("CARGO_BIN_EXE_broadcast"));
+    selftest(env!("CARGO_BIN_EXE_counter"));
+    selftest(env!("CARGO_BIN_EXE_txn-list-append"));
+}
@@ -0,0 +1,79 @@
+use dist_sys::config::NodeConfig;
+use dist_sys::services::Services;
+use dist_sys::*;
+use serde_json::json;
+use std::sync::Mutex;
+use tokio::io::{AsyncWriteExt, BufReader};
+
+/// Records what the roles `kv`, `log` and `lin-tso` resolve to in its state.
+struct Resolving;
+
+impl Node<&'static Mutex<Vec<String>>, ()> for Resolving {
+    async fn from_init(
+        resolved: &'static Mutex<Vec<String>>,
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<()>>,
+        ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        *resolved.lock().unwrap() = ["kv", "log", "lin-tso"].map(|role| ctx.service(role).to_string()).to_vec();
+        Ok(Resolving)
+    }
+
+    async fn step(&self, _input: Event<()>, _ctx: Ctx) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn knobs_and_init_map_roles_to_services() {
+    let config = NodeConfig::from_parts(
+        ["--service-kv", "lin-kv"].map(String::from),
+        [("DIST_SYS_SERVICE_LOG".to_string(), "lww-kv".to_string())],
+    )
+    .unwrap();
+    let configured = Services::from_config(&config);
+    assert_eq!(configured.get("kv"), Some("lin-kv"));
+    assert_eq!(configured.get("log"), Some("lww-kv"));
+    assert_eq!(configured.resolve("seq-kv"), "seq-kv");
+
+    let init = Services::from_init(&json!({"type": "init", "services": {"kv": "seq-kv", "tso": "lin-tso"}})).unwrap();
+    let merged = init.merge(configured);
+    assert_eq!(merged.resolve("kv"), "lin-kv");
+    assert_eq!(merged.resolve("tso"), "lin-tso");
+
+    assert_eq!(Services::from_init(&json!({"type": "init"})).unwrap(), Services::new());
+    assert!(Services::from_init(&json!({"services": ["lin-kv"]})).is_err());
+}
+
+/// Runs `runtime` through an `init` that maps `kv` to `lin-kv`, returning
+/// what the node resolved.
+async fn resolve(runtime: Runtime, resolved: &'static Mutex<Vec<String>>) -> Vec<String> {
+    let (mut input, node_input) = tokio::io::duplex(4096);
+    let (node_output, _output) = tokio::io::duplex(4096);
+    let init = json!({"src": "c0", "dest": "n1", "body": {
+        "type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1"],
+        "services": {"kv": "lin-kv"},
+    }});
+    input.write_all(format!("{}\n", init).as_bytes()).await.unwrap();
+    drop(input);
+
+    runtime
+        .run_with_io::<_, Resolving, (), (), ()>(resolved, BufReader::new(node_input), node_output)
+        .await
+        .unwrap();
+    resolved.lock().unwrap().clone()
+}
+
+#[tokio::test]
+async fn init_overrides_runtime_defaults() {
+    static RESOLVED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    let runtime = Runtime::new().default_service("kv", "seq-kv").default_service("log", "lww-kv");
+    assert_eq!(resolve(runtime, &RESOLVED).await, ["lin-kv", "lww-kv", "lin-tso"]);
+}
+
+#[tokio::test]
+async fn knobs_given_to_the_runtime_override_init() {
+    static RESOLVED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    let config = NodeConfig::from_parts(["--service-kv", "lww-kv"].map(String::from), []).unwrap();
+    assert_eq!(resolve(Runtime::from_config(&config), &RESOLVED).await, ["lww-kv", "log", "lin-tso"]);
+}
@@ -0,0 +1,88 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::error::{ErrorCode, MaelstromError};
+use dist_sys::kv::{Kv, SessionKv};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Write { key: String, value: u64 },
+    WriteOk,
+    Read { key: String },
+    ReadOk { value: Option<u64> },
+}
+
+/// Writes and reads seq-kv through one session.
+struct Session(SessionKv);
+
+impl Node<(), Payload> for Session {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Session(SessionKv::new(Kv::seq(ctx)).retry(Duration::from_millis(10), 3)))
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        let result = match payload {
+            Payload::Write { key, value } => self.0.write(&key, value).await.map(|_| Payload::WriteOk),
+            Payload::Read { key } => self.0.read_after_my_writes(&key).await.map(|value| Payload::ReadOk { value }),
+            _ => return Ok(()),
+        };
+        match result {
+            Ok(reply) => request.reply_with(reply, &ctx).send(&ctx),
+            Err(e) => request.reply_error(e.downcast::<MaelstromError>()?, &ctx),
+        }
+    }
+}
+
+/// Answers the node's next request to seq-kv, which should be a `kind`,
+/// with `body`.
+async fn answer(client: &mut MaelstromClient, kind: &str, mut body: Value) {
+    let request = client.next_other().await.unwrap();
+    assert_eq!(request["dest"], "seq-kv", "{}", request);
+    assert_eq!(request["body"]["type"], kind, "{}", request);
+    body["in_reply_to"] = request["body"]["msg_id"].clone();
+    client.send_from("seq-kv", body).await.unwrap();
+}
+
+fn missing() -> Value {
+    json!({"type": "error", "code": ErrorCode::KEY_DOES_NOT_EXIST.0, "text": "not found"})
+}
+
+#[tokio::test]
+async fn stale_reads_are_retried_until_they_catch_up_with_the_session() {
+    let (mut client, node) = MaelstromClient::in_process::<_, Session, _, (), ()>(Runtime::new(), (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+
+    // version 1, created
+    let write = client.send(json!({"type": "write", "key": "k", "value": 5})).await.unwrap();
+    answer(&mut client, "read", missing()).await;
+    answer(&mut client, "cas", json!({"type": "cas_ok"})).await;
+    assert_eq!(client.reply_to("c1", write).await.unwrap()["body"]["type"], "write_ok");
+
+    // a replica that hasn't seen the write yet, then one that has
+    let read = client.send(json!({"type": "read", "key": "k"})).await.unwrap();
+    answer(&mut client, "read", missing()).await;
+    answer(&mut client, "read", json!({"type": "read_ok", "value": {"version": 1, "value": 5}})).await;
+    assert_eq!(client.reply_to("c1", read).await.unwrap()["body"]["value"], 5);
+
+    // and one that never catches up
+    let read = client.send(json!({"type": "read", "key": "k"})).await.unwrap();
+    for _ in 0..3 {
+        answer(&mut client, "read", missing()).await;
+    }
+    let reply = client.reply_to("c1", read).await.unwrap();
+    assert_eq!(reply["body"]["code"], ErrorCode::TEMPORARILY_UNAVAILABLE.0, "{}", reply);
+    node.abort();
+}
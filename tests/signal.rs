@@ -0,0 +1,67 @@
+#![cfg(unix)]
+
+use dist_sys::client::MaelstromClient;
+use dist_sys::signal::Signal;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Signalled { signal: String },
+}
+
+/// Tells c1 of every signal.
+struct Listening;
+
+impl Node<(), Payload> for Listening {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Listening)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Signal(signal) = input else {
+            return Ok(());
+        };
+        let signalled = Message {
+            src: ctx.node_id().to_string(),
+            dst: "c1".to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                payload: Payload::Signalled {
+                    signal: format!("{:?}", signal),
+                },
+            },
+        };
+        signalled.send(&ctx)
+    }
+}
+
+fn kill(signal: &str) {
+    let pid = std::process::id().to_string();
+    let status = std::process::Command::new("kill").args([signal, &pid]).status().unwrap();
+    assert!(status.success());
+}
+
+#[tokio::test]
+async fn signals_reach_the_node_and_sigterm_ends_the_run() {
+    let (mut client, node) = MaelstromClient::in_process::<_, Listening, _, (), ()>(Runtime::new().signals(), (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+
+    kill("-USR1");
+    let signalled = client.next_other().await.unwrap();
+    assert_eq!(signalled["body"]["signal"], format!("{:?}", Signal::DumpStats));
+
+    // the node shuts down with its input still open
+    kill("-TERM");
+    let signalled = client.next_other().await.unwrap();
+    assert_eq!(signalled["body"]["signal"], format!("{:?}", Signal::Terminate));
+    node.await.unwrap().unwrap();
+}
@@ -0,0 +1,72 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::sizes::SizeStats;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Pad { bytes: usize },
+    PadOk { padding: String },
+    Sizes,
+    SizesOk { sizes: BTreeMap<String, SizeStats> },
+}
+
+struct Padder;
+
+impl Node<(), Payload> for Padder {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Padder)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let mut reply = input.into_reply(None);
+        reply.body.payload = match reply.body.payload {
+            Payload::Pad { bytes } => Payload::PadOk {
+                padding: "x".repeat(bytes),
+            },
+            Payload::Sizes => Payload::SizesOk {
+                sizes: ctx.message_sizes(),
+            },
+            _ => return Ok(()),
+        };
+        reply.send(&ctx)
+    }
+}
+
+#[tokio::test]
+async fn outbound_sizes_are_tracked_per_type() {
+    let (mut client, node) = MaelstromClient::in_process::<_, Padder, _, (), ()>(Runtime::new().message_size_warning(5000), (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+
+    let mut lengths = Vec::new();
+    for bytes in [100, 100, 100, 10_000] {
+        let msg_id = client.send(json!({"type": "pad", "bytes": bytes})).await.unwrap();
+        let reply = client.reply_to("c1", msg_id).await.unwrap();
+        lengths.push(reply.to_string().len());
+    }
+    let sizes = client.request(json!({"type": "sizes"})).await.unwrap()["sizes"].clone();
+    let sizes: BTreeMap<String, SizeStats> = serde_json::from_value(sizes).unwrap();
+
+    let pad_ok = sizes["pad_ok"];
+    assert_eq!(pad_ok.count, 4);
+    // msg_ids and the like differ by a few bytes between replies
+    assert!(pad_ok.p50.abs_diff(lengths[0]) < 10, "{:?} vs {:?}", pad_ok, lengths);
+    assert!(pad_ok.max.abs_diff(lengths[3]) < 10, "{:?} vs {:?}", pad_ok, lengths);
+    assert_eq!(pad_ok.p99, pad_ok.max);
+    assert!(pad_ok.total_bytes > 10_300);
+    assert_eq!(sizes["init_ok"].count, 1);
+    assert!(!sizes.contains_key("sizes_ok"));
+    node.abort();
+}
@@ -0,0 +1,112 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::stall::StallStats;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+use tokio::io::BufReader;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Flood { count: usize, bytes: usize },
+    Flooded { padding: String },
+    Stalls,
+    StallsOk { stalls: StallStats },
+}
+
+struct Flooder;
+
+impl Node<(), Payload> for Flooder {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Flooder)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        match payload {
+            Payload::Flood { count, bytes } => {
+                for _ in 0..count {
+                    let padding = "x".repeat(bytes);
+                    let flooded = Message {
+                        src: ctx.node_id().to_string(),
+                        dst: request.src.clone(),
+                        body: Body {
+                            id: None,
+                            in_reply_to: None,
+                            payload: Payload::Flooded { padding },
+                        },
+                    };
+                    flooded.send(&ctx)?;
+                }
+                Ok(())
+            }
+            Payload::Stalls => request.reply_with(Payload::StallsOk { stalls: ctx.write_stalls() }, &ctx).send(&ctx),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A node whose output pipe holds barely one message, with nobody reading it
+/// yet, and a client to it.
+async fn stalling() -> (MaelstromClient, tokio::task::JoinHandle<anyhow::Result<()>>) {
+    let (input, node_input) = tokio::io::duplex(64 * 1024);
+    let (node_output, output) = tokio::io::duplex(1024);
+    let runtime = Runtime::new().write_stall(Duration::from_millis(20), 10_000);
+    let node = runtime.run_with_io::<_, Flooder, _, (), ()>((), BufReader::new(node_input), node_output);
+    let node = tokio::spawn(node);
+    // short, so draining the output ends soon after it goes quiet
+    let mut client = MaelstromClient::new("c1", "n1", input, BufReader::new(output)).with_timeout(Duration::from_millis(200));
+    client.init(&["n1", "n2"]).await.unwrap();
+    (client, node)
+}
+
+/// Has n1 send two floods of 20 messages to `to`, the second while the
+/// first has it stalled with the buffer full, and returns how many came out.
+async fn flood_twice(client: &mut MaelstromClient, to: &str) -> usize {
+    client.send_from(to, json!({"type": "flood", "count": 20, "bytes": 1000})).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    client.send_from(to, json!({"type": "flood", "count": 20, "bytes": 1000})).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut flooded = 0;
+    while let Ok(msg) = client.next_other().await {
+        assert_eq!(msg["body"]["type"], "flooded");
+        flooded += 1;
+    }
+    flooded
+}
+
+#[tokio::test]
+async fn a_blocked_output_is_reported_and_stops_queueing_past_the_buffer() {
+    let (mut client, node) = stalling().await;
+    // none of the second flood made it out
+    assert_eq!(flood_twice(&mut client, "n2").await, 20);
+
+    let stalls = client.request(json!({"type": "stalls"})).await.unwrap()["stalls"].clone();
+    let stalls: StallStats = serde_json::from_value(stalls).unwrap();
+    assert!(stalls.stalls >= 1, "{:?}", stalls);
+    assert!(!stalls.stalled);
+    assert!(stalls.longest_ms >= 100, "{:?}", stalls);
+    assert_eq!(stalls.dropped, 20);
+    node.abort();
+}
+
+#[tokio::test]
+async fn messages_to_clients_are_queued_past_the_buffer() {
+    let (mut client, node) = stalling().await;
+    assert_eq!(flood_twice(&mut client, "c1").await, 40);
+
+    let stalls = client.request(json!({"type": "stalls"})).await.unwrap()["stalls"].clone();
+    let stalls: StallStats = serde_json::from_value(stalls).unwrap();
+    assert!(stalls.stalls >= 1, "{:?}", stalls);
+    assert_eq!(stalls.dropped, 0);
+    node.abort();
+}
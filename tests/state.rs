@@ -0,0 +1,16 @@
+use dist_sys::state::StateCell;
+
+#[test]
+fn state_is_only_reached_through_closures() {
+    let cell = StateCell::new(vec![1, 2]);
+    let len = cell.with(|v| {
+        v.push(3);
+        v.len()
+    });
+    assert_eq!(len, 3);
+    assert_eq!(cell.get(), [1, 2, 3]);
+    assert_eq!(cell.with(|_| format!("{:?}", cell)), "StateCell(<locked>)");
+    assert_eq!(cell.replace(vec![4]), [1, 2, 3]);
+    assert_eq!(format!("{:?}", cell), "StateCell([4])");
+    assert_eq!(cell.into_inner(), [4]);
+}
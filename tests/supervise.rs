@@ -0,0 +1,79 @@
+use dist_sys::supervise::{RestartPolicy, TaskStatus};
+use dist_sys::*;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, BufReader};
+
+static CTX: Mutex<Option<Ctx>> = Mutex::new(None);
+
+struct Supervising;
+
+impl Node<(), ()> for Supervising {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<()>>,
+        ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        let policy = RestartPolicy {
+            max_restarts: 5,
+            backoff: Duration::from_millis(1),
+        };
+        let attempts = AtomicUsize::new(0);
+        ctx.spawn_supervised("flaky", policy, move |_ctx| {
+            let attempt = attempts.fetch_add(1, Ordering::Relaxed);
+            async move {
+                assert!(attempt >= 2, "attempt {} fails", attempt);
+                Ok(())
+            }
+        });
+        ctx.spawn_supervised("broken", RestartPolicy::NEVER, |_ctx| async { anyhow::bail!("no luck") });
+        ctx.spawn_supervised("forever", policy, |ctx| async move {
+            ctx.sleep(Duration::from_secs(3600)).await;
+            Ok(())
+        });
+        *CTX.lock().unwrap() = Some(ctx.clone());
+        Ok(Supervising)
+    }
+
+    async fn step(&self, _input: Event<()>, _ctx: Ctx) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn restarts_failed_tasks_and_cancels_the_rest_at_eof() {
+    let (mut input, node_input) = tokio::io::duplex(4096);
+    let (node_output, _output) = tokio::io::duplex(4096);
+    let init = r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1"]}}"#;
+    input.write_all(format!("{}\n", init).as_bytes()).await.unwrap();
+    let feeder = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        drop(input);
+    });
+
+    // returns only because the long-running task is cancelled at EOF
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        Runtime::new().run_with_io::<_, Supervising, (), (), ()>((), BufReader::new(node_input), node_output),
+    )
+    .await
+    .expect("runtime exited")
+    .unwrap();
+    feeder.await.unwrap();
+
+    let reports = CTX.lock().unwrap().as_ref().unwrap().supervised();
+    let status = |name: &str| reports.iter().find(|r| r.name == name).map(|r| (r.status.clone(), r.restarts));
+    assert_eq!(status("flaky"), Some((TaskStatus::Finished, 2)));
+    assert_eq!(
+        status("broken"),
+        Some((
+            TaskStatus::Failed {
+                error: "no luck".to_string()
+            },
+            0
+        ))
+    );
+    assert_eq!(status("forever"), Some((TaskStatus::Cancelled, 0)));
+}
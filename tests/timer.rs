@@ -0,0 +1,105 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::time::ManualClock;
+use dist_sys::timer::TimerHandle;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Arm { id: u64, ms: u64 },
+    Reset { ms: u64 },
+    Cancel,
+    Done { pending: bool },
+    Fired { id: u64 },
+}
+
+struct Fired(u64);
+
+impl Coalescible for Fired {}
+
+/// One timer at a time, announcing to c1 when it fires.
+struct Timers {
+    inject: UnboundedSender<Event<Payload, (), Fired>>,
+    timer: Mutex<Option<TimerHandle>>,
+}
+
+impl Node<(), Payload, (), Fired> for Timers {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        inject: UnboundedSender<Event<Payload, (), Fired>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Timers {
+            inject,
+            timer: Mutex::default(),
+        })
+    }
+
+    async fn step(&self, input: Event<Payload, (), Fired>, ctx: Ctx) -> anyhow::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            Event::Injected(Fired(id)) => {
+                let fired = Message {
+                    src: ctx.node_id().to_string(),
+                    dst: "c1".to_string(),
+                    body: Body {
+                        id: None,
+                        in_reply_to: None,
+                        payload: Payload::Fired { id },
+                    },
+                };
+                return fired.send(&ctx);
+            }
+            _ => return Ok(()),
+        };
+        let mut reply = input.into_reply(None);
+        let mut timer = self.timer.lock().unwrap();
+        let pending = match reply.body.payload {
+            Payload::Arm { id, ms } => {
+                *timer = Some(ctx.after(Duration::from_millis(ms), &self.inject, Fired(id)));
+                true
+            }
+            Payload::Reset { ms } => timer.as_ref().unwrap().reset(Duration::from_millis(ms)),
+            Payload::Cancel => timer.as_ref().unwrap().cancel(),
+            _ => return Ok(()),
+        };
+        reply.body.payload = Payload::Done { pending };
+        reply.send(&ctx)
+    }
+}
+
+#[tokio::test]
+async fn timers_fire_unless_pushed_back_or_cancelled() {
+    let clock = ManualClock::new();
+    let ms = |ms| Duration::from_millis(ms);
+    let (mut client, node) =
+        MaelstromClient::in_process::<_, Timers, _, (), _>(Runtime::new().clock(clock.clone()), (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+    let done = |pending: bool| json!({"type": "done", "pending": pending});
+
+    // pushed back past its first deadline
+    client.expect(json!({"type": "arm", "id": 1, "ms": 100}), done(true)).await.unwrap();
+    clock.advance(ms(60));
+    client.expect(json!({"type": "reset", "ms": 100}), done(true)).await.unwrap();
+    clock.advance(ms(60));
+    clock.advance(ms(50));
+    assert_eq!(client.next_other().await.unwrap()["body"]["id"], 1);
+    client.expect(json!({"type": "reset", "ms": 100}), done(false)).await.unwrap();
+
+    // cancelled, so the next timer is the next to fire
+    client.expect(json!({"type": "arm", "id": 2, "ms": 100}), done(true)).await.unwrap();
+    client.expect(json!({"type": "cancel"}), done(true)).await.unwrap();
+    clock.advance(ms(200));
+    client.expect(json!({"type": "cancel"}), done(false)).await.unwrap();
+    client.expect(json!({"type": "arm", "id": 3, "ms": 10}), done(true)).await.unwrap();
+    clock.advance(ms(10));
+    assert_eq!(client.next_other().await.unwrap()["body"]["id"], 3);
+    node.abort();
+}
@@ -0,0 +1,180 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::emulate::Emulation;
+use dist_sys::kv::{Kv, Slot, SlotLock, Txn};
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    /// Adds 1 to `x`, while another transaction sets it on the first run.
+    Conflict,
+    /// Sets `b` to `a + b`, while another transaction sets `a` on the first
+    /// run.
+    ReadOnly,
+    /// Adds 1 to `k`, which a transaction that never finished has locked.
+    Stale,
+    Done {
+        runs: usize,
+        value: Option<u64>,
+        records: Vec<Value>,
+    },
+}
+
+/// Sets `key` in a transaction of its own.
+async fn set(kv: &Kv, key: &str, value: u64) -> anyhow::Result<()> {
+    let key = key.to_string();
+    kv.transact(async move |txn: &mut Txn<'_, u64>| {
+        txn.write(&key, value);
+        Ok(())
+    })
+    .await
+}
+
+/// Runs transactions over the emulated lin-kv that something gets in the
+/// way of.
+struct Transacting;
+
+impl Transacting {
+    async fn run(&self, payload: Payload, kv: &Kv) -> anyhow::Result<Payload> {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        let (key, value) = match payload {
+            Payload::Conflict => {
+                let inner = kv.clone();
+                let value = kv
+                    .transact(async move |txn: &mut Txn<'_, u64>| {
+                        let x = txn.read("x").await?.unwrap_or(0);
+                        if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                            set(&inner, "x", 10).await?;
+                        }
+                        txn.write("x", x + 1);
+                        Ok(x + 1)
+                    })
+                    .await?;
+                ("x", value)
+            }
+            Payload::ReadOnly => {
+                kv.transact(async |txn: &mut Txn<'_, u64>| {
+                    txn.write("a", 1);
+                    txn.write("b", 2);
+                    Ok(())
+                })
+                .await?;
+                let inner = kv.clone();
+                let value = kv
+                    .transact(async move |txn: &mut Txn<'_, u64>| {
+                        let a = txn.read("a").await?.unwrap_or(0);
+                        let b = txn.read("b").await?.unwrap_or(0);
+                        if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                            set(&inner, "a", 5).await?;
+                        }
+                        txn.write("b", a + b);
+                        Ok(a + b)
+                    })
+                    .await?;
+                ("b", value)
+            }
+            Payload::Stale => {
+                kv.write("txn/n9/0", json!({"status": "pending", "txn": "ghost"})).await?;
+                let locked = Slot {
+                    version: 1,
+                    value: Some(3),
+                    lock: Some(SlotLock {
+                        record: "txn/n9/0".to_string(),
+                        txn: "ghost".to_string(),
+                        write: Some(99),
+                    }),
+                };
+                kv.write("k", locked).await?;
+                let value = kv
+                    .transact(async move |txn: &mut Txn<'_, u64>| {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        let k = txn.read("k").await?.unwrap_or(0);
+                        txn.write("k", k + 1);
+                        Ok(k + 1)
+                    })
+                    .await?;
+                ("k", value)
+            }
+            _ => anyhow::bail!("not a test"),
+        };
+        // what a later transaction sees
+        let key = key.to_string();
+        let read = kv
+            .transact(async move |txn: &mut Txn<'_, u64>| {
+                let read = txn.read(&key).await?;
+                Ok(read)
+            })
+            .await?;
+        assert_eq!(read, Some(value));
+        let mut records = Vec::new();
+        for key in ["txn/n1/0", "txn/n9/0"] {
+            records.push(kv.read::<Value>(key).await?.unwrap_or_default());
+        }
+        Ok(Payload::Done {
+            runs: runs.load(Ordering::SeqCst),
+            value: Some(value),
+            records,
+        })
+    }
+}
+
+impl Node<(), Payload> for Transacting {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Transacting)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        let done = self.run(payload, &Kv::lin(&ctx)).await?;
+        request.reply_with(done, &ctx).send(&ctx)
+    }
+}
+
+async fn run(test: &str) -> Value {
+    let runtime = Runtime::new().emulate(Emulation::all());
+    let (mut client, node) = MaelstromClient::in_process::<_, Transacting, _, (), ()>(runtime, (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+    let done = client.request(json!({"type": test})).await.unwrap();
+    node.abort();
+    done
+}
+
+#[tokio::test]
+async fn a_conflicting_commit_makes_the_body_run_again() {
+    let done = run("conflict").await;
+    assert_eq!(done["runs"], 2, "{}", done);
+    assert_eq!(done["value"], 11);
+    // freed for the next transaction
+    assert_eq!(done["records"][0], json!({"status": "free"}));
+}
+
+#[tokio::test]
+async fn a_read_only_key_changed_underneath_fails_validation() {
+    let done = run("read_only").await;
+    assert_eq!(done["runs"], 2, "{}", done);
+    // not 1 + 2 from the first run
+    assert_eq!(done["value"], 7);
+}
+
+#[tokio::test]
+async fn a_stale_pending_lock_is_aborted_and_rolled_back() {
+    let done = run("stale").await;
+    assert_eq!(done["runs"], 1, "{}", done);
+    // 3 + 1, not the 99 the lock would have written
+    assert_eq!(done["value"], 4);
+    assert_eq!(done["records"][1], json!({"status": "aborted", "txn": "ghost"}));
+}
@@ -0,0 +1,63 @@
+use dist_sys::backoff::{Backoff, Policy};
+use dist_sys::client::MaelstromClient;
+use dist_sys::emulate::Emulation;
+use dist_sys::fanout;
+use dist_sys::kv::Kv;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Go,
+    GoOk { total: u64 },
+}
+
+/// Races ten increments of one key against each other.
+struct Racing;
+
+impl Node<(), Payload> for Racing {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Racing)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let kv = Kv::lin(&ctx);
+        let increments = (0..10).map(|_| kv.update("n", |n: u64| n + 1));
+        fanout::all(increments).await?;
+
+        // uncontended, one attempt is all an update takes
+        let impatient = kv.clone().backoff(Policy::new(Backoff::Constant(Duration::ZERO)).max_attempts(1));
+        let mut calls = 0;
+        let written = impatient
+            .update("n", |n: u64| {
+                calls += 1;
+                n + 1
+            })
+            .await?;
+        assert_eq!((written, calls), (11, 1));
+
+        let total = kv.read("n").await?.unwrap();
+        input.reply_with(Payload::GoOk { total }, &ctx).send(&ctx)
+    }
+}
+
+#[tokio::test]
+async fn racing_updates_all_land() {
+    let runtime = Runtime::new().emulate(Emulation::all());
+    let (mut client, node) = MaelstromClient::in_process::<_, Racing, _, (), ()>(runtime, (), "n1");
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+    client.expect(json!({"type": "go"}), json!({"type": "go_ok", "total": 11})).await.unwrap();
+    node.abort();
+}
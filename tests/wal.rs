@@ -0,0 +1,91 @@
+use dist_sys::wal::{Durability, Wal, crc32};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn scratch(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("dist-sys-wal-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn crc32_matches_the_ieee_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+}
+
+#[test]
+fn records_survive_reopening() {
+    let path = scratch("reopen");
+    let (mut wal, records) = Wal::<(u64, String)>::open(&path).unwrap();
+    assert!(records.is_empty());
+    wal.append(&(1, "a".to_string())).unwrap();
+    wal.append(&(2, "b".to_string())).unwrap();
+    wal.sync().unwrap();
+    drop(wal);
+
+    let (mut wal, records) = Wal::<(u64, String)>::open(&path).unwrap();
+    assert_eq!(records, [(1, "a".to_string()), (2, "b".to_string())]);
+    wal.append(&(3, "c".to_string())).unwrap();
+    drop(wal);
+    assert_eq!(Wal::<(u64, String)>::open(&path).unwrap().1.len(), 3);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn a_torn_or_corrupt_tail_is_truncated() {
+    let path = scratch("torn");
+    let (mut wal, _) = Wal::<u64>::open(&path).unwrap();
+    for n in 1..=3 {
+        wal.append(&n).unwrap();
+    }
+    drop(wal);
+    let whole = std::fs::metadata(&path).unwrap().len();
+
+    // half a record, as a kill mid-write leaves it
+    let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[9, 0, 0, 0, 1, 2]).unwrap();
+    drop(file);
+    let (mut wal, records) = Wal::<u64>::open(&path).unwrap();
+    assert_eq!(records, [1, 2, 3]);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), whole);
+    wal.append(&4).unwrap();
+    drop(wal);
+    assert_eq!(Wal::<u64>::open(&path).unwrap().1, [1, 2, 3, 4]);
+
+    // a flipped bit in the second record drops it and everything after it
+    let mut bytes = std::fs::read(&path).unwrap();
+    let second = 8 + 1;
+    bytes[second + 8] ^= 1;
+    std::fs::write(&path, bytes).unwrap();
+    assert_eq!(Wal::<u64>::open(&path).unwrap().1, [1]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn durability_decides_when_appends_are_flushed() {
+    let path = scratch("durability");
+    let syncs_after = |durability, appends: u64| {
+        let _ = std::fs::remove_file(&path);
+        let (mut wal, _) = Wal::<u64>::open_with(&path, durability).unwrap();
+        for n in 0..appends {
+            wal.append(&n).unwrap();
+        }
+        let syncs = wal.sync_stats().syncs;
+        wal.clear().unwrap();
+        (syncs, wal.sync_stats().syncs)
+    };
+    assert_eq!(syncs_after(Durability::Always, 3), (3, 4));
+    assert_eq!(syncs_after(Durability::OnSnapshot, 3), (0, 1));
+    assert_eq!(syncs_after(Durability::Never, 3), (0, 0));
+
+    let _ = std::fs::remove_file(&path);
+    let (mut wal, _) = Wal::<u64>::open_with(&path, Durability::Every(Duration::from_millis(10))).unwrap();
+    wal.append(&1).unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    let stats = wal.sync_stats();
+    assert_eq!(stats.syncs, 1, "flushes only while there is something to flush");
+    assert!(stats.max >= stats.mean());
+    drop(wal);
+    std::fs::remove_file(path).unwrap();
+}
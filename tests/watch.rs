@@ -0,0 +1,101 @@
+use dist_sys::client::MaelstromClient;
+use dist_sys::emulate::Emulation;
+use dist_sys::kv::Kv;
+use dist_sys::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    /// Watches `key` for `changes` changes, polling every 10ms to 20ms.
+    Watch { key: String, changes: usize },
+    /// Each value seen, with the millisecond it was seen at.
+    WatchOk { seen: Vec<(Option<u64>, u64)> },
+    /// Writes `value` to `key` `after_ms` from now.
+    Write { key: String, value: u64, after_ms: u64 },
+    WriteOk,
+}
+
+struct Watcher;
+
+impl Node<(), Payload> for Watcher {
+    async fn from_init(
+        _state: (),
+        _init: Init,
+        _tx: tokio::sync::mpsc::UnboundedSender<Event<Payload>>,
+        _ctx: &Ctx,
+    ) -> anyhow::Result<Self> {
+        Ok(Watcher)
+    }
+
+    async fn step(&self, input: Event<Payload>, ctx: Ctx) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let (payload, request) = input.take_payload();
+        let kv = Kv::lin(&ctx);
+        let reply = match payload {
+            Payload::Watch { key, changes } => {
+                let start = ctx.now();
+                let mut watch = kv
+                    .watch(key)
+                    .poll_interval(Duration::from_millis(10), Duration::from_millis(20));
+                let mut seen = Vec::new();
+                for _ in 0..changes {
+                    let value = watch.changed().await?;
+                    seen.push((value, start.elapsed().as_millis() as u64));
+                }
+                Payload::WatchOk { seen }
+            }
+            Payload::Write { key, value, after_ms } => {
+                ctx.sleep(Duration::from_millis(after_ms)).await;
+                kv.write(&key, value).await?;
+                Payload::WriteOk
+            }
+            _ => return Ok(()),
+        };
+        request.reply_with(reply, &ctx).send(&ctx)
+    }
+}
+
+async fn watcher() -> (MaelstromClient, tokio::task::JoinHandle<anyhow::Result<()>>) {
+    let runtime = Runtime::new().emulate(Emulation::all());
+    let (client, node) = MaelstromClient::in_process::<_, Watcher, _, (), ()>(runtime, (), "n1");
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    let node = tokio::spawn(node);
+    client.init(&["n1"]).await.unwrap();
+    (client, node)
+}
+
+// paused, so when each poll lands is exact
+#[tokio::test(start_paused = true)]
+async fn watches_back_off_while_a_key_is_idle_and_catch_up_after_a_change() {
+    let (mut client, node) = watcher().await;
+    let watching = client.send(json!({"type": "watch", "key": "k", "changes": 4})).await.unwrap();
+    for (value, after_ms) in [(5, 35), (6, 85), (7, 102), (8, 104)] {
+        client
+            .send(json!({"type": "write", "key": "k", "value": value, "after_ms": after_ms}))
+            .await
+            .unwrap();
+    }
+    let reply = client.reply_to("c1", watching).await.unwrap();
+    // polls at 0, 10, 30 and then every 20ms; back to 10ms after 5 shows up
+    // at 50, so 6 is seen at 100 and 7 and 8 come in the same 10ms
+    assert_eq!(reply["body"]["seen"], json!([[null, 0], [5, 50], [6, 100], [8, 110]]));
+    node.abort();
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_watch_starts_from_the_current_value() {
+    let (mut client, node) = watcher().await;
+    client
+        .request(json!({"type": "write", "key": "k", "value": 1, "after_ms": 0}))
+        .await
+        .unwrap();
+    let reply = client.request(json!({"type": "watch", "key": "k", "changes": 1})).await.unwrap();
+    assert_eq!(reply["seen"], json!([[1, 0]]));
+    node.abort();
+}
+